@@ -0,0 +1,50 @@
+#![no_main]
+//! Arbitrary record sequences straight into the engine, skipping the parser.
+//!
+//! Every 12 bytes are one record: type, client, tx, amount scale and an i64 amount mantissa
+//! (little endian). Clients and transaction ids are folded into small ranges so records keep
+//! running into each other. A scale byte with the high bit set stands for `Decimal::MAX` signed
+//! like the mantissa, to push balances to the edge of the range, and one with the next bit set
+//! for a blank amount.
+use libfuzzer_sys::fuzz_target;
+use playing_with_money::conservation::assert_invariants;
+use playing_with_money::{process_record, ClientId, Record, SituatedRecord, TransactionType, TxId};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+
+fn record(bytes: &[u8]) -> Record {
+    let mut mantissa = [0u8; 8];
+    mantissa.copy_from_slice(&bytes[4..12]);
+    let mantissa = i64::from_le_bytes(mantissa);
+    let amount = if bytes[3] & 0x80 != 0 {
+        if mantissa < 0 {
+            Some(Decimal::MIN)
+        } else {
+            Some(Decimal::MAX)
+        }
+    } else if bytes[3] & 0x40 != 0 {
+        None
+    } else {
+        Some(Decimal::new(mantissa, u32::from(bytes[3] % 29)))
+    };
+    Record {
+        transaction_type: TransactionType::ALL[usize::from(bytes[0]) % TransactionType::ALL.len()],
+        client_id: ClientId(u16::from(bytes[1] % 4)),
+        transaction_id: TxId(u32::from(bytes[2] % 16)),
+        amount: amount.map(Into::into),
+        timestamp: None,
+        reason: None,
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut clients = HashMap::new();
+    for (monotonic_counter, bytes) in data.chunks_exact(12).enumerate() {
+        let situated_record = SituatedRecord {
+            monotonic_counter,
+            record: record(bytes),
+        };
+        let _ = process_record(situated_record, &mut clients);
+    }
+    assert_invariants(&clients);
+});
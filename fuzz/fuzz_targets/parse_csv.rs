@@ -0,0 +1,56 @@
+#![no_main]
+//! Arbitrary bytes through the header check, the parser and the engine, the same path a
+//! transactions file takes. The first byte picks the parsing options so lenient normalization
+//! and the amount policies get fuzzed too.
+use csv::{ReaderBuilder, Trim};
+use libfuzzer_sys::fuzz_target;
+use playing_with_money::amount::{AmountPolicy, ExcessPrecision, Scientific};
+use playing_with_money::conservation::assert_invariants;
+use playing_with_money::pipeline::{self, PipelineConfig};
+use playing_with_money::schema::{self, ColumnMap};
+use playing_with_money::process_record;
+use std::collections::HashMap;
+use std::io::Cursor;
+
+fuzz_target!(|data: &[u8]| {
+    let (options, input) = match data.split_first() {
+        Some((options, input)) => (*options, input),
+        None => return,
+    };
+    let config = PipelineConfig {
+        read_queue_capacity: 8,
+        parse_queue_capacity: 8,
+        lenient: options & 1 != 0,
+        amount_policy: AmountPolicy {
+            scientific: if options & 2 != 0 {
+                Scientific::Accept
+            } else {
+                Scientific::Reject
+            },
+            excess_precision: match (options >> 2) % 3 {
+                0 => ExcessPrecision::Round,
+                1 => ExcessPrecision::Truncate,
+                _ => ExcessPrecision::Reject,
+            },
+        },
+        ..PipelineConfig::default()
+    };
+    let mut reader = ReaderBuilder::new()
+        .trim(Trim::All)
+        .flexible(config.lenient)
+        .from_reader(Cursor::new(input.to_vec()));
+    if schema::prepare(&mut reader, &ColumnMap::default()).is_err() {
+        return;
+    }
+    let mut clients = HashMap::new();
+    // unparsable rows are skipped, as they would be with a dead letter file
+    let _ = pipeline::run(
+        reader,
+        &config,
+        |situated_record| {
+            let _ = process_record(situated_record, &mut clients);
+        },
+        |_| Ok(()),
+    );
+    assert_invariants(&clients);
+});
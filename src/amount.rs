@@ -0,0 +1,243 @@
+use csv::StringRecord;
+use rust_decimal::prelude::RoundingStrategy;
+use rust_decimal::Decimal;
+use std::fmt;
+use std::str::FromStr;
+
+/// What to do with amounts written in scientific notation, e.g. `1e4`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Scientific {
+    #[default]
+    Reject,
+    Accept,
+}
+
+impl FromStr for Scientific {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(Scientific::Reject),
+            "accept" => Ok(Scientific::Accept),
+            _ => Err(format!(
+                "Unknown scientific notation policy ({}), expected reject or accept.",
+                s
+            )),
+        }
+    }
+}
+
+/// What to do with amounts carrying more decimal places than the engine keeps.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ExcessPrecision {
+    /// to the nearest, ties to even
+    #[default]
+    Round,
+    Truncate,
+    Reject,
+}
+
+impl FromStr for ExcessPrecision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "round" => Ok(ExcessPrecision::Round),
+            "truncate" => Ok(ExcessPrecision::Truncate),
+            "reject" => Ok(ExcessPrecision::Reject),
+            _ => Err(format!(
+                "Unknown excess precision policy ({}), expected round, truncate or reject.",
+                s
+            )),
+        }
+    }
+}
+
+/// How amounts are parsed. The defaults keep the original behaviour: scientific notation is an
+/// error and extra decimal places are rounded away.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct AmountPolicy {
+    pub scientific: Scientific,
+    pub excess_precision: ExcessPrecision,
+}
+
+/// An amount that was changed to fit the policy, as written and as parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Adjustment {
+    pub raw: String,
+    pub adjusted: Decimal,
+}
+
+/// An amount the policy turned down, with where its row starts in the input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Refused {
+    pub message: String,
+    pub position: Option<csv::Position>,
+}
+
+impl fmt::Display for Refused {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.position {
+            Some(position) => write!(
+                f,
+                "record (line: {}, byte: {}): {}",
+                position.line(),
+                position.byte(),
+                self.message
+            ),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for Refused {}
+
+impl AmountPolicy {
+    /// `raw` as a decimal without trailing zeros, so `1.50` and `1.5` are the same from here on.
+    pub fn parse(&self, raw: &str, precision: u32) -> Result<Decimal, String> {
+        let scientific = raw.contains(['e', 'E']);
+        let parsed = match (scientific, self.scientific) {
+            (true, Scientific::Reject) => {
+                return Err(format!(
+                    "amount ({}) is in scientific notation, see --scientific",
+                    raw
+                ))
+            }
+            (true, Scientific::Accept) => Decimal::from_scientific(raw),
+            (false, _) => Decimal::from_str(raw),
+        }
+        .map_err(|e| e.to_string())?
+        .normalize();
+        if parsed.scale() <= precision {
+            return Ok(parsed);
+        }
+        match self.excess_precision {
+            ExcessPrecision::Round => Ok(parsed.round_dp(precision).normalize()),
+            ExcessPrecision::Truncate => Ok(parsed
+                .round_dp_with_strategy(precision, RoundingStrategy::ToZero)
+                .normalize()),
+            ExcessPrecision::Reject => Err(format!(
+                "amount ({}) has more than {} decimal places, see --excess-precision",
+                raw, precision
+            )),
+        }
+    }
+
+    /// `row` as the engine reads it: its amount, if it has one, parsed by this policy and written
+    /// back as the engine keeps it, so a [`crate::Record`] deserialized from it, whose amount is
+    /// parsed by the default policy, has the amount this policy makes of it. Also returns the
+    /// adjustment made, if any. An amount this policy turns down is [`Refused`] at the row's position.
+    pub fn apply(
+        &self,
+        headers: &StringRecord,
+        row: &StringRecord,
+    ) -> Result<(StringRecord, Option<Adjustment>), Refused> {
+        let column = headers.iter().position(|header| header == "amount");
+        let raw = match column.and_then(|column| row.get(column)) {
+            Some(raw) if !raw.is_empty() => raw,
+            _ => return Ok((row.clone(), None)),
+        };
+        let parsed = match self.parse(raw, crate::PRECISION) {
+            Ok(parsed) => parsed,
+            Err(message) => {
+                return Err(Refused {
+                    message,
+                    position: row.position().cloned(),
+                })
+            }
+        };
+        let adjustment = (Decimal::from_str(raw) != Ok(parsed)).then(|| Adjustment {
+            raw: raw.to_string(),
+            adjusted: parsed,
+        });
+        let adjusted = parsed.to_string();
+        let mut policed: StringRecord = row
+            .iter()
+            .enumerate()
+            .map(|(index, field)| match Some(index) == column {
+                true => adjusted.as_str(),
+                false => field,
+            })
+            .collect();
+        policed.set_position(row.position().cloned());
+        Ok((policed, adjustment))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::money::Money;
+
+    #[test]
+    fn test_scientific_notation() {
+        let reject = AmountPolicy::default();
+        assert!(reject.parse("1e4", 4).is_err());
+        let accept = AmountPolicy {
+            scientific: Scientific::Accept,
+            ..AmountPolicy::default()
+        };
+        assert_eq!(Ok(Decimal::new(10000, 0)), accept.parse("1e4", 4));
+        assert_eq!(Ok(Decimal::new(125, 2)), accept.parse("1.25E0", 4));
+    }
+
+    #[test]
+    fn test_excess_precision() {
+        let raw = "1.234567890123";
+        let policy = |excess_precision| AmountPolicy {
+            excess_precision,
+            ..AmountPolicy::default()
+        };
+        assert_eq!(
+            Ok(Decimal::new(12346, 4)),
+            policy(ExcessPrecision::Round).parse(raw, 4)
+        );
+        assert_eq!(
+            Ok(Decimal::new(12345, 4)),
+            policy(ExcessPrecision::Truncate).parse(raw, 4)
+        );
+        assert!(policy(ExcessPrecision::Reject).parse(raw, 4).is_err());
+        // trailing zeros aren't precision
+        assert_eq!(
+            Ok(Decimal::new(15, 1)),
+            policy(ExcessPrecision::Reject).parse("1.500000", 4)
+        );
+    }
+
+    #[test]
+    fn test_adjustments_are_reported() {
+        let policy = AmountPolicy {
+            scientific: Scientific::Accept,
+            excess_precision: ExcessPrecision::Truncate,
+        };
+        let headers = StringRecord::from(vec!["type", "client", "tx", "amount"]);
+        let row = |amount| StringRecord::from(vec!["deposit", "1", "1", amount]);
+        let (policed, adjustment) = policy.apply(&headers, &row("2.50")).unwrap();
+        assert_eq!(row("2.5"), policed);
+        assert_eq!(None, adjustment);
+        let (policed, adjustment) = policy.apply(&headers, &row("2e-5")).unwrap();
+        assert_eq!(row("0"), policed);
+        assert_eq!(
+            Some(Adjustment {
+                raw: "2e-5".to_string(),
+                adjusted: Decimal::ZERO,
+            }),
+            adjustment
+        );
+        // what the default policy reads back is what this policy made of the amount
+        let record: crate::Record = policed.deserialize(Some(&headers)).unwrap();
+        assert_eq!(Some(Money::ZERO), record.amount);
+        let mut scientific = row("1e4");
+        let mut position = csv::Position::new();
+        position.set_line(3).set_byte(40).set_record(2);
+        scientific.set_position(Some(position.clone()));
+        let refused = AmountPolicy::default()
+            .apply(&headers, &scientific)
+            .unwrap_err();
+        assert_eq!(
+            "amount (1e4) is in scientific notation, see --scientific",
+            refused.message
+        );
+        assert_eq!(Some(position), refused.position);
+    }
+}
@@ -0,0 +1,199 @@
+use crate::pipeline::PipelineConfig;
+use crate::schema::ColumnMap;
+use crate::{play_with_money, ClientId, ClientState, Sinks, TransactionType};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io;
+
+/// The percentiles written for every metric, by nearest rank.
+pub const PERCENTILES: [u32; 3] = [50, 90, 99];
+
+/// How the values of a metric are spread, e.g. the total funds of every client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Distribution {
+    pub metric: &'static str,
+    /// sorted, smallest first
+    values: Vec<Decimal>,
+}
+
+impl Distribution {
+    fn new(metric: &'static str, mut values: Vec<Decimal>) -> Self {
+        values.sort_unstable();
+        Distribution { metric, values }
+    }
+
+    pub fn count(&self) -> usize {
+        self.values.len()
+    }
+
+    /// the smallest value at or above `percent` of the values, None without any.
+    pub fn percentile(&self, percent: u32) -> Option<Decimal> {
+        let rank = (self.values.len() * percent as usize).div_ceil(100);
+        self.values.get(rank.saturating_sub(1)).copied()
+    }
+
+    pub fn min(&self) -> Option<Decimal> {
+        self.values.first().copied()
+    }
+
+    pub fn max(&self) -> Option<Decimal> {
+        self.values.last().copied()
+    }
+}
+
+/// Distributions across every client of a run, see `analyze`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Analysis(pub Vec<Distribution>);
+
+impl Analysis {
+    /// The balances of each client, the size of each deposit and withdrawal and how long each
+    /// dispute came after the transaction it disputes, in rows and, when both are stamped, in
+    /// seconds. Latencies need the clients' history, so there are none without it.
+    pub fn of(clients: &HashMap<ClientId, ClientState>) -> Self {
+        let mut available = vec![];
+        let mut held = vec![];
+        let mut total = vec![];
+        let mut deposits = vec![];
+        let mut withdrawals = vec![];
+        let mut latency_rows = vec![];
+        let mut latency_seconds = vec![];
+        for client in clients.values() {
+            available.push(client.get_available_funds().amount());
+            held.push(client.get_held_funds().amount());
+            total.push(client.get_total_funds().amount());
+            for (tx_id, disputable) in client.disputables() {
+                match disputable.transaction_type {
+                    TransactionType::Deposit => deposits.push(disputable.amount.amount()),
+                    _ => withdrawals.push(disputable.amount.amount()),
+                }
+                let history = client.transaction_history(tx_id).unwrap_or_default();
+                let original = history
+                    .iter()
+                    .find(|situated| situated.monotonic_counter == disputable.monotonic_counter);
+                for dispute in history.iter().filter(|situated| {
+                    matches!(situated.record.transaction_type, TransactionType::Dispute)
+                }) {
+                    let rows = dispute.monotonic_counter - disputable.monotonic_counter;
+                    latency_rows.push(Decimal::from(rows));
+                    let stamped = original.and_then(|original| original.record.timestamp);
+                    if let (Some(from), Some(to)) = (stamped, dispute.record.timestamp) {
+                        latency_seconds.push(Decimal::from(to) - Decimal::from(from));
+                    }
+                }
+            }
+        }
+        Analysis(vec![
+            Distribution::new("available", available),
+            Distribution::new("held", held),
+            Distribution::new("total", total),
+            Distribution::new("deposit", deposits),
+            Distribution::new("withdrawal", withdrawals),
+            Distribution::new("dispute_latency_rows", latency_rows),
+            Distribution::new("dispute_latency_seconds", latency_seconds),
+        ])
+    }
+
+    /// A row per metric: how many values it has, the smallest, each of [`PERCENTILES`] and the
+    /// largest, blank for a metric without values.
+    pub fn write<W: io::Write>(&self, writer: W) -> csv::Result<()> {
+        let mut wtr = csv::Writer::from_writer(writer);
+        let mut headers = vec!["metric".to_string(), "count".to_string(), "min".to_string()];
+        headers.extend(PERCENTILES.iter().map(|percent| format!("p{}", percent)));
+        headers.push("max".to_string());
+        wtr.write_record(&headers)?;
+        let field = |value: Option<Decimal>| value.map(|v| v.to_string()).unwrap_or_default();
+        for distribution in &self.0 {
+            let mut row = vec![
+                distribution.metric.to_string(),
+                distribution.count().to_string(),
+                field(distribution.min()),
+            ];
+            row.extend(
+                PERCENTILES
+                    .iter()
+                    .map(|&percent| field(distribution.percentile(percent))),
+            );
+            row.push(field(distribution.max()));
+            wtr.write_record(&row)?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+/// Run `input` once, publishing nothing and keeping every client's history for the latencies,
+/// and analyze the clients it ends up with.
+pub fn run(
+    input: &OsStr,
+    pipeline_config: &PipelineConfig,
+    columns: &ColumnMap,
+) -> io::Result<Analysis> {
+    let pipeline_config = PipelineConfig {
+        retain_history: true,
+        ..pipeline_config.clone()
+    };
+    let mut clients = HashMap::new();
+    play_with_money(
+        Some(input),
+        &pipeline_config,
+        columns,
+        &Sinks::default(),
+        None,
+        &mut clients,
+    )?;
+    Ok(Analysis::of(&clients))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_percentile() {
+        let distribution = Distribution::new("x", (1..=10).rev().map(Decimal::from).collect());
+        assert_eq!(Some(Decimal::from(1)), distribution.min());
+        assert_eq!(Some(Decimal::from(5)), distribution.percentile(50));
+        assert_eq!(Some(Decimal::from(9)), distribution.percentile(90));
+        assert_eq!(Some(Decimal::from(10)), distribution.percentile(99));
+        assert_eq!(None, Distribution::new("x", vec![]).percentile(50));
+    }
+
+    #[test]
+    fn test_analyze() {
+        let input = env::temp_dir().join(format!("analyze-{}.csv", std::process::id()));
+        fs::write(
+            &input,
+            "type,client,tx,amount,timestamp\n\
+            deposit,1,1,10,100\n\
+            deposit,1,2,30,110\n\
+            deposit,2,3,20,120\n\
+            withdrawal,2,4,5,130\n\
+            dispute,1,1,,400\n\
+            dispute,2,3,,\n",
+        )
+        .unwrap();
+        let analysis = run(
+            input.as_os_str(),
+            &PipelineConfig::default(),
+            &ColumnMap::default(),
+        )
+        .unwrap();
+        let mut output = vec![];
+        analysis.write(&mut output).unwrap();
+        assert_eq!(
+            "metric,count,min,p50,p90,p99,max\n\
+            available,2,-5,-5,30,30,30\n\
+            held,2,10,10,20,20,20\n\
+            total,2,15,15,40,40,40\n\
+            deposit,3,10,20,30,30,30\n\
+            withdrawal,1,5,5,5,5,5\n\
+            dispute_latency_rows,2,3,3,4,4,4\n\
+            dispute_latency_seconds,1,300,300,300,300,300\n",
+            String::from_utf8(output).unwrap()
+        );
+        fs::remove_file(&input).unwrap();
+    }
+}
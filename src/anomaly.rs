@@ -0,0 +1,262 @@
+use crate::pseudonym::Pseudonyms;
+use crate::{ClientId, SituatedRecord, TransactionType};
+use rust_decimal::prelude::ToPrimitive;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+
+/// When a deposit or withdrawal counts as unusual for its client, see --anomalies.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Thresholds {
+    /// standard deviations past the client's moving mean
+    pub z: f64,
+    /// weight of the newest transaction in the moving mean and variance, between 0 and 1
+    pub alpha: f64,
+    /// transactions of a client seen before any of its are flagged
+    pub warmup: usize,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Thresholds {
+            z: 3.0,
+            alpha: 0.1,
+            warmup: 5,
+        }
+    }
+}
+
+/// What's unusual about a transaction.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Metric {
+    /// much larger than the client's transactions of that type usually are
+    Amount,
+    /// much sooner after the client's previous transaction than usual
+    Frequency,
+}
+
+impl Metric {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Metric::Amount => "amount",
+            Metric::Frequency => "frequency",
+        }
+    }
+}
+
+/// An exponentially weighted moving mean and variance, which starts out as the plain ones.
+#[derive(Debug, Copy, Clone, Default)]
+struct Ewma {
+    mean: f64,
+    variance: f64,
+    seen: usize,
+}
+
+impl Ewma {
+    /// how many standard deviations `value` is above the mean so far, then take it in.
+    fn score(&mut self, value: f64, alpha: f64) -> f64 {
+        let deviation = value - self.mean;
+        let score = match self.seen {
+            // the first value is the mean rather than pulled towards zero
+            0 => 0.0,
+            _ => deviation / self.variance.sqrt(),
+        };
+        match self.seen {
+            0 => self.mean = value,
+            seen => {
+                // a plain mean and variance until there are enough values for alpha to weigh
+                let alpha = alpha.max(1.0 / (seen + 1) as f64);
+                let increment = alpha * deviation;
+                self.mean += increment;
+                self.variance = (1.0 - alpha) * (self.variance + deviation * increment);
+            }
+        }
+        self.seen += 1;
+        score
+    }
+}
+
+#[derive(Debug, Default)]
+struct Baseline {
+    deposits: Ewma,
+    withdrawals: Ewma,
+    /// of the gaps between transactions, in seconds when both ends are stamped, else in rows
+    gaps: Ewma,
+    last: Option<(usize, Option<u64>)>,
+}
+
+/// A transaction flagged as unusual, with how many standard deviations off it was.
+#[derive(Debug, Copy, Clone)]
+pub struct Anomaly {
+    pub situated_record: SituatedRecord,
+    pub metric: Metric,
+    pub score: f64,
+}
+
+/// Keeps a moving baseline of each client's deposit and withdrawal amounts and of how often it
+/// transacts, and flags the transactions far off it. Flagged transactions still count towards the
+/// baseline, so a client whose habits change stops being flagged after a while.
+#[derive(Debug, Default)]
+pub struct Detector {
+    thresholds: Thresholds,
+    baselines: HashMap<ClientId, Baseline>,
+}
+
+impl Detector {
+    pub fn new(thresholds: Thresholds) -> Self {
+        Detector {
+            thresholds,
+            baselines: HashMap::new(),
+        }
+    }
+
+    /// Take in an applied deposit or withdrawal, in its client's order, and return what's unusual
+    /// about it.
+    pub fn observe(&mut self, situated_record: &SituatedRecord) -> Vec<Anomaly> {
+        let Thresholds { z, alpha, warmup } = self.thresholds;
+        let record = &situated_record.record;
+        let baseline = self.baselines.entry(record.client_id).or_default();
+        let amounts = match record.transaction_type {
+            TransactionType::Deposit => &mut baseline.deposits,
+            TransactionType::Withdrawal => &mut baseline.withdrawals,
+            _ => return vec![],
+        };
+        let warm = amounts.seen >= warmup;
+        let amount = record
+            .amount
+            .and_then(|amount| amount.amount().to_f64())
+            .unwrap_or(f64::MAX);
+        let mut scores = vec![(Metric::Amount, amounts.score(amount, alpha), warm)];
+        let now = (situated_record.monotonic_counter, record.timestamp);
+        if let Some(last) = baseline.last.replace(now) {
+            let gap = match (last, now) {
+                ((_, Some(then)), (_, Some(now))) => now.saturating_sub(then) as f64,
+                ((then, _), (now, _)) => now.saturating_sub(then) as f64,
+            };
+            let warm = baseline.gaps.seen >= warmup;
+            // a burst is a gap far below the usual one
+            scores.push((Metric::Frequency, -baseline.gaps.score(gap, alpha), warm));
+        }
+        scores
+            .into_iter()
+            .filter(|&(_, score, warm)| warm && score > z)
+            .map(|(metric, score, _)| Anomaly {
+                situated_record: *situated_record,
+                metric,
+                score,
+            })
+            .collect()
+    }
+}
+
+/// Flags unusual deposits and withdrawals from a background thread fed by the engine stage (or
+/// its shards) through [`Anomalies::sender`], and writes them to a CSV file without holding any
+/// of them up. Each client's transactions reach it in order, so with several workers only the
+/// order of the rows changes from run to run.
+pub struct Anomalies {
+    sender: Sender<SituatedRecord>,
+    writer: JoinHandle<io::Result<usize>>,
+}
+
+impl Anomalies {
+    pub fn create(path: &Path, thresholds: Thresholds, pseudonyms: Pseudonyms) -> io::Result<Self> {
+        let mut wtr = csv::Writer::from_writer(BufWriter::new(File::create(path)?));
+        let (sender, receiver) = mpsc::channel::<SituatedRecord>();
+        let writer = thread::spawn(move || {
+            wtr.write_record([
+                "client", "tx", "tx_type", "amount", "counter", "metric", "score",
+            ])?;
+            let mut detector = Detector::new(thresholds);
+            let mut written = 0;
+            for situated_record in receiver {
+                for anomaly in detector.observe(&situated_record) {
+                    let record = &anomaly.situated_record.record;
+                    wtr.write_record([
+                        pseudonyms.client(record.client_id),
+                        record.transaction_id.to_string(),
+                        record.transaction_type.as_str().to_string(),
+                        record.amount.map(|a| a.to_string()).unwrap_or_default(),
+                        anomaly.situated_record.monotonic_counter.to_string(),
+                        anomaly.metric.as_str().to_string(),
+                        format!("{:.2}", anomaly.score),
+                    ])?;
+                    written += 1;
+                }
+            }
+            wtr.flush()?;
+            Ok(written)
+        });
+        Ok(Anomalies { sender, writer })
+    }
+
+    pub fn sender(&self) -> &Sender<SituatedRecord> {
+        &self.sender
+    }
+
+    /// Wait for every transaction to be looked at and return how many were flagged. Clones of the
+    /// sender must be dropped first.
+    pub fn finish(self) -> io::Result<usize> {
+        drop(self.sender);
+        match self.writer.join() {
+            Ok(written) => written,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::money::Money;
+    use crate::Record;
+    use crate::TxId;
+
+    #[test]
+    fn test_detector() {
+        let mut detector = Detector::new(Thresholds {
+            z: 3.0,
+            alpha: 0.2,
+            warmup: 3,
+        });
+        let mut observe = |monotonic_counter: usize, amount: i64, timestamp: u64| {
+            detector.observe(&SituatedRecord {
+                monotonic_counter,
+                record: Record {
+                    transaction_type: TransactionType::Deposit,
+                    client_id: ClientId(1),
+                    transaction_id: TxId(monotonic_counter as u32),
+                    amount: Some(Money::new(amount, 0)),
+                    timestamp: Some(timestamp),
+                    reason: None,
+                },
+            })
+        };
+        // a deposit of about 10 about every hour
+        let usual = [
+            (9, 0),
+            (11, 3000),
+            (10, 7500),
+            (12, 10500),
+            (8, 14700),
+            (10, 18000),
+        ];
+        for (counter, (amount, timestamp)) in usual.into_iter().enumerate() {
+            assert!(observe(counter, amount, timestamp).is_empty());
+        }
+        let flagged = observe(6, 500, 6 * 3600);
+        assert_eq!(
+            vec![Metric::Amount],
+            flagged.iter().map(|a| a.metric).collect::<Vec<_>>()
+        );
+        assert!(flagged[0].score > 3.0);
+        // then a second later
+        let flagged = observe(7, 10, 6 * 3600 + 1);
+        assert_eq!(
+            vec![Metric::Frequency],
+            flagged.iter().map(|a| a.metric).collect::<Vec<_>>()
+        );
+    }
+}
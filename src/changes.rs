@@ -0,0 +1,168 @@
+use crate::money::Money;
+use crate::pseudonym::Pseudonyms;
+use crate::{ClientId, ClientState, SituatedRecord, TransactionType, TxId};
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+
+/// What the change stream reports of a client: its balances and whether it's locked.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    pub available: Money,
+    pub held: Money,
+    pub locked: bool,
+}
+
+impl Snapshot {
+    /// of `client`, or of a client that doesn't exist yet.
+    pub fn of(client: Option<&ClientState>) -> Self {
+        client
+            .map(|client| Snapshot {
+                available: client.get_available_funds(),
+                held: client.get_held_funds(),
+                locked: client.is_locked(),
+            })
+            .unwrap_or_default()
+    }
+
+    fn to_json(self) -> String {
+        // amounts are strings so consumers don't lose precision through floats
+        format!(
+            r#"{{"available":"{}","held":"{}","total":"{}","locked":{}}}"#,
+            self.available,
+            self.held,
+            self.available.amount() + self.held.amount(),
+            self.locked
+        )
+    }
+}
+
+/// A client's balances or lock before and after the record that changed them.
+#[derive(Debug, Copy, Clone)]
+pub struct Change {
+    pub monotonic_counter: usize,
+    pub client_id: ClientId,
+    pub transaction_id: TxId,
+    pub transaction_type: TransactionType,
+    pub prior: Snapshot,
+    pub new: Snapshot,
+}
+
+impl Change {
+    /// the change `situated_record` made by taking its client from `prior` to `new`, if any.
+    pub fn of(situated_record: &SituatedRecord, prior: Snapshot, new: Snapshot) -> Option<Self> {
+        let record = situated_record.record;
+        (prior != new).then_some(Change {
+            monotonic_counter: situated_record.monotonic_counter,
+            client_id: record.client_id,
+            transaction_id: record.transaction_id,
+            transaction_type: record.transaction_type,
+            prior,
+            new,
+        })
+    }
+
+    /// the change as a JSON line, its client written as `pseudonyms` says.
+    pub fn to_json(&self, pseudonyms: &Pseudonyms) -> String {
+        let mut json = String::new();
+        let client = match pseudonyms.is_salted() {
+            true => format!(r#""{}""#, pseudonyms.client(self.client_id)),
+            false => self.client_id.to_string(),
+        };
+        let _ = write!(
+            json,
+            r#"{{"client":{},"prior":{},"new":{},"cause_tx":{},"cause_type":"{}","counter":{}}}"#,
+            client,
+            self.prior.to_json(),
+            self.new.to_json(),
+            self.transaction_id,
+            self.transaction_type.as_str(),
+            self.monotonic_counter
+        );
+        json
+    }
+}
+
+/// Writes every change to a client's balances or lock as a JSON line from a background thread,
+/// fed by the engine stage (or its shards) through [`ChangeLog::sender`], for downstream read
+/// models to follow. Changes of one client are in the order they happened, with several workers
+/// those of different clients interleave differently from run to run.
+pub struct ChangeLog {
+    sender: Sender<Change>,
+    writer: JoinHandle<io::Result<usize>>,
+}
+
+impl ChangeLog {
+    pub fn create(path: &Path, pseudonyms: Pseudonyms) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let (sender, receiver) = mpsc::channel::<Change>();
+        let writer = thread::spawn(move || {
+            let mut written = 0;
+            for change in receiver {
+                writeln!(writer, "{}", change.to_json(&pseudonyms))?;
+                written += 1;
+            }
+            writer.flush()?;
+            Ok(written)
+        });
+        Ok(ChangeLog { sender, writer })
+    }
+
+    pub fn sender(&self) -> &Sender<Change> {
+        &self.sender
+    }
+
+    /// Wait for every change to be written and return how many were. Clones of the sender must be
+    /// dropped first.
+    pub fn finish(self) -> io::Result<usize> {
+        drop(self.sender);
+        match self.writer.join() {
+            Ok(written) => written,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pipeline::PipelineConfig;
+    use crate::{apply_and_publish, situated, Sinks};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_changes() {
+        let (changes, changed) = mpsc::channel();
+        let sinks = Sinks {
+            changes: Some(changes),
+            ..Sinks::default()
+        };
+        let mut clients = HashMap::new();
+        let records = [
+            situated(0, TransactionType::Deposit, Money::new(10, 0)),
+            // declined, so nothing changes
+            situated(1, TransactionType::Withdrawal, Money::new(99, 0)),
+            situated(2, TransactionType::Dispute, Money::ZERO).of(ClientId(1), TxId(0)),
+            situated(3, TransactionType::Chargeback, Money::ZERO).of(ClientId(1), TxId(0)),
+        ];
+        for record in records {
+            apply_and_publish(record, &mut clients, &PipelineConfig::default(), &sinks);
+        }
+        drop(sinks);
+        let changed: Vec<Change> = changed.iter().collect();
+        assert_eq!(
+            vec![0, 2, 3],
+            changed
+                .iter()
+                .map(|change| change.monotonic_counter)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            r#"{"client":1,"prior":{"available":"0","held":"10","total":"10","locked":false},"new":{"available":"0","held":"0","total":"0","locked":true},"cause_tx":0,"cause_type":"chargeback","counter":3}"#,
+            changed[2].to_json(&Pseudonyms::default())
+        );
+    }
+}
@@ -0,0 +1,934 @@
+use crate::digest::Sha256;
+use crate::migrate::{self, Artifact, Rows};
+use crate::money::Money;
+use crate::output::{AmountFormat, OutputColumns};
+use crate::pipeline::PipelineConfig;
+use crate::pseudonym::Pseudonyms;
+use crate::{
+    process_record_with, write_client_state_to, Activity, Balances, ClientId, ClientState,
+    DisputeReason, Record, SituatedRecord,
+};
+use csv::{StringRecord, Writer};
+use log::info;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+pub(crate) const SNAPSHOT: &str = "snapshot.csv";
+pub(crate) const HISTORY: &str = "history.csv";
+const BALANCES: &str = "balances.csv";
+
+pub(crate) const SNAPSHOT_COLUMNS: [&str; 13] = [
+    "client",
+    "available",
+    "held",
+    "locked",
+    "deposited",
+    "withdrawn",
+    "charged_back",
+    "applied",
+    "transactions",
+    "last_counter",
+    "last_timestamp",
+    "admin_held",
+    "credit_used",
+];
+pub(crate) const HISTORY_COLUMNS: [&str; 7] = [
+    "counter",
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "timestamp",
+    "reason_code",
+];
+
+/// A client's state apart from its transaction history, which goes to the history file as it
+/// grows instead of being rewritten at every checkpoint.
+#[derive(Debug, Clone)]
+pub struct Saved {
+    client_id: ClientId,
+    balances: Balances,
+    locked: bool,
+    applied: String,
+    activity: Activity,
+}
+
+impl ClientState {
+    pub fn save(&self) -> Saved {
+        Saved {
+            client_id: self.client_id,
+            balances: self.balances(),
+            locked: self.locked,
+            applied: self.applied.save(),
+            activity: self.activity,
+        }
+    }
+
+    fn restore(saved: &Saved) -> Option<Self> {
+        let balances = saved.balances.normalized();
+        let mut client = ClientState::new(saved.client_id);
+        client.available_funds = balances.available;
+        client.held_funds = balances.held;
+        client.admin_held_funds = balances.admin_held;
+        client.credit_used = balances.credit_used;
+        client.deposited = balances.deposited;
+        client.withdrawn = balances.withdrawn;
+        client.charged_back = balances.charged_back;
+        client.locked = saved.locked;
+        client.applied = Sha256::load(&saved.applied)?;
+        client.activity = saved.activity;
+        Some(client)
+    }
+}
+
+/// What the engine sends the history writer, see [`crate::Sinks`].
+#[derive(Debug)]
+pub enum Entry {
+    /// a record that was kept in its client's history
+    Recorded(SituatedRecord),
+    /// make everything sent before durable, then reply
+    Sync(Sender<io::Result<()>>),
+}
+
+/// When the history writer makes what it wrote durable, besides at every checkpoint.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum Fsync {
+    /// after every record
+    Always,
+    /// once a batch of records or a time window is full, whichever is first, see [`Commit`]
+    Interval,
+    /// whenever the OS gets round to it
+    #[default]
+    Os,
+}
+
+impl FromStr for Fsync {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(Fsync::Always),
+            "interval" => Ok(Fsync::Interval),
+            "os" => Ok(Fsync::Os),
+            _ => Err(format!(
+                "Unknown fsync policy ({}), expected always, interval or os.",
+                s
+            )),
+        }
+    }
+}
+
+/// How the history writer groups records into commits: with [`Fsync::Interval`] every `records`
+/// records or once `window` passed since the first one not yet committed, so a busy run commits
+/// in large batches and a quiet one still commits every so often.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Commit {
+    pub fsync: Fsync,
+    pub records: usize,
+    pub window: Duration,
+}
+
+impl Default for Commit {
+    fn default() -> Self {
+        Commit {
+            fsync: Fsync::Os,
+            records: 1024,
+            window: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Periodic checkpoints of a run in a directory, so a failed run can be resumed from the last one
+/// with [`Checkpoints::resume`] instead of from the first record:
+/// - `history.csv` every record kept in a client's history, appended to as the run goes
+/// - `snapshot.csv` every client's balances as of the checkpoint and the row to resume from
+/// - `balances.csv` the same balances in the output format, for keeping an eye on a long run
+pub struct Checkpoints {
+    dir: PathBuf,
+    every: usize,
+    next: usize,
+    /// of the amounts and client ids in `balances.csv`
+    amounts: AmountFormat,
+    pseudonyms: Pseudonyms,
+    sender: Sender<Entry>,
+    writer: JoinHandle<io::Result<()>>,
+}
+
+impl Checkpoints {
+    /// Checkpoint a new run into `dir` every `every` rows, replacing any earlier checkpoint, and
+    /// commit the history in between as `commit` says.
+    pub fn start(
+        dir: &Path,
+        every: usize,
+        commit: Commit,
+        amounts: AmountFormat,
+        pseudonyms: Pseudonyms,
+    ) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        match fs::remove_file(dir.join(SNAPSHOT)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        let mut history = File::create(dir.join(HISTORY))?;
+        history.write_all(migrate::version_line().as_bytes())?;
+        let mut history = Writer::from_writer(history);
+        history.write_record(HISTORY_COLUMNS)?;
+        history.flush()?;
+        Self::open(dir, every, 0, commit, amounts, pseudonyms)
+    }
+
+    /// Load the last checkpoint in `dir` into `clients`, which should be empty, and return the
+    /// counter of the first row that still has to be applied.
+    pub fn resume(
+        dir: &Path,
+        every: usize,
+        commit: Commit,
+        amounts: AmountFormat,
+        pseudonyms: Pseudonyms,
+        clients: &mut HashMap<ClientId, ClientState>,
+    ) -> io::Result<(Self, usize)> {
+        migrate::upgrade(dir)?;
+        let resume_from = restore_snapshot(dir, clients)?;
+        // rows recorded after the snapshot was taken are applied again, so they're dropped
+        let mut kept = File::create(dir.join(HISTORY).with_extension("tmp"))?;
+        kept.write_all(migrate::version_line().as_bytes())?;
+        let mut kept = Writer::from_writer(kept);
+        kept.write_record(HISTORY_COLUMNS)?;
+        let (headers, rows) = history_rows(dir)?;
+        for row in rows {
+            let row = row?;
+            let situated_record = read_history(&headers, &row)?;
+            if situated_record.monotonic_counter >= resume_from {
+                continue;
+            }
+            push_history(clients, situated_record)?;
+            kept.write_record(&row)?;
+        }
+        kept.flush()?;
+        drop(kept);
+        fs::rename(dir.join(HISTORY).with_extension("tmp"), dir.join(HISTORY))?;
+        info!(
+            "Resuming from row ({}) with {} clients.",
+            resume_from,
+            clients.len()
+        );
+        Ok((
+            Self::open(dir, every, resume_from, commit, amounts, pseudonyms)?,
+            resume_from,
+        ))
+    }
+
+    /// [`load`] the state in `dir` into `clients`, which should be empty, applying the records in
+    /// the history after the last checkpoint again rather than drop them like
+    /// [`Checkpoints::resume`], for adding records to a run that ended some way past its last
+    /// checkpoint, or before its first one. Returns the counter after the last record in the
+    /// history, which is where the added records go on from; only [`Checkpoints::save`] takes
+    /// checkpoints from then on.
+    pub fn reopen(
+        dir: &Path,
+        pipeline_config: &PipelineConfig,
+        amounts: AmountFormat,
+        clients: &mut HashMap<ClientId, ClientState>,
+    ) -> io::Result<(Self, usize)> {
+        // the history is appended to in the current layout
+        migrate::upgrade(dir)?;
+        let next = load(dir, pipeline_config, clients)?;
+        info!(
+            "Reopened checkpoints at row ({}) with {} clients.",
+            next,
+            clients.len()
+        );
+        Ok((
+            Self::open(
+                dir,
+                usize::MAX,
+                next,
+                Commit::default(),
+                amounts,
+                pipeline_config.pseudonyms.clone(),
+            )?,
+            next,
+        ))
+    }
+
+    fn open(
+        dir: &Path,
+        every: usize,
+        resume_from: usize,
+        commit: Commit,
+        amounts: AmountFormat,
+        pseudonyms: Pseudonyms,
+    ) -> io::Result<Self> {
+        let mut history = BufWriter::new(OpenOptions::new().append(true).open(dir.join(HISTORY))?);
+        let (sender, receiver) = mpsc::channel::<Entry>();
+        let writer = thread::spawn(move || {
+            // a failed write is reported at the next sync rather than lost
+            let mut failed = Ok(());
+            // records written since the last commit, and when the first of them was
+            let (mut uncommitted, mut since) = (0, Instant::now());
+            loop {
+                let entry = match (commit.fsync, uncommitted) {
+                    (Fsync::Interval, 1..) => {
+                        match receiver.recv_timeout(commit.window.saturating_sub(since.elapsed())) {
+                            Ok(entry) => Some(entry),
+                            Err(RecvTimeoutError::Timeout) => None,
+                            Err(RecvTimeoutError::Disconnected) => break,
+                        }
+                    }
+                    _ => match receiver.recv() {
+                        Ok(entry) => Some(entry),
+                        Err(_) => break,
+                    },
+                };
+                match entry {
+                    Some(Entry::Recorded(situated_record)) => {
+                        if failed.is_ok() {
+                            failed = write_history(&mut history, &situated_record);
+                        }
+                        if uncommitted == 0 {
+                            since = Instant::now();
+                        }
+                        uncommitted += 1;
+                    }
+                    Some(Entry::Sync(reply)) => {
+                        let synced = std::mem::replace(&mut failed, Ok(()))
+                            .and_then(|_| history.flush())
+                            .and_then(|_| history.get_ref().sync_data());
+                        uncommitted = 0;
+                        let _ = reply.send(synced);
+                    }
+                    None => {}
+                }
+                let due = match commit.fsync {
+                    Fsync::Always => uncommitted > 0,
+                    Fsync::Interval => {
+                        uncommitted >= commit.records
+                            || (uncommitted > 0 && since.elapsed() >= commit.window)
+                    }
+                    Fsync::Os => false,
+                };
+                if due && failed.is_ok() {
+                    failed = history.flush().and_then(|_| history.get_ref().sync_data());
+                    uncommitted = 0;
+                }
+            }
+            failed.and_then(|_| history.flush())
+        });
+        Ok(Checkpoints {
+            dir: dir.to_path_buf(),
+            every: every.max(1),
+            next: next_checkpoint(resume_from, every.max(1)),
+            amounts,
+            pseudonyms,
+            sender,
+            writer,
+        })
+    }
+
+    pub fn sender(&self) -> &Sender<Entry> {
+        &self.sender
+    }
+
+    /// whether a checkpoint should be taken before the row with `monotonic_counter` is applied.
+    pub fn is_due(&self, monotonic_counter: usize) -> bool {
+        monotonic_counter >= self.next
+    }
+
+    /// Record that every row before `resume_from` has been applied and `saved` is the resulting
+    /// state of every client. The snapshot is only replaced once the history it relies on is on
+    /// disk, so a crash part way through leaves the previous checkpoint usable.
+    pub fn save(&mut self, resume_from: usize, saved: &[Saved]) -> io::Result<()> {
+        self.next = next_checkpoint(resume_from, self.every);
+        let (reply, synced) = mpsc::channel();
+        let _ = self.sender.send(Entry::Sync(reply));
+        synced
+            .recv()
+            .map_err(|_| io::Error::other("history writer stopped"))??;
+        replace(&self.dir.join(SNAPSHOT), |file| {
+            write_snapshot(file, resume_from, saved)
+        })?;
+        let clients: HashMap<ClientId, ClientState> = saved
+            .iter()
+            .filter_map(|saved| Some((saved.client_id, ClientState::restore(saved)?)))
+            .collect();
+        replace(&self.dir.join(BALANCES), |file| {
+            Ok(write_client_state_to(
+                file,
+                &clients,
+                &OutputColumns::standard(false),
+                self.amounts,
+                &self.pseudonyms,
+            )?)
+        })?;
+        info!(
+            "Checkpointed {} clients before row ({}).",
+            saved.len(),
+            resume_from
+        );
+        Ok(())
+    }
+
+    /// Wait for the history to be written. Clones of the sender must be dropped first.
+    pub fn finish(self) -> io::Result<()> {
+        drop(self.sender);
+        match self.writer.join() {
+            Ok(written) => written,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+/// Client `client_id` with every record of it in the history in `dir`, whose balances are only
+/// what [`ClientState::as_of`] rebuilds from them. None if the client has no history there.
+pub fn client_history(dir: &Path, client_id: ClientId) -> io::Result<Option<ClientState>> {
+    let (headers, rows) = history_rows(dir)?;
+    let mut client: Option<ClientState> = None;
+    for row in rows {
+        let situated_record = read_history(&headers, &row?)?;
+        let record = situated_record.record;
+        if record.client_id == client_id {
+            client
+                .get_or_insert_with(|| ClientState::new(client_id))
+                .push_transaction(record.transaction_id, situated_record);
+        }
+    }
+    Ok(client)
+}
+
+/// Every record in the history in `dir`, in the order they were recorded.
+pub fn history(dir: &Path) -> io::Result<Vec<SituatedRecord>> {
+    let (headers, rows) = history_rows(dir)?;
+    rows.map(|row| read_history(&headers, &row?)).collect()
+}
+
+/// the header of the history in `dir` and the rows after it, migrated from whichever version
+/// wrote it.
+fn history_rows(dir: &Path) -> io::Result<(StringRecord, Rows)> {
+    let mut rows = Rows::open(&dir.join(HISTORY), Artifact::History)?;
+    let headers = rows
+        .next()
+        .transpose()?
+        .ok_or_else(|| invalid(format!("History in ({:?}) has no header.", dir)))?;
+    Ok((headers, rows))
+}
+
+/// Load the last checkpoint in `dir` into `clients`, which should be empty, then apply every
+/// record in the history after it again with `pipeline_config`, and return the counter after the
+/// last record in the history. Nothing in `dir` is written, see [`Checkpoints::reopen`].
+pub fn load(
+    dir: &Path,
+    pipeline_config: &PipelineConfig,
+    clients: &mut HashMap<ClientId, ClientState>,
+) -> io::Result<usize> {
+    load_except(dir, pipeline_config, clients, &|_| false)
+}
+
+/// [`load`], leaving out the records in the history whose counter is `held_back`. Only records
+/// that changed no balance, like a declined withdrawal, can be left out of what the last
+/// checkpoint accounts for.
+pub fn load_except(
+    dir: &Path,
+    pipeline_config: &PipelineConfig,
+    clients: &mut HashMap<ClientId, ClientState>,
+    held_back: &dyn Fn(usize) -> bool,
+) -> io::Result<usize> {
+    let resume_from = match dir.join(SNAPSHOT).exists() {
+        true => restore_snapshot(dir, clients)?,
+        false => 0,
+    };
+    for client in clients.values_mut() {
+        client.configure(pipeline_config);
+    }
+    let mut next = resume_from;
+    let (headers, rows) = history_rows(dir)?;
+    for row in rows {
+        let situated_record = read_history(&headers, &row?)?;
+        next = next.max(situated_record.monotonic_counter + 1);
+        if held_back(situated_record.monotonic_counter) {
+            continue;
+        }
+        if situated_record.monotonic_counter < resume_from {
+            push_history(clients, situated_record)?;
+        } else {
+            // declined withdrawals are in the history too, and are declined again
+            let _ = process_record_with(situated_record, clients, pipeline_config);
+        }
+    }
+    Ok(next)
+}
+
+fn next_checkpoint(resume_from: usize, every: usize) -> usize {
+    (resume_from / every + 1).saturating_mul(every)
+}
+
+/// load the snapshot in `dir` into `clients` and return the row it resumes from.
+fn restore_snapshot(dir: &Path, clients: &mut HashMap<ClientId, ClientState>) -> io::Result<usize> {
+    let (resume_from, saved) = read_snapshot(&dir.join(SNAPSHOT))?;
+    for saved in &saved {
+        let client = ClientState::restore(saved).ok_or_else(|| {
+            invalid(format!(
+                "Malformed digest state for client ({}) in the snapshot.",
+                saved.client_id
+            ))
+        })?;
+        clients.insert(saved.client_id, client);
+    }
+    Ok(resume_from)
+}
+
+/// put a record the snapshot already accounts for back in its client's history.
+fn push_history(
+    clients: &mut HashMap<ClientId, ClientState>,
+    situated_record: SituatedRecord,
+) -> io::Result<()> {
+    let record = situated_record.record;
+    clients
+        .get_mut(&record.client_id)
+        .ok_or_else(|| {
+            invalid(format!(
+                "History has client ({}) which isn't in the snapshot.",
+                record.client_id
+            ))
+        })?
+        .push_transaction(record.transaction_id, situated_record);
+    Ok(())
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// write `path` through a temporary file, so readers never see it half written.
+pub(crate) fn replace<F: FnOnce(&mut File) -> io::Result<()>>(
+    path: &Path,
+    write: F,
+) -> io::Result<()> {
+    let temporary = path.with_extension("tmp");
+    let mut file = File::create(&temporary)?;
+    write(&mut file)?;
+    file.sync_data()?;
+    fs::rename(temporary, path)
+}
+
+pub(crate) fn write_history<W: Write>(
+    writer: &mut W,
+    situated_record: &SituatedRecord,
+) -> io::Result<()> {
+    let record = &situated_record.record;
+    // amounts keep their scale, so resumed balances print exactly as uninterrupted ones
+    writeln!(
+        writer,
+        "{},{},{},{},{},{},{}",
+        situated_record.monotonic_counter,
+        record.transaction_type.as_str(),
+        record.client_id,
+        record.transaction_id,
+        record.amount.map(|a| a.to_string()).unwrap_or_default(),
+        record.timestamp.map(|t| t.to_string()).unwrap_or_default(),
+        DisputeReason::field(record.reason)
+    )
+}
+
+pub(crate) fn read_history(
+    headers: &StringRecord,
+    row: &StringRecord,
+) -> io::Result<SituatedRecord> {
+    let malformed =
+        |e: &dyn std::fmt::Display| invalid(format!("Malformed history row ({:?}): {}.", row, e));
+    let monotonic_counter = row
+        .get(0)
+        .unwrap_or_default()
+        .parse()
+        .map_err(|e| malformed(&e))?;
+    let record: Record = row.deserialize(Some(headers)).map_err(|e| malformed(&e))?;
+    Ok(SituatedRecord {
+        monotonic_counter,
+        record,
+    })
+}
+
+fn write_snapshot<W: Write>(mut writer: W, resume_from: usize, saved: &[Saved]) -> io::Result<()> {
+    writer.write_all(migrate::version_line().as_bytes())?;
+    let mut writer = csv::WriterBuilder::new().flexible(true).from_writer(writer);
+    writer.write_record(["resume_from", &resume_from.to_string()])?;
+    writer.write_record(SNAPSHOT_COLUMNS)?;
+    for saved in saved {
+        writer.write_record(snapshot_row(saved))?;
+    }
+    writer.flush()
+}
+
+/// `saved` as a row of [`SNAPSHOT_COLUMNS`].
+pub(crate) fn snapshot_row(saved: &Saved) -> [String; 13] {
+    let balances = &saved.balances;
+    [
+        saved.client_id.to_string(),
+        balances.available.to_string(),
+        balances.held.to_string(),
+        saved.locked.to_string(),
+        balances.deposited.to_string(),
+        balances.withdrawn.to_string(),
+        balances.charged_back.to_string(),
+        saved.applied.clone(),
+        saved.activity.applied.to_string(),
+        optional(saved.activity.last_counter),
+        optional(saved.activity.last_timestamp),
+        balances.admin_held.to_string(),
+        balances.credit_used.to_string(),
+    ]
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+fn read_snapshot(path: &Path) -> io::Result<(usize, Vec<Saved>)> {
+    let mut rows = Rows::open(path, Artifact::Snapshot)
+        .map_err(|e| invalid(format!("No checkpoint to resume from ({:?}): {}.", path, e)))?;
+    let mut next = || rows.next().transpose();
+    let resume_from = next()?
+        .filter(|row| row.get(0) == Some("resume_from"))
+        .and_then(|row| row.get(1)?.parse().ok())
+        .ok_or_else(|| invalid(format!("Snapshot ({:?}) has no resume_from row.", path)))?;
+    next()?
+        .filter(|header| header.iter().eq(SNAPSHOT_COLUMNS))
+        .ok_or_else(|| invalid(format!("Snapshot ({:?}) has unexpected columns.", path)))?;
+    let mut saved = vec![];
+    while let Some(row) = next()? {
+        saved.push(read_saved(&row)?);
+    }
+    Ok((resume_from, saved))
+}
+
+/// a row of [`SNAPSHOT_COLUMNS`] back into what was saved.
+pub(crate) fn read_saved(row: &StringRecord) -> io::Result<Saved> {
+    let malformed = || invalid(format!("Malformed snapshot row ({:?}).", row));
+    let money = |index: usize| -> io::Result<Money> {
+        row.get(index)
+            .and_then(|field| field.parse::<Decimal>().ok())
+            .map(Money::from)
+            .ok_or_else(malformed)
+    };
+    // blank when the client hasn't had a record applied yet
+    let optional = |index: usize| -> io::Result<Option<u64>> {
+        match row.get(index) {
+            Some("") => Ok(None),
+            field => field
+                .and_then(|field| field.parse().ok())
+                .map(Some)
+                .ok_or_else(malformed),
+        }
+    };
+    Ok(Saved {
+        client_id: row
+            .get(0)
+            .and_then(|field| field.parse().ok())
+            .ok_or_else(malformed)?,
+        balances: Balances {
+            available: money(1)?,
+            held: money(2)?,
+            admin_held: money(11)?,
+            credit_used: money(12)?,
+            deposited: money(4)?,
+            withdrawn: money(5)?,
+            charged_back: money(6)?,
+        },
+        locked: row
+            .get(3)
+            .and_then(|field| field.parse().ok())
+            .ok_or_else(malformed)?,
+        applied: row.get(7).ok_or_else(malformed)?.to_string(),
+        activity: Activity {
+            applied: row
+                .get(8)
+                .and_then(|field| field.parse().ok())
+                .ok_or_else(malformed)?,
+            last_counter: optional(9)?.map(|counter| counter as usize),
+            last_timestamp: optional(10)?,
+        },
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pipeline::PipelineConfig;
+    use crate::run_hash::RunHash;
+    use crate::schema::ColumnMap;
+    use crate::{play_with_money, Sinks, TransactionType, TxId};
+    use std::env;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn transactions() -> String {
+        let mut csv = String::from("type,client,tx,amount\n");
+        // each client gets six rows at a time, so disputes straddle checkpoints
+        for tx in 0..60u32 {
+            let client = tx / 6 % 4;
+            csv += &match tx % 6 {
+                0 | 1 => format!("deposit,{},{},{}.50\n", client, tx, tx),
+                2 => format!("withdrawal,{},{},7.0\n", client, tx),
+                3 => format!("dispute,{},{},\n", client, tx - 3),
+                4 => format!("chargeback,{},{},\n", client, tx - 4),
+                _ => format!("resolve,{},{},\n", client, tx - 5),
+            };
+        }
+        csv
+    }
+
+    fn run(
+        input: &Path,
+        workers: usize,
+        checkpoint: Option<(&Path, bool)>,
+        interrupted: fn() -> bool,
+    ) -> HashMap<ClientId, ClientState> {
+        let mut config = PipelineConfig {
+            workers,
+            interrupted,
+            ..PipelineConfig::default()
+        };
+        let mut clients = HashMap::new();
+        let mut checkpoints = checkpoint.map(|(dir, resume)| match resume {
+            true => {
+                let (checkpoints, resume_from) = Checkpoints::resume(
+                    dir,
+                    7,
+                    Commit::default(),
+                    AmountFormat::default(),
+                    Pseudonyms::default(),
+                    &mut clients,
+                )
+                .unwrap();
+                config.resume_from = resume_from;
+                checkpoints
+            }
+            false => Checkpoints::start(
+                dir,
+                7,
+                Commit::default(),
+                AmountFormat::default(),
+                Pseudonyms::default(),
+            )
+            .unwrap(),
+        });
+        let sinks = Sinks {
+            history: checkpoints.as_ref().map(|c| c.sender().clone()),
+            ..Sinks::default()
+        };
+        play_with_money(
+            Some(input.as_os_str()),
+            &config,
+            &ColumnMap::default(),
+            &sinks,
+            checkpoints.as_mut(),
+            &mut clients,
+        )
+        .unwrap();
+        drop(sinks);
+        if let Some(checkpoints) = checkpoints {
+            checkpoints.finish().unwrap();
+        }
+        clients
+    }
+
+    #[test]
+    fn test_resume_matches_uninterrupted_run() {
+        let dir = env::temp_dir().join(format!("checkpoints-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("input.csv");
+        let transactions = transactions();
+        fs::write(&input, &transactions).unwrap();
+        // the run "crashes" a few rows after its last checkpoint, whose history is then dropped
+        let crashed = dir.join("crashed.csv");
+        let rows: Vec<&str> = transactions.lines().take(41).collect();
+        fs::write(&crashed, rows.join("\n")).unwrap();
+
+        let uninterrupted = run(&input, 1, None, || false);
+        let expected = RunHash::of(&uninterrupted);
+        for workers in [1, 3] {
+            let checkpoints = dir.join(format!("workers-{}", workers));
+            run(&crashed, workers, Some((&checkpoints, false)), || false);
+            let snapshot = fs::read_to_string(checkpoints.join(SNAPSHOT)).unwrap();
+            assert!(snapshot.starts_with(&(migrate::version_line() + "resume_from,35\n")));
+            let resumed = run(&input, workers, Some((&checkpoints, true)), || false);
+            assert_eq!(expected, RunHash::of(&resumed));
+            for (client_id, client) in &uninterrupted {
+                assert_eq!(client.activity(), resumed[client_id].activity());
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reopen_picks_up_after_the_last_checkpoint() {
+        let dir = env::temp_dir().join(format!("reopen-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("input.csv");
+        fs::write(&input, transactions()).unwrap();
+        let expected = RunHash::of(&run(&input, 1, None, || false));
+        let checkpoints = dir.join("checkpoints");
+        run(&input, 1, Some((&checkpoints, false)), || false);
+        let snapshot = fs::read_to_string(checkpoints.join(SNAPSHOT)).unwrap();
+        assert!(snapshot.starts_with(&(migrate::version_line() + "resume_from,56\n")));
+        let mut clients = HashMap::new();
+        let (reopened, next) = Checkpoints::reopen(
+            &checkpoints,
+            &PipelineConfig::default(),
+            AmountFormat::default(),
+            &mut clients,
+        )
+        .unwrap();
+        reopened.finish().unwrap();
+        // the last three rows are turned down for a locked client, so they aren't in the history
+        assert_eq!(57, next);
+        assert_eq!(expected, RunHash::of(&clients));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_client_history() {
+        let dir = env::temp_dir().join(format!("history-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("input.csv");
+        let transactions = transactions();
+        fs::write(&input, &transactions).unwrap();
+        run(&input, 1, Some((&dir.join("checkpoints"), false)), || false);
+        // the first 22 rows leave client 3 with a dispute open
+        let partial = dir.join("partial.csv");
+        let rows: Vec<&str> = transactions.lines().take(23).collect();
+        fs::write(&partial, rows.join("\n")).unwrap();
+        let expected = &run(&partial, 1, None, || false)[&ClientId(3)];
+        let client = client_history(&dir.join("checkpoints"), ClientId(3))
+            .unwrap()
+            .unwrap()
+            .as_of(22)
+            .unwrap();
+        assert_eq!(expected.get_available_funds(), client.get_available_funds());
+        assert_eq!(expected.get_held_funds(), client.get_held_funds());
+        assert_eq!(1, client.open_disputes());
+        assert!(client_history(&dir.join("checkpoints"), ClientId(9))
+            .unwrap()
+            .is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_interrupted_run_checkpoints_where_it_stopped() {
+        static READ: AtomicUsize = AtomicUsize::new(0);
+        let dir = env::temp_dir().join(format!("interrupted-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("input.csv");
+        fs::write(&input, transactions()).unwrap();
+
+        let expected = RunHash::of(&run(&input, 1, None, || false));
+        for workers in [1, 3] {
+            let checkpoints = dir.join(format!("workers-{}", workers));
+            READ.store(0, Ordering::SeqCst);
+            // stops reading at row 40, between the periodic checkpoints at 35 and 42
+            run(&input, workers, Some((&checkpoints, false)), || {
+                READ.fetch_add(1, Ordering::SeqCst) >= 40
+            });
+            let snapshot = fs::read_to_string(checkpoints.join(SNAPSHOT)).unwrap();
+            assert!(snapshot.starts_with(&(migrate::version_line() + "resume_from,40\n")));
+            let resumed = run(&input, workers, Some((&checkpoints, true)), || false);
+            assert_eq!(expected, RunHash::of(&resumed));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_group_commit() {
+        let dir = env::temp_dir().join(format!("group-commit-{}", std::process::id()));
+        let recorded = |monotonic_counter| {
+            Entry::Recorded(SituatedRecord {
+                monotonic_counter,
+                record: Record {
+                    transaction_type: TransactionType::Deposit,
+                    client_id: ClientId(1),
+                    transaction_id: TxId(monotonic_counter as u32),
+                    amount: Some(Money::new(1, 0)),
+                    timestamp: None,
+                    reason: None,
+                },
+            })
+        };
+        let rows = || {
+            fs::read_to_string(dir.join(HISTORY))
+                .unwrap()
+                .lines()
+                .count()
+                - 2
+        };
+        // committed rows show up in the file, uncommitted ones sit in the writer's buffer
+        let committed = |expected: usize| {
+            let waited = Instant::now();
+            while rows() < expected && waited.elapsed() < Duration::from_secs(5) {
+                thread::sleep(Duration::from_millis(5));
+            }
+            rows()
+        };
+        let batched = Commit {
+            fsync: Fsync::Interval,
+            records: 3,
+            window: Duration::from_secs(3600),
+        };
+        let checkpoints = Checkpoints::start(
+            &dir,
+            100,
+            batched,
+            AmountFormat::default(),
+            Pseudonyms::default(),
+        )
+        .unwrap();
+        for counter in 0..2 {
+            checkpoints.sender().send(recorded(counter)).unwrap();
+        }
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(0, rows());
+        checkpoints.sender().send(recorded(2)).unwrap();
+        assert_eq!(3, committed(3));
+        checkpoints.finish().unwrap();
+
+        let windowed = Commit {
+            window: Duration::from_millis(10),
+            records: 1000,
+            ..batched
+        };
+        let checkpoints = Checkpoints::start(
+            &dir,
+            100,
+            windowed,
+            AmountFormat::default(),
+            Pseudonyms::default(),
+        )
+        .unwrap();
+        checkpoints.sender().send(recorded(0)).unwrap();
+        assert_eq!(1, committed(1));
+        checkpoints.finish().unwrap();
+
+        let always = Commit {
+            fsync: Fsync::Always,
+            ..Commit::default()
+        };
+        let checkpoints = Checkpoints::start(
+            &dir,
+            100,
+            always,
+            AmountFormat::default(),
+            Pseudonyms::default(),
+        )
+        .unwrap();
+        checkpoints.sender().send(recorded(0)).unwrap();
+        assert_eq!(1, committed(1));
+        checkpoints.finish().unwrap();
+        assert_eq!(Ok(Fsync::Interval), "interval".parse());
+        assert!("sometimes".parse::<Fsync>().is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
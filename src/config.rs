@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+/// The value of a key in a config file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    /// `true` or `false`, for options that don't take a value
+    Flag(bool),
+    /// a string or a number, as written
+    Text(String),
+    /// an array of strings or numbers, for options given more than once
+    List(Vec<String>),
+}
+
+type Options = Vec<(String, Value)>;
+
+/// Command-line options kept in a file, see --config: the top-level keys apply to every run, and
+/// a `[profiles.NAME]` table names a set of them for one kind of run, on top of the top-level ones.
+/// A key is the long name of an option, its value a string, number, boolean or array of strings
+/// and numbers. Options go on the command line in the order of their keys.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    shared: Options,
+    profiles: HashMap<String, Options>,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// a string or number as an option is given it on the command line.
+fn text(value: toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(text) => Some(text),
+        toml::Value::Integer(number) => Some(number.to_string()),
+        toml::Value::Float(number) => Some(number.to_string()),
+        _ => None,
+    }
+}
+
+/// the keys of `table` as options, in the order of their keys. `within` names the table in
+/// errors.
+fn options(table: HashMap<String, toml::Value>, within: &str) -> io::Result<Options> {
+    let mut options = table
+        .into_iter()
+        .map(|(key, value)| {
+            let value = match value {
+                toml::Value::Boolean(flag) => Some(Value::Flag(flag)),
+                toml::Value::Array(array) => array
+                    .into_iter()
+                    .map(text)
+                    .collect::<Option<_>>()
+                    .map(Value::List),
+                value => text(value).map(Value::Text),
+            };
+            match value {
+                Some(value) => Ok((key, value)),
+                None => Err(invalid(format!(
+                    "({}) of {} isn't a string, number, boolean or array of strings and numbers.",
+                    key, within
+                ))),
+            }
+        })
+        .collect::<io::Result<Options>>()?;
+    options.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(options)
+}
+
+impl Config {
+    pub fn read<R: io::Read>(mut reader: R) -> io::Result<Self> {
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+        let mut shared: HashMap<String, toml::Value> = toml::from_str(&text)
+            .map_err(|e| invalid(format!("The config isn't valid TOML: {}", e)))?;
+        let profiles = match shared.remove("profiles") {
+            Some(toml::Value::Table(profiles)) => profiles
+                .into_iter()
+                .map(|(name, profile)| match profile {
+                    toml::Value::Table(profile) => Ok((
+                        name.clone(),
+                        options(
+                            profile.into_iter().collect(),
+                            &format!("profile ({})", name),
+                        )?,
+                    )),
+                    _ => Err(invalid(format!("Profile ({}) isn't a table.", name))),
+                })
+                .collect::<io::Result<_>>()?,
+            Some(_) => return Err(invalid("(profiles) isn't a table.".to_string())),
+            None => HashMap::new(),
+        };
+        Ok(Config {
+            shared: options(shared, "the config")?,
+            profiles,
+        })
+    }
+
+    pub fn read_file(path: &Path) -> io::Result<Self> {
+        Self::read(std::fs::File::open(path)?)
+    }
+
+    /// The options of `profile`, or the top-level ones without one, as command-line arguments,
+    /// leaving out the options `given` says are on the command line already, which take their
+    /// place.
+    pub fn args(
+        &self,
+        profile: Option<&str>,
+        given: impl Fn(&str) -> bool,
+    ) -> io::Result<Vec<String>> {
+        let profiled = match profile {
+            Some(name) => self.profiles.get(name).ok_or_else(|| {
+                let mut names: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+                names.sort_unstable();
+                invalid(format!(
+                    "Unknown profile ({}), the config has: {}.",
+                    name,
+                    names.join(", ")
+                ))
+            })?,
+            None => &vec![],
+        };
+        let mut args = vec![];
+        let shared = self
+            .shared
+            .iter()
+            .filter(|(key, _)| !profiled.iter().any(|(k, _)| k == key));
+        for (key, value) in shared.chain(profiled).filter(|(key, _)| !given(key)) {
+            let option = format!("--{}", key);
+            match value {
+                Value::Flag(true) => args.push(option),
+                Value::Flag(false) => {}
+                Value::Text(text) => args.extend([option, text.clone()]),
+                Value::List(list) => {
+                    for text in list {
+                        args.extend([option.clone(), text.clone()]);
+                    }
+                }
+            }
+        }
+        Ok(args)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_config() {
+        let config = Config::read(
+            r#"
+            # every job
+            workers = 4
+            excess-precision = "round"
+
+            [profiles.nightly-reconciliation]
+            output-scale = 4 # for the ledger
+            summary = true
+            report = ["pending=pending.csv", 'suspense=#suspense.csv']
+            run-hash = false
+
+            [profiles."intraday"]
+            excess-precision = "reject"
+            "#
+            .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                "--excess-precision",
+                "round",
+                "--workers",
+                "4",
+                "--output-scale",
+                "4",
+                "--report",
+                "pending=pending.csv",
+                "--report",
+                "suspense=#suspense.csv",
+                "--summary"
+            ],
+            config
+                .args(Some("nightly-reconciliation"), |_| false)
+                .unwrap()
+        );
+        assert_eq!(
+            vec!["--excess-precision", "reject"],
+            config
+                .args(Some("intraday"), |key| key == "workers")
+                .unwrap()
+        );
+        assert_eq!(4, config.args(None, |_| false).unwrap().len());
+        assert!(config.args(Some("weekly"), |_| false).is_err());
+
+        assert!(Config::read("workers = 4\nworkers = 2\n".as_bytes()).is_err());
+        assert!(Config::read("[tables.x]\n".as_bytes()).is_err());
+        assert!(Config::read("summary = yes\n".as_bytes()).is_err());
+        assert!(Config::read("report = [\"a\" \"b\"]\n".as_bytes()).is_err());
+        assert!(Config::read("[profiles.x]\n[profiles.x]\n".as_bytes()).is_err());
+        assert!(Config::read("profiles = 1\n".as_bytes()).is_err());
+        assert!(Config::read("report = [[\"a\"]]\n".as_bytes()).is_err());
+
+        // what TOML has beyond one line per key
+        let config = Config::read(
+            r#"
+            report = [
+                "pending=pending.csv",
+                "held=held.csv", # trailing comma
+            ]
+            profiles = { weekly = { top = 10, summary = true } }
+            "#
+            .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                "--report",
+                "pending=pending.csv",
+                "--report",
+                "held=held.csv",
+                "--summary",
+                "--top",
+                "10"
+            ],
+            config.args(Some("weekly"), |_| false).unwrap()
+        );
+    }
+}
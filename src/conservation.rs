@@ -0,0 +1,215 @@
+use crate::pseudonym::Pseudonyms;
+use crate::{ClientId, ClientState, TransactionType, TxId};
+use log::warn;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+
+/// A client whose balances don't add up to the money that moved through the account.
+#[derive(Debug, PartialEq)]
+pub struct Violation {
+    pub client_id: ClientId,
+    /// deposits - withdrawals - chargebacks
+    pub expected: Decimal,
+    /// available + held
+    pub actual: Decimal,
+    /// disputed transactions, the candidates for having created or destroyed the difference
+    pub transactions: Vec<TxId>,
+}
+
+impl Violation {
+    pub fn drift(&self) -> Decimal {
+        self.actual.saturating_sub(self.expected)
+    }
+}
+
+/// Engine wide reconciliation of money flows against the sum of client balances.
+#[derive(Debug)]
+pub struct Conservation {
+    pub deposits: Decimal,
+    pub withdrawals: Decimal,
+    pub chargebacks: Decimal,
+    pub total_funds: Decimal,
+    pub violations: Vec<Violation>,
+}
+
+impl Conservation {
+    pub fn check(clients: &HashMap<ClientId, ClientState>) -> Self {
+        let mut conservation = Conservation {
+            deposits: Decimal::ZERO,
+            withdrawals: Decimal::ZERO,
+            chargebacks: Decimal::ZERO,
+            total_funds: Decimal::ZERO,
+            violations: vec![],
+        };
+        for client in clients.values() {
+            // engine wide sums can go beyond Decimal's range even when no single client does,
+            // pin them to the limit rather than panicking over a report
+            conservation.deposits = conservation
+                .deposits
+                .saturating_add(client.deposited.amount());
+            conservation.withdrawals = conservation
+                .withdrawals
+                .saturating_add(client.withdrawn.amount());
+            conservation.chargebacks = conservation
+                .chargebacks
+                .saturating_add(client.charged_back.amount());
+            conservation.total_funds = conservation
+                .total_funds
+                .saturating_add(client.get_total_funds().amount());
+            if client.get_net_flows() != client.get_total_funds() {
+                conservation.violations.push(Violation {
+                    client_id: client.client_id,
+                    expected: client.get_net_flows().amount(),
+                    actual: client.get_total_funds().amount(),
+                    transactions: client.disputed_transactions(),
+                });
+            }
+        }
+        conservation
+            .violations
+            .sort_by_key(|violation| violation.client_id);
+        conservation
+    }
+
+    pub fn drift(&self) -> Decimal {
+        let net_flows = self
+            .deposits
+            .saturating_sub(self.withdrawals)
+            .saturating_sub(self.chargebacks);
+        self.total_funds.saturating_sub(net_flows)
+    }
+
+    /// log the violations, if there are any, with client ids written as `pseudonyms` says.
+    pub fn warn(&self, pseudonyms: &Pseudonyms) {
+        if self.violations.is_empty() {
+            return;
+        }
+        warn!(
+            "Money is not conserved: deposits {} - withdrawals {} - chargebacks {} != total funds {} (drift {}).",
+            self.deposits,
+            self.withdrawals,
+            self.chargebacks,
+            self.total_funds,
+            self.drift()
+        );
+        for violation in &self.violations {
+            warn!(
+                "Conservation violation for client ({}): expected total {} but found {} (drift {}), contributing transactions {:?}.",
+                pseudonyms.client(violation.client_id),
+                violation.expected,
+                violation.actual,
+                violation.drift(),
+                violation.transactions
+            );
+        }
+    }
+}
+
+/// Panics if the engine broke an invariant that holds whatever the input: every client's totals
+/// are representable, and money is only created or destroyed by disputing a withdrawal (see the
+/// README). The fuzz targets run this after every input.
+pub fn assert_invariants(clients: &HashMap<ClientId, ClientState>) {
+    for client in clients.values() {
+        let total = client.available_funds.checked_add(client.held_funds);
+        let net_flows = client
+            .deposited
+            .checked_sub(client.withdrawn)
+            .and_then(|net| net.checked_sub(client.charged_back));
+        assert!(
+            total.is_some() && net_flows.is_some(),
+            "Totals of client ({}) overflow.",
+            client.client_id
+        );
+        if total != net_flows {
+            let disputed_withdrawal = client.disputed_transactions().iter().any(|tx_id| {
+                matches!(
+                    client.disputables[tx_id].transaction_type,
+                    TransactionType::Withdrawal
+                )
+            });
+            assert!(
+                disputed_withdrawal,
+                "Client ({}) drifted from {:?} to {:?} without a disputed withdrawal.",
+                client.client_id, net_flows, total
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::money::Money;
+    use crate::{process_record, Record, SituatedRecord};
+
+    fn run(script: &[(TransactionType, u16, u32, i64)]) -> HashMap<ClientId, ClientState> {
+        let mut clients = HashMap::new();
+        for (monotonic_counter, (transaction_type, client_id, transaction_id, amount)) in
+            script.iter().copied().enumerate()
+        {
+            let record = Record {
+                transaction_type,
+                client_id: ClientId(client_id),
+                transaction_id: TxId(transaction_id),
+                amount: Some(Money::new(amount, 0)),
+                timestamp: None,
+                reason: None,
+            };
+            let _ = process_record(
+                SituatedRecord {
+                    monotonic_counter,
+                    record,
+                },
+                &mut clients,
+            );
+        }
+        clients
+    }
+
+    #[test]
+    fn test_conserved() {
+        let clients = run(&[
+            (TransactionType::Deposit, 1, 1, 100),
+            (TransactionType::Withdrawal, 1, 2, 40),
+            (TransactionType::Deposit, 1, 3, 10),
+            (TransactionType::Dispute, 1, 3, 0),
+            (TransactionType::Chargeback, 1, 3, 0),
+            (TransactionType::Deposit, 2, 4, 5),
+            (TransactionType::Dispute, 2, 4, 0),
+            (TransactionType::Resolve, 2, 4, 0),
+        ]);
+        let conservation = Conservation::check(&clients);
+        assert!(conservation.violations.is_empty());
+        assert_eq!(Decimal::new(115, 0), conservation.deposits);
+        assert_eq!(Decimal::new(40, 0), conservation.withdrawals);
+        assert_eq!(Decimal::new(10, 0), conservation.chargebacks);
+        assert_eq!(Decimal::new(65, 0), conservation.total_funds);
+        assert_eq!(Decimal::ZERO, conservation.drift());
+        assert_invariants(&clients);
+    }
+
+    #[test]
+    fn test_disputed_rejected_withdrawal_creates_money() {
+        let clients = run(&[
+            (TransactionType::Deposit, 1, 1, 10),
+            // insufficient funds, so nothing is withdrawn
+            (TransactionType::Withdrawal, 1, 2, 30),
+            (TransactionType::Dispute, 1, 2, 0),
+            (TransactionType::Resolve, 1, 2, 0),
+            (TransactionType::Deposit, 2, 3, 5),
+        ]);
+        let conservation = Conservation::check(&clients);
+        assert_eq!(Decimal::new(30, 0), conservation.drift());
+        assert_eq!(
+            vec![Violation {
+                client_id: ClientId(1),
+                expected: Decimal::new(10, 0),
+                actual: Decimal::new(40, 0),
+                transactions: vec![TxId(2)],
+            }],
+            conservation.violations
+        );
+        // explained by the disputed withdrawal, so not a broken invariant
+        assert_invariants(&clients);
+    }
+}
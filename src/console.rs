@@ -0,0 +1,126 @@
+use crate::pipeline::ParseFailure;
+use crate::pseudonym::Pseudonyms;
+use crate::{Rejection, SituatedRecord};
+#[cfg(feature = "cli")]
+use env_logger::fmt::{Color, Formatter};
+#[cfg(feature = "cli")]
+use log::{Level, Record};
+#[cfg(feature = "cli")]
+use std::io::{self, Write};
+
+/// The target every record turned down or set aside as unparsable is logged under, with its
+/// `PWM-E` number first, after the warning that says why in words.
+pub const REJECTED: &str = "playing_with_money::rejected";
+
+// the width of the level column, "warning" and a space
+#[cfg(feature = "cli")]
+const INDENT: usize = 8;
+
+/// Whether stderr, where the log goes, is a terminal someone is watching.
+pub fn is_terminal() -> bool {
+    // SAFETY: isatty only looks at the descriptor
+    unsafe { libc::isatty(libc::STDERR_FILENO) == 1 }
+}
+
+/// The error code of `rejection` and `situated_record` as a line of the input, as far as its
+/// parsed fields go.
+pub fn rejected(
+    situated_record: &SituatedRecord,
+    rejection: &Rejection,
+    pseudonyms: &Pseudonyms,
+) -> String {
+    let record = &situated_record.record;
+    format!(
+        "{} record {}: {},{},{},{} ({})",
+        rejection.error_code(),
+        situated_record.monotonic_counter,
+        record.transaction_type.as_str(),
+        pseudonyms.client(record.client_id),
+        record.transaction_id,
+        record.amount.map(|a| a.to_string()).unwrap_or_default(),
+        rejection.code()
+    )
+}
+
+/// The same for a row that couldn't be parsed, as it was read.
+pub fn unparsable(failure: &ParseFailure) -> String {
+    match failure.line() {
+        Some(line) => format!(
+            "{} record {}: {} (parse_error)",
+            ParseFailure::ERROR_CODE,
+            failure.monotonic_counter,
+            line
+        ),
+        None => format!(
+            "{} record {} (parse_error)",
+            ParseFailure::ERROR_CODE,
+            failure.monotonic_counter
+        ),
+    }
+}
+
+/// Write `record` for someone reading along: the level in color in a column of its own, every
+/// other line of the message lined up under the first, and the records turned down set apart
+/// under the warning about them.
+#[cfg(feature = "cli")]
+pub fn format(buf: &mut Formatter, record: &Record) -> io::Result<()> {
+    let mut gutter = buf.style();
+    gutter.set_color(Color::Cyan);
+    let message = record.args().to_string();
+    if record.target() == REJECTED {
+        return writeln!(buf, "{:INDENT$}{} {}", "", gutter.value("|"), message);
+    }
+    let (label, color) = match record.level() {
+        Level::Error => ("error", Color::Red),
+        Level::Warn => ("warning", Color::Yellow),
+        Level::Info => ("info", Color::Green),
+        Level::Debug => ("debug", Color::Blue),
+        Level::Trace => ("trace", Color::Magenta),
+    };
+    let mut level = buf.style();
+    level.set_color(color).set_bold(true);
+    let mut lines = message.lines();
+    writeln!(
+        buf,
+        "{} {}",
+        level.value(format!("{:<width$}", label, width = INDENT - 1)),
+        lines.next().unwrap_or_default()
+    )?;
+    for line in lines {
+        match line.trim_start().strip_prefix("row: ") {
+            Some(row) => writeln!(buf, "{:INDENT$}{} {}", "", gutter.value("|"), row)?,
+            None => writeln!(buf, "{:INDENT$}{}", "", line.trim_start())?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ClientId, Record, TransactionType, TxId};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_rejected() {
+        let situated_record = SituatedRecord {
+            monotonic_counter: 3,
+            record: Record {
+                transaction_type: TransactionType::Withdrawal,
+                client_id: ClientId(1),
+                transaction_id: TxId(2),
+                amount: Some(dec!(50).into()),
+                timestamp: None,
+                reason: None,
+            },
+        };
+        assert_eq!(
+            "PWM-E001 record 3: withdrawal,1,2,50 (insufficient_funds)",
+            rejected(
+                &situated_record,
+                &Rejection::InsufficientFunds,
+                &Pseudonyms::default()
+            )
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use crate::messages::Messages;
+use crate::pipeline::ParseFailure;
+use crate::pseudonym::Pseudonyms;
+use crate::{Disputable, DisputeReason, Rejection, SituatedRecord, REFERENCE_COLUMNS};
+use csv::Writer;
+use std::io;
+use std::path::Path;
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+
+/// Input columns carried over verbatim, so a dead letter file can be fed back in as input.
+const RECORD_COLUMNS: [&str; 6] = ["type", "client", "tx", "amount", "timestamp", "reason_code"];
+
+/// A record the engine couldn't use, with the row it came from and why it was set aside.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeadLetter {
+    pub monotonic_counter: usize,
+    pub reason: &'static str,
+    /// the `PWM-E` number of the reason
+    pub error_code: &'static str,
+    pub detail: String,
+    pub fields: [String; 6],
+    /// see [`REFERENCE_COLUMNS`]
+    pub reference: [String; 3],
+}
+
+impl DeadLetter {
+    pub fn rejected(
+        situated_record: &SituatedRecord,
+        rejection: Rejection,
+        reference: Option<&Disputable>,
+        pseudonyms: &Pseudonyms,
+        messages: &Messages,
+    ) -> Self {
+        let record = situated_record.record;
+        DeadLetter {
+            monotonic_counter: situated_record.monotonic_counter,
+            reason: rejection.code(),
+            error_code: rejection.error_code(),
+            detail: messages.rejection(&rejection),
+            fields: [
+                record.transaction_type.as_str().to_string(),
+                pseudonyms.client(record.client_id),
+                record.transaction_id.to_string(),
+                record.amount.map(|a| a.to_string()).unwrap_or_default(),
+                record
+                    .timestamp
+                    .map(|timestamp| timestamp.to_string())
+                    .unwrap_or_default(),
+                DisputeReason::field(record.reason).to_string(),
+            ],
+            reference: Disputable::fields(reference),
+        }
+    }
+
+    pub fn unparsable(failure: &ParseFailure) -> Self {
+        DeadLetter {
+            monotonic_counter: failure.monotonic_counter,
+            reason: "parse_error",
+            error_code: ParseFailure::ERROR_CODE,
+            detail: failure.error.to_string(),
+            fields: RECORD_COLUMNS.map(|column| failure.written(column)),
+            reference: Default::default(),
+        }
+    }
+}
+
+/// Writes dead letters to a CSV file from a background thread, fed by the engine stage (or its
+/// shards) through [`DeadLetterQueue::sender`].
+pub struct DeadLetterQueue {
+    sender: Sender<DeadLetter>,
+    writer: JoinHandle<csv::Result<usize>>,
+}
+
+impl DeadLetterQueue {
+    pub fn create(path: &Path) -> csv::Result<Self> {
+        let mut writer = Writer::from_path(path)?;
+        let mut header = vec!["counter", "reason", "error_code", "detail"];
+        header.extend(RECORD_COLUMNS);
+        header.extend(REFERENCE_COLUMNS);
+        writer.write_record(&header)?;
+        let (sender, receiver) = mpsc::channel::<DeadLetter>();
+        let writer = thread::spawn(move || {
+            let mut written = 0;
+            for dead_letter in receiver {
+                write_dead_letter(&mut writer, &dead_letter)?;
+                written += 1;
+            }
+            writer.flush()?;
+            Ok(written)
+        });
+        Ok(DeadLetterQueue { sender, writer })
+    }
+
+    pub fn sender(&self) -> &Sender<DeadLetter> {
+        &self.sender
+    }
+
+    /// Wait for every dead letter to be written and return how many were. Clones of the sender
+    /// must be dropped first.
+    pub fn finish(self) -> csv::Result<usize> {
+        drop(self.sender);
+        match self.writer.join() {
+            Ok(written) => written,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+fn write_dead_letter<W: io::Write>(
+    writer: &mut Writer<W>,
+    dead_letter: &DeadLetter,
+) -> csv::Result<()> {
+    let counter = dead_letter.monotonic_counter.to_string();
+    let mut row = vec![
+        counter.as_str(),
+        dead_letter.reason,
+        dead_letter.error_code,
+        dead_letter.detail.as_str(),
+    ];
+    row.extend(dead_letter.fields.iter().map(String::as_str));
+    row.extend(dead_letter.reference.iter().map(String::as_str));
+    writer.write_record(&row)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::money::Money;
+    use crate::{ClientId, DisputeStatus, Record, TransactionType, TxId};
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_dead_letter_file_is_replayable() {
+        let path = env::temp_dir().join(format!("dead-letters-{}.csv", std::process::id()));
+        let queue = DeadLetterQueue::create(&path).unwrap();
+        let situated_record = SituatedRecord {
+            monotonic_counter: 4,
+            record: Record {
+                transaction_type: TransactionType::Withdrawal,
+                client_id: ClientId(2),
+                transaction_id: TxId(5),
+                amount: Some(Money::new(30033, 4)),
+                timestamp: None,
+                reason: None,
+            },
+        };
+        queue
+            .sender()
+            .send(DeadLetter::rejected(
+                &situated_record,
+                Rejection::InsufficientFunds,
+                None,
+                &Pseudonyms::default(),
+                &Messages::default(),
+            ))
+            .unwrap();
+        let dispute = SituatedRecord {
+            monotonic_counter: 6,
+            record: Record {
+                transaction_type: TransactionType::Dispute,
+                transaction_id: TxId(1),
+                amount: None,
+                reason: Some(DisputeReason::Fraud),
+                ..situated_record.record
+            },
+        };
+        let deposit = Disputable {
+            transaction_type: TransactionType::Deposit,
+            amount: Money::new(10, 0),
+            status: DisputeStatus::Resolved,
+            monotonic_counter: 1,
+            reason: None,
+        };
+        queue
+            .sender()
+            .send(DeadLetter::rejected(
+                &dispute,
+                Rejection::AlreadyDisputed,
+                Some(&deposit),
+                &Pseudonyms::default(),
+                &Messages::default(),
+            ))
+            .unwrap();
+        assert_eq!(2, queue.finish().unwrap());
+
+        let written = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            "counter,reason,error_code,detail,type,client,tx,amount,timestamp,reason_code,ref_type,ref_amount,ref_counter\n\
+            4,insufficient_funds,PWM-E001,insufficient available funds,withdrawal,2,5,3.0033,,,,,\n\
+            6,already_disputed,PWM-E005,transaction is already disputed,dispute,2,1,,,fraud,deposit,10,1\n",
+            written
+        );
+        // the extra columns are ignored when the file is read back as input
+        let mut reader = csv::Reader::from_reader(written.as_bytes());
+        let records: Vec<Record> = reader.deserialize().map(Result::unwrap).collect();
+        assert_eq!(TxId(5), records[0].transaction_id);
+        assert_eq!(Some(Money::new(30033, 4)), records[0].amount);
+        assert_eq!(Some(DisputeReason::Fraud), records[1].reason);
+    }
+}
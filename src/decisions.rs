@@ -0,0 +1,298 @@
+use crate::pipeline::PipelineConfig;
+use crate::pseudonym::Pseudonyms;
+use crate::{
+    process_record_with, ClientId, ClientState, Record, Rejection, Sinks, SituatedRecord,
+    TransactionType, TxId,
+};
+use csv::ReaderBuilder;
+use log::warn;
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::str::FromStr;
+
+/// How a dispute waiting in the pending report was decided.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Decision {
+    Resolve,
+    Chargeback,
+}
+
+impl FromStr for Decision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "resolve" => Ok(Decision::Resolve),
+            "chargeback" => Ok(Decision::Chargeback),
+            _ => Err(format!(
+                "Unknown decision ({}), expected resolve or chargeback.",
+                s
+            )),
+        }
+    }
+}
+
+/// A decision on the dispute of a client's transaction.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Decided {
+    pub client_id: ClientId,
+    pub transaction_id: TxId,
+    pub decision: Decision,
+}
+
+impl Decided {
+    /// the resolve or chargeback that carries out the decision.
+    pub fn record(&self) -> Record {
+        Record {
+            transaction_type: match self.decision {
+                Decision::Resolve => TransactionType::Resolve,
+                Decision::Chargeback => TransactionType::Chargeback,
+            },
+            client_id: self.client_id,
+            transaction_id: self.transaction_id,
+            amount: None,
+            timestamp: None,
+            reason: None,
+        }
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The decisions in a pending report (see [`crate::report::ReportKind::Pending`]) once its
+/// decision column is filled in. Rows left blank are still pending and skipped, other columns
+/// are ignored so the rest of the report can stay as it was. A report written with `pseudonyms`
+/// salted has its clients mapped back to the ids of `clients` they stand for.
+pub fn read<R: Read>(
+    reader: R,
+    clients: &HashMap<ClientId, ClientState>,
+    pseudonyms: &Pseudonyms,
+) -> io::Result<Vec<Decided>> {
+    let mut reader = ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|header| header == name)
+            .ok_or_else(|| invalid(format!("Decisions have no {} column.", name)))
+    };
+    let (client_column, tx_column, decision_column) =
+        (column("client")?, column("tx")?, column("decision")?);
+    let unmasked: HashMap<String, ClientId> = match pseudonyms.is_salted() {
+        true => clients
+            .keys()
+            .map(|client_id| (pseudonyms.client(client_id), *client_id))
+            .collect(),
+        false => HashMap::new(),
+    };
+    let mut decided = Vec::new();
+    for row in reader.records() {
+        let row = row?;
+        let field = |index: usize| row.get(index).unwrap_or_default();
+        let decision = match field(decision_column) {
+            "" => continue,
+            decision => decision.parse().map_err(invalid)?,
+        };
+        let malformed = || {
+            let line = row.position().map_or(0, csv::Position::line);
+            invalid(format!("Decision on line ({}) is malformed.", line))
+        };
+        let client = field(client_column);
+        decided.push(Decided {
+            client_id: match unmasked.get(client) {
+                Some(client_id) => *client_id,
+                None => client.parse().map_err(|_| malformed())?,
+            },
+            transaction_id: field(tx_column).parse().map_err(|_| malformed())?,
+            decision,
+        });
+    }
+    Ok(decided)
+}
+
+/// Carry out `decided` in order against `clients`, numbering them on from `next_counter`, and
+/// publish each to `sinks` like any other record. Returns the decisions that were turned down,
+/// e.g. for a dispute that was settled since the report was written.
+pub fn apply(
+    decided: &[Decided],
+    next_counter: usize,
+    clients: &mut HashMap<ClientId, ClientState>,
+    pipeline_config: &PipelineConfig,
+    sinks: &Sinks,
+) -> Vec<(Decided, Rejection)> {
+    let mut rejected = Vec::new();
+    for (monotonic_counter, decided) in (next_counter..).zip(decided) {
+        let situated_record = SituatedRecord {
+            monotonic_counter,
+            record: decided.record(),
+        };
+        let prior = sinks.before(&situated_record, clients);
+        let processed = process_record_with(situated_record, clients, pipeline_config);
+        let client = clients.get(&decided.client_id);
+        let reference = client.and_then(|client| client.referenced(&situated_record.record));
+        if let Err(rejection) = processed {
+            warn!(
+                "Decision to {:?} transaction ({}) of client ({}) was turned down: {}.",
+                decided.decision,
+                decided.transaction_id,
+                pipeline_config.pseudonyms.client(decided.client_id),
+                rejection
+            );
+            rejected.push((*decided, rejection));
+        }
+        sinks.publish(&situated_record, processed, reference, pipeline_config);
+        sinks.changed(&situated_record, prior, clients.get(&decided.client_id));
+    }
+    rejected
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::money::Money;
+    use crate::output::AmountFormat;
+    use crate::process_record;
+    use crate::report::{self, ReportKind};
+
+    #[test]
+    fn test_decisions() {
+        let mut clients = HashMap::new();
+        for (monotonic_counter, (transaction_type, transaction_id)) in [
+            (TransactionType::Deposit, 1),
+            (TransactionType::Deposit, 2),
+            (TransactionType::Deposit, 3),
+            (TransactionType::Dispute, 1),
+            (TransactionType::Dispute, 2),
+            (TransactionType::Dispute, 3),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let record = Record {
+                transaction_type,
+                client_id: ClientId(1),
+                transaction_id: TxId(transaction_id),
+                amount: Some(Money::new(10, 0)),
+                timestamp: None,
+                reason: None,
+            };
+            process_record(
+                SituatedRecord {
+                    monotonic_counter,
+                    record,
+                },
+                &mut clients,
+            )
+            .unwrap();
+        }
+        let mut pending = vec![];
+        report::write(
+            ReportKind::Pending,
+            &mut pending,
+            &clients,
+            AmountFormat::default(),
+            &Pseudonyms::default(),
+        )
+        .unwrap();
+        let pending = String::from_utf8(pending).unwrap();
+        assert_eq!(
+            "client,tx,tx_type,amount,reason_code,decision\n\
+            1,1,deposit,10,,\n\
+            1,2,deposit,10,,\n\
+            1,3,deposit,10,,\n",
+            pending
+        );
+        // decided in the report: tx 2 twice, the second is turned down, tx 3 is left for later
+        let filled = pending
+            .replace("1,1,deposit,10,,\n", "1,1,deposit,10,,resolve\n")
+            .replace("1,2,deposit,10,,\n", "1,2,deposit,10,,chargeback\n")
+            + "1,2,deposit,10,,resolve\n";
+        let decided = read(filled.as_bytes(), &clients, &Pseudonyms::default()).unwrap();
+        assert_eq!(3, decided.len());
+        let rejected = apply(
+            &decided,
+            6,
+            &mut clients,
+            &PipelineConfig::default(),
+            &Sinks::default(),
+        );
+        assert_eq!(vec![(decided[2], Rejection::AccountLocked)], rejected);
+        let client = &clients[&ClientId(1)];
+        assert_eq!(Money::new(10, 0), client.get_available_funds());
+        assert_eq!(Money::new(10, 0), client.get_held_funds());
+        assert!(client.is_locked());
+        assert_eq!(1, client.open_disputes());
+        let unread = |decisions: &str| {
+            read(decisions.as_bytes(), &clients, &Pseudonyms::default())
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(unread("client,tx,decision\n1,1,refund\n").starts_with("Unknown decision"));
+        assert_eq!(
+            "Decision on line (3) is malformed.",
+            unread("client,tx,decision\n1,1,resolve\n1,x,resolve\n")
+        );
+    }
+
+    #[test]
+    fn test_pseudonymized_decisions() {
+        let mut clients = HashMap::new();
+        for (monotonic_counter, transaction_type) in
+            [TransactionType::Deposit, TransactionType::Dispute]
+                .into_iter()
+                .enumerate()
+        {
+            let record = Record {
+                transaction_type,
+                client_id: ClientId(7),
+                transaction_id: TxId(1),
+                amount: Some(Money::new(10, 0)),
+                timestamp: None,
+                reason: None,
+            };
+            process_record(
+                SituatedRecord {
+                    monotonic_counter,
+                    record,
+                },
+                &mut clients,
+            )
+            .unwrap();
+        }
+        let pseudonyms = Pseudonyms::salted("s3cret");
+        let mut pending = vec![];
+        report::write(
+            ReportKind::Pending,
+            &mut pending,
+            &clients,
+            AmountFormat::default(),
+            &pseudonyms,
+        )
+        .unwrap();
+        let pending = String::from_utf8(pending).unwrap();
+        assert!(!pending.contains("\n7,"));
+        let filled = pending.replace(",,\n", ",,resolve\n");
+        let decided = read(filled.as_bytes(), &clients, &pseudonyms).unwrap();
+        assert_eq!(
+            vec![Decided {
+                client_id: ClientId(7),
+                transaction_id: TxId(1),
+                decision: Decision::Resolve,
+            }],
+            decided
+        );
+        let rejected = apply(
+            &decided,
+            2,
+            &mut clients,
+            &PipelineConfig::default(),
+            &Sinks::default(),
+        );
+        assert!(rejected.is_empty());
+        assert_eq!(0, clients[&ClientId(7)].open_disputes());
+    }
+}
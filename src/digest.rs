@@ -0,0 +1,157 @@
+use hmac::{Hmac, KeyInit, Mac};
+use sha2::digest::common::hazmat::SerializableState;
+use sha2::Digest;
+use std::fmt::Write;
+
+/// Streaming SHA-256 whose unfinished state can be saved across a restart, see [`Sha256::save`].
+#[derive(Debug, Clone, Default)]
+pub struct Sha256(sha2::Sha256);
+
+impl Sha256 {
+    pub fn new() -> Self {
+        Sha256::default()
+    }
+
+    pub fn digest(data: &[u8]) -> [u8; 32] {
+        sha2::Sha256::digest(data).into()
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    pub fn finish(self) -> [u8; 32] {
+        self.0.finalize().into()
+    }
+
+    /// The unfinished hash as text, so it can be carried across a restart with [`Sha256::load`]:
+    /// the bytes hashed so far, the state words and the bytes of the unfinished block.
+    pub fn save(&self) -> String {
+        let serialized = self.0.serialize();
+        let state: Vec<u8> = serialized[..32]
+            .chunks(4)
+            .flat_map(|word| [word[3], word[2], word[1], word[0]])
+            .collect();
+        let blocks = u64::from_le_bytes(serialized[32..40].try_into().unwrap_or_default());
+        let block_len = serialized[40] as usize;
+        format!(
+            "{}:{}:{}",
+            blocks * 64 + block_len as u64,
+            hex(&state),
+            hex(&serialized[41..41 + block_len])
+        )
+    }
+
+    pub fn load(saved: &str) -> Option<Self> {
+        let mut parts = saved.split(':');
+        let len: u64 = parts.next()?.parse().ok()?;
+        let state = unhex(parts.next()?)?;
+        let block = unhex(parts.next()?)?;
+        if parts.next().is_some() || state.len() != 32 || block.len() as u64 != len % 64 {
+            return None;
+        }
+        // the state words and block count, then the length and bytes of the unfinished block
+        let mut serialized = sha2::Sha256::default().serialize();
+        for (word, bytes) in serialized[..32].chunks_mut(4).zip(state.chunks(4)) {
+            word.copy_from_slice(&[bytes[3], bytes[2], bytes[1], bytes[0]]);
+        }
+        serialized[32..40].copy_from_slice(&(len / 64).to_le_bytes());
+        serialized[40] = block.len() as u8;
+        serialized[41..41 + block.len()].copy_from_slice(&block);
+        sha2::Sha256::deserialize(&serialized).ok().map(Sha256)
+    }
+}
+
+/// HMAC-SHA256 (RFC 2104) of the concatenated `parts`.
+pub fn hmac(key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let mut mac = <Hmac<sha2::Sha256> as KeyInit>::new_from_slice(key)
+        .expect("HMAC takes keys of any length");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+pub fn hex(digest: &[u8]) -> String {
+    let mut hex = String::with_capacity(digest.len() * 2);
+    for byte in digest {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    hex
+}
+
+fn unhex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sha256() {
+        assert_eq!(
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            hex(&Sha256::digest(b""))
+        );
+        assert_eq!(
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            hex(&Sha256::digest(b"abc"))
+        );
+        let two_blocks = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+        assert_eq!(
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+            hex(&Sha256::digest(two_blocks))
+        );
+        // fed in pieces that straddle block boundaries
+        let mut sha = Sha256::new();
+        for _ in 0..1000 {
+            sha.update(&[b'a'; 1000]);
+        }
+        assert_eq!(
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0",
+            hex(&sha.finish())
+        );
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let mut sha = Sha256::new();
+        sha.update(&[b'a'; 100]);
+        // as snapshots written before sha2 was used have it
+        assert_eq!(
+            format!(
+                "100:df5bb81ce81e0626fb45a8944fd40f31b25e6816d6d499c1ab90492900635e66:{}",
+                hex(&[b'a'; 36])
+            ),
+            sha.save()
+        );
+        let mut loaded = Sha256::load(&sha.save()).unwrap();
+        sha.update(b"more");
+        loaded.update(b"more");
+        assert_eq!(sha.finish(), loaded.finish());
+        assert!(Sha256::load("3:00:").is_none());
+    }
+
+    #[test]
+    fn test_hmac() {
+        // RFC 4231 test cases 2 and 6
+        assert_eq!(
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            hex(&hmac(b"Jefe", &[b"what do ya want ", b"for nothing?"]))
+        );
+        assert_eq!(
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+            hex(&hmac(
+                &[0xaa; 131],
+                &[b"Test Using Larger Than Block-Size Key - Hash Key First"]
+            ))
+        );
+    }
+}
@@ -0,0 +1,126 @@
+use crate::checkpoint;
+use crate::pipeline::PipelineConfig;
+use crate::schema::ColumnMap;
+use crate::shadow::{self, Divergence};
+use crate::{play_with_money, Sinks};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io;
+use std::path::Path;
+
+/// What applying `input` on top of the state checkpointed in `dir` would change, worked out on a
+/// copy of that state so nothing in `dir` is written. Returns how many clients there would be
+/// and those whose balances or lock would differ, with the state in `dir` as the primary and the
+/// state after `input` as the shadow of each [`Divergence`].
+pub fn run(
+    input: &OsStr,
+    dir: &Path,
+    pipeline_config: &PipelineConfig,
+    columns: &ColumnMap,
+) -> io::Result<(usize, Vec<Divergence>)> {
+    let mut clients = HashMap::new();
+    checkpoint::load(dir, pipeline_config, &mut clients)?;
+    let before = clients.clone();
+    play_with_money(
+        Some(input),
+        pipeline_config,
+        columns,
+        &Sinks::default(),
+        None,
+        &mut clients,
+    )?;
+    Ok((clients.len(), shadow::compare(&before, &clients)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::checkpoint::{Checkpoints, Commit};
+    use crate::output::AmountFormat;
+    use crate::pseudonym::Pseudonyms;
+    use crate::ClientState;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_dry_run() {
+        let dir = env::temp_dir().join(format!("dry-run-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("input.csv");
+        fs::write(
+            &input,
+            "type,client,tx,amount\n\
+            deposit,1,1,10\n\
+            deposit,2,2,20\n",
+        )
+        .unwrap();
+        let checkpoints_dir = dir.join("checkpoints");
+        let mut checkpoints = Checkpoints::start(
+            &checkpoints_dir,
+            usize::MAX,
+            Commit::default(),
+            AmountFormat::default(),
+            Pseudonyms::default(),
+        )
+        .unwrap();
+        let mut clients = HashMap::new();
+        let sinks = Sinks {
+            history: Some(checkpoints.sender().clone()),
+            ..Sinks::default()
+        };
+        let config = PipelineConfig::default();
+        play_with_money(
+            Some(input.as_os_str()),
+            &config,
+            &ColumnMap::default(),
+            &sinks,
+            None,
+            &mut clients,
+        )
+        .unwrap();
+        drop(sinks);
+        let saved: Vec<_> = clients.values().map(ClientState::save).collect();
+        checkpoints.save(2, &saved).unwrap();
+        checkpoints.finish().unwrap();
+        let persisted = fs::read_to_string(checkpoints_dir.join("snapshot.csv")).unwrap();
+
+        let make_up = dir.join("make-up.csv");
+        fs::write(
+            &make_up,
+            "type,client,tx,amount\n\
+            deposit,3,3,5\n\
+            dispute,1,1,\n\
+            withdrawal,2,4,50\n",
+        )
+        .unwrap();
+        let (clients, divergences) = run(
+            make_up.as_os_str(),
+            &checkpoints_dir,
+            &config,
+            &ColumnMap::default(),
+        )
+        .unwrap();
+        assert_eq!(3, clients);
+        let mut report = vec![];
+        shadow::write_report(
+            &mut report,
+            &divergences,
+            "new_",
+            AmountFormat::default(),
+            &Pseudonyms::default(),
+        )
+        .unwrap();
+        // client 2's withdrawal is declined, so it wouldn't change
+        assert_eq!(
+            "client,available,held,total,locked,new_available,new_held,new_total,new_locked\n\
+            1,10,0,10,false,0,10,10,false\n\
+            3,,,,,5,0,5,false\n",
+            String::from_utf8(report).unwrap()
+        );
+        assert_eq!(
+            persisted,
+            fs::read_to_string(checkpoints_dir.join("snapshot.csv")).unwrap()
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
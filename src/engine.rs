@@ -0,0 +1,441 @@
+use crate::events::Event;
+use crate::id_map;
+use crate::pipeline::PipelineConfig;
+use crate::{
+    check_bounds, process_record_with, ClientId, ClientState, DisputeStatus, Record, Rejection,
+    Sinks, SituatedRecord,
+};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+
+/// What applying a record did to its client, so whoever submitted it can be answered right away.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Applied {
+    pub monotonic_counter: usize,
+    pub client_id: ClientId,
+    /// change in available funds
+    pub available: Decimal,
+    /// change in held funds
+    pub held: Decimal,
+    /// whether the client is locked now
+    pub locked: bool,
+    /// of the deposit or withdrawal the record refers to, now that it's applied
+    pub dispute_status: Option<DisputeStatus>,
+    pub events: Vec<Event>,
+}
+
+/// Custom side effects and checks for the records an [`Engine`] applies, see
+/// [`Engine::register`]. Every method does nothing by default, so a hook only implements what it
+/// needs.
+pub trait TransactionHook: Send {
+    /// whether `situated_record` may be applied to `client`, None if the client isn't known yet.
+    /// Asked after the engine's own bounds check and before anything changes, an error turns the
+    /// record down with it like any other rejection, usually [`Rejection::Vetoed`].
+    fn check(
+        &mut self,
+        _situated_record: &SituatedRecord,
+        _client: Option<&ClientState>,
+    ) -> Result<(), Rejection> {
+        Ok(())
+    }
+
+    /// `situated_record` was applied and left its client at `client`, raising `events`.
+    fn on_accepted(
+        &mut self,
+        _situated_record: &SituatedRecord,
+        _client: &ClientState,
+        _events: &[Event],
+    ) {
+    }
+
+    /// `situated_record` was turned down and changed nothing.
+    fn on_rejected(&mut self, _situated_record: &SituatedRecord, _rejection: Rejection) {}
+
+    /// `situated_record` locked `client`, called after [`TransactionHook::on_accepted`].
+    fn on_lock(&mut self, _situated_record: &SituatedRecord, _client: &ClientState) {}
+}
+
+/// The engine for embedding in another service: records are applied one at a time as they're
+/// submitted instead of streamed from a file. Only `max_amount`, `retain_history` and
+/// `unlock_on_representment` of the config apply. Sinks get every record like they do in a streamed run.
+///
+/// ```
+/// use playing_with_money::engine::Engine;
+/// use playing_with_money::{ClientId, DisputeStatus, Record, TransactionType, TxId};
+/// use rust_decimal_macros::dec;
+///
+/// let record = |transaction_type, amount| Record {
+///     transaction_type,
+///     client_id: ClientId(1),
+///     transaction_id: TxId(1),
+///     amount,
+///     timestamp: None,
+///     reason: None,
+/// };
+/// let mut engine = Engine::default();
+/// engine.apply(record(TransactionType::Deposit, Some(dec!(10).into()))).unwrap();
+///
+/// // a dispute moves the deposit from available to held
+/// let disputed = engine.apply(record(TransactionType::Dispute, None)).unwrap();
+/// assert_eq!((dec!(-10), dec!(10)), (disputed.available, disputed.held));
+/// assert_eq!(Some(DisputeStatus::Disputed), disputed.dispute_status);
+///
+/// // and resolving it moves it back
+/// let resolved = engine.apply(record(TransactionType::Resolve, None)).unwrap();
+/// assert_eq!((dec!(10), dec!(-10)), (resolved.available, resolved.held));
+/// assert_eq!(Some(DisputeStatus::Resolved), resolved.dispute_status);
+///
+/// let client = engine.client(ClientId(1)).unwrap();
+/// assert_eq!(dec!(10), client.get_available_funds().amount());
+/// assert!(!client.is_locked());
+/// ```
+pub struct Engine {
+    config: PipelineConfig,
+    sinks: Sinks,
+    clients: HashMap<ClientId, ClientState>,
+    next_counter: usize,
+    hooks: Vec<Box<dyn TransactionHook>>,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new(PipelineConfig::default(), Sinks::default())
+    }
+}
+
+impl Engine {
+    pub fn new(config: PipelineConfig, sinks: Sinks) -> Self {
+        Engine {
+            config,
+            sinks,
+            clients: HashMap::new(),
+            next_counter: 0,
+            hooks: Vec::new(),
+        }
+    }
+
+    /// Have `hook` check and hear about every record applied from now on, after the hooks
+    /// registered before it.
+    pub fn register(&mut self, hook: impl TransactionHook + 'static) {
+        self.hooks.push(Box::new(hook));
+    }
+
+    /// Apply `record` to its client and return its effect, or why it was turned down in which
+    /// case nothing changed.
+    pub fn apply(&mut self, record: Record) -> Result<Applied, Rejection> {
+        let situated_record = SituatedRecord {
+            monotonic_counter: self.next_counter,
+            record,
+        };
+        self.next_counter += 1;
+        let canonical = id_map::canonical(situated_record, self.config.id_map.as_deref());
+        let situated_record = canonical.unwrap_or(situated_record);
+        let record = situated_record.record;
+        let client_id = record.client_id;
+        let (available, held, was_locked) = self
+            .clients
+            .get(&client_id)
+            .map(|client| {
+                (
+                    client.get_available_funds(),
+                    client.get_held_funds(),
+                    client.is_locked(),
+                )
+            })
+            .unwrap_or_default();
+        let prior = self.sinks.before(&situated_record, &self.clients);
+        let processed = canonical
+            .and_then(|_| check_bounds(&situated_record, &self.config))
+            .and_then(|_| {
+                let client = self.clients.get(&client_id);
+                self.hooks
+                    .iter_mut()
+                    .try_for_each(|hook| hook.check(&situated_record, client))
+            })
+            .and_then(|_| process_record_with(situated_record, &mut self.clients, &self.config));
+        let reference = self
+            .clients
+            .get(&client_id)
+            .and_then(|client| client.referenced(&record));
+        self.sinks
+            .publish(&situated_record, processed.clone(), reference, &self.config);
+        self.sinks
+            .changed(&situated_record, prior, self.clients.get(&client_id));
+        let events = match processed {
+            Ok(events) => events,
+            Err(rejection) => {
+                for hook in &mut self.hooks {
+                    hook.on_rejected(&situated_record, rejection);
+                }
+                return Err(rejection);
+            }
+        };
+        let client = &self.clients[&client_id];
+        for hook in &mut self.hooks {
+            hook.on_accepted(&situated_record, client, &events);
+            if client.is_locked() && !was_locked {
+                hook.on_lock(&situated_record, client);
+            }
+        }
+        Ok(Applied {
+            monotonic_counter: situated_record.monotonic_counter,
+            client_id,
+            available: client.get_available_funds().amount() - available.amount(),
+            held: client.get_held_funds().amount() - held.amount(),
+            locked: client.is_locked(),
+            dispute_status: client
+                .disputable(record.transaction_id)
+                .map(|disputable| disputable.status),
+            events,
+        })
+    }
+
+    pub fn client(&self, client_id: ClientId) -> Option<&ClientState> {
+        self.clients.get(&client_id)
+    }
+
+    /// `client_id` as it was just before the record at `counter` was applied, e.g. to see what
+    /// was available before a chargeback. None for an unknown client or without retained history.
+    pub fn balance_at(&self, client_id: ClientId, counter: usize) -> Option<ClientState> {
+        self.client(client_id)?.as_of(counter)
+    }
+
+    pub fn clients(&self) -> &HashMap<ClientId, ClientState> {
+        &self.clients
+    }
+
+    pub fn into_clients(self) -> HashMap<ClientId, ClientState> {
+        self.clients
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::events::EventKind;
+    use crate::money::Money;
+    use crate::{TransactionType, TxId};
+    use std::sync::{Arc, Mutex};
+
+    fn record(transaction_type: TransactionType, transaction_id: TxId, amount: i64) -> Record {
+        Record {
+            transaction_type,
+            client_id: ClientId(1),
+            transaction_id,
+            amount: Some(Money::new(amount, 0)),
+            timestamp: None,
+            reason: None,
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut engine = Engine::new(
+            PipelineConfig {
+                max_amount: Some(Decimal::new(1000, 0)),
+                ..PipelineConfig::default()
+            },
+            Sinks::default(),
+        );
+        let deposit = engine
+            .apply(record(TransactionType::Deposit, TxId(1), 100))
+            .unwrap();
+        assert_eq!(Decimal::new(100, 0), deposit.available);
+        assert_eq!(Some(DisputeStatus::Undisputed), deposit.dispute_status);
+        assert_eq!(
+            Err(Rejection::InsufficientFunds),
+            engine.apply(record(TransactionType::Withdrawal, TxId(2), 150))
+        );
+        assert_eq!(
+            Err(Rejection::AmountOutOfBounds),
+            engine.apply(record(TransactionType::Deposit, TxId(3), 5000))
+        );
+        let dispute = engine
+            .apply(record(TransactionType::Dispute, TxId(1), 0))
+            .unwrap();
+        assert_eq!(3, dispute.monotonic_counter);
+        assert_eq!(Decimal::new(-100, 0), dispute.available);
+        assert_eq!(Decimal::new(100, 0), dispute.held);
+        assert_eq!(Some(DisputeStatus::Disputed), dispute.dispute_status);
+        assert_eq!(EventKind::DisputeOpened, dispute.events[0].kind);
+        let chargeback = engine
+            .apply(record(TransactionType::Chargeback, TxId(1), 0))
+            .unwrap();
+        assert_eq!(Decimal::new(-100, 0), chargeback.held);
+        assert!(chargeback.locked);
+        assert_eq!(Some(DisputeStatus::ChargedBack), chargeback.dispute_status);
+        assert_eq!(
+            Money::ZERO,
+            engine.client(ClientId(1)).unwrap().get_total_funds()
+        );
+    }
+
+    /// turns down withdrawals over its limit and notes what it hears
+    struct Recorder {
+        max_withdrawal: Decimal,
+        heard: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl TransactionHook for Recorder {
+        fn check(
+            &mut self,
+            situated_record: &SituatedRecord,
+            _client: Option<&ClientState>,
+        ) -> Result<(), Rejection> {
+            let record = situated_record.record;
+            match record.transaction_type {
+                TransactionType::Withdrawal
+                    if record
+                        .amount
+                        .is_some_and(|amount| amount.amount() > self.max_withdrawal) =>
+                {
+                    Err(Rejection::Vetoed)
+                }
+                _ => Ok(()),
+            }
+        }
+
+        fn on_accepted(&mut self, situated_record: &SituatedRecord, _: &ClientState, _: &[Event]) {
+            let counter = situated_record.monotonic_counter;
+            self.heard
+                .lock()
+                .unwrap()
+                .push(format!("accepted {}", counter));
+        }
+
+        fn on_rejected(&mut self, situated_record: &SituatedRecord, rejection: Rejection) {
+            let counter = situated_record.monotonic_counter;
+            let heard = format!("rejected {} {}", counter, rejection.code());
+            self.heard.lock().unwrap().push(heard);
+        }
+
+        fn on_lock(&mut self, situated_record: &SituatedRecord, _: &ClientState) {
+            let counter = situated_record.monotonic_counter;
+            self.heard
+                .lock()
+                .unwrap()
+                .push(format!("locked {}", counter));
+        }
+    }
+
+    #[test]
+    fn test_hooks() {
+        let heard = Arc::new(Mutex::new(Vec::new()));
+        let mut engine = Engine::default();
+        engine.register(Recorder {
+            max_withdrawal: Decimal::new(50, 0),
+            heard: heard.clone(),
+        });
+        for (transaction_type, transaction_id, amount) in [
+            (TransactionType::Deposit, 1, 100),
+            (TransactionType::Withdrawal, 2, 80),
+            (TransactionType::Withdrawal, 3, 20),
+            (TransactionType::Dispute, 1, 0),
+            (TransactionType::Chargeback, 1, 0),
+            (TransactionType::Withdrawal, 4, 10),
+        ] {
+            let _ = engine.apply(record(transaction_type, TxId(transaction_id), amount));
+        }
+        assert_eq!(
+            vec![
+                "accepted 0",
+                "rejected 1 vetoed",
+                "accepted 2",
+                "accepted 3",
+                "accepted 4",
+                "locked 4",
+                "rejected 5 account_locked",
+            ],
+            *heard.lock().unwrap()
+        );
+        // a vetoed record changes nothing
+        assert_eq!(
+            Money::new(-20, 0),
+            engine.client(ClientId(1)).unwrap().get_total_funds()
+        );
+    }
+
+    #[test]
+    fn test_representment() {
+        let mut engine = Engine::new(
+            PipelineConfig {
+                unlock_on_representment: true,
+                ..PipelineConfig::default()
+            },
+            Sinks::default(),
+        );
+        for (transaction_type, transaction_id, amount) in [
+            (TransactionType::Deposit, 1, 100),
+            (TransactionType::Deposit, 2, 20),
+            (TransactionType::Dispute, 1, 0),
+            (TransactionType::Chargeback, 1, 0),
+        ] {
+            let _ = engine.apply(record(transaction_type, TxId(transaction_id), amount));
+        }
+        assert!(engine.client(ClientId(1)).unwrap().is_locked());
+        assert_eq!(
+            Err(Rejection::NotChargedBack),
+            engine.apply(record(TransactionType::RepresentmentWon, TxId(1), 0))
+        );
+        let represented = engine
+            .apply(record(TransactionType::Representment, TxId(1), 0))
+            .unwrap();
+        assert_eq!(Decimal::ZERO, represented.available);
+        assert_eq!(Some(DisputeStatus::Represented), represented.dispute_status);
+        let won = engine
+            .apply(record(TransactionType::RepresentmentWon, TxId(1), 0))
+            .unwrap();
+        assert_eq!(Decimal::new(100, 0), won.available);
+        assert_eq!(Some(DisputeStatus::Reversed), won.dispute_status);
+        assert!(!won.locked);
+        let client = engine.client(ClientId(1)).unwrap();
+        assert_eq!(Money::new(120, 0), client.get_total_funds());
+        assert_eq!(Money::ZERO, client.get_charged_back());
+        assert!(client.charged_back_transactions().is_empty());
+        let rebuilt = engine.balance_at(ClientId(1), usize::MAX).unwrap();
+        assert!(!rebuilt.is_locked());
+        assert_eq!(Money::new(120, 0), rebuilt.get_available_funds());
+    }
+
+    #[test]
+    fn test_balance_at() {
+        let mut engine = Engine::default();
+        engine
+            .apply(record(TransactionType::Deposit, TxId(1), 100))
+            .unwrap();
+        let _ = engine.apply(record(TransactionType::Withdrawal, TxId(2), 150));
+        engine
+            .apply(record(TransactionType::Withdrawal, TxId(3), 30))
+            .unwrap();
+        engine
+            .apply(record(TransactionType::Dispute, TxId(1), 0))
+            .unwrap();
+        let chargeback = engine
+            .apply(record(TransactionType::Chargeback, TxId(1), 0))
+            .unwrap();
+        let before = engine
+            .balance_at(ClientId(1), chargeback.monotonic_counter)
+            .unwrap();
+        assert_eq!(Money::new(-30, 0), before.get_available_funds());
+        assert_eq!(Money::new(100, 0), before.get_held_funds());
+        assert!(!before.is_locked());
+        let start = engine.balance_at(ClientId(1), 0).unwrap();
+        assert_eq!(Money::ZERO, start.get_total_funds());
+        let now = engine.balance_at(ClientId(1), usize::MAX).unwrap();
+        assert_eq!(Money::new(-30, 0), now.get_total_funds());
+        assert!(now.is_locked());
+        assert!(engine.balance_at(ClientId(2), 0).is_none());
+
+        let mut forgetful = Engine::new(
+            PipelineConfig {
+                retain_history: false,
+                ..PipelineConfig::default()
+            },
+            Sinks::default(),
+        );
+        forgetful
+            .apply(record(TransactionType::Deposit, TxId(1), 100))
+            .unwrap();
+        assert!(forgetful.balance_at(ClientId(1), 1).is_none());
+    }
+}
@@ -0,0 +1,248 @@
+use crate::{Record, TxId};
+use csv::{ReaderBuilder, StringRecord, Trim};
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+/// A field the side tables of --enrich add to a record.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Field {
+    Merchant,
+    Channel,
+    Country,
+}
+
+impl Field {
+    pub const ALL: [Field; 3] = [Field::Merchant, Field::Channel, Field::Country];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Field::Merchant => "merchant",
+            Field::Channel => "channel",
+            Field::Country => "country",
+        }
+    }
+}
+
+impl std::str::FromStr for Field {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Field::ALL
+            .into_iter()
+            .find(|field| field.as_str() == s)
+            .ok_or_else(|| {
+                format!(
+                    "Unknown field ({}), expected merchant, channel or country",
+                    s
+                )
+            })
+    }
+}
+
+/// What the side tables know about a record's transaction, blank where they don't.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Enrichment {
+    pub merchant: Option<String>,
+    pub channel: Option<String>,
+    pub country: Option<String>,
+}
+
+impl Enrichment {
+    pub fn get(&self, field: Field) -> Option<&str> {
+        match field {
+            Field::Merchant => self.merchant.as_deref(),
+            Field::Channel => self.channel.as_deref(),
+            Field::Country => self.country.as_deref(),
+        }
+    }
+
+    /// merchant, channel and country as the journal writes them.
+    pub fn fields(enrichment: Option<&Enrichment>) -> [String; 3] {
+        Field::ALL.map(|field| {
+            enrichment
+                .and_then(|enrichment| enrichment.get(field))
+                .unwrap_or_default()
+                .to_string()
+        })
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The side tables of --enrich: an enrichment for a transaction id, or for every id starting
+/// with some digits, where a table is kept per range of ids handed out to a merchant or
+/// channel. An id's own row comes first, then the longest prefix it starts with.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Lookup {
+    ids: HashMap<TxId, Enrichment>,
+    /// longest first
+    prefixes: Vec<(String, Enrichment)>,
+}
+
+impl Lookup {
+    /// a lookup from a CSV with a `tx` column, an id or digits followed by `*` for a prefix, and
+    /// any of the merchant, channel and country columns. An id or prefix listed twice is an
+    /// error, as it leaves unclear which row applies.
+    pub fn read<R: io::Read>(reader: R) -> io::Result<Self> {
+        let mut reader = ReaderBuilder::new().trim(Trim::All).from_reader(reader);
+        let headers = reader.headers()?.clone();
+        let mut columns = vec![];
+        let mut tx = None;
+        for (i, header) in headers.iter().enumerate() {
+            match header {
+                "tx" => tx = Some(i),
+                header => columns.push((i, header.parse::<Field>().map_err(invalid)?)),
+            }
+        }
+        let tx = tx.ok_or_else(|| invalid("The enrichment has no tx column.".to_string()))?;
+        let mut lookup = Lookup::default();
+        for row in reader.records() {
+            let row: StringRecord = row?;
+            let key = &row[tx];
+            let mut enrichment = Enrichment::default();
+            for (i, field) in &columns {
+                let value = Some(row[*i].to_string()).filter(|value| !value.is_empty());
+                match field {
+                    Field::Merchant => enrichment.merchant = value,
+                    Field::Channel => enrichment.channel = value,
+                    Field::Country => enrichment.country = value,
+                }
+            }
+            let twice = || invalid(format!("Transaction ({}) is enriched twice.", key));
+            match key.strip_suffix('*') {
+                Some(prefix) if prefix.chars().all(|c| c.is_ascii_digit()) => {
+                    if lookup.prefixes.iter().any(|(p, _)| p == prefix) {
+                        return Err(twice());
+                    }
+                    lookup.prefixes.push((prefix.to_string(), enrichment));
+                }
+                _ => {
+                    let tx_id: TxId = key.parse().map_err(|_| {
+                        invalid(format!(
+                            "Malformed transaction ({}), expected an id or digits and *.",
+                            key
+                        ))
+                    })?;
+                    if lookup.ids.insert(tx_id, enrichment).is_some() {
+                        return Err(twice());
+                    }
+                }
+            }
+        }
+        lookup
+            .prefixes
+            .sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+        Ok(lookup)
+    }
+
+    pub fn read_file(path: &Path) -> io::Result<Self> {
+        Self::read(std::fs::File::open(path)?)
+    }
+
+    pub fn of(&self, tx_id: TxId) -> Option<&Enrichment> {
+        self.ids.get(&tx_id).or_else(|| {
+            let id = tx_id.to_string();
+            self.prefixes
+                .iter()
+                .find(|(prefix, _)| id.starts_with(prefix.as_str()))
+                .map(|(_, enrichment)| enrichment)
+        })
+    }
+}
+
+/// A risk rule on an enriched field, see --deny: records whose field has the value are turned
+/// down.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub field: Field,
+    pub value: String,
+}
+
+impl std::str::FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (field, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Unknown rule ({}), expected FIELD=VALUE", s))?;
+        Ok(Rule {
+            field: field.trim().parse()?,
+            value: value.trim().to_string(),
+        })
+    }
+}
+
+/// The side tables of --enrich and the --deny rules on what they add.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Enricher {
+    pub lookup: Lookup,
+    pub rules: Vec<Rule>,
+}
+
+impl Enricher {
+    /// What the side tables know about `record`, for hooks and the journal; None if they have no
+    /// row for it.
+    pub fn of(&self, record: &Record) -> Option<&Enrichment> {
+        self.lookup.of(record.transaction_id)
+    }
+
+    /// The first rule `record`'s enrichment matches, if any.
+    pub fn denied(&self, record: &Record) -> Option<&Rule> {
+        let enrichment = self.of(record)?;
+        self.rules
+            .iter()
+            .find(|rule| enrichment.get(rule.field) == Some(rule.value.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::money::Money;
+    use crate::{situated, TransactionType};
+
+    #[test]
+    fn test_lookup() {
+        let lookup = Lookup::read(
+            "tx,merchant,country\n7,corner shop,IE\n12*,acme,US\n123*,acme labs,\n".as_bytes(),
+        )
+        .unwrap();
+        let merchant = |tx_id| lookup.of(TxId(tx_id)).and_then(|e| e.merchant.clone());
+        assert_eq!(Some("corner shop".to_string()), merchant(7));
+        assert_eq!(Some("acme".to_string()), merchant(129));
+        assert_eq!(Some("acme labs".to_string()), merchant(1234));
+        assert_eq!(None, merchant(71));
+        assert_eq!(None, lookup.of(TxId(1234)).unwrap().country);
+        assert_eq!(
+            ["acme".to_string(), String::new(), "US".to_string()],
+            Enrichment::fields(lookup.of(TxId(12)))
+        );
+
+        assert!(Lookup::read("tx,mcc\n1,5411\n".as_bytes()).is_err());
+        assert!(Lookup::read("merchant\nacme\n".as_bytes()).is_err());
+        assert!(Lookup::read("tx,merchant\n1,a\n1,b\n".as_bytes()).is_err());
+        assert!(Lookup::read("tx,merchant\nab*,a\n".as_bytes()).is_err());
+
+        assert_eq!(
+            Ok(Rule {
+                field: Field::Country,
+                value: "KP".to_string()
+            }),
+            "country=KP".parse()
+        );
+        assert!("country".parse::<Rule>().is_err());
+        assert!("mcc=5411".parse::<Rule>().is_err());
+
+        let enricher = Enricher {
+            lookup,
+            rules: vec!["country=US".parse().unwrap()],
+        };
+        let mut record = situated(12, TransactionType::Deposit, Money::new(1, 0)).record;
+        assert_eq!(Some(&enricher.rules[0]), enricher.denied(&record));
+        record.transaction_id = TxId(7);
+        assert_eq!(None, enricher.denied(&record));
+    }
+}
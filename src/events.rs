@@ -0,0 +1,155 @@
+use crate::pseudonym::Pseudonyms;
+use crate::{ClientId, ClientState, SituatedRecord, TransactionType, TxId};
+use rust_decimal::Decimal;
+use std::fmt::Write;
+use std::str::FromStr;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EventKind {
+    DisputeOpened,
+    Chargeback,
+    AccountLocked,
+}
+
+impl EventKind {
+    pub const ALL: [EventKind; 3] = [
+        EventKind::DisputeOpened,
+        EventKind::Chargeback,
+        EventKind::AccountLocked,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::DisputeOpened => "dispute_opened",
+            EventKind::Chargeback => "chargeback",
+            EventKind::AccountLocked => "account_locked",
+        }
+    }
+}
+
+impl FromStr for EventKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        EventKind::ALL
+            .iter()
+            .find(|kind| kind.name() == s)
+            .copied()
+            .ok_or_else(|| format!("Unknown event ({}), expected one of {:?}.", s, names()))
+    }
+}
+
+fn names() -> Vec<&'static str> {
+    EventKind::ALL.iter().map(EventKind::name).collect()
+}
+
+/// A state change downstream systems care about, raised once the causing record is applied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub kind: EventKind,
+    pub client_id: ClientId,
+    pub transaction_id: TxId,
+    /// amount of the disputed/charged back transaction
+    pub amount: Decimal,
+    pub monotonic_counter: usize,
+}
+
+impl Event {
+    /// the event as JSON, its client written as `pseudonyms` says.
+    pub fn to_json(&self, pseudonyms: &Pseudonyms) -> String {
+        let mut json = String::new();
+        let client = match pseudonyms.is_salted() {
+            true => format!(r#""{}""#, pseudonyms.client(self.client_id)),
+            false => self.client_id.to_string(),
+        };
+        // amounts are strings so consumers don't lose precision through floats
+        let _ = write!(
+            json,
+            r#"{{"event":"{}","client":{},"tx":{},"amount":"{}","counter":{}}}"#,
+            self.kind.name(),
+            client,
+            self.transaction_id,
+            self.amount,
+            self.monotonic_counter
+        );
+        json
+    }
+}
+
+/// Events raised by `applied`, given whether the client was locked before it was applied.
+pub fn events_for(applied: &SituatedRecord, client: &ClientState, was_locked: bool) -> Vec<Event> {
+    let record = applied.record;
+    let event = |kind| Event {
+        kind,
+        client_id: record.client_id,
+        transaction_id: record.transaction_id,
+        amount: client
+            .original_amount(record.transaction_id)
+            .or(record.amount)
+            .unwrap_or_default()
+            .amount(),
+        monotonic_counter: applied.monotonic_counter,
+    };
+    let mut events = vec![];
+    match record.transaction_type {
+        TransactionType::Dispute => events.push(event(EventKind::DisputeOpened)),
+        TransactionType::Chargeback => events.push(event(EventKind::Chargeback)),
+        _ => {}
+    }
+    if client.is_locked() && !was_locked {
+        events.push(event(EventKind::AccountLocked));
+    }
+    events
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::money::Money;
+    use crate::{process_record, situated, Rejection};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_dispute_and_chargeback_events() {
+        let mut clients = HashMap::new();
+        let amount = Money::new(1250, 2);
+        let deposit = situated(0, TransactionType::Deposit, amount).of(ClientId(7), TxId(1));
+        assert_eq!(Ok(vec![]), process_record(deposit, &mut clients));
+        let dispute = situated(1, TransactionType::Dispute, Money::ZERO).of(ClientId(7), TxId(1));
+        let events = process_record(dispute, &mut clients).unwrap();
+        assert_eq!(1, events.len());
+        assert_eq!(EventKind::DisputeOpened, events[0].kind);
+        assert_eq!(amount.amount(), events[0].amount);
+        let chargeback =
+            situated(2, TransactionType::Chargeback, Money::ZERO).of(ClientId(7), TxId(1));
+        let kinds: Vec<EventKind> = process_record(chargeback, &mut clients)
+            .unwrap()
+            .iter()
+            .map(|event| event.kind)
+            .collect();
+        assert_eq!(vec![EventKind::Chargeback, EventKind::AccountLocked], kinds);
+        // rejected records raise nothing
+        let repeat = situated(3, TransactionType::Chargeback, Money::ZERO).of(ClientId(7), TxId(1));
+        assert_eq!(
+            Err(Rejection::AccountLocked),
+            process_record(repeat, &mut clients)
+        );
+    }
+
+    #[test]
+    fn test_event_json() {
+        let event = Event {
+            kind: EventKind::AccountLocked,
+            client_id: ClientId(3),
+            transaction_id: TxId(1),
+            amount: Decimal::new(10000, 2),
+            monotonic_counter: 13,
+        };
+        assert_eq!(
+            r#"{"event":"account_locked","client":3,"tx":1,"amount":"100.00","counter":13}"#,
+            event.to_json(&Pseudonyms::default())
+        );
+        assert_eq!(Ok(EventKind::Chargeback), "chargeback".parse());
+        assert!("refund".parse::<EventKind>().is_err());
+    }
+}
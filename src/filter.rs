@@ -0,0 +1,336 @@
+use crate::money::Money;
+use crate::output::STANDARD_COLUMNS;
+use crate::{ClientId, ClientState, EXTENDED_COLUMNS};
+use rust_decimal::Decimal;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// What a client's field is compared as.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    Number(Decimal),
+    Bool(bool),
+    Text(String),
+    /// a field the client has nothing in, e.g. no tier or no timestamped records
+    Blank,
+}
+
+impl Value {
+    fn literal(raw: &str) -> Self {
+        match raw {
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            "" => Value::Blank,
+            raw => match Decimal::from_str(raw) {
+                Ok(number) => Value::Number(number),
+                Err(_) => Value::Text(raw.to_string()),
+            },
+        }
+    }
+
+    fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Number(a), Value::Number(b)) => Some(a.cmp(b)),
+            (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+            (Value::Text(a), Value::Text(b)) => Some(a.cmp(b)),
+            (Value::Blank, Value::Blank) => Some(Ordering::Equal),
+            _ => None,
+        }
+    }
+}
+
+/// `client`'s value of the output column `name`, see [`crate::EXTENDED_COLUMNS`].
+fn field(client: &ClientState, name: &str) -> Value {
+    let number = |n: Money| Value::Number(n.amount());
+    let count = |n: Option<u64>| n.map_or(Value::Blank, |n| Value::Number(n.into()));
+    let profile = client.profile();
+    let text = |text: Option<&String>| text.map_or(Value::Blank, |t| Value::Text(t.clone()));
+    let activity = client.activity();
+    match name {
+        "client" => count(Some(client.client_id.0.into())),
+        "available" => number(client.get_available_funds()),
+        "held" => number(client.get_held_funds()),
+        "total" => number(client.get_total_funds()),
+        "locked" => Value::Bool(client.is_locked()),
+        "admin_held" => number(client.get_admin_held_funds()),
+        "reserve" => number(client.get_reserve()),
+        "credit_limit" => number(client.get_credit_limit()),
+        "credit_used" => number(client.get_credit_used()),
+        "tier" => text(profile.and_then(|profile| profile.tier.as_ref())),
+        "currency" => client
+            .currency()
+            .map_or(Value::Blank, |currency| Value::Text(currency.to_string())),
+        "transactions" => count(Some(activity.applied as u64)),
+        "open_disputes" => count(Some(client.open_disputes() as u64)),
+        "deposited" => number(client.get_deposited()),
+        "withdrawn" => number(client.get_withdrawn()),
+        "last_counter" => count(activity.last_counter.map(|counter| counter as u64)),
+        "last_timestamp" => count(activity.last_timestamp),
+        _ => unreachable!("fields are checked when parsed"),
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Op {
+    fn holds(self, ordering: Option<Ordering>) -> bool {
+        match (self, ordering) {
+            (Op::Eq, ordering) => ordering == Some(Ordering::Equal),
+            (Op::Ne, ordering) => ordering != Some(Ordering::Equal),
+            // values of different kinds don't order
+            (_, None) => false,
+            (Op::Lt, Some(ordering)) => ordering == Ordering::Less,
+            (Op::Le, Some(ordering)) => ordering != Ordering::Greater,
+            (Op::Gt, Some(ordering)) => ordering == Ordering::Greater,
+            (Op::Ge, Some(ordering)) => ordering != Ordering::Less,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Compare(&'static str, Op, Value),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn matches(&self, client: &ClientState) -> bool {
+        match self {
+            Expr::Compare(name, op, value) => op.holds(field(client, name).compare(value)),
+            Expr::Not(expr) => !expr.matches(client),
+            Expr::And(a, b) => a.matches(client) && b.matches(client),
+            Expr::Or(a, b) => a.matches(client) || b.matches(client),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Op(Op),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let is_word = |c: char| c.is_alphanumeric() || "_.-".contains(c);
+    let mut tokens = vec![];
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '&' if chars.next_if_eq(&'&').is_some() => Token::And,
+            '|' if chars.next_if_eq(&'|').is_some() => Token::Or,
+            '=' if chars.next_if_eq(&'=').is_some() => Token::Op(Op::Eq),
+            '!' if chars.next_if_eq(&'=').is_some() => Token::Op(Op::Ne),
+            '!' => Token::Not,
+            '<' if chars.next_if_eq(&'=').is_some() => Token::Op(Op::Le),
+            '<' => Token::Op(Op::Lt),
+            '>' if chars.next_if_eq(&'=').is_some() => Token::Op(Op::Ge),
+            '>' => Token::Op(Op::Gt),
+            '"' => Token::Word(chars.by_ref().take_while(|&c| c != '"').collect()),
+            c if is_word(c) => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.next_if(|&c| is_word(c)) {
+                    word.push(c);
+                }
+                Token::Word(word)
+            }
+            _ => return Err(format!("Unexpected ({}) in filter ({}).", c, s)),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent over `or := and ("||" and)*`, `and := unary ("&&" unary)*` and
+/// `unary := "!" unary | "(" or ")" | FIELD OP VALUE`.
+struct Parser {
+    tokens: std::vec::IntoIter<Token>,
+    peeked: Option<Token>,
+}
+
+impl Parser {
+    fn peek(&mut self) -> Option<&Token> {
+        if self.peeked.is_none() {
+            self.peeked = self.tokens.next();
+        }
+        self.peeked.as_ref()
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        self.peek();
+        self.peeked.take()
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Not) => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let expr = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err("Unclosed ( in filter.".to_string()),
+                }
+            }
+            Some(Token::Word(name)) => {
+                let name = STANDARD_COLUMNS
+                    .into_iter()
+                    .chain(EXTENDED_COLUMNS)
+                    .find(|column| *column == name)
+                    .ok_or_else(|| format!("Unknown field ({}) in filter.", name))?;
+                let op = match self.next() {
+                    Some(Token::Op(op)) => op,
+                    _ => return Err(format!("Expected a comparison after ({}).", name)),
+                };
+                match self.next() {
+                    Some(Token::Word(value)) => Ok(Expr::Compare(name, op, Value::literal(&value))),
+                    _ => Err(format!("Expected a value to compare ({}) with.", name)),
+                }
+            }
+            token => Err(format!("Expected a comparison, found ({:?}).", token)),
+        }
+    }
+}
+
+/// Which clients a report or the balances output covers, e.g.
+/// `locked == true && total > 1000`: comparisons of an output column with a value joined by
+/// `&&`, `||` and `!`, with parentheses. Amounts and counts compare as numbers, `locked` as a
+/// boolean, `tier` and `currency` as text, and `""` is a blank field. See --where.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filter(Expr);
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?.into_iter(),
+            peeked: None,
+        };
+        let expr = parser.or()?;
+        match parser.next() {
+            None => Ok(Filter(expr)),
+            Some(token) => Err(format!("Unexpected ({:?}) at the end of filter.", token)),
+        }
+    }
+}
+
+impl Filter {
+    pub fn matches(&self, client: &ClientState) -> bool {
+        self.0.matches(client)
+    }
+
+    /// the clients that match.
+    pub fn select(
+        &self,
+        clients: &HashMap<ClientId, ClientState>,
+    ) -> HashMap<ClientId, ClientState> {
+        clients
+            .iter()
+            .filter(|(_, client)| self.matches(client))
+            .map(|(client_id, client)| (*client_id, client.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{process_record, Record, SituatedRecord, TransactionType, TxId};
+
+    #[test]
+    fn test_filter() {
+        let mut clients = HashMap::new();
+        for (monotonic_counter, (transaction_type, client_id, transaction_id, amount)) in [
+            (TransactionType::Deposit, 1, 1, 2000),
+            (TransactionType::Deposit, 1, 2, 50),
+            (TransactionType::Dispute, 1, 2, 0),
+            (TransactionType::Chargeback, 1, 2, 0),
+            (TransactionType::Deposit, 2, 3, 5000),
+            (TransactionType::Deposit, 3, 4, 10),
+            (TransactionType::Dispute, 3, 4, 0),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let record = Record {
+                transaction_type,
+                client_id: ClientId(client_id),
+                transaction_id: TxId(transaction_id),
+                amount: Some(Money::new(amount, 0)),
+                timestamp: None,
+                reason: None,
+            };
+            let _ = process_record(
+                SituatedRecord {
+                    monotonic_counter,
+                    record,
+                },
+                &mut clients,
+            );
+        }
+        let selected = |filter: &str| {
+            let filter: Filter = filter.parse().unwrap();
+            let mut ids: Vec<u16> = filter
+                .select(&clients)
+                .into_keys()
+                .map(|client_id| client_id.0)
+                .collect();
+            ids.sort_unstable();
+            ids
+        };
+        assert_eq!(vec![1], selected("locked == true && total > 1000"));
+        assert_eq!(vec![1, 2], selected("total>=2000"));
+        assert_eq!(vec![2, 3], selected("!locked == true"));
+        assert_eq!(
+            vec![1, 3],
+            selected("locked==true || (open_disputes > 0 && held < 11)")
+        );
+        assert_eq!(
+            vec![3],
+            selected("last_timestamp == \"\" && client != 1 && client != 2")
+        );
+        // a number never equals text, so nothing matches rather than everything
+        assert!(selected("total == lots").is_empty());
+        assert!("balance > 1".parse::<Filter>().is_err());
+        assert!("total >".parse::<Filter>().is_err());
+        assert!("(total > 1".parse::<Filter>().is_err());
+        assert!("total > 1 locked".parse::<Filter>().is_err());
+        assert!("total => 1".parse::<Filter>().is_err());
+    }
+}
@@ -0,0 +1,190 @@
+use crate::{ClientId, ClientState, Record, Rejection, SituatedRecord, TransactionType, TxId};
+use log::{info, warn};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+/// What to do with a dispute, resolve or chargeback whose client doesn't have the transaction
+/// it refers to while another client does.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ForeignPolicy {
+    #[default]
+    Reject,
+    /// apply it to the client that owns the transaction
+    Route,
+    /// park it with its own client for someone to look at, see --report suspense
+    Suspense,
+}
+
+impl FromStr for ForeignPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(ForeignPolicy::Reject),
+            "route" => Ok(ForeignPolicy::Route),
+            "suspense" => Ok(ForeignPolicy::Suspense),
+            _ => Err(format!(
+                "Unknown foreign dispute policy ({}), expected reject, route or suspense.",
+                s
+            )),
+        }
+    }
+}
+
+/// Where a record goes once its client is checked against the transaction's owner.
+#[derive(Debug, Copy, Clone)]
+pub enum Dispatch {
+    Apply(SituatedRecord),
+    Reject(Rejection),
+    Park(SituatedRecord),
+}
+
+/// Which clients deposited or withdrew each transaction id, seen by the stage that hands records
+/// to the engine so it works the same with any number of workers. Ids usually have a single
+/// owner, the rare others are kept apart.
+#[derive(Debug, Default)]
+pub struct Owners {
+    first: HashMap<TxId, ClientId>,
+    others: HashSet<(TxId, ClientId)>,
+}
+
+impl Owners {
+    /// the owners of the transactions `clients` already have, e.g. restored from a checkpoint.
+    pub fn of(clients: &HashMap<ClientId, ClientState>) -> Self {
+        let mut owned: Vec<(usize, TxId, ClientId)> = clients
+            .values()
+            .flat_map(|client| {
+                client.disputables().map(|(tx_id, disputable)| {
+                    (disputable.monotonic_counter, tx_id, client.client_id)
+                })
+            })
+            .collect();
+        owned.sort_unstable();
+        let mut owners = Owners::default();
+        for (_, tx_id, client_id) in owned {
+            owners.own(tx_id, client_id);
+        }
+        owners
+    }
+
+    fn own(&mut self, tx_id: TxId, client_id: ClientId) {
+        let first = *self.first.entry(tx_id).or_insert(client_id);
+        if first != client_id {
+            self.others.insert((tx_id, client_id));
+        }
+    }
+
+    fn owns(&self, tx_id: TxId, client_id: ClientId) -> bool {
+        self.first.get(&tx_id) == Some(&client_id) || self.others.contains(&(tx_id, client_id))
+    }
+
+    /// the client that first deposited or withdrew the transaction `record` refers to, if it's
+    /// a dispute, resolve or chargeback whose own client never did.
+    fn foreign_owner(&self, record: &Record) -> Option<ClientId> {
+        let owner = *self.first.get(&record.transaction_id)?;
+        (!self.owns(record.transaction_id, record.client_id)).then_some(owner)
+    }
+
+    /// Note who owns a deposit or withdrawal and decide what happens to a reference to another
+    /// client's transaction under `policy`. Anything else is applied as is.
+    pub fn dispatch(&mut self, situated_record: SituatedRecord, policy: ForeignPolicy) -> Dispatch {
+        let record = situated_record.record;
+        match record.transaction_type {
+            TransactionType::Deposit | TransactionType::Withdrawal => {
+                self.own(record.transaction_id, record.client_id);
+                return Dispatch::Apply(situated_record);
+            }
+            transaction_type if !transaction_type.is_reference() => {
+                return Dispatch::Apply(situated_record);
+            }
+            _ => {}
+        }
+        let owner = match self.foreign_owner(&record) {
+            Some(owner) => owner,
+            None => return Dispatch::Apply(situated_record),
+        };
+        match policy {
+            ForeignPolicy::Reject => {
+                warn!(
+                    "Record ({}) refers to transaction ({}) of another client, rejecting it.",
+                    situated_record.monotonic_counter, record.transaction_id
+                );
+                Dispatch::Reject(Rejection::ForeignTransaction)
+            }
+            ForeignPolicy::Route => {
+                info!(
+                    "Record ({}) refers to transaction ({}) of another client, routing it there.",
+                    situated_record.monotonic_counter, record.transaction_id
+                );
+                let mut routed = situated_record;
+                routed.record.client_id = owner;
+                Dispatch::Apply(routed)
+            }
+            ForeignPolicy::Suspense => {
+                info!(
+                    "Record ({}) refers to transaction ({}) of another client, parking it.",
+                    situated_record.monotonic_counter, record.transaction_id
+                );
+                Dispatch::Park(situated_record)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::money::Money;
+    use crate::situated;
+
+    #[test]
+    fn test_dispatch() {
+        let mut owners = Owners::default();
+        let client_of = |dispatch: Dispatch| match dispatch {
+            Dispatch::Apply(applied) => Ok(applied.record.client_id.0),
+            Dispatch::Reject(rejection) => Err(rejection.code()),
+            Dispatch::Park(_) => Err("parked"),
+        };
+        let deposit =
+            situated(0, TransactionType::Deposit, Money::new(1, 0)).of(ClientId(1), TxId(7));
+        assert_eq!(
+            Ok(1),
+            client_of(owners.dispatch(deposit, ForeignPolicy::Reject))
+        );
+        let dispute =
+            situated(1, TransactionType::Dispute, Money::new(1, 0)).of(ClientId(2), TxId(7));
+        assert_eq!(
+            Err("foreign_transaction"),
+            client_of(owners.dispatch(dispute, ForeignPolicy::Reject))
+        );
+        assert_eq!(
+            Ok(1),
+            client_of(owners.dispatch(dispute, ForeignPolicy::Route))
+        );
+        assert_eq!(
+            Err("parked"),
+            client_of(owners.dispatch(dispute, ForeignPolicy::Suspense))
+        );
+        // unknown to everyone is left to the engine
+        let unknown =
+            situated(2, TransactionType::Dispute, Money::new(1, 0)).of(ClientId(2), TxId(8));
+        assert_eq!(
+            Ok(2),
+            client_of(owners.dispatch(unknown, ForeignPolicy::Route))
+        );
+        // ids aren't unique across clients, a client's own transaction comes first
+        let own =
+            situated(3, TransactionType::Withdrawal, Money::new(1, 0)).of(ClientId(2), TxId(7));
+        owners.dispatch(own, ForeignPolicy::Reject);
+        assert_eq!(
+            Ok(2),
+            client_of(owners.dispatch(dispute, ForeignPolicy::Reject))
+        );
+    }
+
+    #[test]
+    fn test_parse_policy() {
+        assert_eq!(Ok(ForeignPolicy::Route), "route".parse());
+        assert!("forward".parse::<ForeignPolicy>().is_err());
+    }
+}
@@ -0,0 +1,121 @@
+use crate::{ClientId, Rejection, SituatedRecord};
+use csv::{ReaderBuilder, Trim};
+use log::warn;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::Path;
+
+/// The canonical id of every client id that was migrated to another, see --id-map. Several ids
+/// may be mapped to one, which merges them on purpose; what it guards against is an id being
+/// merged by accident, by records under an id that isn't in the map but is some id's canonical
+/// one. Such an id is a different account as far as the map says, so its records are turned down
+/// with [`Rejection::IdCollision`] rather than landing on the migrated client.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IdMap {
+    canonical: HashMap<ClientId, ClientId>,
+    targets: HashSet<ClientId>,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl IdMap {
+    /// an id map from a CSV of `client,canonical` rows. An id listed twice, or mapped to an id
+    /// that's itself mapped to another, is an error, as either leaves it unclear where its
+    /// records go.
+    pub fn read<R: io::Read>(reader: R) -> io::Result<Self> {
+        let mut reader = ReaderBuilder::new().trim(Trim::All).from_reader(reader);
+        let mut canonical = HashMap::new();
+        for row in reader.deserialize() {
+            let (client_id, canonical_id): (ClientId, ClientId) =
+                row.map_err(|e| invalid(format!("Malformed id mapping: {}.", e)))?;
+            if let Some(earlier) = canonical.insert(client_id, canonical_id) {
+                return Err(invalid(format!(
+                    "Client ({}) is mapped twice, to ({}) and ({}).",
+                    client_id, earlier, canonical_id
+                )));
+            }
+        }
+        for (client_id, canonical_id) in &canonical {
+            match canonical.get(canonical_id) {
+                Some(next) if next != canonical_id => {
+                    return Err(invalid(format!(
+                        "Client ({}) is mapped to ({}), which is mapped to ({}) in turn.",
+                        client_id, canonical_id, next
+                    )))
+                }
+                _ => {}
+            }
+        }
+        let targets = canonical.values().copied().collect();
+        Ok(IdMap { canonical, targets })
+    }
+
+    pub fn read_file(path: &Path) -> io::Result<Self> {
+        Self::read(std::fs::File::open(path)?)
+    }
+
+    /// The id records of `client_id` are applied to.
+    pub fn of(&self, client_id: ClientId) -> Result<ClientId, Rejection> {
+        match self.canonical.get(&client_id) {
+            Some(canonical_id) => Ok(*canonical_id),
+            None if self.targets.contains(&client_id) => Err(Rejection::IdCollision),
+            None => Ok(client_id),
+        }
+    }
+}
+
+/// `situated_record` with its client id mapped to the canonical one by `id_map`, the record as it
+/// is without one.
+pub fn canonical(
+    situated_record: SituatedRecord,
+    id_map: Option<&IdMap>,
+) -> Result<SituatedRecord, Rejection> {
+    let Some(id_map) = id_map else {
+        return Ok(situated_record);
+    };
+    let record = situated_record.record;
+    let client_id = id_map.of(record.client_id).inspect_err(|_| {
+        warn!(
+            "Record ({}) is for client ({}), which another id is mapped to.",
+            situated_record.monotonic_counter, record.client_id
+        )
+    })?;
+    let mut situated_record = situated_record;
+    situated_record.record.client_id = client_id;
+    Ok(situated_record)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::money::Money;
+    use crate::{situated, TransactionType};
+
+    #[test]
+    fn test_id_map() {
+        let id_map = IdMap::read("client,canonical\n5,105\n6,105\n7,7\n8,7\n".as_bytes()).unwrap();
+        assert_eq!(Ok(ClientId(105)), id_map.of(ClientId(5)));
+        assert_eq!(Ok(ClientId(105)), id_map.of(ClientId(6)));
+        assert_eq!(Ok(ClientId(7)), id_map.of(ClientId(8)));
+        // 7 is listed, so it's meant to be merged with 8, 105 isn't
+        assert_eq!(Ok(ClientId(7)), id_map.of(ClientId(7)));
+        assert_eq!(Err(Rejection::IdCollision), id_map.of(ClientId(105)));
+        assert_eq!(Ok(ClientId(9)), id_map.of(ClientId(9)));
+        assert!(IdMap::read("client,canonical\n5,105\n5,106\n".as_bytes()).is_err());
+        assert!(IdMap::read("client,canonical\n5,105\n105,106\n".as_bytes()).is_err());
+
+        let id_map = IdMap::read("client,canonical\n1,101\n".as_bytes()).unwrap();
+        let record = situated(0, TransactionType::Deposit, Money::new(1, 0));
+        assert_eq!(
+            ClientId(101),
+            canonical(record, Some(&id_map)).unwrap().record.client_id
+        );
+        // a run without a map leaves ids as they are
+        assert_eq!(
+            ClientId(1),
+            canonical(record, None).unwrap().record.client_id
+        );
+    }
+}
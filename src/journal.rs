@@ -0,0 +1,280 @@
+use crate::digest::{hex, hmac, Sha256};
+use crate::enrich::{Enricher, Enrichment};
+use crate::pseudonym::Pseudonyms;
+use crate::run_hash::applied_line;
+use crate::{Disputable, SituatedRecord};
+use csv::{Reader, Writer};
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+const HEADER: [&str; 10] = [
+    "counter",
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "timestamp",
+    "ref_type",
+    "ref_amount",
+    "ref_counter",
+    "chain",
+];
+
+/// of journals written with --enrich, whose entries carry what it added to them.
+const ENRICHED_HEADER: [&str; 13] = [
+    "counter",
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "timestamp",
+    "ref_type",
+    "ref_amount",
+    "ref_counter",
+    "merchant",
+    "channel",
+    "country",
+    "chain",
+];
+
+/// of journals written before entries were linked to the transaction they refer to, which still
+/// verify.
+const UNLINKED_HEADER: [&str; 7] = [
+    "counter",
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "timestamp",
+    "chain",
+];
+
+/// An applied record and the deposit or withdrawal it refers to, see [`crate::Sinks`].
+pub type Entry = (SituatedRecord, Option<Disputable>);
+
+/// What the first entry is chained to.
+const GENESIS: [u8; 32] = [0; 32];
+
+/// Each entry's chain value covers the previous entry's and the entry itself, so changing,
+/// dropping or reordering an entry breaks every chain value after it. Keyed entries use
+/// HMAC-SHA256, so they can't be recomputed by whoever edited the file either.
+fn chain(key: Option<&[u8]>, previous: &[u8; 32], line: &str) -> [u8; 32] {
+    match key {
+        Some(key) => hmac(key, &[previous, line.as_bytes()]),
+        None => {
+            let mut sha = Sha256::new();
+            sha.update(previous);
+            sha.update(line.as_bytes());
+            sha.finish()
+        }
+    }
+}
+
+/// the journal entry for a record, which is its applied line with the client pseudonymized and
+/// the [`crate::REFERENCE_COLUMNS`] appended, then the fields `enricher` adds when there is one.
+fn entry_line(
+    (situated_record, reference): &Entry,
+    pseudonyms: &Pseudonyms,
+    enricher: Option<&Enricher>,
+) -> String {
+    let line = applied_line(situated_record);
+    let mut fields: Vec<String> = line.trim_end().split(',').map(str::to_string).collect();
+    if pseudonyms.is_salted() {
+        fields[2] = pseudonyms.client(&fields[2]);
+    }
+    fields.extend(Disputable::fields(reference.as_ref()));
+    if let Some(enricher) = enricher {
+        fields.extend(Enrichment::fields(enricher.of(&situated_record.record)));
+    }
+    format!("{}\n", fields.join(","))
+}
+
+/// Appends every applied record to a tamper evident CSV journal from a background thread, fed
+/// by the engine stage (or its shards) through [`Journal::sender`]. With several workers the
+/// entries of different clients interleave differently from run to run.
+pub struct Journal {
+    sender: Sender<Entry>,
+    writer: JoinHandle<csv::Result<usize>>,
+}
+
+impl Journal {
+    /// A journal at `path`, whose entries carry what `enricher` adds to them when there is one.
+    pub fn create(
+        path: &Path,
+        key: Option<Vec<u8>>,
+        pseudonyms: Pseudonyms,
+        enricher: Option<Arc<Enricher>>,
+    ) -> csv::Result<Self> {
+        let mut writer = Writer::from_path(path)?;
+        match &enricher {
+            Some(_) => writer.write_record(ENRICHED_HEADER)?,
+            None => writer.write_record(HEADER)?,
+        }
+        let (sender, receiver) = mpsc::channel::<Entry>();
+        let writer = thread::spawn(move || {
+            let mut previous = GENESIS;
+            let mut written = 0;
+            for entry in receiver {
+                let line = entry_line(&entry, &pseudonyms, enricher.as_deref());
+                previous = chain(key.as_deref(), &previous, &line);
+                let mut row: Vec<&str> = line.trim_end().split(',').collect();
+                let chain = hex(&previous);
+                row.push(&chain);
+                writer.write_record(&row)?;
+                written += 1;
+            }
+            writer.flush()?;
+            Ok(written)
+        });
+        Ok(Journal { sender, writer })
+    }
+
+    pub fn sender(&self) -> &Sender<Entry> {
+        &self.sender
+    }
+
+    /// Wait for every entry to be written and return how many were. Clones of the sender must be
+    /// dropped first.
+    pub fn finish(self) -> csv::Result<usize> {
+        drop(self.sender);
+        match self.writer.join() {
+            Ok(written) => written,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+/// Check every entry's chain value, returning how many entries there are. A journal written with
+/// a key only verifies with the same key. Entries cut off the end can't be detected this way,
+/// compare the entry count or the last chain value with one kept elsewhere for that.
+pub fn verify<R: io::Read>(reader: R, key: Option<&[u8]>) -> io::Result<usize> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let mut reader = Reader::from_reader(reader);
+    let headers = reader.headers()?.clone();
+    if headers.iter().ne(HEADER)
+        && headers.iter().ne(ENRICHED_HEADER)
+        && headers.iter().ne(UNLINKED_HEADER)
+    {
+        return Err(invalid(format!(
+            "Journal header ({:?}) isn't {:?}.",
+            headers, HEADER
+        )));
+    }
+    let mut previous = GENESIS;
+    let mut verified = 0;
+    for row in reader.records() {
+        let row = row?;
+        let fields: Vec<&str> = row.iter().collect();
+        let (recorded, entry) = fields
+            .split_last()
+            .filter(|(_, entry)| entry.len() == headers.len() - 1)
+            .ok_or_else(|| invalid(format!("Malformed journal entry ({:?}).", row)))?;
+        let expected = chain(key, &previous, &format!("{}\n", entry.join(",")));
+        if hex(&expected) != *recorded {
+            return Err(invalid(format!(
+                "Journal entry {} (counter {}) doesn't match its chain value, the journal was altered from there on or the key is wrong.",
+                verified + 1,
+                entry[0]
+            )));
+        }
+        previous = expected;
+        verified += 1;
+    }
+    Ok(verified)
+}
+
+pub fn verify_file(path: &Path, key: Option<&[u8]>) -> io::Result<usize> {
+    verify(File::open(path)?, key)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::money::Money;
+    use crate::{ClientId, DisputeStatus, Record, TransactionType, TxId};
+    use std::env;
+    use std::fs;
+
+    fn journal(key: Option<&[u8]>) -> String {
+        let path = env::temp_dir().join(format!(
+            "journal-{}-{}.csv",
+            std::process::id(),
+            key.is_some()
+        ));
+        let journal =
+            Journal::create(&path, key.map(<[u8]>::to_vec), Pseudonyms::default(), None).unwrap();
+        let deposit = Disputable {
+            transaction_type: TransactionType::Deposit,
+            amount: Money::new(150, 2),
+            status: DisputeStatus::Undisputed,
+            monotonic_counter: 0,
+            reason: None,
+        };
+        let records = [
+            (0, TransactionType::Deposit, Money::new(150, 2), None),
+            (3, TransactionType::Dispute, Money::ZERO, Some(deposit)),
+        ];
+        for (monotonic_counter, transaction_type, amount, reference) in records {
+            let situated_record = SituatedRecord {
+                monotonic_counter,
+                record: Record {
+                    transaction_type,
+                    client_id: ClientId(1),
+                    transaction_id: TxId(0),
+                    amount: Some(amount),
+                    timestamp: None,
+                    reason: None,
+                },
+            };
+            journal.sender().send((situated_record, reference)).unwrap();
+        }
+        assert_eq!(2, journal.finish().unwrap());
+        let written = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        written
+    }
+
+    #[test]
+    fn test_journal_chain() {
+        let written = journal(None);
+        let rows: Vec<&str> = written.lines().collect();
+        assert_eq!(
+            "counter,type,client,tx,amount,timestamp,ref_type,ref_amount,ref_counter,chain",
+            rows[0]
+        );
+        assert!(rows[1].starts_with("0,deposit,1,0,1.5,,,,,"));
+        assert!(rows[2].starts_with("3,dispute,1,0,0,,deposit,1.50,0,"));
+        assert_eq!(2, verify(written.as_bytes(), None).unwrap());
+        let tampered = written.replace("deposit,1,0,1.5", "deposit,1,0,15");
+        assert!(verify(tampered.as_bytes(), None).is_err());
+        let mut rows: Vec<&str> = written.lines().collect();
+        rows.swap(1, 2);
+        assert!(verify(rows.join("\n").as_bytes(), None).is_err());
+    }
+
+    #[test]
+    fn test_unlinked_journal_verifies() {
+        let line = "0,deposit,1,0,1.5,\n";
+        let chain = hex(&chain(None, &GENESIS, line));
+        let written = format!(
+            "{}\n{},{}\n",
+            UNLINKED_HEADER.join(","),
+            line.trim_end(),
+            chain
+        );
+        assert_eq!(1, verify(written.as_bytes(), None).unwrap());
+    }
+
+    #[test]
+    fn test_signed_journal() {
+        let written = journal(Some(b"secret"));
+        assert_eq!(2, verify(written.as_bytes(), Some(b"secret")).unwrap());
+        assert!(verify(written.as_bytes(), Some(b"guess")).is_err());
+        // a keyless chain can't be passed off as the signed one
+        assert!(verify(written.as_bytes(), None).is_err());
+    }
+}
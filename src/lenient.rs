@@ -0,0 +1,120 @@
+use crate::pseudonym::Pseudonyms;
+use csv::StringRecord;
+use log::trace;
+
+const CURRENCY_SYMBOLS: [char; 7] = ['$', '€', '£', '¥', '₹', '¢', '₩'];
+
+/// Clean up a row from a hand-edited or exported file before it's deserialized: type names are
+/// lowercased and amounts lose their formatting (see [`normalize_amount`]). Returns None for rows
+/// that are blank apart from separators and whitespace, which should be skipped.
+pub fn normalize(
+    headers: &StringRecord,
+    row: &StringRecord,
+    pseudonyms: &Pseudonyms,
+) -> Option<StringRecord> {
+    if row.iter().all(|field| field.trim().is_empty()) {
+        trace!("Skipping blank row at {:?}.", row.position());
+        return None;
+    }
+    let mut normalized: StringRecord = headers
+        .iter()
+        .zip(row.iter())
+        .map(|(header, field)| match header {
+            "type" => field.trim().to_lowercase(),
+            "amount" => normalize_amount(field),
+            _ => field.to_string(),
+        })
+        .chain(row.iter().skip(headers.len()).map(str::to_string))
+        .collect();
+    if normalized != *row {
+        trace!(
+            "Normalized row {:?} to {:?}.",
+            pseudonyms.row(headers, row),
+            pseudonyms.row(headers, &normalized)
+        );
+    }
+    normalized.set_position(row.position().cloned());
+    Some(normalized)
+}
+
+/// `$1,234.56` -> `1234.56`, `(12.50)` -> `-12.50`. Commas are only dropped when they group the
+/// integer part in threes, anything else is left alone for the decimal parser to reject.
+pub fn normalize_amount(raw: &str) -> String {
+    let mut amount = raw.trim();
+    let mut negative = false;
+    if let Some(inner) = amount.strip_prefix('(').and_then(|a| a.strip_suffix(')')) {
+        negative = true;
+        amount = inner;
+    }
+    // the sign can sit either side of the currency symbol, "-$5" or "$-5"
+    for _ in 0..2 {
+        amount = amount.trim_matches(|c: char| c.is_whitespace() || CURRENCY_SYMBOLS.contains(&c));
+        if let Some(unsigned) = amount.strip_prefix('-') {
+            negative = true;
+            amount = unsigned;
+        }
+    }
+    let amount = match amount.split_once('.') {
+        Some((integer, fraction)) if is_grouped(integer) => {
+            format!("{}.{}", integer.replace(',', ""), fraction)
+        }
+        None if is_grouped(amount) => amount.replace(',', ""),
+        _ => amount.to_string(),
+    };
+    if negative && !amount.is_empty() {
+        format!("-{}", amount)
+    } else {
+        amount
+    }
+}
+
+/// whether `integer` is digits grouped by commas in threes, like 1,234,567.
+fn is_grouped(integer: &str) -> bool {
+    let mut groups = integer.split(',');
+    let first = groups.next().unwrap_or_default();
+    integer.contains(',')
+        && (1..=3).contains(&first.len())
+        && first.chars().all(|c| c.is_ascii_digit())
+        && groups.all(|group| group.len() == 3 && group.chars().all(|c| c.is_ascii_digit()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::money::Money;
+
+    #[test]
+    fn test_normalize_amount() {
+        assert_eq!("1234.56", normalize_amount("1,234.56"));
+        assert_eq!("1234567", normalize_amount(" $1,234,567 "));
+        assert_eq!("-12.50", normalize_amount("(12.50)"));
+        assert_eq!("-12.50", normalize_amount("($12.50)"));
+        assert_eq!("-5", normalize_amount("-€5"));
+        assert_eq!("-5", normalize_amount("£-5"));
+        assert_eq!("", normalize_amount(""));
+        // not thousands grouping, left for the decimal parser to reject
+        assert_eq!("1,5", normalize_amount("1,5"));
+        assert_eq!("12,34.5", normalize_amount("12,34.5"));
+    }
+
+    #[test]
+    fn test_normalize_row() {
+        let headers = StringRecord::from(vec!["type", "client", "tx", "amount"]);
+        let row = StringRecord::from(vec!["Deposit", "1", "2", "$1,000.25"]);
+        let normalized = normalize(&headers, &row, &Pseudonyms::default()).unwrap();
+        assert_eq!(
+            StringRecord::from(vec!["deposit", "1", "2", "1000.25"]),
+            normalized
+        );
+        let record: crate::Record = normalized.deserialize(Some(&headers)).unwrap();
+        assert_eq!(Some(Money::new(100025, 2)), record.amount);
+        assert_eq!(
+            None,
+            normalize(
+                &headers,
+                &StringRecord::from(vec!["", " ", "", ""]),
+                &Pseudonyms::default()
+            )
+        );
+    }
+}
@@ -0,0 +1,3026 @@
+pub mod amount;
+pub mod analyze;
+pub mod anomaly;
+pub mod changes;
+pub mod checkpoint;
+pub mod config;
+pub mod conservation;
+pub mod console;
+pub mod dead_letter;
+pub mod decisions;
+pub mod digest;
+pub mod dry_run;
+pub mod engine;
+pub mod enrich;
+pub mod events;
+pub mod filter;
+pub mod foreign;
+pub mod id_map;
+pub mod journal;
+pub mod lenient;
+pub mod limits;
+pub mod loadtest;
+pub mod manifest;
+pub mod merge;
+pub mod messages;
+pub mod migrate;
+pub mod money;
+pub mod ordering;
+pub mod output;
+pub mod partition;
+pub mod pipeline;
+pub mod processed;
+pub mod profile;
+pub mod pseudonym;
+pub mod query;
+pub mod replay;
+pub mod report;
+pub mod run_hash;
+pub mod schema;
+pub mod screening;
+pub mod shadow;
+pub mod shards;
+pub mod shutdown;
+pub mod source;
+pub mod spill;
+pub mod state;
+pub mod summary;
+mod sync;
+pub mod trailer;
+pub mod velocity;
+pub mod webhook;
+
+use amount::AmountPolicy;
+use changes::{Change, Snapshot};
+use checkpoint::{Checkpoints, Saved};
+use csv::{Reader, ReaderBuilder, Trim};
+use dead_letter::DeadLetter;
+use digest::Sha256;
+use events::Event;
+use foreign::{Dispatch, ForeignPolicy, Owners};
+use limits::{Limits, Profile};
+use log::{error, info, trace, warn};
+use money::{Currency, Money};
+use output::{AmountFormat, OutputColumns};
+use pipeline::{ParseError, ParseFailure, PipelineConfig};
+use processed::Spooled;
+use profile::Stage;
+use pseudonym::Pseudonyms;
+use rust_decimal::Decimal;
+use schema::ColumnMap;
+use screening::Action;
+use serde::{de, Deserialize};
+use shards::Shards;
+use source::Source;
+use spill::{Chunk, Spill};
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use velocity::Velocity;
+
+pub fn validate_input(input: Option<&OsStr>) -> io::Result<&Path> {
+    let err_str = "Invalid! Input must be path to file that exists on the filesystem.";
+    if let Some(transactions_csv) = input {
+        let possible_path = Path::new(transactions_csv);
+        if possible_path.exists() {
+            Ok(possible_path)
+        } else {
+            Err(io::Error::new(io::ErrorKind::InvalidInput, err_str))
+        }
+    } else {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, err_str))
+    }
+}
+
+const PRECISION: u32 = 4u32;
+/// an empty amount is None rather than zero, whether the record needs one is up to its type, see
+/// [`Record::required_amount`]. Amounts are parsed by the default policy, the parser stage
+/// applies that of the run beforehand, see [`AmountPolicy::apply`].
+pub fn deserialize_with_precision_of_4<'de, D>(deserializer: D) -> Result<Option<Money>, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    let buf = String::deserialize(deserializer)?;
+    match buf.is_empty() {
+        true => Ok(None),
+        false => AmountPolicy::default()
+            .parse(&buf, PRECISION)
+            .map(|amount| Some(amount.into()))
+            .map_err(de::Error::custom),
+    }
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionType {
+    Withdrawal,
+    Deposit,
+    Dispute,
+    Resolve,
+    Chargeback,
+    /// the disputing party withdrawing its dispute, which releases the held funds like a resolve
+    #[serde(rename = "dispute_cancel")]
+    DisputeCancel,
+    /// the merchant contesting a chargeback
+    Representment,
+    /// the contested chargeback being overturned, which reverses it
+    #[serde(rename = "representment_won")]
+    RepresentmentWon,
+    /// moving funds from available to held for compliance, unrelated to any transaction
+    #[serde(rename = "admin_hold")]
+    AdminHold,
+    /// moving funds an admin hold put on hold back to available
+    #[serde(rename = "admin_release")]
+    AdminRelease,
+}
+
+impl TransactionType {
+    pub const ALL: [TransactionType; 10] = [
+        TransactionType::Withdrawal,
+        TransactionType::Deposit,
+        TransactionType::Dispute,
+        TransactionType::Resolve,
+        TransactionType::Chargeback,
+        TransactionType::DisputeCancel,
+        TransactionType::Representment,
+        TransactionType::RepresentmentWon,
+        TransactionType::AdminHold,
+        TransactionType::AdminRelease,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Deposit => "deposit",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::DisputeCancel => "dispute_cancel",
+            TransactionType::Representment => "representment",
+            TransactionType::RepresentmentWon => "representment_won",
+            TransactionType::AdminHold => "admin_hold",
+            TransactionType::AdminRelease => "admin_release",
+        }
+    }
+
+    /// whether the record refers to an earlier deposit or withdrawal by its tx id, like disputes
+    /// and the rest of their lifecycle do.
+    pub fn is_reference(&self) -> bool {
+        !matches!(
+            self,
+            TransactionType::Withdrawal
+                | TransactionType::Deposit
+                | TransactionType::AdminHold
+                | TransactionType::AdminRelease
+        )
+    }
+}
+
+impl std::str::FromStr for TransactionType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TransactionType::ALL
+            .into_iter()
+            .find(|transaction_type| transaction_type.as_str() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = TransactionType::ALL.iter().map(|t| t.as_str()).collect();
+                format!(
+                    "Unknown transaction type ({}), expected one of {}.",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// A set of transaction types, small enough to copy around with the pipeline's config.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct TransactionTypes(u16);
+
+impl TransactionTypes {
+    pub fn insert(&mut self, transaction_type: TransactionType) {
+        self.0 |= 1 << transaction_type as u16;
+    }
+
+    pub fn contains(&self, transaction_type: TransactionType) -> bool {
+        self.0 & 1 << transaction_type as u16 != 0
+    }
+}
+
+impl FromIterator<TransactionType> for TransactionTypes {
+    fn from_iter<I: IntoIterator<Item = TransactionType>>(iter: I) -> Self {
+        let mut types = TransactionTypes::default();
+        for transaction_type in iter {
+            types.insert(transaction_type);
+        }
+        types
+    }
+}
+
+/// Why a dispute was raised, when the feed says.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DisputeReason {
+    Fraud,
+    /// goods or services not received or not as described
+    Service,
+}
+
+impl DisputeReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DisputeReason::Fraud => "fraud",
+            DisputeReason::Service => "service",
+        }
+    }
+
+    /// the `reason_code` column for an optional reason, blank without one.
+    pub fn field(reason: Option<DisputeReason>) -> &'static str {
+        reason.map(|reason| reason.as_str()).unwrap_or_default()
+    }
+}
+
+/// What to do with a deposit to a frozen account, see --locked-deposits.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum LockedDeposits {
+    #[default]
+    Accept,
+    /// turn it down as [`Rejection::AccountLocked`]
+    Reject,
+    /// apply it and put it on admin hold, like a deposit of a client --screen blocks
+    Hold,
+}
+
+impl std::str::FromStr for LockedDeposits {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "accept" => Ok(LockedDeposits::Accept),
+            "reject" => Ok(LockedDeposits::Reject),
+            "hold" => Ok(LockedDeposits::Hold),
+            _ => Err(format!(
+                "Unknown locked deposit policy ({}), expected accept, reject or hold.",
+                s
+            )),
+        }
+    }
+}
+
+/// Why the engine refused to apply a record. A rejected record changes nothing, and its
+/// [`Rejection::code`] and [`Rejection::error_code`] are what the dead letters and log carry.
+///
+/// ```
+/// use playing_with_money::engine::Engine;
+/// use playing_with_money::{ClientId, Record, Rejection, TransactionType, TxId};
+/// use rust_decimal_macros::dec;
+///
+/// let mut engine = Engine::default();
+/// let withdrawal = Record {
+///     transaction_type: TransactionType::Withdrawal,
+///     client_id: ClientId(1),
+///     transaction_id: TxId(1),
+///     amount: Some(dec!(50).into()),
+///     timestamp: None,
+///     reason: None,
+/// };
+/// let rejection = engine.apply(withdrawal).unwrap_err();
+/// assert_eq!(Rejection::InsufficientFunds, rejection);
+/// assert_eq!("insufficient_funds", rejection.code());
+/// assert_eq!("PWM-E001", rejection.error_code());
+/// assert_eq!("insufficient available funds", rejection.to_string());
+///
+/// // a deposit re-using the id of one already applied
+/// let deposit = Record {
+///     transaction_type: TransactionType::Deposit,
+///     transaction_id: TxId(2),
+///     ..withdrawal
+/// };
+/// engine.apply(deposit).unwrap();
+/// let rejection = engine.apply(deposit).unwrap_err();
+/// assert_eq!(Rejection::DuplicateTransaction, rejection);
+/// assert_eq!("PWM-E002", rejection.error_code());
+///
+/// // a resolve for a transaction that isn't disputed
+/// let resolve = Record {
+///     transaction_type: TransactionType::Resolve,
+///     amount: None,
+///     ..withdrawal
+/// };
+/// assert_eq!(Err(Rejection::NotDisputed), engine.apply(resolve).map(|_| ()));
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Rejection {
+    InsufficientFunds,
+    AccountLocked,
+    /// a withdrawal or deposit re-using a transaction id
+    DuplicateTransaction,
+    /// a dispute, resolve or chargeback for a transaction the client doesn't have
+    UnknownTransaction,
+    AlreadyDisputed,
+    /// a resolve or chargeback for a transaction that isn't under dispute
+    NotDisputed,
+    /// a resolve or chargeback for a dispute that was already resolved or charged back
+    AlreadySettled,
+    /// a representment for a transaction that isn't charged back, or a won one that wasn't
+    /// represented
+    NotChargedBack,
+    /// an admin release of more than the admin holds left on hold
+    ExceedsAdminHold,
+    /// a withdrawal that would leave less available than the client's reserve, see --reserve
+    BelowReserve,
+    /// a dispute, resolve or chargeback for another client's transaction, see --foreign-disputes
+    ForeignTransaction,
+    /// an amount beyond the configured --max-amount, or the client's own, see --clients
+    AmountOutOfBounds,
+    /// applying the record would overflow a balance, or need more precision than it has
+    Overflow,
+    /// turned down by a hook registered on the engine, see [`engine::TransactionHook::check`]
+    Vetoed,
+    /// for a client id another one is mapped to without being mapped itself, see --id-map
+    IdCollision,
+    /// a type of record the run was told to turn down, see --disallow
+    TypeDisabled,
+    /// a deposit, withdrawal, admin hold or admin release without an amount
+    MissingAmount,
+    /// enriched with a value a rule turns down, see --deny
+    Denied,
+    /// a withdrawal of a client --screen blocks
+    Screened,
+}
+
+impl Rejection {
+    pub const ALL: [Rejection; 19] = [
+        Rejection::InsufficientFunds,
+        Rejection::AccountLocked,
+        Rejection::DuplicateTransaction,
+        Rejection::UnknownTransaction,
+        Rejection::AlreadyDisputed,
+        Rejection::NotDisputed,
+        Rejection::AlreadySettled,
+        Rejection::NotChargedBack,
+        Rejection::ExceedsAdminHold,
+        Rejection::BelowReserve,
+        Rejection::ForeignTransaction,
+        Rejection::AmountOutOfBounds,
+        Rejection::Overflow,
+        Rejection::Vetoed,
+        Rejection::IdCollision,
+        Rejection::TypeDisabled,
+        Rejection::MissingAmount,
+        Rejection::Denied,
+        Rejection::Screened,
+    ];
+
+    /// stable identifier written to reject outputs
+    pub fn code(&self) -> &'static str {
+        match self {
+            Rejection::InsufficientFunds => "insufficient_funds",
+            Rejection::AccountLocked => "account_locked",
+            Rejection::DuplicateTransaction => "duplicate_transaction",
+            Rejection::UnknownTransaction => "unknown_transaction",
+            Rejection::AlreadyDisputed => "already_disputed",
+            Rejection::NotDisputed => "not_disputed",
+            Rejection::AlreadySettled => "already_settled",
+            Rejection::NotChargedBack => "not_charged_back",
+            Rejection::ExceedsAdminHold => "exceeds_admin_hold",
+            Rejection::BelowReserve => "below_reserve",
+            Rejection::ForeignTransaction => "foreign_transaction",
+            Rejection::AmountOutOfBounds => "amount_out_of_bounds",
+            Rejection::Overflow => "overflow",
+            Rejection::Vetoed => "vetoed",
+            Rejection::IdCollision => "id_collision",
+            Rejection::TypeDisabled => "type_disabled",
+            Rejection::MissingAmount => "missing_amount",
+            Rejection::Denied => "denied",
+            Rejection::Screened => "screened",
+        }
+    }
+
+    /// stable `PWM-E` number logged and written with the code, for alerting to match on. Numbers
+    /// are never reused or reordered; a new rejection takes the next one.
+    pub fn error_code(&self) -> &'static str {
+        match self {
+            Rejection::InsufficientFunds => "PWM-E001",
+            Rejection::DuplicateTransaction => "PWM-E002",
+            Rejection::AccountLocked => "PWM-E003",
+            Rejection::UnknownTransaction => "PWM-E004",
+            Rejection::AlreadyDisputed => "PWM-E005",
+            Rejection::NotDisputed => "PWM-E006",
+            Rejection::AlreadySettled => "PWM-E007",
+            Rejection::NotChargedBack => "PWM-E008",
+            Rejection::ExceedsAdminHold => "PWM-E009",
+            Rejection::BelowReserve => "PWM-E010",
+            Rejection::ForeignTransaction => "PWM-E011",
+            Rejection::AmountOutOfBounds => "PWM-E012",
+            Rejection::Overflow => "PWM-E013",
+            Rejection::Vetoed => "PWM-E014",
+            Rejection::IdCollision => "PWM-E015",
+            Rejection::TypeDisabled => "PWM-E016",
+            Rejection::MissingAmount => "PWM-E017",
+            Rejection::Denied => "PWM-E018",
+            Rejection::Screened => "PWM-E019",
+        }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            Rejection::InsufficientFunds => "insufficient available funds",
+            Rejection::AccountLocked => "client account is frozen",
+            Rejection::DuplicateTransaction => "transaction id is already in use",
+            Rejection::UnknownTransaction => "no withdrawal or deposit with this transaction id",
+            Rejection::AlreadyDisputed => "transaction is already disputed",
+            Rejection::NotDisputed => "transaction is not under dispute",
+            Rejection::AlreadySettled => "dispute was already resolved or charged back",
+            Rejection::NotChargedBack => "transaction isn't charged back or represented",
+            Rejection::ExceedsAdminHold => "release is more than the funds on admin hold",
+            Rejection::BelowReserve => "available funds would drop below the reserve",
+            Rejection::ForeignTransaction => "transaction belongs to another client",
+            Rejection::AmountOutOfBounds => "amount is beyond the allowed maximum",
+            Rejection::Overflow => "balance would overflow or lose precision",
+            Rejection::Vetoed => "turned down by a registered hook",
+            Rejection::IdCollision => "client id is the canonical id of another one",
+            Rejection::TypeDisabled => "transaction type is disabled for this run",
+            Rejection::MissingAmount => "amount is required for this transaction type",
+            Rejection::Denied => "turned down by a rule on its merchant, channel or country",
+            Rejection::Screened => "client is blocked by screening",
+        };
+        f.write_str(description)
+    }
+}
+
+/// A client's id, which [`pseudonym::Pseudonyms`] writes in its place when results are shared.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize)]
+#[serde(transparent)]
+pub struct ClientId(pub u16);
+
+/// The id of a deposit or withdrawal, which the records disputing it refer to it by.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize)]
+#[serde(transparent)]
+pub struct TxId(pub u32);
+
+impl fmt::Display for ClientId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl fmt::Display for TxId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::str::FromStr for ClientId {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(ClientId)
+    }
+}
+
+impl std::str::FromStr for TxId {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(TxId)
+    }
+}
+
+#[derive(Deserialize, Debug, Copy, Clone)]
+pub struct Record {
+    #[serde(rename = "type")]
+    pub transaction_type: TransactionType,
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    #[serde(rename = "tx")]
+    pub transaction_id: TxId,
+    /// blank for disputes and the rest of their lifecycle, which take the amount of the
+    /// transaction they refer to
+    #[serde(deserialize_with = "deserialize_with_precision_of_4")]
+    pub amount: Option<Money>,
+    /// optional, when the feed carries one. Any integer clock (e.g. unix seconds) will do as long
+    /// as it's used consistently with --max-skew.
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+    /// optional, only read for disputes
+    #[serde(default, rename = "reason_code")]
+    pub reason: Option<DisputeReason>,
+}
+
+impl Record {
+    /// The amount of a deposit, withdrawal, admin hold or admin release, which can't go without
+    /// one: [`Rejection::MissingAmount`] when the row left it blank.
+    pub fn required_amount(&self) -> Result<Money, Rejection> {
+        self.amount.ok_or_else(|| {
+            warn!(
+                "Record of type ({}) for transaction ({}) has no amount.",
+                self.transaction_type.as_str(),
+                self.transaction_id
+            );
+            Rejection::MissingAmount
+        })
+    }
+}
+
+/// as in, a record that has some context. In this case, embedding a "chronological" element.
+/// The app is currently not "stateful" a full implementation would track monotonic_counter offsets
+/// in some crash-safe persistent store to guarantee monotonicty.
+#[derive(Debug, Copy, Clone)]
+pub struct SituatedRecord {
+    pub monotonic_counter: usize,
+    pub record: Record,
+}
+
+/// A client's balances and the transactions it can still dispute, which records are applied to
+/// one at a time by [`ClientState::add_transaction`].
+///
+/// ```
+/// use playing_with_money::{ClientId, ClientState, Record, SituatedRecord, TransactionType, TxId};
+/// use rust_decimal_macros::dec;
+///
+/// let mut client = ClientState::new(ClientId(1));
+/// let mut apply = |monotonic_counter, transaction_type, transaction_id, amount| {
+///     client.add_transaction(SituatedRecord {
+///         monotonic_counter,
+///         record: Record {
+///             transaction_type,
+///             client_id: ClientId(1),
+///             transaction_id: TxId(transaction_id),
+///             amount,
+///             timestamp: None,
+///             reason: None,
+///         },
+///     })
+/// };
+/// apply(0, TransactionType::Deposit, 1, Some(dec!(10).into())).unwrap();
+/// apply(1, TransactionType::Deposit, 2, Some(dec!(5).into())).unwrap();
+/// apply(2, TransactionType::Dispute, 2, None).unwrap();
+/// apply(3, TransactionType::Chargeback, 2, None).unwrap();
+///
+/// // the charged back deposit is gone and the client is locked
+/// assert_eq!(dec!(10), client.get_total_funds().amount());
+/// assert_eq!(dec!(0), client.get_held_funds().amount());
+/// assert!(client.is_locked());
+/// let charged_back: Vec<TxId> = client
+///     .charged_back_transactions()
+///     .into_iter()
+///     .map(|(tx_id, _)| tx_id)
+///     .collect();
+/// assert_eq!(vec![TxId(2)], charged_back);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ClientState {
+    client_id: ClientId,
+    /// what every amount of the client is in, from its profile, see [`limits`]
+    currency: Option<Currency>,
+    available_funds: Money,
+    /// including admin holds
+    held_funds: Money,
+    admin_held_funds: Money,
+    /// available funds withdrawals have to leave, see [`limits`]
+    reserve: Money,
+    /// how far withdrawals can overdraw available funds, and how much of that is drawn
+    credit_limit: Money,
+    credit_used: Money,
+    locked: bool,
+    // lifetime money flows, kept apart from the balances so the two can be reconciled
+    deposited: Money,
+    withdrawn: Money,
+    charged_back: Money,
+    /// what disputes need to know about each transaction id, see [`Disputable`]
+    disputables: HashMap<TxId, Disputable>,
+    /// every record kept for a transaction id, in order, unless retention is off
+    history: Option<HashMap<TxId, Vec<SituatedRecord>>>,
+    /// where the history goes once the records kept by every client are over budget
+    spill: Option<Arc<Spill>>,
+    /// the history written out to `spill` so far, oldest first
+    spilled: Vec<Chunk>,
+    /// the records applied so far, in order, see run_hash
+    applied: Sha256,
+    activity: Activity,
+    /// disputes, resolves and chargebacks waiting for the transaction they refer to, by its id
+    suspense: HashMap<TxId, Vec<SituatedRecord>>,
+    /// whether a won representment unfreezes the account once no chargeback is left standing
+    unlock_on_representment: bool,
+    /// freeze the account once this many of its transactions were disputed as fraud
+    fraud_lock_after: Option<usize>,
+    /// the --velocity of the run, for clients whose tier or clients file row doesn't set one
+    velocity: Option<Velocity>,
+    /// the reserves, credit limits and profiles of the run, see [`limits`]
+    limits: Arc<Limits>,
+    recent: velocity::Recent,
+    locked_deposits: LockedDeposits,
+    /// what the --screen list says to do with the client's records, if it's on it
+    screening: Option<Action>,
+    /// how the client id is written in logs
+    pseudonyms: Pseudonyms,
+}
+
+/// How many records were applied to a client and when the last one was.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Activity {
+    pub applied: usize,
+    pub last_counter: Option<usize>,
+    /// of the last record applied, if it had one
+    pub last_timestamp: Option<u64>,
+}
+
+/// Where a deposit or withdrawal is in its dispute lifecycle.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DisputeStatus {
+    Undisputed,
+    Disputed,
+    /// after which it can't be disputed again, nor can a cancelled or charged back one
+    Resolved,
+    /// withdrawn by whoever disputed it
+    Cancelled,
+    ChargedBack,
+    /// charged back, and the chargeback contested
+    Represented,
+    /// the chargeback was overturned and reversed
+    Reversed,
+}
+
+impl DisputeStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DisputeStatus::Undisputed => "undisputed",
+            DisputeStatus::Disputed => "disputed",
+            DisputeStatus::Resolved => "resolved",
+            DisputeStatus::Cancelled => "cancelled",
+            DisputeStatus::ChargedBack => "charged_back",
+            DisputeStatus::Represented => "represented",
+            DisputeStatus::Reversed => "reversed",
+        }
+    }
+}
+
+/// All a dispute, resolve or chargeback needs of the deposit or withdrawal it refers to, kept
+/// whether or not the records themselves are.
+#[derive(Debug, Copy, Clone)]
+pub struct Disputable {
+    pub transaction_type: TransactionType,
+    pub amount: Money,
+    pub status: DisputeStatus,
+    /// of the deposit or withdrawal
+    pub monotonic_counter: usize,
+    /// of its latest dispute
+    pub reason: Option<DisputeReason>,
+}
+
+/// Columns that link a dispute, resolve or chargeback to the transaction it refers to in the
+/// dead letter, journal and replay outputs, see [`Disputable::fields`].
+pub const REFERENCE_COLUMNS: [&str; 3] = ["ref_type", "ref_amount", "ref_counter"];
+
+impl Disputable {
+    /// [`REFERENCE_COLUMNS`] for a record referring to `reference`, blank if it refers to none.
+    pub fn fields(reference: Option<&Disputable>) -> [String; 3] {
+        match reference {
+            Some(reference) => [
+                reference.transaction_type.as_str().to_string(),
+                reference.amount.to_string(),
+                reference.monotonic_counter.to_string(),
+            ],
+            None => Default::default(),
+        }
+    }
+}
+
+impl ClientState {
+    pub fn new(client_id: ClientId) -> Self {
+        Self::retaining(client_id, true)
+    }
+
+    /// A client that keeps the records of its transactions only if `retain_history`, with
+    /// disputes adjudicated from the [`Disputable`] index either way.
+    pub fn retaining(client_id: ClientId, retain_history: bool) -> Self {
+        ClientState {
+            client_id,
+            currency: None,
+            available_funds: Money::ZERO,
+            held_funds: Money::ZERO,
+            admin_held_funds: Money::ZERO,
+            reserve: Money::ZERO,
+            credit_limit: Money::ZERO,
+            credit_used: Money::ZERO,
+            locked: false,
+            deposited: Money::ZERO,
+            withdrawn: Money::ZERO,
+            charged_back: Money::ZERO,
+            disputables: HashMap::new(),
+            history: retain_history.then(HashMap::new),
+            spill: None,
+            spilled: vec![],
+            applied: Sha256::new(),
+            activity: Activity::default(),
+            suspense: HashMap::new(),
+            unlock_on_representment: false,
+            fraud_lock_after: None,
+            velocity: None,
+            limits: Arc::default(),
+            recent: velocity::Recent::default(),
+            locked_deposits: LockedDeposits::Accept,
+            screening: None,
+            pseudonyms: Pseudonyms::default(),
+        }
+    }
+
+    /// A client created the way `config` asks for: keeping its history or not, see
+    /// [`ClientState::retaining`], and unfreezing after a won representment or not.
+    pub fn configured(client_id: ClientId, config: &PipelineConfig) -> Self {
+        let mut client = Self::retaining(client_id, config.retain_history);
+        client.configure(config);
+        client
+    }
+
+    /// take on the settings of `config` a client keeps, which aren't part of a checkpoint.
+    pub(crate) fn configure(&mut self, config: &PipelineConfig) {
+        self.unlock_on_representment = config.unlock_on_representment;
+        self.fraud_lock_after = config.fraud_lock_after;
+        self.velocity = config.velocity;
+        self.limits = config.limits.clone();
+        self.currency = self.profile().and_then(|profile| profile.currency);
+        self.reserve = self.money(config.limits.reserve(self.client_id));
+        self.credit_limit = self.money(config.limits.credit_limit(self.client_id));
+        let balances = self.balances();
+        self.set_balances(balances);
+        // records kept before the client was handed the spill count against its budget too
+        if let (None, Some(spill), Some(history)) = (&self.spill, &config.spill, &self.history) {
+            spill.kept(history.values().map(Vec::len).sum());
+        }
+        self.spill = config.spill.clone();
+        self.locked_deposits = config.locked_deposits;
+        self.screening = screening::action(config.screening.as_deref(), self.client_id);
+        self.pseudonyms = config.pseudonyms.clone();
+    }
+
+    pub fn get_available_funds(&self) -> Money {
+        self.available_funds
+    }
+
+    pub fn get_held_funds(&self) -> Money {
+        self.held_funds
+    }
+
+    /// the part of held funds put on hold by admin holds rather than disputes.
+    pub fn get_admin_held_funds(&self) -> Money {
+        self.admin_held_funds
+    }
+
+    /// the available funds withdrawals can't dip into.
+    pub fn get_reserve(&self) -> Money {
+        self.reserve
+    }
+
+    pub fn get_credit_limit(&self) -> Money {
+        self.credit_limit
+    }
+
+    /// the clients file profile of the client, if it has one.
+    pub fn profile(&self) -> Option<&Profile> {
+        self.limits.profile(self.client_id)
+    }
+
+    /// what withdrawals overdrew, plus credit charges, that deposits haven't paid back yet.
+    pub fn get_credit_used(&self) -> Money {
+        self.credit_used
+    }
+
+    /// Charge `fee`, e.g. interest on [`ClientState::get_credit_used`], to the client's credit
+    /// line whatever its limit. It counts as withdrawn, so the money flows still reconcile.
+    pub fn charge_credit(&mut self, fee: Money) -> Result<(), Rejection> {
+        let fee = self.money(fee.amount());
+        let mut next = self.balances();
+        next.available = sub(next.available, fee)?;
+        next.withdrawn = add(next.withdrawn, fee)?;
+        next.credit_used = add(next.credit_used, fee)?;
+        self.commit(next)
+    }
+
+    pub fn get_total_funds(&self) -> Money {
+        // commit only applies balances whose total is representable
+        self.held_funds
+            .checked_add(self.available_funds)
+            .expect("the total funds are representable")
+    }
+
+    pub fn currency(&self) -> Option<Currency> {
+        self.currency
+    }
+
+    /// `amount` in the client's currency.
+    fn money(&self, amount: Decimal) -> Money {
+        Money::from(amount).in_currency(self.currency)
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    pub fn get_deposited(&self) -> Money {
+        self.deposited
+    }
+
+    pub fn get_withdrawn(&self) -> Money {
+        self.withdrawn
+    }
+
+    pub fn get_charged_back(&self) -> Money {
+        self.charged_back
+    }
+
+    pub fn activity(&self) -> Activity {
+        self.activity
+    }
+
+    /// transactions under dispute that haven't been resolved or charged back yet.
+    pub fn open_disputes(&self) -> usize {
+        self.disputables
+            .values()
+            .filter(|disputable| disputable.status == DisputeStatus::Disputed)
+            .count()
+    }
+
+    /// what the client's total funds should be given the money that moved in and out.
+    pub fn get_net_flows(&self) -> Money {
+        // as are the net flows
+        self.deposited
+            .checked_sub(self.withdrawn)
+            .and_then(|net| net.checked_sub(self.charged_back))
+            .expect("the net flows are representable")
+    }
+
+    /// transactions that were disputed, the only way funds move without a deposit or withdrawal.
+    pub fn disputed_transactions(&self) -> Vec<TxId> {
+        let mut disputed: Vec<TxId> = self
+            .disputables
+            .iter()
+            .filter(|(_, disputable)| disputable.status != DisputeStatus::Undisputed)
+            .map(|(tx_id, _)| *tx_id)
+            .collect();
+        disputed.sort_unstable();
+        disputed
+    }
+
+    /// the transactions charged back and not reversed (yet), by transaction id.
+    pub fn charged_back_transactions(&self) -> Vec<(TxId, Disputable)> {
+        let mut charged_back: Vec<(TxId, Disputable)> = self
+            .disputables
+            .iter()
+            .filter(|(_, disputable)| {
+                matches!(
+                    disputable.status,
+                    DisputeStatus::ChargedBack | DisputeStatus::Represented
+                )
+            })
+            .map(|(tx_id, disputable)| (*tx_id, *disputable))
+            .collect();
+        charged_back.sort_unstable_by_key(|(tx_id, _)| *tx_id);
+        charged_back
+    }
+
+    pub fn disputable(&self, tx_id: TxId) -> Option<&Disputable> {
+        self.disputables.get(&tx_id)
+    }
+
+    /// every deposit and withdrawal of the client, by transaction id, in no particular order.
+    pub fn disputables(&self) -> impl Iterator<Item = (TxId, &Disputable)> {
+        self.disputables
+            .iter()
+            .map(|(tx_id, disputable)| (*tx_id, disputable))
+    }
+
+    /// the deposit or withdrawal `record` refers to, if it's a dispute, resolve or chargeback.
+    pub fn referenced(&self, record: &Record) -> Option<Disputable> {
+        match record.transaction_type.is_reference() {
+            true => self.disputable(record.transaction_id).copied(),
+            false => None,
+        }
+    }
+
+    /// the records kept for tx_id, in the order they were applied, or None if history isn't
+    /// retained.
+    pub fn transaction_history(&self, tx_id: TxId) -> Option<Cow<'_, [SituatedRecord]>> {
+        let history = self.history.as_ref()?;
+        let kept = history.get(&tx_id).map(Vec::as_slice).unwrap_or_default();
+        if self.spilled.is_empty() {
+            return Some(Cow::Borrowed(kept));
+        }
+        let mut records: Vec<SituatedRecord> = self
+            .spilled_records()
+            .into_iter()
+            .filter(|record| record.record.transaction_id == tx_id)
+            .collect();
+        records.extend_from_slice(kept);
+        Some(Cow::Owned(records))
+    }
+
+    /// every record in the history, or None if history isn't retained.
+    fn kept_records(&self) -> Option<Vec<SituatedRecord>> {
+        let history = self.history.as_ref()?;
+        let mut records = self.spilled_records();
+        records.extend(history.values().flatten());
+        Some(records)
+    }
+
+    /// the records of the history written out to the spill, read back.
+    fn spilled_records(&self) -> Vec<SituatedRecord> {
+        let Some(spill) = &self.spill else {
+            return vec![];
+        };
+        let mut records = vec![];
+        for chunk in &self.spilled {
+            match spill.read(*chunk) {
+                Ok(chunk) => records.extend(chunk),
+                Err(e) => error!(
+                    "Unable to read back history of client ({}) from the spill file!\n{}",
+                    self.pseudonyms.client(self.client_id),
+                    e
+                ),
+            }
+        }
+        records
+    }
+
+    /// Write every record of the history out to `spill` and free them, or keep them and stop
+    /// spilling this client if they can't be written.
+    fn spill_history(&mut self, spill: &Spill) {
+        let Some(history) = &mut self.history else {
+            return;
+        };
+        let mut records: Vec<SituatedRecord> = history.drain().flat_map(|(_, kept)| kept).collect();
+        records.sort_by_key(|record| record.monotonic_counter);
+        match spill.write(&records) {
+            Ok(chunk) => {
+                spill.released(records.len());
+                self.spilled.push(chunk);
+            }
+            Err(e) => {
+                error!(
+                    "Unable to spill history of client ({}), keeping it in memory!\n{}",
+                    self.pseudonyms.client(self.client_id),
+                    e
+                );
+                for record in records {
+                    let tx_id = record.record.transaction_id;
+                    history.entry(tx_id).or_default().push(record);
+                }
+                self.spill = None;
+            }
+        }
+    }
+
+    /// The client as it was just before the record at `counter` was applied, rebuilt from its
+    /// history, or None if history isn't retained.
+    pub fn as_of(&self, counter: usize) -> Option<ClientState> {
+        let mut records: Vec<SituatedRecord> = self
+            .kept_records()?
+            .into_iter()
+            .filter(|record| record.monotonic_counter < counter)
+            .collect();
+        records.sort_by_key(|record| record.monotonic_counter);
+        let mut client = ClientState::new(self.client_id);
+        client.unlock_on_representment = self.unlock_on_representment;
+        client.fraud_lock_after = self.fraud_lock_after;
+        client.velocity = self.velocity;
+        client.reserve = self.reserve;
+        client.credit_limit = self.credit_limit;
+        client.limits = self.limits.clone();
+        client.locked_deposits = self.locked_deposits;
+        client.screening = self.screening;
+        client.pseudonyms = self.pseudonyms.clone();
+        for record in records {
+            // declined withdrawals are in the history too and are declined again, and records
+            // that were parked are applied once what they refer to is, as they were
+            match client.add_transaction(record) {
+                Err(Rejection::UnknownTransaction) => client.park(record),
+                _ => {
+                    for parked in client.unpark(record.record.transaction_id) {
+                        let _ = client.add_transaction(parked);
+                    }
+                }
+            }
+        }
+        Some(client)
+    }
+
+    /// counter of the first record in the client's history stamped later than `timestamp`, if
+    /// history is retained and it has one.
+    pub fn first_after(&self, timestamp: u64) -> Option<usize> {
+        self.kept_records()?
+            .into_iter()
+            .filter(|record| record.record.timestamp.is_some_and(|t| t > timestamp))
+            .map(|record| record.monotonic_counter)
+            .min()
+    }
+
+    /// hold on to a dispute, resolve or chargeback for a transaction the client doesn't have yet.
+    pub fn park(&mut self, situated_record: SituatedRecord) {
+        self.suspense
+            .entry(situated_record.record.transaction_id)
+            .or_default()
+            .push(situated_record);
+    }
+
+    /// the records parked for tx_id, in the order they arrived, taken out of suspense.
+    pub fn unpark(&mut self, tx_id: TxId) -> Vec<SituatedRecord> {
+        self.suspense.remove(&tx_id).unwrap_or_default()
+    }
+
+    /// the records still parked, by monotonic counter.
+    pub fn parked(&self) -> Vec<SituatedRecord> {
+        let mut parked: Vec<SituatedRecord> = self.suspense.values().flatten().copied().collect();
+        parked.sort_by_key(|record| record.monotonic_counter);
+        parked
+    }
+
+    /// stop keeping the records of this client's transactions and free those kept so far.
+    pub fn drop_history(&mut self) {
+        if let (Some(history), Some(spill)) = (self.history.take(), &self.spill) {
+            spill.released(history.values().map(Vec::len).sum());
+        }
+        self.spilled.clear();
+    }
+
+    /// return why the record was rejected, if it was. in a persistent system an applied record is
+    /// now durable. a crash safe persistent system should indicate the last record it actually
+    /// processed (see monotonic_counter) so restarts are possible.
+    pub fn add_transaction(&mut self, situated_record: SituatedRecord) -> Result<(), Rejection> {
+        let tx_id = situated_record.record.transaction_id;
+        let transact = self.transact(situated_record);
+        if is_recorded(&situated_record.record, transact.as_ref().err()) {
+            self.push_transaction(tx_id, situated_record);
+        }
+        if transact.is_ok() {
+            self.check_fraud(&situated_record.record);
+            self.check_velocity(&situated_record);
+            self.hold_deposit(situated_record);
+            self.applied
+                .update(run_hash::applied_line(&situated_record).as_bytes());
+            self.activity = Activity {
+                applied: self.activity.applied + 1,
+                last_counter: Some(situated_record.monotonic_counter),
+                last_timestamp: situated_record.record.timestamp,
+            };
+        }
+        transact
+    }
+
+    /// SHA-256 of the records applied to this client, in the order they were applied.
+    pub fn applied_digest(&self) -> [u8; 32] {
+        self.applied.clone().finish()
+    }
+
+    /// amount of the withdrawal/deposit a dispute, resolve or chargeback for tx_id refers to.
+    pub fn original_amount(&self, tx_id: TxId) -> Option<Money> {
+        self.disputable(tx_id).map(|disputable| disputable.amount)
+    }
+
+    fn transact_withdrawal_or_deposit(
+        &mut self,
+        situated_record: SituatedRecord,
+    ) -> Result<(), Rejection> {
+        let amount = self.money(situated_record.record.required_amount()?.amount());
+        let tx_type = situated_record.record.transaction_type;
+        let tx_id = situated_record.record.transaction_id;
+        let zero = self.money(Decimal::ZERO);
+        match (tx_type, self.locked) {
+            (TransactionType::Withdrawal, false) => {
+                // the part of the withdrawal available funds don't cover
+                let drawn = sub(amount, self.available_funds.max(zero))?.max(zero);
+                if amount <= self.available_funds
+                    && sub(self.available_funds, amount)? < self.reserve
+                {
+                    warn!(
+                        "Withdrawal ({}) failed to withdraw as it would leave less than the reserve ({}).",
+                        tx_id, self.reserve
+                    );
+                    Err(Rejection::BelowReserve)
+                } else if amount <= self.available_funds
+                    // a client held to a reserve can't overdraw
+                    || (self.reserve.is_zero()
+                        && add(self.credit_used, drawn)? <= self.credit_limit)
+                {
+                    let mut next = self.balances();
+                    next.available = sub(next.available, amount)?;
+                    next.withdrawn = add(next.withdrawn, amount)?;
+                    next.credit_used = add(next.credit_used, drawn)?;
+                    self.commit(next)
+                } else {
+                    warn!(
+                        "Withdrawal ({}) failed to withdraw due to insufficient funds.",
+                        tx_id
+                    );
+                    Err(Rejection::InsufficientFunds)
+                }
+            }
+            (TransactionType::Withdrawal, true) => {
+                warn!(
+                    "Withdrawal ({}) failed to process because client account ({}) is frozen.",
+                    tx_id,
+                    self.pseudonyms.client(self.client_id)
+                );
+                Err(Rejection::AccountLocked)
+            }
+            (TransactionType::Deposit, true) if self.locked_deposits == LockedDeposits::Reject => {
+                warn!(
+                    "Deposit ({}) failed to process because client account ({}) is frozen.",
+                    tx_id,
+                    self.pseudonyms.client(self.client_id)
+                );
+                Err(Rejection::AccountLocked)
+            }
+            (TransactionType::Deposit, _) => {
+                let mut next = self.balances();
+                // pays back credit first
+                next.credit_used = sub(next.credit_used, amount.min(next.credit_used))?;
+                next.available = add(next.available, amount)?;
+                next.deposited = add(next.deposited, amount)?;
+                self.commit(next)
+            }
+            (_, _) => Err(Rejection::UnknownTransaction),
+        }
+    }
+
+    fn balances(&self) -> Balances {
+        Balances {
+            available: self.available_funds,
+            held: self.held_funds,
+            admin_held: self.admin_held_funds,
+            credit_used: self.credit_used,
+            deposited: self.deposited,
+            withdrawn: self.withdrawn,
+            charged_back: self.charged_back,
+        }
+    }
+
+    /// apply `next` if the totals derived from it are representable too, so `get_total_funds` and
+    /// `get_net_flows` can't overflow later on. Balances are kept without trailing zeros, so they
+    /// compare, hash and print the same whatever scale the amounts that made them were written at.
+    fn commit(&mut self, next: Balances) -> Result<(), Rejection> {
+        add(next.available, next.held)?;
+        sub(sub(next.deposited, next.withdrawn)?, next.charged_back)?;
+        self.set_balances(next.normalized());
+        Ok(())
+    }
+
+    /// `balances` in the client's currency, as they are.
+    fn set_balances(&mut self, balances: Balances) {
+        let next = balances.in_currency(self.currency);
+        self.available_funds = next.available;
+        self.held_funds = next.held;
+        self.admin_held_funds = next.admin_held;
+        self.credit_used = next.credit_used;
+        self.deposited = next.deposited;
+        self.withdrawn = next.withdrawn;
+        self.charged_back = next.charged_back;
+    }
+
+    fn push_transaction(&mut self, tx_id: TxId, situated_record: SituatedRecord) {
+        let record = situated_record.record;
+        match record.transaction_type {
+            TransactionType::Withdrawal | TransactionType::Deposit => {
+                self.disputables.insert(
+                    tx_id,
+                    Disputable {
+                        transaction_type: record.transaction_type,
+                        // only recorded once it was found to have one
+                        amount: self
+                            .money(record.amount.unwrap_or_default().amount())
+                            .normalize(),
+                        status: DisputeStatus::Undisputed,
+                        monotonic_counter: situated_record.monotonic_counter,
+                        reason: None,
+                    },
+                );
+            }
+            TransactionType::Dispute => {
+                self.set_status(tx_id, DisputeStatus::Disputed);
+                if let Some(disputable) = self.disputables.get_mut(&tx_id) {
+                    disputable.reason = record.reason;
+                }
+            }
+            TransactionType::Resolve => self.set_status(tx_id, DisputeStatus::Resolved),
+            TransactionType::Chargeback => self.set_status(tx_id, DisputeStatus::ChargedBack),
+            TransactionType::DisputeCancel => self.set_status(tx_id, DisputeStatus::Cancelled),
+            TransactionType::Representment => self.set_status(tx_id, DisputeStatus::Represented),
+            TransactionType::RepresentmentWon => self.set_status(tx_id, DisputeStatus::Reversed),
+            // not a transaction anything can refer to, only kept in the history
+            TransactionType::AdminHold | TransactionType::AdminRelease => {}
+        }
+        if let Some(history) = &mut self.history {
+            history.entry(tx_id).or_default().push(situated_record);
+            if let Some(spill) = self.spill.clone().filter(|spill| spill.kept(1)) {
+                self.spill_history(&spill);
+            }
+        }
+    }
+
+    fn set_status(&mut self, tx_id: TxId, status: DisputeStatus) {
+        if let Some(disputable) = self.disputables.get_mut(&tx_id) {
+            disputable.status = status;
+        }
+    }
+
+    fn transact(&mut self, situated_record: SituatedRecord) -> Result<(), Rejection> {
+        let tx_id = situated_record.record.transaction_id;
+        let client_id = situated_record.record.client_id;
+        let status = self.disputable(tx_id).map(|disputable| disputable.status);
+        trace!(
+            "Type {:?}, id {}, dispute status is {:?}.",
+            situated_record.record.transaction_type,
+            tx_id,
+            status
+        );
+        match (situated_record.record.transaction_type, self.locked) {
+            (TransactionType::Withdrawal | TransactionType::Deposit, _) => {
+                // must have original withdrawal/deposit transaction ids
+                if status.is_none() {
+                    self.transact_withdrawal_or_deposit(situated_record)
+                } else {
+                    warn!("Record of type ({:?}) is re-using existent transaction id ({}), this is not allowed!)", situated_record.record.transaction_type, tx_id);
+                    Err(Rejection::DuplicateTransaction)
+                }
+            }
+            //TOD0 self.locked needs to behave differently for disputes/resolves/chargebacks
+            (TransactionType::Dispute, false) => {
+                if status == Some(DisputeStatus::Undisputed) {
+                    self.transact_dispute(situated_record)
+                } else {
+                    warn!("Dispute [transaction_id={}, client_id={}] will be ignored as it either does not exist or has already been addressed.", tx_id, self.pseudonyms.client(client_id));
+                    match status {
+                        None => Err(Rejection::UnknownTransaction),
+                        _ => Err(Rejection::AlreadyDisputed),
+                    }
+                }
+            }
+            (
+                TransactionType::Resolve
+                | TransactionType::Chargeback
+                | TransactionType::DisputeCancel,
+                false,
+            ) => {
+                if status == Some(DisputeStatus::Disputed) {
+                    self.transaction_resolution(situated_record)
+                } else {
+                    warn!("Resolution/Chargeback for transaction ({}) will be ignored as it has already been addressed.", tx_id);
+                    match status {
+                        None => Err(Rejection::UnknownTransaction),
+                        Some(DisputeStatus::Undisputed) => Err(Rejection::NotDisputed),
+                        _ => Err(Rejection::AlreadySettled),
+                    }
+                }
+            }
+            // compliance holds apply to frozen accounts all the same
+            (TransactionType::AdminHold | TransactionType::AdminRelease, _) => {
+                self.transact_admin(situated_record)
+            }
+            // a frozen account can still contest the chargebacks that froze it
+            (TransactionType::Representment | TransactionType::RepresentmentWon, _) => {
+                let expected = match situated_record.record.transaction_type {
+                    TransactionType::Representment => DisputeStatus::ChargedBack,
+                    _ => DisputeStatus::Represented,
+                };
+                if status == Some(expected) {
+                    self.transact_representment(situated_record)
+                } else {
+                    warn!("Representment for transaction ({}) will be ignored as it isn't charged back, or represented for a won one.", tx_id);
+                    match status {
+                        None => Err(Rejection::UnknownTransaction),
+                        _ => Err(Rejection::NotChargedBack),
+                    }
+                }
+            }
+            (
+                TransactionType::Resolve
+                | TransactionType::Chargeback
+                | TransactionType::Dispute
+                | TransactionType::DisputeCancel,
+                true,
+            ) => {
+                warn!(
+                    "Resolution/Chargeback/Dispute  ({}) failed to process because client account ({}) is frozen.",
+                    tx_id, self.pseudonyms.client(client_id));
+                Err(Rejection::AccountLocked)
+            }
+        }
+    }
+
+    fn transact_dispute(&mut self, dispute: SituatedRecord) -> Result<(), Rejection> {
+        let tx_id = dispute.record.transaction_id;
+        if let Some(disputed) = self.disputable(tx_id).copied() {
+            let mut next = self.balances();
+            match disputed.transaction_type {
+                TransactionType::Withdrawal => {
+                    next.held = add(next.held, disputed.amount)?;
+                }
+                TransactionType::Deposit => {
+                    next.available = sub(next.available, disputed.amount)?;
+                    next.held = add(next.held, disputed.amount)?;
+                }
+                _ => {}
+            }
+            self.commit(next)
+        } else {
+            error!("Internal state of records for transaction id ({}) is incorrect, it has no disputable transaction.", tx_id);
+            Err(Rejection::UnknownTransaction)
+        }
+    }
+
+    fn transact_admin(&mut self, situated_record: SituatedRecord) -> Result<(), Rejection> {
+        let amount = self.money(situated_record.record.required_amount()?.amount());
+        let tx_id = situated_record.record.transaction_id;
+        let mut next = self.balances();
+        match situated_record.record.transaction_type {
+            TransactionType::AdminHold if amount > self.available_funds => {
+                warn!(
+                    "Admin hold ({}) failed to hold due to insufficient funds.",
+                    tx_id
+                );
+                return Err(Rejection::InsufficientFunds);
+            }
+            TransactionType::AdminHold => {
+                next.available = sub(next.available, amount)?;
+                next.held = add(next.held, amount)?;
+                next.admin_held = add(next.admin_held, amount)?;
+            }
+            _ if amount > self.admin_held_funds => {
+                warn!(
+                    "Admin release ({}) is more than the {} on admin hold.",
+                    tx_id, self.admin_held_funds
+                );
+                return Err(Rejection::ExceedsAdminHold);
+            }
+            _ => {
+                next.held = sub(next.held, amount)?;
+                next.admin_held = sub(next.admin_held, amount)?;
+                next.available = add(next.available, amount)?;
+            }
+        }
+        self.commit(next)
+    }
+
+    /// put a deposit of a client --screen blocks, or of a frozen account with --locked-deposits
+    /// hold, on admin hold as soon as it's applied, as much of it as is available, until compliance
+    /// releases it with an admin release.
+    fn hold_deposit(&mut self, situated_record: SituatedRecord) {
+        let record = situated_record.record;
+        if !matches!(record.transaction_type, TransactionType::Deposit) {
+            return;
+        }
+        let why = if self.screening == Some(Action::Block) {
+            "screening blocks"
+        } else if self.locked && self.locked_deposits == LockedDeposits::Hold {
+            "is frozen"
+        } else {
+            return;
+        };
+        let amount = self
+            .money(record.amount.unwrap_or_default().amount())
+            .min(self.available_funds);
+        if amount.amount() <= Decimal::ZERO {
+            return;
+        }
+        info!(
+            "Holding {} of deposit ({}) of client ({}), which {}.",
+            amount,
+            record.transaction_id,
+            self.pseudonyms.client(self.client_id),
+            why
+        );
+        let hold = SituatedRecord {
+            record: Record {
+                transaction_type: TransactionType::AdminHold,
+                amount: Some(amount),
+                ..record
+            },
+            ..situated_record
+        };
+        if let Err(rejection) = self.transact_admin(hold) {
+            error!(
+                "Unable to hold deposit ({}) of client ({}): {}.",
+                record.transaction_id,
+                self.pseudonyms.client(self.client_id),
+                rejection
+            );
+        }
+    }
+
+    /// freeze the account once a fraud dispute brings it to the --fraud-lock-after threshold,
+    /// disputes for other reasons (or none) don't count.
+    fn check_fraud(&mut self, record: &Record) {
+        let threshold = match (
+            record.transaction_type,
+            record.reason,
+            self.fraud_lock_after,
+        ) {
+            (TransactionType::Dispute, Some(DisputeReason::Fraud), Some(threshold)) => threshold,
+            _ => return,
+        };
+        let fraud = self
+            .disputables
+            .values()
+            .filter(|disputable| disputable.reason == Some(DisputeReason::Fraud))
+            .count();
+        if fraud >= threshold && !self.locked {
+            warn!(
+                "Freezing client account ({}) after {} fraud disputes.",
+                self.pseudonyms.client(self.client_id),
+                fraud
+            );
+            self.locked = true;
+        }
+    }
+
+    /// lock the account, or warn about it, once its disputes and chargebacks within the window of
+    /// its --velocity reach the limit. Its tier or clients file row sets one before the run does.
+    fn check_velocity(&mut self, situated_record: &SituatedRecord) {
+        let record = situated_record.record;
+        if !matches!(
+            record.transaction_type,
+            TransactionType::Dispute | TransactionType::Chargeback
+        ) {
+            return;
+        }
+        let Some(velocity) = self.limits.velocity(self.client_id).or(self.velocity) else {
+            return;
+        };
+        let recent = self.recent.observe(
+            velocity.window,
+            situated_record.monotonic_counter,
+            record.timestamp,
+        );
+        if recent < velocity.limit {
+            return;
+        }
+        match velocity.action {
+            velocity::Action::Lock if !self.locked => {
+                warn!(
+                    "Freezing client account ({}) after {} disputes and chargebacks within {}.",
+                    self.pseudonyms.client(self.client_id),
+                    recent,
+                    velocity.window
+                );
+                self.locked = true;
+            }
+            velocity::Action::Lock => {}
+            velocity::Action::Flag => warn!(
+                "Client ({}) had {} disputes and chargebacks within {}, record ({}) for transaction ({}) included.",
+                self.pseudonyms.client(self.client_id),
+                recent,
+                velocity.window,
+                situated_record.monotonic_counter,
+                record.transaction_id
+            ),
+        }
+    }
+
+    /// a representment is only a record of the chargeback being contested, winning it gives the
+    /// client back what the chargeback took.
+    fn transact_representment(&mut self, representment: SituatedRecord) -> Result<(), Rejection> {
+        let tx_id = representment.record.transaction_id;
+        let charged_back = match self.disputable(tx_id).copied() {
+            Some(charged_back) => charged_back,
+            None => return Err(Rejection::UnknownTransaction),
+        };
+        if let TransactionType::Representment = representment.record.transaction_type {
+            return Ok(());
+        }
+        let mut next = self.balances();
+        next.available = add(next.available, charged_back.amount)?;
+        next.charged_back = sub(next.charged_back, charged_back.amount)?;
+        self.commit(next)?;
+        let standing = self
+            .charged_back_transactions()
+            .iter()
+            .any(|(charged_back, _)| *charged_back != tx_id);
+        if self.unlock_on_representment && !standing {
+            info!(
+                "Unfreezing client account ({}) as its last chargeback ({}) was reversed.",
+                self.pseudonyms.client(self.client_id),
+                tx_id
+            );
+            self.locked = false;
+        }
+        Ok(())
+    }
+
+    fn transaction_resolution(&mut self, resolution: SituatedRecord) -> Result<(), Rejection> {
+        let tx_id = resolution.record.transaction_id;
+        if let Some(disputed) = self.disputable(tx_id).copied() {
+            match resolution.record.transaction_type {
+                // a cancelled dispute releases the funds the same way, only its record differs
+                TransactionType::Resolve | TransactionType::DisputeCancel => {
+                    self.transact_resolve(disputed.transaction_type, disputed.amount)
+                }
+                TransactionType::Chargeback => {
+                    self.transact_chargeback(disputed.transaction_type, disputed.amount)
+                }
+                _ => Err(Rejection::UnknownTransaction),
+            }
+        } else {
+            error!("Internal state of records for transaction id ({}) is incorrect, it has no disputable transaction.", tx_id);
+            Err(Rejection::UnknownTransaction)
+        }
+    }
+
+    fn transact_resolve(
+        &mut self,
+        prev_type: TransactionType,
+        tx_amount: Money,
+    ) -> Result<(), Rejection> {
+        match prev_type {
+            TransactionType::Withdrawal | TransactionType::Deposit => {
+                let mut next = self.balances();
+                next.held = sub(next.held, tx_amount)?;
+                next.available = add(next.available, tx_amount)?;
+                self.commit(next)
+            }
+            _ => Err(Rejection::UnknownTransaction),
+        }
+    }
+
+    fn transact_chargeback(
+        &mut self,
+        prev_type: TransactionType,
+        tx_amount: Money,
+    ) -> Result<(), Rejection> {
+        match prev_type {
+            TransactionType::Withdrawal | TransactionType::Deposit => {
+                let mut next = self.balances();
+                next.held = sub(next.held, tx_amount)?;
+                next.charged_back = add(next.charged_back, tx_amount)?;
+                self.commit(next)?;
+                self.locked = true;
+                Ok(())
+            }
+            _ => Err(Rejection::UnknownTransaction),
+        }
+    }
+}
+
+/// Whether a record that was processed, with `rejection` if it was turned down, is kept in its
+/// client's history. A declined withdrawal still claims its transaction id, see README.
+pub fn is_recorded(record: &Record, rejection: Option<&Rejection>) -> bool {
+    match rejection {
+        None => true,
+        Some(Rejection::InsufficientFunds | Rejection::AccountLocked) => {
+            matches!(record.transaction_type, TransactionType::Withdrawal)
+        }
+        Some(_) => false,
+    }
+}
+
+/// Balances are updated with checked arithmetic, computing every new value before assigning any,
+/// so a record that would overflow `Decimal` is rejected and leaves the client untouched.
+#[derive(Debug, Copy, Clone)]
+struct Balances {
+    available: Money,
+    held: Money,
+    admin_held: Money,
+    credit_used: Money,
+    deposited: Money,
+    withdrawn: Money,
+    charged_back: Money,
+}
+
+impl Balances {
+    fn normalized(self) -> Self {
+        Balances {
+            available: self.available.normalize(),
+            held: self.held.normalize(),
+            admin_held: self.admin_held.normalize(),
+            credit_used: self.credit_used.normalize(),
+            deposited: self.deposited.normalize(),
+            withdrawn: self.withdrawn.normalize(),
+            charged_back: self.charged_back.normalize(),
+        }
+    }
+
+    fn in_currency(self, currency: Option<Currency>) -> Self {
+        Balances {
+            available: self.available.in_currency(currency),
+            held: self.held.in_currency(currency),
+            admin_held: self.admin_held.in_currency(currency),
+            credit_used: self.credit_used.in_currency(currency),
+            deposited: self.deposited.in_currency(currency),
+            withdrawn: self.withdrawn.in_currency(currency),
+            charged_back: self.charged_back.in_currency(currency),
+        }
+    }
+}
+
+/// an inexact result counts as an overflow too, see [`Money::checked_add`].
+fn checked(result: Option<Money>) -> Result<Money, Rejection> {
+    result.ok_or_else(|| {
+        warn!("Balance update would overflow or lose precision, rejecting the record.");
+        Rejection::Overflow
+    })
+}
+
+fn add(a: Money, b: Money) -> Result<Money, Rejection> {
+    checked(a.checked_add(b))
+}
+
+fn sub(a: Money, b: Money) -> Result<Money, Rejection> {
+    checked(a.checked_sub(b))
+}
+
+/// Apply a record to its client and return the events it raised, or why it was turned down.
+pub fn process_record(
+    situated_record: SituatedRecord,
+    clients: &mut HashMap<ClientId, ClientState>,
+) -> Result<Vec<Event>, Rejection> {
+    process_record_with(situated_record, clients, &PipelineConfig::default())
+}
+
+/// [`process_record`], with clients it creates configured by `config`, see
+/// [`ClientState::configured`].
+pub fn process_record_with(
+    situated_record: SituatedRecord,
+    clients: &mut HashMap<ClientId, ClientState>,
+    config: &PipelineConfig,
+) -> Result<Vec<Event>, Rejection> {
+    let client_id = situated_record.record.client_id;
+    let client_state = clients
+        .entry(client_id)
+        .or_insert_with(|| ClientState::configured(client_id, config));
+    let was_locked = client_state.is_locked();
+    client_state.add_transaction(situated_record)?;
+    Ok(events::events_for(
+        &situated_record,
+        client_state,
+        was_locked,
+    ))
+}
+
+/// Apply a record that passed [`check_bounds`] to its client and publish the outcome to `sinks`.
+/// With `config.suspense`, a dispute, resolve or chargeback for a transaction the client doesn't
+/// have yet is parked instead of rejected, then applied and published right after that
+/// transaction.
+pub fn apply_and_publish(
+    situated_record: SituatedRecord,
+    clients: &mut HashMap<ClientId, ClientState>,
+    config: &PipelineConfig,
+    sinks: &Sinks,
+) {
+    let record = situated_record.record;
+    let prior = sinks.before(&situated_record, clients);
+    let started = profile::start(config.profiler.as_deref());
+    let processed = process_record_with(situated_record, clients, config);
+    profile::finish(
+        config.profiler.as_deref(),
+        started,
+        Stage::Apply,
+        record.transaction_type.as_str(),
+        situated_record.monotonic_counter,
+    );
+    let moves_money = matches!(
+        record.transaction_type,
+        TransactionType::Deposit | TransactionType::Withdrawal
+    );
+    let client = match clients.get_mut(&record.client_id) {
+        Some(client) => client,
+        None => return sinks.publish(&situated_record, processed, None, config),
+    };
+    if config.suspense
+        && record.transaction_type.is_reference()
+        && matches!(processed, Err(Rejection::UnknownTransaction))
+    {
+        info!(
+            "Parking record ({}) until transaction ({}) arrives.",
+            situated_record.monotonic_counter, record.transaction_id
+        );
+        return client.park(situated_record);
+    }
+    let reference = client.referenced(&record);
+    let applied = processed.is_ok();
+    sinks.publish(&situated_record, processed, reference, config);
+    sinks.changed(&situated_record, prior, Some(client));
+    if applied && moves_money {
+        for parked in client.unpark(record.transaction_id) {
+            info!(
+                "Applying record ({}) parked for transaction ({}), which arrived as record ({}).",
+                parked.monotonic_counter, record.transaction_id, situated_record.monotonic_counter
+            );
+            apply_and_publish(parked, clients, config, sinks);
+        }
+    }
+}
+
+/// Park a record with its client, creating the client if it's new, see
+/// [`foreign::ForeignPolicy::Suspense`].
+pub fn park(
+    situated_record: SituatedRecord,
+    clients: &mut HashMap<ClientId, ClientState>,
+    config: &PipelineConfig,
+) {
+    let client_id = situated_record.record.client_id;
+    clients
+        .entry(client_id)
+        .or_insert_with(|| ClientState::configured(client_id, config))
+        .park(situated_record);
+}
+
+/// Where the engine stage sends what it produces besides client state. Each is optional and
+/// cloned into every shard.
+#[derive(Debug, Clone, Default)]
+pub struct Sinks {
+    pub events: Option<Sender<Event>>,
+    pub dead_letters: Option<Sender<DeadLetter>>,
+    pub journal: Option<Sender<journal::Entry>>,
+    pub history: Option<Sender<checkpoint::Entry>>,
+    pub changes: Option<Sender<Change>>,
+    /// applied deposits and withdrawals, see [`anomaly::Anomalies`]
+    pub anomalies: Option<Sender<SituatedRecord>>,
+    /// counts of what became of every record, see [`manifest::Manifest`]
+    pub tally: Option<Arc<manifest::Tally>>,
+    /// the records of clients on the screening list, see [`screening::Compliance`]
+    pub compliance: Option<Sender<screening::Entry>>,
+}
+
+impl Sinks {
+    /// `situated_record`'s client before the record is applied, for [`Sinks::changed`], if
+    /// anyone is listening for changes.
+    pub fn before(
+        &self,
+        situated_record: &SituatedRecord,
+        clients: &HashMap<ClientId, ClientState>,
+    ) -> Option<Snapshot> {
+        self.changes
+            .as_ref()
+            .map(|_| Snapshot::of(clients.get(&situated_record.record.client_id)))
+    }
+
+    /// send the change applying `situated_record` made to `client`, which was at `prior` before.
+    pub fn changed(
+        &self,
+        situated_record: &SituatedRecord,
+        prior: Option<Snapshot>,
+        client: Option<&ClientState>,
+    ) {
+        if let (Some(sink), Some(prior)) = (&self.changes, prior) {
+            if let Some(change) = Change::of(situated_record, prior, Snapshot::of(client)) {
+                let _ = sink.send(change);
+            }
+        }
+    }
+
+    /// `reference` is the transaction the record refers to, see [`ClientState::referenced`].
+    /// `config` is that of the run, which says how to write the record's client.
+    pub fn publish(
+        &self,
+        situated_record: &SituatedRecord,
+        processed: Result<Vec<Event>, Rejection>,
+        reference: Option<Disputable>,
+        config: &PipelineConfig,
+    ) {
+        // the receiving ends only go away if their thread panicked, which finish surfaces
+        if let Some(sink) = &self.history {
+            if is_recorded(&situated_record.record, processed.as_ref().err()) {
+                let _ = sink.send(checkpoint::Entry::Recorded(*situated_record));
+            }
+        }
+        if let Some(sink) = &self.compliance {
+            if let Some(action) = screening::action(
+                config.screening.as_deref(),
+                situated_record.record.client_id,
+            ) {
+                let _ = sink.send(screening::Entry {
+                    situated_record: *situated_record,
+                    action,
+                    outcome: screening::Outcome::of(
+                        situated_record,
+                        action,
+                        processed.as_ref().err(),
+                    ),
+                });
+            }
+        }
+        if let Some(tally) = &self.tally {
+            match processed {
+                Ok(_) => tally.applied(),
+                Err(_) => tally.rejected(),
+            }
+        }
+        match processed {
+            Ok(events) => {
+                if let Some(sink) = &self.journal {
+                    let _ = sink.send((*situated_record, reference));
+                }
+                if let Some(sink) = &self.anomalies {
+                    if matches!(
+                        situated_record.record.transaction_type,
+                        TransactionType::Deposit | TransactionType::Withdrawal
+                    ) {
+                        let _ = sink.send(*situated_record);
+                    }
+                }
+                if let Some(sink) = &self.events {
+                    for event in events {
+                        let _ = sink.send(event);
+                    }
+                }
+            }
+            Err(rejection) => {
+                warn!(
+                    target: console::REJECTED,
+                    "{}",
+                    console::rejected(situated_record, &rejection, &config.pseudonyms)
+                );
+                if let Some(sink) = &self.dead_letters {
+                    let _ = sink.send(DeadLetter::rejected(
+                        situated_record,
+                        rejection,
+                        reference.as_ref(),
+                        &config.pseudonyms,
+                        &config.messages,
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Dead letter a row that couldn't be parsed, or fail the run if there's nowhere to put it.
+    /// I/O errors always fail the run since the rest of the input can't be trusted.
+    pub fn reject_unparsable(&self, failure: ParseFailure) -> Result<(), ParseError> {
+        match &self.dead_letters {
+            Some(sink) if !failure.error.is_io_error() => {
+                warn!(
+                    "Dead lettering unparsable record ({}).\n{}",
+                    failure.monotonic_counter, failure.error
+                );
+                warn!(target: console::REJECTED, "{}", console::unparsable(&failure));
+                let _ = sink.send(DeadLetter::unparsable(&failure));
+                if let Some(tally) = &self.tally {
+                    tally.unparsable();
+                }
+                Ok(())
+            }
+            _ => Err(failure.into()),
+        }
+    }
+}
+
+/// Sanity bounds on parsed values, checked before a record reaches the engine so an obviously
+/// corrupt row is turned down instead of distorting balances.
+pub fn check_bounds(
+    situated_record: &SituatedRecord,
+    pipeline_config: &PipelineConfig,
+) -> Result<(), Rejection> {
+    let record = situated_record.record;
+    if pipeline_config.disallowed.contains(record.transaction_type) {
+        warn!(
+            "Record ({}) for transaction ({}) is a {}, which this run turns down.",
+            situated_record.monotonic_counter,
+            record.transaction_id,
+            record.transaction_type.as_str()
+        );
+        return Err(Rejection::TypeDisabled);
+    }
+    if let Some(rule) = pipeline_config
+        .enricher
+        .as_ref()
+        .and_then(|enricher| enricher.denied(&record))
+    {
+        warn!(
+            "Record ({}) for transaction ({}) has {} ({}), which this run turns down.",
+            situated_record.monotonic_counter,
+            record.transaction_id,
+            rule.field.as_str(),
+            rule.value
+        );
+        return Err(Rejection::Denied);
+    }
+    if matches!(record.transaction_type, TransactionType::Withdrawal)
+        && screening::action(pipeline_config.screening.as_deref(), record.client_id)
+            == Some(Action::Block)
+    {
+        warn!(
+            "Record ({}) for transaction ({}) is a withdrawal of client ({}), which screening blocks.",
+            situated_record.monotonic_counter,
+            record.transaction_id,
+            pipeline_config.pseudonyms.client(record.client_id)
+        );
+        return Err(Rejection::Screened);
+    }
+    // the amounts of references are ignored, the referenced transaction's is used
+    let moves_money = !record.transaction_type.is_reference();
+    let max_amount = pipeline_config
+        .limits
+        .max_amount(record.client_id)
+        .or(pipeline_config.max_amount);
+    match (max_amount, record.amount) {
+        (Some(max_amount), Some(amount)) if moves_money && amount.amount().abs() > max_amount => {
+            warn!(
+                "Record ({}) for transaction ({}) has amount {} beyond the maximum of {}.",
+                situated_record.monotonic_counter, record.transaction_id, amount, max_amount
+            );
+            Err(Rejection::AmountOutOfBounds)
+        }
+        _ => Ok(()),
+    }
+}
+
+pub fn get_reader(path: &Path) -> Result<Reader<File>, csv::Error> {
+    let reader = ReaderBuilder::new().trim(Trim::All).from_path(path);
+    reader
+}
+
+/// lenient readers let rows have any number of fields, so stray separators and blank rows reach
+/// `lenient`.
+pub fn get_source_reader(source: Source, lenient: bool) -> Reader<Source> {
+    ReaderBuilder::new()
+        .trim(Trim::All)
+        .flexible(lenient)
+        .from_reader(source)
+}
+
+pub fn play_with_money(
+    input: Option<&OsStr>,
+    pipeline_config: &PipelineConfig,
+    columns: &ColumnMap,
+    sinks: &Sinks,
+    mut checkpoints: Option<&mut Checkpoints>,
+    clients: &mut HashMap<ClientId, ClientState>,
+) -> io::Result<()> {
+    if checkpoints.is_some() && pipeline_config.reorder_window > 0 {
+        // reordered rows aren't applied in counter order, so there's no single row to resume from
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Checkpoints can't be taken with a reorder window.",
+        ));
+    }
+    let parks =
+        pipeline_config.suspense || pipeline_config.foreign_disputes == ForeignPolicy::Suspense;
+    if checkpoints.is_some() && parks {
+        // nor are parked ones, which checkpoints don't keep either
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Checkpoints can't be taken with a suspense store.",
+        ));
+    }
+    if !pipeline_config.retain_history {
+        // clients restored from a checkpoint come back with their history
+        clients.values_mut().for_each(ClientState::drop_history);
+    }
+    for client in clients.values_mut() {
+        // nor is whether they unfreeze kept in the checkpoint
+        client.configure(pipeline_config);
+    }
+    // the input is read once, into a copy both the check and the run read, so a remote or piped
+    // input can't change between them
+    let spooled = match pipeline_config.trailer {
+        true => Some(Spooled::create(source::open(input)?, &env::temp_dir())?),
+        false => None,
+    };
+    let input = spooled
+        .as_ref()
+        .map_or(input, |spooled| Some(spooled.path.as_os_str()));
+    let trailer_row = match &spooled {
+        Some(_) => {
+            // a pass of its own, so a batch that doesn't add up is turned down before any of it
+            // applies
+            let mut batch = get_source_reader(source::open(input)?, pipeline_config.lenient);
+            schema::prepare(&mut batch, columns)?;
+            Some(trailer::verify(&mut batch, pipeline_config.lenient)?)
+        }
+        None => None,
+    };
+    let mut reader = get_source_reader(source::open(input)?, pipeline_config.lenient);
+    schema::prepare(&mut reader, columns)?;
+    // the row after the last one read, where an interrupted run carries on from
+    let next_row = Cell::new(pipeline_config.resume_from);
+    let read = |monotonic_counter: usize| next_row.set(next_row.get().max(monotonic_counter + 1));
+    let mut owners = Owners::of(clients);
+    let mut dispatch = |situated_record: SituatedRecord| {
+        let situated_record =
+            id_map::canonical(situated_record, pipeline_config.id_map.as_deref())?;
+        check_bounds(&situated_record, pipeline_config)
+            .map(|_| owners.dispatch(situated_record, pipeline_config.foreign_disputes))
+    };
+    let mut checkpoint =
+        |resume_from: usize, last: bool, save: &dyn Fn() -> io::Result<Vec<Saved>>| {
+            if let Some(checkpoints) = checkpoints
+                .as_mut()
+                .filter(|c| last || c.is_due(resume_from))
+            {
+                if let Err(e) = save().and_then(|saved| checkpoints.save(resume_from, &saved)) {
+                    error!(
+                        "Unable to checkpoint before row ({}), carrying on without.\n{}",
+                        resume_from, e
+                    );
+                }
+            }
+        };
+    if pipeline_config.workers > 1 {
+        let shards = Shards::spawn(
+            pipeline_config.clone(),
+            sinks.clone(),
+            std::mem::take(clients),
+        );
+        let streamed = pipeline::run(
+            reader,
+            pipeline_config,
+            |situated_record| {
+                read(situated_record.monotonic_counter);
+                checkpoint(situated_record.monotonic_counter, false, &|| shards.save());
+                match dispatch(situated_record) {
+                    Ok(Dispatch::Apply(situated_record)) => shards.apply(situated_record),
+                    Ok(Dispatch::Park(situated_record)) => shards.park(situated_record),
+                    Ok(Dispatch::Reject(rejection)) | Err(rejection) => {
+                        sinks.publish(&situated_record, Err(rejection), None, pipeline_config)
+                    }
+                }
+            },
+            |failure| {
+                read(failure.monotonic_counter);
+                if trailer_row == Some(failure.monotonic_counter) {
+                    return Ok(());
+                }
+                sinks.reject_unparsable(failure)
+            },
+        );
+        if streamed.is_ok() && (pipeline_config.interrupted)() {
+            warn!("Interrupted, stopped before row ({}).", next_row.get());
+            checkpoint(next_row.get(), true, &|| shards.save());
+        }
+        clients.extend(shards.join());
+        streamed?;
+    } else {
+        let streamed = pipeline::run(
+            reader,
+            pipeline_config,
+            |situated_record| {
+                read(situated_record.monotonic_counter);
+                checkpoint(situated_record.monotonic_counter, false, &|| {
+                    Ok(clients.values().map(ClientState::save).collect())
+                });
+                match dispatch(situated_record) {
+                    Ok(Dispatch::Apply(situated_record)) => {
+                        apply_and_publish(situated_record, clients, pipeline_config, sinks)
+                    }
+                    Ok(Dispatch::Park(situated_record)) => {
+                        park(situated_record, clients, pipeline_config)
+                    }
+                    Ok(Dispatch::Reject(rejection)) | Err(rejection) => {
+                        sinks.publish(&situated_record, Err(rejection), None, pipeline_config)
+                    }
+                }
+            },
+            |failure| {
+                read(failure.monotonic_counter);
+                if trailer_row == Some(failure.monotonic_counter) {
+                    return Ok(());
+                }
+                sinks.reject_unparsable(failure)
+            },
+        );
+        if streamed.is_ok() && (pipeline_config.interrupted)() {
+            warn!("Interrupted, stopped before row ({}).", next_row.get());
+            checkpoint(next_row.get(), true, &|| {
+                Ok(clients.values().map(ClientState::save).collect())
+            });
+        }
+        streamed?;
+    }
+    Ok(())
+}
+
+/// Written after the standard columns with --extended-output, see [`OutputColumns`].
+pub const EXTENDED_COLUMNS: [&str; 12] = [
+    "admin_held",
+    "reserve",
+    "credit_limit",
+    "credit_used",
+    "tier",
+    "currency",
+    "transactions",
+    "open_disputes",
+    "deposited",
+    "withdrawn",
+    "last_counter",
+    "last_timestamp",
+];
+
+pub fn write_client_state(
+    clients: &HashMap<ClientId, ClientState>,
+    columns: &OutputColumns,
+    amounts: AmountFormat,
+    pseudonyms: &Pseudonyms,
+) -> Result<(), csv::Error> {
+    write_client_state_to(io::stdout(), clients, columns, amounts, pseudonyms)
+}
+
+pub fn write_client_state_to<W: io::Write>(
+    writer: W,
+    clients: &HashMap<ClientId, ClientState>,
+    columns: &OutputColumns,
+    amounts: AmountFormat,
+    pseudonyms: &Pseudonyms,
+) -> Result<(), csv::Error> {
+    write_clients_to(writer, clients.values(), columns, amounts, pseudonyms)
+}
+
+/// the balances output of `clients`, in the order given, with amounts and client ids written as
+/// `amounts` and `pseudonyms` say.
+pub fn write_clients_to<'a, W: io::Write, I: IntoIterator<Item = &'a ClientState>>(
+    writer: W,
+    clients: I,
+    columns: &OutputColumns,
+    amounts: AmountFormat,
+    pseudonyms: &Pseudonyms,
+) -> Result<(), csv::Error> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    wtr.write_record(columns.names())?;
+    for client in clients {
+        let mut row = vec![
+            pseudonyms.client(client.client_id),
+            amounts.format(client.get_available_funds()),
+            amounts.format(client.get_held_funds()),
+            amounts.format(client.get_total_funds()),
+            format!("{}", client.is_locked()),
+        ];
+        if columns.is_extended() {
+            let activity = client.activity();
+            let profile = client.profile();
+            row.extend([
+                amounts.format(client.get_admin_held_funds()),
+                amounts.format(client.get_reserve()),
+                amounts.format(client.get_credit_limit()),
+                amounts.format(client.get_credit_used()),
+                profile
+                    .and_then(|profile| profile.tier.clone())
+                    .unwrap_or_default(),
+                client
+                    .currency()
+                    .map(|currency| currency.to_string())
+                    .unwrap_or_default(),
+                activity.applied.to_string(),
+                client.open_disputes().to_string(),
+                amounts.format(client.get_deposited()),
+                amounts.format(client.get_withdrawn()),
+                activity
+                    .last_counter
+                    .map(|counter| counter.to_string())
+                    .unwrap_or_default(),
+                activity
+                    .last_timestamp
+                    .map(|timestamp| timestamp.to_string())
+                    .unwrap_or_default(),
+            ]);
+        }
+        wtr.write_record(columns.select(&row))?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// a record of client 1 with its counter as transaction id, which tests build on.
+#[cfg(test)]
+pub(crate) fn situated(
+    monotonic_counter: usize,
+    transaction_type: TransactionType,
+    amount: Money,
+) -> SituatedRecord {
+    SituatedRecord {
+        monotonic_counter,
+        record: Record {
+            transaction_type,
+            client_id: ClientId(1),
+            transaction_id: TxId(monotonic_counter as u32),
+            amount: Some(amount),
+            timestamp: None,
+            reason: None,
+        },
+    }
+}
+
+#[cfg(test)]
+impl SituatedRecord {
+    /// the same record of `client_id` for `transaction_id`.
+    pub(crate) fn of(mut self, client_id: ClientId, transaction_id: TxId) -> Self {
+        self.record.client_id = client_id;
+        self.record.transaction_id = transaction_id;
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::money::Money;
+    use events::EventKind;
+    use std::path::PathBuf;
+
+    /// Return the repo root directory path.
+    fn repo_dir() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+    }
+
+    /// Return the directory containing the example data.
+    fn data_dir() -> PathBuf {
+        repo_dir().join("examples").join("data")
+    }
+
+    fn read_into_memory(reader: Reader<File>) -> io::Result<Vec<SituatedRecord>> {
+        let mut all_records = vec![];
+        for (monotonic_counter, record) in reader.into_deserialize().enumerate() {
+            let record = record?;
+            all_records.push(SituatedRecord {
+                monotonic_counter,
+                record,
+            });
+        }
+        Ok(all_records)
+    }
+
+    fn read_records_into_memory(path: &Path) -> io::Result<Vec<SituatedRecord>> {
+        let reader = get_reader(path)?;
+        read_into_memory(reader)
+    }
+
+    #[test]
+    fn test_reader() {
+        let p = data_dir().join("sample.csv");
+        let valid_input = validate_input(Some(p.as_os_str()));
+        assert!(valid_input.is_ok());
+        assert!(valid_input.unwrap().exists());
+        let invalid_input1 = validate_input(None);
+        assert!(invalid_input1.is_err());
+        let invalid_input2 = validate_input(Some("this is nota filepath at all!".as_ref()));
+        assert!(invalid_input2.is_err());
+    }
+
+    #[test]
+    fn test_read_in_records_whitespace() {
+        let p = data_dir().join("whitespace-sample.csv");
+        let vec = read_records_into_memory(&p).unwrap();
+        assert_eq!(5, vec.len());
+        let mut test_amounts = Decimal::ZERO;
+        for x in vec {
+            test_amounts += x.record.amount.unwrap_or_default().amount();
+        }
+        assert_eq!(Decimal::new(96214, 4), test_amounts);
+    }
+
+    #[test]
+    fn test_sample_csv() {
+        let p = data_dir().join("sample.csv");
+        let mut clients = HashMap::new();
+        play_with_money(
+            Some(p.as_os_str()),
+            &PipelineConfig::default(),
+            &ColumnMap::default(),
+            &Sinks::default(),
+            None,
+            &mut clients,
+        )
+        .unwrap();
+        for client_id in clients.keys() {
+            let state = clients.get(client_id).unwrap();
+            match client_id {
+                ClientId(1) => {
+                    assert_eq!(Money::new(14848, 4), state.available_funds);
+                    assert_eq!(Money::ZERO, state.held_funds);
+                    assert!(!state.locked);
+                }
+                ClientId(2) => {
+                    assert_eq!(Money::new(80290, 4), state.available_funds);
+                    assert_eq!(Money::ZERO, state.held_funds);
+                    assert!(!state.locked);
+                }
+                ClientId(3) => {
+                    assert_eq!(Money::new(1000, 1), state.available_funds);
+                    assert_eq!(Money::ZERO, state.held_funds);
+                    assert!(state.locked);
+                }
+                ClientId(4) => {
+                    assert_eq!(Money::ZERO, state.available_funds);
+                    assert_eq!(Money::new(-100, 0), state.held_funds);
+                    assert!(!state.locked);
+                }
+                ClientId(5) => {
+                    assert_eq!(Money::new(10000, 2), state.available_funds);
+                    assert_eq!(Money::ZERO, state.held_funds);
+                    assert!(!state.locked);
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    /// Rows of an output CSV in a form that can be compared: client order, padding, case and
+    /// trailing zeros (`1.50` vs `1.5`) don't matter. The header stays first.
+    fn normalize_output(csv: &[u8]) -> Vec<String> {
+        let mut reader = ReaderBuilder::new()
+            .has_headers(false)
+            .trim(Trim::All)
+            .from_reader(csv);
+        let mut rows: Vec<String> = reader
+            .records()
+            .map(|row| {
+                row.unwrap()
+                    .iter()
+                    .map(|field| match field.parse::<Decimal>() {
+                        Ok(amount) => amount.normalize().to_string(),
+                        Err(_) => field.to_lowercase(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+            .collect();
+        if rows.len() > 1 {
+            rows[1..].sort_by_key(|row| {
+                let client = row.split(',').next().unwrap_or_default();
+                (client.parse::<u16>().ok(), row.clone())
+            });
+        }
+        rows
+    }
+
+    fn diff(expected: &[String], actual: &[String]) -> String {
+        let mut diff = String::new();
+        for i in 0..expected.len().max(actual.len()) {
+            match (expected.get(i), actual.get(i)) {
+                (Some(expected), Some(actual)) if expected == actual => {
+                    diff += &format!("   {}\n", expected)
+                }
+                (expected, actual) => {
+                    if let Some(expected) = expected {
+                        diff += &format!(" - {}\n", expected);
+                    }
+                    if let Some(actual) = actual {
+                        diff += &format!(" + {}\n", actual);
+                    }
+                }
+            }
+        }
+        diff
+    }
+
+    /// a batch served only once is checked against its trailer and applied only if it matches.
+    #[test]
+    fn test_trailer() {
+        let config = PipelineConfig {
+            trailer: true,
+            ..PipelineConfig::default()
+        };
+        let run = |response| {
+            let url = source::serve(response);
+            let url = url.to_string();
+            let mut clients = HashMap::new();
+            play_with_money(
+                Some(OsStr::new(&url)),
+                &config,
+                &ColumnMap::default(),
+                &Sinks::default(),
+                None,
+                &mut clients,
+            )
+            .map(|_| clients)
+        };
+        // served once, so the check and the run have to read the same download
+        let clients = run("HTTP/1.1 200 OK\r\n\r\ntype,client,tx,amount\ndeposit,1,1,2.5\ndeposit,1,2,1\ntrailer,,2,3.5\n")
+            .unwrap();
+        assert_eq!(
+            Money::new(35, 1),
+            clients[&ClientId(1)].get_available_funds()
+        );
+        assert!(run(
+            "HTTP/1.1 200 OK\r\n\r\ntype,client,tx,amount\ndeposit,1,1,2.5\ntrailer,,2,3.5\n"
+        )
+        .is_err());
+    }
+
+    /// Every directory under examples/data with an input.csv and expected.csv is a scenario: the
+    /// input is run through the whole pipeline, with and without history retained, and the
+    /// balances written must match expected.csv.
+    #[test]
+    fn test_golden_files() {
+        let mut scenarios: Vec<PathBuf> = std::fs::read_dir(data_dir())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.join("input.csv").is_file() && path.join("expected.csv").is_file())
+            .collect();
+        scenarios.sort();
+        assert!(!scenarios.is_empty());
+        let mut failures = vec![];
+        for (scenario, retain_history) in scenarios
+            .iter()
+            .flat_map(|scenario| [(scenario, true), (scenario, false)])
+        {
+            let input = scenario.join("input.csv");
+            let config = PipelineConfig {
+                retain_history,
+                ..PipelineConfig::default()
+            };
+            let mut clients = HashMap::new();
+            play_with_money(
+                Some(input.as_os_str()),
+                &config,
+                &ColumnMap::default(),
+                &Sinks::default(),
+                None,
+                &mut clients,
+            )
+            .unwrap();
+            let mut output = vec![];
+            write_client_state_to(
+                &mut output,
+                &clients,
+                &OutputColumns::standard(false),
+                AmountFormat::default(),
+                &Pseudonyms::default(),
+            )
+            .unwrap();
+            let actual = normalize_output(&output);
+            let expected = normalize_output(&std::fs::read(scenario.join("expected.csv")).unwrap());
+            if actual != expected {
+                failures.push(format!(
+                    "{:?} (retain_history {}):\n{}",
+                    scenario.file_name().unwrap(),
+                    retain_history,
+                    diff(&expected, &actual)
+                ));
+            }
+        }
+        assert!(
+            failures.is_empty(),
+            "output differs from expected.csv (- expected, + actual)\n{}",
+            failures.join("\n")
+        );
+    }
+
+    #[test]
+    fn test_disputes_without_history() {
+        // every record refers to the deposit's transaction id
+        let dispute = |monotonic_counter, transaction_type| {
+            let mut dispute = situated(monotonic_counter, transaction_type, Money::ZERO);
+            dispute.record.transaction_id = TxId(0);
+            dispute
+        };
+        let mut retained = ClientState::new(ClientId(1));
+        let mut dropped = ClientState::retaining(ClientId(1), false);
+        for client in [&mut retained, &mut dropped] {
+            let deposit = situated(0, TransactionType::Deposit, Money::new(5, 0));
+            client.add_transaction(deposit).unwrap();
+            client
+                .add_transaction(dispute(1, TransactionType::Dispute))
+                .unwrap();
+            assert_eq!(
+                Err(Rejection::AlreadyDisputed),
+                client.add_transaction(dispute(2, TransactionType::Dispute))
+            );
+            client
+                .add_transaction(dispute(3, TransactionType::Chargeback))
+                .unwrap();
+            assert_eq!(
+                Err(Rejection::AccountLocked),
+                client.add_transaction(dispute(4, TransactionType::Resolve))
+            );
+            assert_eq!(Money::ZERO, client.get_total_funds());
+            assert!(client.is_locked());
+            assert_eq!(vec![TxId(0)], client.disputed_transactions());
+            assert_eq!(
+                Some(DisputeStatus::ChargedBack),
+                client
+                    .disputable(TxId(0))
+                    .map(|disputable| disputable.status)
+            );
+        }
+        assert_eq!(3, retained.transaction_history(TxId(0)).unwrap().len());
+        assert!(dropped.transaction_history(TxId(0)).is_none());
+        assert_eq!(retained.applied_digest(), dropped.applied_digest());
+    }
+
+    #[test]
+    fn test_extended_output() {
+        let mut clients = HashMap::new();
+        let mut records = vec![
+            situated(0, TransactionType::Deposit, Money::new(10, 0)),
+            situated(1, TransactionType::Deposit, Money::new(5, 0)),
+            situated(2, TransactionType::Withdrawal, Money::new(3, 0)),
+            situated(3, TransactionType::Withdrawal, Money::new(99, 0)),
+            situated(4, TransactionType::Dispute, Money::ZERO),
+        ];
+        records[4].record.transaction_id = TxId(1);
+        records[2].record.timestamp = Some(1700000000);
+        for record in records {
+            let _ = process_record(record, &mut clients);
+        }
+        let mut output = vec![];
+        write_client_state_to(
+            &mut output,
+            &clients,
+            &OutputColumns::standard(true),
+            AmountFormat::default(),
+            &Pseudonyms::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            "client,available,held,total,locked,admin_held,reserve,credit_limit,credit_used,tier,currency,transactions,open_disputes,deposited,withdrawn,last_counter,last_timestamp\n\
+            1,7,5,12,false,0,0,0,0,,,4,1,15,3,4,\n",
+            String::from_utf8(output).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_suspense() {
+        let (journal, journaled) = std::sync::mpsc::channel();
+        let (dead_letters, dead_lettered) = std::sync::mpsc::channel();
+        let sinks = Sinks {
+            journal: Some(journal),
+            dead_letters: Some(dead_letters),
+            ..Sinks::default()
+        };
+        let mut records = vec![
+            situated(0, TransactionType::Dispute, Money::ZERO),
+            situated(1, TransactionType::Chargeback, Money::ZERO),
+            situated(2, TransactionType::Dispute, Money::ZERO),
+            situated(3, TransactionType::Deposit, Money::new(10, 0)),
+        ];
+        for record in &mut records[..2] {
+            record.record.transaction_id = TxId(3);
+        }
+        records[2].record.transaction_id = TxId(7);
+        let parking = PipelineConfig {
+            suspense: true,
+            ..PipelineConfig::default()
+        };
+        let mut clients = HashMap::new();
+        for record in records {
+            apply_and_publish(record, &mut clients, &parking, &sinks);
+        }
+        drop(sinks);
+        let journaled: Vec<(usize, Option<usize>)> = journaled
+            .iter()
+            .map(|(record, reference)| {
+                (
+                    record.monotonic_counter,
+                    reference.map(|reference| reference.monotonic_counter),
+                )
+            })
+            .collect();
+        assert_eq!(vec![(3, None), (0, Some(3)), (1, Some(3))], journaled);
+        assert_eq!(0, dead_lettered.iter().count());
+        let client = &clients[&ClientId(1)];
+        assert!(client.is_locked());
+        assert_eq!(Money::ZERO, client.get_total_funds());
+        let parked: Vec<usize> = client
+            .parked()
+            .iter()
+            .map(|r| r.monotonic_counter)
+            .collect();
+        assert_eq!(vec![2], parked);
+        // rebuilt from history the parked records only count once what they refer to arrived
+        assert_eq!(Money::ZERO, client.as_of(3).unwrap().get_total_funds());
+        assert!(client.as_of(4).unwrap().is_locked());
+
+        let mut clients = HashMap::new();
+        let (dead_letters, dead_lettered) = std::sync::mpsc::channel();
+        let sinks = Sinks {
+            dead_letters: Some(dead_letters),
+            ..Sinks::default()
+        };
+        let mut dispute = situated(0, TransactionType::Dispute, Money::ZERO);
+        dispute.record.transaction_id = TxId(1);
+        apply_and_publish(dispute, &mut clients, &PipelineConfig::default(), &sinks);
+        let deposit = situated(1, TransactionType::Deposit, Money::new(10, 0));
+        apply_and_publish(deposit, &mut clients, &PipelineConfig::default(), &sinks);
+        drop(sinks);
+        assert_eq!(1, dead_lettered.iter().count());
+        assert_eq!(
+            Money::new(10, 0),
+            clients[&ClientId(1)].get_available_funds()
+        );
+    }
+
+    #[test]
+    fn test_admin_hold() {
+        let mut client = ClientState::new(ClientId(1));
+        let records = [
+            situated(0, TransactionType::Deposit, Money::new(100, 0)),
+            situated(1, TransactionType::AdminHold, Money::new(30, 0)),
+            situated(2, TransactionType::Dispute, Money::ZERO),
+        ];
+        for mut record in records {
+            record.record.transaction_id = TxId(0);
+            client.add_transaction(record).unwrap();
+        }
+        assert_eq!(Money::new(130, 0), client.get_held_funds());
+        assert_eq!(Money::new(30, 0), client.get_admin_held_funds());
+        assert_eq!(Money::new(-30, 0), client.get_available_funds());
+        assert_eq!(
+            Err(Rejection::ExceedsAdminHold),
+            client.add_transaction(situated(
+                3,
+                TransactionType::AdminRelease,
+                Money::new(31, 0)
+            ))
+        );
+        client
+            .add_transaction(situated(
+                4,
+                TransactionType::AdminRelease,
+                Money::new(30, 0),
+            ))
+            .unwrap();
+        assert_eq!(Money::ZERO, client.get_admin_held_funds());
+        assert_eq!(Money::new(100, 0), client.get_held_funds());
+        // the hold didn't claim the deposit's transaction id
+        assert_eq!(
+            Some(DisputeStatus::Disputed),
+            client
+                .disputable(TxId(0))
+                .map(|disputable| disputable.status)
+        );
+    }
+
+    #[test]
+    fn test_scale_normalized() {
+        let scaled = |amounts: [Money; 2]| {
+            let mut client = ClientState::new(ClientId(1));
+            for (counter, amount) in amounts.into_iter().enumerate() {
+                client
+                    .add_transaction(situated(counter, TransactionType::Deposit, amount))
+                    .unwrap();
+            }
+            client
+        };
+        let padded = scaled([Money::new(150, 2), Money::new(2500, 3)]);
+        let plain = scaled([Money::new(15, 1), Money::new(25, 1)]);
+        assert_eq!("4", padded.get_available_funds().to_string());
+        assert_eq!(
+            padded.get_total_funds().to_string(),
+            plain.get_total_funds().to_string()
+        );
+        assert_eq!(
+            padded.original_amount(TxId(0)),
+            plain.original_amount(TxId(0))
+        );
+        assert_eq!("1.5", padded.original_amount(TxId(0)).unwrap().to_string());
+        assert_eq!(padded.applied_digest(), plain.applied_digest());
+    }
+
+    #[test]
+    fn test_reserve() {
+        let mut client = ClientState::new(ClientId(1));
+        client.reserve = Money::new(20, 0);
+        client
+            .add_transaction(situated(0, TransactionType::Deposit, Money::new(100, 0)))
+            .unwrap();
+        assert_eq!(
+            Err(Rejection::BelowReserve),
+            client.add_transaction(situated(1, TransactionType::Withdrawal, Money::new(81, 0)))
+        );
+        // more than available is still just insufficient
+        assert_eq!(
+            Err(Rejection::InsufficientFunds),
+            client.add_transaction(situated(2, TransactionType::Withdrawal, Money::new(101, 0)))
+        );
+        client
+            .add_transaction(situated(3, TransactionType::Withdrawal, Money::new(80, 0)))
+            .unwrap();
+        assert_eq!(Money::new(20, 0), client.get_available_funds());
+    }
+
+    #[test]
+    fn test_configured_limits() {
+        let config = |reserve| PipelineConfig {
+            limits: Arc::new(Limits {
+                reserve: limits::PerClient::uniform(Decimal::new(reserve, 0)),
+                ..Limits::default()
+            }),
+            ..PipelineConfig::default()
+        };
+        // clients of two runs in one process each keep their own run's limits
+        let strict = ClientState::configured(ClientId(1), &config(20));
+        let lax = ClientState::configured(ClientId(1), &config(5));
+        assert_eq!(Money::new(20, 0), strict.get_reserve());
+        assert_eq!(Money::new(5, 0), lax.get_reserve());
+        assert_eq!(Money::new(20, 0), strict.as_of(0).unwrap().get_reserve());
+    }
+
+    #[test]
+    fn test_client_currency() {
+        let eur = Some("EUR".parse().unwrap());
+        let profile = limits::Profile {
+            currency: eur,
+            ..limits::Profile::default()
+        };
+        let config = PipelineConfig {
+            limits: Arc::new(Limits {
+                profiles: HashMap::from([(ClientId(1), profile)]),
+                ..Limits::default()
+            }),
+            ..PipelineConfig::default()
+        };
+        let mut client = ClientState::configured(ClientId(1), &config);
+        assert_eq!(eur, client.currency());
+        // record amounts come without a currency and take on the client's
+        client
+            .add_transaction(situated(0, TransactionType::Deposit, Money::new(10, 0)))
+            .unwrap();
+        assert_eq!(eur, client.get_available_funds().currency());
+        assert_eq!(eur, client.get_total_funds().currency());
+        assert_eq!(
+            None,
+            ClientState::new(ClientId(2)).get_total_funds().currency()
+        );
+    }
+
+    #[test]
+    fn test_credit_line() {
+        let mut client = ClientState::new(ClientId(1));
+        client.credit_limit = Money::new(50, 0);
+        let records = [
+            situated(0, TransactionType::Deposit, Money::new(30, 0)),
+            situated(1, TransactionType::Withdrawal, Money::new(70, 0)),
+        ];
+        for record in records {
+            client.add_transaction(record).unwrap();
+        }
+        assert_eq!(Money::new(-40, 0), client.get_available_funds());
+        assert_eq!(Money::new(40, 0), client.get_credit_used());
+        assert_eq!(
+            Err(Rejection::InsufficientFunds),
+            client.add_transaction(situated(2, TransactionType::Withdrawal, Money::new(11, 0)))
+        );
+        client.charge_credit(Money::new(2, 0)).unwrap();
+        assert_eq!(Money::new(42, 0), client.get_credit_used());
+        client
+            .add_transaction(situated(3, TransactionType::Deposit, Money::new(50, 0)))
+            .unwrap();
+        assert_eq!(Money::ZERO, client.get_credit_used());
+        assert_eq!(Money::new(8, 0), client.get_available_funds());
+        assert_eq!(Money::new(72, 0), client.get_withdrawn());
+    }
+
+    #[test]
+    fn test_fraud_lock() {
+        let config = PipelineConfig {
+            fraud_lock_after: Some(2),
+            ..PipelineConfig::default()
+        };
+        let mut clients = HashMap::new();
+        let dispute = |monotonic_counter, transaction_id, reason| {
+            let mut dispute = situated(monotonic_counter, TransactionType::Dispute, Money::ZERO);
+            dispute.record.transaction_id = transaction_id;
+            dispute.record.reason = reason;
+            dispute
+        };
+        let records = [
+            situated(0, TransactionType::Deposit, Money::new(10, 0)),
+            situated(1, TransactionType::Deposit, Money::new(10, 0)),
+            situated(2, TransactionType::Deposit, Money::new(10, 0)),
+            situated(3, TransactionType::Deposit, Money::new(10, 0)),
+            dispute(4, TxId(0), Some(DisputeReason::Fraud)),
+            dispute(5, TxId(1), Some(DisputeReason::Service)),
+            dispute(6, TxId(2), None),
+        ];
+        for record in records {
+            process_record_with(record, &mut clients, &config).unwrap();
+        }
+        assert!(!clients[&ClientId(1)].is_locked());
+        assert_eq!(
+            Some(DisputeReason::Service),
+            clients[&ClientId(1)].disputable(TxId(1)).unwrap().reason
+        );
+        let events = process_record_with(
+            dispute(7, TxId(3), Some(DisputeReason::Fraud)),
+            &mut clients,
+            &config,
+        )
+        .unwrap();
+        assert!(clients[&ClientId(1)].is_locked());
+        assert_eq!(EventKind::AccountLocked, events[1].kind);
+        // rebuilt from history the same threshold applies
+        assert!(clients[&ClientId(1)].as_of(8).unwrap().is_locked());
+        assert!(!clients[&ClientId(1)].as_of(7).unwrap().is_locked());
+    }
+
+    #[test]
+    fn test_velocity_lock() {
+        let dispute = |monotonic_counter, transaction_id| {
+            let mut dispute = situated(monotonic_counter, TransactionType::Dispute, Money::ZERO);
+            dispute.record.transaction_id = TxId(transaction_id);
+            dispute
+        };
+        for (velocity, locks) in [("2/3", true), ("2/3:flag", false)] {
+            let config = PipelineConfig {
+                velocity: Some(velocity.parse().unwrap()),
+                ..PipelineConfig::default()
+            };
+            let mut clients = HashMap::new();
+            for counter in 0..8 {
+                let deposit = situated(counter, TransactionType::Deposit, Money::new(1, 0));
+                process_record_with(deposit, &mut clients, &config).unwrap();
+            }
+            process_record_with(dispute(8, 0), &mut clients, &config).unwrap();
+            // the first dispute is out of the window by now
+            process_record_with(dispute(11, 1), &mut clients, &config).unwrap();
+            assert!(!clients[&ClientId(1)].is_locked());
+            process_record_with(dispute(12, 2), &mut clients, &config).unwrap();
+            assert_eq!(locks, clients[&ClientId(1)].is_locked(), "{}", velocity);
+        }
+    }
+
+    #[test]
+    fn test_screened() {
+        let config = PipelineConfig {
+            screening: Some(Arc::new(
+                screening::Screening::read("client,action\n1,block\n".as_bytes()).unwrap(),
+            )),
+            ..PipelineConfig::default()
+        };
+        let mut clients = HashMap::new();
+        let deposit = situated(0, TransactionType::Deposit, Money::new(10, 0));
+        check_bounds(&deposit, &config).unwrap();
+        process_record_with(deposit, &mut clients, &config).unwrap();
+        // the deposit lands on admin hold and withdrawals are turned down
+        let client = &clients[&ClientId(1)];
+        assert_eq!(Money::ZERO, client.get_available_funds());
+        assert_eq!(Money::new(10, 0), client.get_admin_held_funds());
+        let withdrawal = situated(1, TransactionType::Withdrawal, Money::new(1, 0));
+        assert_eq!(Err(Rejection::Screened), check_bounds(&withdrawal, &config));
+        assert_eq!(
+            Ok(()),
+            check_bounds(&withdrawal, &PipelineConfig::default())
+        );
+    }
+
+    #[test]
+    fn test_locked_deposits() {
+        let refer = |monotonic_counter, transaction_type| {
+            let mut record = situated(monotonic_counter, transaction_type, Money::ZERO);
+            record.record.transaction_id = TxId(0);
+            record
+        };
+        let expected = [
+            (
+                LockedDeposits::Accept,
+                Ok(()),
+                Money::new(15, 0),
+                Money::ZERO,
+            ),
+            (
+                LockedDeposits::Reject,
+                Err(Rejection::AccountLocked),
+                Money::new(10, 0),
+                Money::ZERO,
+            ),
+            (
+                LockedDeposits::Hold,
+                Ok(()),
+                Money::new(10, 0),
+                Money::new(5, 0),
+            ),
+        ];
+        for (locked_deposits, result, available, held) in expected {
+            let config = PipelineConfig {
+                locked_deposits,
+                ..PipelineConfig::default()
+            };
+            let mut clients = HashMap::new();
+            let records = [
+                situated(0, TransactionType::Deposit, Money::new(10, 0)),
+                situated(1, TransactionType::Deposit, Money::new(10, 0)),
+                refer(2, TransactionType::Dispute),
+                refer(3, TransactionType::Chargeback),
+            ];
+            for record in records {
+                process_record_with(record, &mut clients, &config).unwrap();
+            }
+            let deposit = situated(4, TransactionType::Deposit, Money::new(5, 0));
+            assert_eq!(
+                result,
+                process_record_with(deposit, &mut clients, &config).map(|_| ()),
+                "{:?}",
+                locked_deposits
+            );
+            let client = &clients[&ClientId(1)];
+            assert!(client.is_locked());
+            assert_eq!(available, client.get_available_funds());
+            assert_eq!(held, client.get_held_funds());
+        }
+        assert_eq!(Ok(LockedDeposits::Hold), "hold".parse());
+        assert!("freeze".parse::<LockedDeposits>().is_err());
+    }
+
+    #[test]
+    fn test_overflow_is_rejected() {
+        let mut clients = HashMap::new();
+        let huge = situated(0, TransactionType::Deposit, Money::from(Decimal::MAX));
+        process_record(huge, &mut clients).unwrap();
+        let more = situated(1, TransactionType::Deposit, Money::new(1, 0));
+        assert_eq!(Err(Rejection::Overflow), process_record(more, &mut clients));
+        let state = &clients[&ClientId(1)];
+        assert_eq!(Money::from(Decimal::MAX), state.get_available_funds());
+        assert_eq!(Money::from(Decimal::MAX), state.get_net_flows());
+        // as must anything that would need more than 28 significant digits
+        let mut clients = HashMap::new();
+        let large = Money::from("79228162514264337593543950".parse::<Decimal>().unwrap());
+        process_record(situated(0, TransactionType::Deposit, large), &mut clients).unwrap();
+        let fraction = situated(1, TransactionType::Deposit, Money::new(1, 4));
+        assert_eq!(
+            Err(Rejection::Overflow),
+            process_record(fraction, &mut clients)
+        );
+        assert_eq!(large, clients[&ClientId(1)].get_available_funds());
+        // the derived totals must stay representable as well
+        let mut state = ClientState::new(ClientId(2));
+        let mut next = state.balances();
+        next.available = Money::from(Decimal::MAX);
+        next.held = Money::new(1, 0);
+        assert_eq!(Err(Rejection::Overflow), state.commit(next));
+        assert_eq!(Money::ZERO, state.get_available_funds());
+    }
+
+    #[test]
+    fn test_max_amount() {
+        let config = PipelineConfig {
+            max_amount: Some(Decimal::new(1_000_000, 0)),
+            ..PipelineConfig::default()
+        };
+        let corrupt = situated(
+            0,
+            TransactionType::Deposit,
+            Money::new(9_999_999_999_999, 0),
+        );
+        assert_eq!(
+            Err(Rejection::AmountOutOfBounds),
+            check_bounds(&corrupt, &config)
+        );
+        let negative = situated(1, TransactionType::Withdrawal, Money::new(-2_000_000, 0));
+        assert_eq!(
+            Err(Rejection::AmountOutOfBounds),
+            check_bounds(&negative, &config)
+        );
+        let fine = situated(2, TransactionType::Deposit, Money::new(1_000_000, 0));
+        assert_eq!(Ok(()), check_bounds(&fine, &config));
+        assert_eq!(Ok(()), check_bounds(&corrupt, &PipelineConfig::default()));
+    }
+
+    #[test]
+    fn test_missing_amounts() {
+        let mut reader = csv::Reader::from_reader(
+            "type,client,tx,amount\ndeposit,1,1,\ndeposit,1,2,5\ndispute,1,2,\n".as_bytes(),
+        );
+        let records: Vec<Record> = reader.deserialize().map(Result::unwrap).collect();
+        assert_eq!(None, records[0].amount);
+        let mut client = ClientState::new(ClientId(1));
+        let applied: Vec<_> = records
+            .into_iter()
+            .enumerate()
+            .map(|(monotonic_counter, record)| {
+                client.add_transaction(SituatedRecord {
+                    monotonic_counter,
+                    record,
+                })
+            })
+            .collect();
+        assert_eq!(vec![Err(Rejection::MissingAmount), Ok(()), Ok(())], applied);
+        assert_eq!(Money::new(5, 0), client.get_held_funds());
+        // a blank deposit isn't taken for a deposit of zero, its tx id stays free
+        assert_eq!(None, client.original_amount(TxId(1)));
+    }
+
+    #[test]
+    fn test_disallowed_types() {
+        let config = PipelineConfig {
+            disallowed: ["chargeback", "admin_hold"]
+                .into_iter()
+                .map(|name| name.parse().unwrap())
+                .collect(),
+            ..PipelineConfig::default()
+        };
+        let chargeback = situated(0, TransactionType::Chargeback, Money::ZERO);
+        assert_eq!(
+            Err(Rejection::TypeDisabled),
+            check_bounds(&chargeback, &config)
+        );
+        let deposit = situated(1, TransactionType::Deposit, Money::new(1, 0));
+        assert_eq!(Ok(()), check_bounds(&deposit, &config));
+        assert!(config.disallowed.contains(TransactionType::AdminHold));
+        assert!(!config.disallowed.contains(TransactionType::AdminRelease));
+        assert!("refund".parse::<TransactionType>().is_err());
+    }
+}
+
+// https://rust-lang-nursery.github.io/rust-cookbook/encoding/csv.html
+// https://docs.rs/csv/1.1.6/csv/struct.Reader.html#method.deserialize
+// https://crates.io/crates/serde
+// https://docs.rs/serial_int/latest/serial_int/
@@ -0,0 +1,296 @@
+use crate::money::Currency;
+use crate::velocity::Velocity;
+use crate::ClientId;
+use csv::{ReaderBuilder, Trim};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+/// An amount for each client, with a default for clients without one of their own.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PerClient {
+    pub default: Decimal,
+    pub clients: HashMap<ClientId, Decimal>,
+}
+
+impl PerClient {
+    pub fn uniform(default: Decimal) -> Self {
+        PerClient {
+            default,
+            clients: HashMap::new(),
+        }
+    }
+
+    /// per client amounts from a CSV of client and amount columns, e.g. `client,reserve`, on top
+    /// of `default`.
+    pub fn read<R: io::Read>(reader: R, default: Decimal) -> io::Result<Self> {
+        let mut reader = ReaderBuilder::new().trim(Trim::All).from_reader(reader);
+        let mut clients = HashMap::new();
+        for row in reader.deserialize() {
+            let (client_id, amount): (ClientId, Decimal) =
+                row.map_err(|e| invalid(format!("Malformed client amount: {}.", e)))?;
+            if amount < Decimal::ZERO {
+                return Err(invalid(format!(
+                    "Amount of client ({}) is negative ({}).",
+                    client_id, amount
+                )));
+            }
+            clients.insert(client_id, amount);
+        }
+        Ok(PerClient { default, clients })
+    }
+
+    pub fn read_file(path: &Path, default: Decimal) -> io::Result<Self> {
+        Self::read(std::fs::File::open(path)?, default)
+    }
+
+    pub fn of(&self, client_id: ClientId) -> Decimal {
+        self.clients
+            .get(&client_id)
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+/// Limits a client's or a tier's row in the clients or tiers file sets, blank if it doesn't.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Policy {
+    pub reserve: Option<Decimal>,
+    pub credit_limit: Option<Decimal>,
+    pub max_amount: Option<Decimal>,
+    pub velocity: Option<Velocity>,
+}
+
+impl Policy {
+    /// this policy, with what it leaves blank taken from `fallback`.
+    fn or(self, fallback: Policy) -> Policy {
+        Policy {
+            reserve: self.reserve.or(fallback.reserve),
+            credit_limit: self.credit_limit.or(fallback.credit_limit),
+            max_amount: self.max_amount.or(fallback.max_amount),
+            velocity: self.velocity.or(fallback.velocity),
+        }
+    }
+
+    fn check(&self, whose: &str) -> io::Result<()> {
+        let negative = [self.reserve, self.credit_limit, self.max_amount]
+            .into_iter()
+            .flatten()
+            .find(|amount| *amount < Decimal::ZERO);
+        match negative {
+            Some(amount) => Err(invalid(format!(
+                "Limit of {} is negative ({}).",
+                whose, amount
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// What a clients file says about a client.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    pub tier: Option<String>,
+    pub currency: Option<Currency>,
+    pub policy: Policy,
+}
+
+#[derive(Deserialize)]
+struct TierRow {
+    tier: String,
+    #[serde(default)]
+    reserve: Option<Decimal>,
+    #[serde(default)]
+    credit_limit: Option<Decimal>,
+    #[serde(default)]
+    max_amount: Option<Decimal>,
+    #[serde(default)]
+    velocity: Option<Velocity>,
+}
+
+#[derive(Deserialize)]
+struct ClientRow {
+    client: ClientId,
+    #[serde(default)]
+    tier: Option<String>,
+    #[serde(default)]
+    currency: Option<Currency>,
+    #[serde(default)]
+    reserve: Option<Decimal>,
+    #[serde(default)]
+    credit_limit: Option<Decimal>,
+    #[serde(default)]
+    max_amount: Option<Decimal>,
+    #[serde(default)]
+    velocity: Option<Velocity>,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The policy of each tier from a `tier,reserve,credit_limit,max_amount,velocity` CSV, any limit
+/// of which can be blank or left out.
+pub fn read_tiers<R: io::Read>(reader: R) -> io::Result<HashMap<String, Policy>> {
+    let mut reader = ReaderBuilder::new().trim(Trim::All).from_reader(reader);
+    let mut tiers = HashMap::new();
+    for row in reader.deserialize() {
+        let row: TierRow = row.map_err(|e| invalid(format!("Malformed tier: {}.", e)))?;
+        let policy = Policy {
+            reserve: row.reserve,
+            credit_limit: row.credit_limit,
+            max_amount: row.max_amount,
+            velocity: row.velocity,
+        };
+        policy.check(&format!("tier ({})", row.tier))?;
+        tiers.insert(row.tier, policy);
+    }
+    Ok(tiers)
+}
+
+/// Each client's profile from a `client,tier,currency,reserve,credit_limit,max_amount,velocity`
+/// CSV, with the limits it leaves blank taken from the client's tier in `tiers`. Only `client` is
+/// required.
+pub fn read_profiles<R: io::Read>(
+    reader: R,
+    tiers: &HashMap<String, Policy>,
+) -> io::Result<HashMap<ClientId, Profile>> {
+    let mut reader = ReaderBuilder::new().trim(Trim::All).from_reader(reader);
+    let mut profiles = HashMap::new();
+    for row in reader.deserialize() {
+        let row: ClientRow = row.map_err(|e| invalid(format!("Malformed client: {}.", e)))?;
+        let own = Policy {
+            reserve: row.reserve,
+            credit_limit: row.credit_limit,
+            max_amount: row.max_amount,
+            velocity: row.velocity,
+        };
+        own.check(&format!("client ({})", row.client))?;
+        let tier_policy = match &row.tier {
+            Some(tier) => *tiers.get(tier).ok_or_else(|| {
+                invalid(format!(
+                    "Client ({}) is of an unknown tier ({}).",
+                    row.client, tier
+                ))
+            })?,
+            None => Policy::default(),
+        };
+        profiles.insert(
+            row.client,
+            Profile {
+                tier: row.tier,
+                currency: row.currency,
+                policy: own.or(tier_policy),
+            },
+        );
+    }
+    Ok(profiles)
+}
+
+/// What clients are held to beyond their own funds.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Limits {
+    /// available funds withdrawals can't dip into
+    pub reserve: PerClient,
+    /// how far withdrawals can overdraw available funds
+    pub credit: PerClient,
+    /// from a clients file, whose limits come before the ones above
+    pub profiles: HashMap<ClientId, Profile>,
+}
+
+impl Limits {
+    fn policy(&self, client_id: ClientId) -> Policy {
+        self.profiles
+            .get(&client_id)
+            .map(|profile| profile.policy)
+            .unwrap_or_default()
+    }
+
+    /// the reserve of `client_id`, its clients file row's or tier's before the --reserve ones.
+    pub fn reserve(&self, client_id: ClientId) -> Decimal {
+        self.policy(client_id)
+            .reserve
+            .unwrap_or_else(|| self.reserve.of(client_id))
+    }
+
+    /// the credit limit of `client_id`, its clients file row's or tier's before the
+    /// --credit-limit ones.
+    pub fn credit_limit(&self, client_id: ClientId) -> Decimal {
+        self.policy(client_id)
+            .credit_limit
+            .unwrap_or_else(|| self.credit.of(client_id))
+    }
+
+    /// the largest deposit or withdrawal `client_id` is allowed by its clients file row or tier,
+    /// if either sets one; --max-amount applies otherwise.
+    pub fn max_amount(&self, client_id: ClientId) -> Option<Decimal> {
+        self.policy(client_id).max_amount
+    }
+
+    /// the --velocity `client_id` is held to by its clients file row or tier, if either sets one.
+    pub fn velocity(&self, client_id: ClientId) -> Option<Velocity> {
+        self.policy(client_id).velocity
+    }
+
+    /// the clients file profile of `client_id`, if it has one.
+    pub fn profile(&self, client_id: ClientId) -> Option<&Profile> {
+        self.profiles.get(&client_id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_per_client() {
+        let reserves =
+            PerClient::read("client,reserve\n1, 10.5\n3,0\n".as_bytes(), Decimal::ONE).unwrap();
+        assert_eq!(Decimal::new(105, 1), reserves.of(ClientId(1)));
+        assert_eq!(Decimal::ONE, reserves.of(ClientId(2)));
+        assert_eq!(Decimal::ZERO, reserves.of(ClientId(3)));
+        assert!(PerClient::read("client,reserve\n1,-1\n".as_bytes(), Decimal::ZERO).is_err());
+        assert!(PerClient::read("client,reserve\nx,1\n".as_bytes(), Decimal::ZERO).is_err());
+    }
+
+    #[test]
+    fn test_read_profiles() {
+        let tiers =
+            read_tiers("tier,reserve,credit_limit\ngold,5,100\nbasic,10,\n".as_bytes()).unwrap();
+        let profiles = read_profiles(
+            "client,tier,currency,reserve\n1,gold,EUR,\n2,basic,,0\n3,,,\n".as_bytes(),
+            &tiers,
+        )
+        .unwrap();
+        let gold = &profiles[&ClientId(1)];
+        assert_eq!(Some("gold"), gold.tier.as_deref());
+        assert_eq!(Some("EUR"), gold.currency.map(|c| c.to_string()).as_deref());
+        assert_eq!(Some(Decimal::new(5, 0)), gold.policy.reserve);
+        assert_eq!(Some(Decimal::new(100, 0)), gold.policy.credit_limit);
+        // a client's own limit beats its tier's
+        assert_eq!(Some(Decimal::ZERO), profiles[&ClientId(2)].policy.reserve);
+        assert_eq!(None, profiles[&ClientId(2)].policy.credit_limit);
+        assert_eq!(Profile::default(), profiles[&ClientId(3)]);
+        assert!(read_profiles("client,tier\n1,platinum\n".as_bytes(), &tiers).is_err());
+        assert!(read_profiles("client,max_amount\n1,-5\n".as_bytes(), &tiers).is_err());
+
+        let tiers = read_tiers("tier,velocity\nrisky,2/10s\nbasic,\n".as_bytes()).unwrap();
+        let profiles = read_profiles(
+            "client,tier,velocity\n1,risky,\n2,risky,5/100:flag\n3,basic,\n".as_bytes(),
+            &tiers,
+        )
+        .unwrap();
+        assert_eq!(
+            Some("2/10s".parse().unwrap()),
+            profiles[&ClientId(1)].policy.velocity
+        );
+        assert_eq!(
+            Some("5/100:flag".parse().unwrap()),
+            profiles[&ClientId(2)].policy.velocity
+        );
+        assert_eq!(None, profiles[&ClientId(3)].policy.velocity);
+        assert!(read_tiers("tier,velocity\nrisky,2\n".as_bytes()).is_err());
+    }
+}
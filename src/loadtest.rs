@@ -0,0 +1,279 @@
+use crate::engine::Engine;
+use crate::money::Money;
+use crate::pipeline::PipelineConfig;
+use crate::{ClientId, Record, Sinks, TransactionType, TxId};
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How hard and how long to drive the engine, see `loadtest`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Load {
+    /// records a second to submit at, or as fast as they're applied with None
+    pub tps: Option<u64>,
+    pub duration: Duration,
+    pub clients: u16,
+    /// of the generated records, so two runs submit the same ones
+    pub seed: u64,
+}
+
+/// xorshift64*, plenty for making up transactions.
+struct Generator {
+    state: u64,
+    /// the latest deposit of each client, for disputes and resolves to refer to
+    deposits: Vec<Option<u32>>,
+    next_tx: u32,
+}
+
+impl Generator {
+    fn new(seed: u64, clients: u16) -> Self {
+        Generator {
+            // a zero state would stay zero
+            state: seed | 1,
+            deposits: vec![None; clients.max(1) as usize],
+            next_tx: 0,
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Mostly deposits and withdrawals of up to 1000.00, with some disputes and resolves of a
+    /// client's latest deposit.
+    fn record(&mut self) -> Record {
+        let client = (self.next_u64() % self.deposits.len() as u64) as usize;
+        let amount = Money::new((self.next_u64() % 100_000) as i64 + 1, 2);
+        let (transaction_type, transaction_id) = match (self.next_u64() % 20, self.deposits[client])
+        {
+            (0, Some(deposit)) => (TransactionType::Dispute, deposit),
+            (1, Some(deposit)) => (TransactionType::Resolve, deposit),
+            (2..=6, _) => (TransactionType::Withdrawal, self.next_tx()),
+            _ => {
+                let deposit = self.next_tx();
+                self.deposits[client] = Some(deposit);
+                (TransactionType::Deposit, deposit)
+            }
+        };
+        Record {
+            transaction_type,
+            client_id: ClientId(client as u16),
+            transaction_id: TxId(transaction_id),
+            amount: match transaction_type.is_reference() {
+                true => None,
+                false => Some(amount),
+            },
+            timestamp: None,
+            reason: None,
+        }
+    }
+
+    fn next_tx(&mut self) -> u32 {
+        self.next_tx = self.next_tx.wrapping_add(1);
+        self.next_tx
+    }
+}
+
+/// Latencies bucketed by their top bits, so recording millions of them takes no memory of its
+/// own: values under 16ns are exact, the rest within an eighth.
+struct Histogram {
+    buckets: Vec<u64>,
+    count: u64,
+    max: u64,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Histogram {
+            buckets: vec![0; 16 + 60 * 8],
+            count: 0,
+            max: 0,
+        }
+    }
+
+    fn bucket(nanos: u64) -> usize {
+        match nanos {
+            0..=15 => nanos as usize,
+            _ => {
+                let exponent = 63 - nanos.leading_zeros() as usize;
+                let sub = (nanos >> (exponent - 3)) as usize & 7;
+                16 + (exponent - 4) * 8 + sub
+            }
+        }
+    }
+
+    /// the smallest value that falls into `bucket`.
+    fn lower_bound(bucket: usize) -> u64 {
+        match bucket {
+            0..=15 => bucket as u64,
+            _ => {
+                let (exponent, sub) = ((bucket - 16) / 8 + 4, (bucket - 16) % 8);
+                (8 + sub as u64) << (exponent - 3)
+            }
+        }
+    }
+
+    fn record(&mut self, latency: Duration) {
+        let nanos = latency.as_nanos().min(u64::MAX.into()) as u64;
+        self.buckets[Self::bucket(nanos)] += 1;
+        self.count += 1;
+        self.max = self.max.max(nanos);
+    }
+
+    /// by nearest rank, to the bucket it falls in.
+    fn percentile(&self, percent: u64) -> Duration {
+        let rank = (self.count * percent).div_ceil(100).max(1);
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Duration::from_nanos(Self::lower_bound(bucket).min(self.max));
+            }
+        }
+        Duration::from_nanos(self.max)
+    }
+}
+
+/// The peak resident memory of the process so far in KiB, None where it can't be told.
+fn max_rss_kib() -> Option<i64> {
+    // SAFETY: getrusage only writes into the struct it's given
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    match unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } {
+        0 => Some(usage.ru_maxrss as i64),
+        _ => None,
+    }
+}
+
+/// How a load test went.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub submitted: u64,
+    pub accepted: u64,
+    pub elapsed: Duration,
+    pub p50: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+    /// of the peak resident memory over the run, in KiB
+    pub memory_growth: Option<i64>,
+    pub clients: usize,
+}
+
+impl Outcome {
+    /// records applied a second, over the whole run.
+    pub fn throughput(&self) -> f64 {
+        self.submitted as f64 / self.elapsed.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "submitted: {} ({} accepted) in {:.3}s",
+            self.submitted,
+            self.accepted,
+            self.elapsed.as_secs_f64()
+        )?;
+        writeln!(f, "throughput: {:.0} records/s", self.throughput())?;
+        writeln!(
+            f,
+            "apply latency: p50 {:?}, p99 {:?}, max {:?}",
+            self.p50, self.p99, self.max
+        )?;
+        writeln!(f, "clients: {}", self.clients)?;
+        match self.memory_growth {
+            Some(growth) => writeln!(f, "peak memory growth: {} KiB", growth),
+            None => writeln!(f, "peak memory growth: unknown"),
+        }
+    }
+}
+
+/// Submit generated records to an [`Engine`] configured by `pipeline_config` for
+/// `load.duration`, paced to `load.tps`, and time how long each apply takes. A run that can't
+/// keep up with `tps` submits as fast as it can, so its throughput falls short of it.
+pub fn run(load: &Load, pipeline_config: &PipelineConfig) -> Outcome {
+    let mut engine = Engine::new(pipeline_config.clone(), Sinks::default());
+    let mut generator = Generator::new(load.seed, load.clients);
+    let mut latencies = Histogram::new();
+    let rss_before = max_rss_kib();
+    let mut accepted = 0;
+    let start = Instant::now();
+    while start.elapsed() < load.duration {
+        if let Some(tps) = load.tps {
+            let due = start + Duration::from_secs_f64(latencies.count as f64 / tps as f64);
+            if let Some(ahead) = due.checked_duration_since(Instant::now()) {
+                thread::sleep(ahead);
+            }
+        }
+        let record = generator.record();
+        let applying = Instant::now();
+        let applied = engine.apply(record);
+        latencies.record(applying.elapsed());
+        if applied.is_ok() {
+            accepted += 1;
+        }
+    }
+    let elapsed = start.elapsed();
+    Outcome {
+        submitted: latencies.count,
+        accepted,
+        elapsed,
+        p50: latencies.percentile(50),
+        p99: latencies.percentile(99),
+        max: Duration::from_nanos(latencies.max),
+        memory_growth: max_rss_kib()
+            .zip(rss_before)
+            .map(|(after, before)| after - before),
+        clients: engine.clients().len(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_histogram() {
+        for nanos in [0, 15, 16, 17, 100, 1_000, 123_456, u64::MAX / 2] {
+            let bucket = Histogram::bucket(nanos);
+            assert!(Histogram::lower_bound(bucket) <= nanos);
+            assert!(Histogram::lower_bound(bucket + 1) > nanos);
+        }
+        let mut histogram = Histogram::new();
+        for micros in 1..=100 {
+            histogram.record(Duration::from_micros(micros));
+        }
+        // within an eighth below
+        let p50 = histogram.percentile(50);
+        assert!(p50 <= Duration::from_micros(50) && p50 > Duration::from_micros(43));
+        assert_eq!(
+            Duration::from_micros(100),
+            Duration::from_nanos(histogram.max)
+        );
+    }
+
+    #[test]
+    fn test_loadtest() {
+        let load = Load {
+            tps: Some(2_000),
+            duration: Duration::from_millis(200),
+            clients: 10,
+            seed: 7,
+        };
+        let outcome = run(&load, &PipelineConfig::default());
+        // paced, so about 400, less on a slow machine
+        assert!(outcome.submitted > 0 && outcome.submitted <= 401);
+        assert!(outcome.accepted <= outcome.submitted);
+        assert!(outcome.clients <= 10);
+        assert!(outcome.p50 <= outcome.p99 && outcome.p99 <= outcome.max);
+        // the same seed makes the same records
+        let mut a = Generator::new(3, 5);
+        let mut b = Generator::new(3, 5);
+        for _ in 0..100 {
+            assert_eq!(format!("{:?}", a.record()), format!("{:?}", b.record()));
+        }
+    }
+}
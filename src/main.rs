@@ -1,482 +1,1752 @@
-use clap::{arg, command};
-use csv::{Reader, ReaderBuilder, Trim};
+use clap::{arg, command, Arg, Command};
 use env_logger::{Builder, Env};
-use log::{debug, error, trace, warn};
-use rust_decimal::Decimal;
-use serde::{de, Deserialize};
+use log::{debug, error, warn, LevelFilter};
+use playing_with_money::amount::AmountPolicy;
+use playing_with_money::analyze;
+use playing_with_money::anomaly::{Anomalies, Thresholds};
+use playing_with_money::changes::ChangeLog;
+use playing_with_money::checkpoint::{Checkpoints, Commit};
+use playing_with_money::config::Config;
+use playing_with_money::conservation::Conservation;
+use playing_with_money::console;
+use playing_with_money::dead_letter::DeadLetterQueue;
+use playing_with_money::decisions;
+use playing_with_money::dry_run;
+use playing_with_money::enrich::{Enricher, Lookup, Rule};
+use playing_with_money::events::EventKind;
+use playing_with_money::filter::Filter;
+use playing_with_money::id_map::IdMap;
+use playing_with_money::journal::{self, Journal};
+use playing_with_money::limits::{self, Limits, PerClient};
+use playing_with_money::loadtest::{self, Load};
+use playing_with_money::manifest::{Hashed, Manifest, Tally};
+use playing_with_money::merge;
+use playing_with_money::messages::Messages;
+use playing_with_money::migrate;
+use playing_with_money::output::{AmountFormat, OutputColumns};
+use playing_with_money::partition;
+use playing_with_money::pipeline::PipelineConfig;
+use playing_with_money::processed::{DuplicatePolicy, Processed, Spooled, PROCESSED};
+use playing_with_money::profile::{Profiler, Sampling};
+use playing_with_money::pseudonym::Pseudonyms;
+use playing_with_money::query::{self, AsOf};
+use playing_with_money::replay;
+use playing_with_money::report::Report;
+use playing_with_money::run_hash::RunHash;
+use playing_with_money::schema::ColumnMap;
+use playing_with_money::screening::{Compliance, Screening};
+use playing_with_money::shadow::{self, Setting};
+use playing_with_money::shutdown;
+use playing_with_money::source;
+use playing_with_money::spill::Spill;
+use playing_with_money::state;
+use playing_with_money::summary::Summary;
+use playing_with_money::webhook::{HttpUrl, Notifier};
+use playing_with_money::{
+    play_with_money, validate_input, write_client_state, write_client_state_to, ClientId,
+    ClientState, Sinks, TransactionType, TransactionTypes,
+};
 use std::collections::HashMap;
-use std::ffi::OsStr;
+use std::env;
+use std::ffi::{OsStr, OsString};
 use std::fs::File;
 use std::io;
-use std::path::Path;
-use std::str::FromStr;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
 
-fn validate_input(input: Option<&OsStr>) -> io::Result<&Path> {
-    let err_str = "Invalid! Input must be path to file that exists on the filesystem.";
-    if let Some(transactions_csv) = input {
-        let possible_path = Path::new(transactions_csv);
-        if possible_path.exists() {
-            Ok(possible_path)
-        } else {
-            Err(io::Error::new(io::ErrorKind::InvalidInput, err_str))
+fn main() -> ExitCode {
+    let command = command!()
+        .arg(
+            arg!([transactions_csv])
+                .help("CSV file (or http:// or https:// URL) containing chronological list of client transactions"),
+        )
+        .arg(
+            Arg::new("read-queue-capacity")
+                .long("read-queue-capacity")
+                .value_name("N")
+                .default_value("1024")
+                .help("Raw rows buffered between the reader and parser stages"),
+        )
+        .arg(
+            Arg::new("parse-queue-capacity")
+                .long("parse-queue-capacity")
+                .value_name("N")
+                .default_value("1024")
+                .help("Parsed records buffered between the parser and engine stages"),
+        )
+        .arg(
+            Arg::new("workers")
+                .long("workers")
+                .value_name("N")
+                .default_value("1")
+                .help("Engine threads; clients are sharded across them by client id"),
+        )
+        .arg(
+            Arg::new("chaos")
+                .long("chaos")
+                .value_name("SEED")
+                .help("Stall the engine threads at random points drawn from SEED, to test that results don't depend on how they interleave"),
+        )
+        .arg(
+            Arg::new("reorder-window")
+                .long("reorder-window")
+                .value_name("N")
+                .default_value("0")
+                .help("Buffer N records and apply them in timestamp order"),
+        )
+        .arg(
+            Arg::new("max-skew")
+                .long("max-skew")
+                .value_name("N")
+                .help("Flag records whose timestamp is more than N behind the latest seen"),
+        )
+        .arg(
+            Arg::new("webhook")
+                .long("webhook")
+                .value_name("URL")
+                .multiple_occurrences(true)
+                .help("http:// or https:// endpoint to POST a JSON payload to for each event"),
+        )
+        .arg(
+            Arg::new("webhook-events")
+                .long("webhook-events")
+                .value_name("EVENTS")
+                .use_value_delimiter(true)
+                .default_value("dispute_opened,chargeback,account_locked")
+                .help("Events sent to webhooks: dispute_opened, chargeback, account_locked"),
+        )
+        .arg(
+            Arg::new("summary")
+                .long("summary")
+                .help("Write batch aggregates and the top clients by total funds to stderr"),
+        )
+        .arg(
+            Arg::new("top")
+                .long("top")
+                .value_name("N")
+                .default_value("5")
+                .help("Number of clients listed in the summary"),
+        )
+        .arg(
+            Arg::new("lang")
+                .long("lang")
+                .value_name("LANG")
+                .default_value("en")
+                .help("Language of the summary and the rejection details in dead letters: en, or a Fluent file of translations named for its language, e.g. fr.ftl"),
+        )
+        .arg(
+            Arg::new("report")
+                .long("report")
+                .value_name("KIND=PATH")
+                .multiple_occurrences(true)
+                .help("Also write a report to PATH at the end of the run, KIND is locked, exposure, suspense, pending or held"),
+        )
+        .arg(
+            Arg::new("disallow")
+                .long("disallow")
+                .value_name("TYPE")
+                .multiple_occurrences(true)
+                .help("Turn down records of TYPE with type_disabled rather than apply them, e.g. chargeback"),
+        )
+        .arg(
+            Arg::new("extended-output")
+                .long("extended-output")
+                .help("Add transaction counts, open disputes, lifetime deposits/withdrawals and last activity to the output"),
+        )
+        .arg(
+            Arg::new("output-columns")
+                .long("output-columns")
+                .value_name("COLUMNS")
+                .use_value_delimiter(true)
+                .help("Write only these output columns in this order, standard or extended, e.g. client,total,locked"),
+        )
+        .arg(
+            Arg::new("where")
+                .long("where")
+                .value_name("FILTER")
+                .help("Only write the clients matching FILTER to the output and reports, e.g. \"locked == true && total > 1000\""),
+        )
+        .arg(
+            Arg::new("output-shards")
+                .long("output-shards")
+                .value_name("N")
+                .help("Write the output to N files in --output-dir partitioned by a hash of the client, with a manifest, instead of stdout"),
+        )
+        .arg(
+            Arg::new("output-dir")
+                .long("output-dir")
+                .value_name("DIR")
+                .default_value("balances")
+                .help("Directory --output-shards writes to"),
+        )
+        .arg(
+            Arg::new("output-scale")
+                .long("output-scale")
+                .value_name("N")
+                .help("Write output amounts with N decimal places, rounding ties to even"),
+        )
+        .arg(
+            Arg::new("trim-zeros")
+                .long("trim-zeros")
+                .help("Drop trailing zeros from output amounts, after --output-scale"),
+        )
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .value_name("N/M")
+                .help("Time N of every M records per stage and transaction type, reported to stderr"),
+        )
+        .arg(
+            Arg::new("pseudonymize")
+                .long("pseudonymize")
+                .value_name("SALT")
+                .help("Write client ids as salted hashes in all outputs, reports and logs"),
+        )
+        .arg(
+            Arg::new("run-hash")
+                .long("run-hash")
+                .help("Write SHA-256 digests of the balances and applied records to stderr"),
+        )
+        .arg(
+            Arg::new("quiet")
+                .long("quiet")
+                .short('q')
+                .conflicts_with("verbose")
+                .help("Log nothing and leave out --summary and the --profile timings"),
+        )
+        .arg(
+            Arg::new("verbose")
+                .long("verbose")
+                .short('v')
+                .multiple_occurrences(true)
+                .help("Log warnings, -vv info and the --summary too, -vvv debug; in place of RUST_LOG"),
+        )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .value_name("TOML")
+                .help("File of options to run with, for those not given on the command line"),
+        )
+        .arg(
+            Arg::new("config-profile")
+                .long("config-profile")
+                .value_name("NAME")
+                .requires("config")
+                .help("Profile of the --config file to run with, on top of its top-level options"),
+        )
+        .arg(
+            Arg::new("manifest")
+                .long("manifest")
+                .value_name("PATH")
+                .help("JSON file to describe the run in: input and output hashes, version, arguments and record counts"),
+        )
+        .arg(
+            Arg::new("journal")
+                .long("journal")
+                .value_name("PATH")
+                .help("CSV file to append every applied record to, each chained to the one before"),
+        )
+        .arg(
+            Arg::new("changes")
+                .long("changes")
+                .value_name("PATH")
+                .help("JSON lines file to write every change to a client's balances or lock to, with the record that caused it"),
+        )
+        .arg(
+            Arg::new("anomalies")
+                .long("anomalies")
+                .value_name("PATH")
+                .help("CSV file to flag deposits and withdrawals far off their client's usual amounts or frequency in, without holding them up"),
+        )
+        .arg(
+            Arg::new("anomaly-z")
+                .long("anomaly-z")
+                .value_name("Z")
+                .default_value("3")
+                .help("Standard deviations off a client's moving mean a transaction is flagged at"),
+        )
+        .arg(
+            Arg::new("anomaly-alpha")
+                .long("anomaly-alpha")
+                .value_name("WEIGHT")
+                .default_value("0.1")
+                .help("Weight of each new transaction in a client's moving mean and variance, between 0 and 1"),
+        )
+        .arg(
+            Arg::new("anomaly-warmup")
+                .long("anomaly-warmup")
+                .value_name("N")
+                .default_value("5")
+                .help("Transactions of a client seen before any of its are flagged"),
+        )
+        .arg(
+            Arg::new("sign-key")
+                .long("sign-key")
+                .value_name("PATH")
+                .help("File holding a secret to sign (HMAC-SHA256) or verify journal entries with"),
+        )
+        .arg(
+            Arg::new("verify")
+                .long("verify")
+                .value_name("JOURNAL")
+                .help("Check a journal written by --journal for tampering instead of processing"),
+        )
+        .arg(
+            Arg::new("checkpoint-every")
+                .long("checkpoint-every")
+                .value_name("N")
+                .help("Checkpoint client state and balances every N rows, see --checkpoint-dir"),
+        )
+        .arg(
+            Arg::new("fsync")
+                .long("fsync")
+                .value_name("POLICY")
+                .default_value("os")
+                .help("When the checkpoint history is made durable between checkpoints: always after every record, interval per --commit-records/--commit-ms, or os"),
+        )
+        .arg(
+            Arg::new("commit-records")
+                .long("commit-records")
+                .value_name("N")
+                .default_value("1024")
+                .help("Records of history committed together with --fsync interval"),
+        )
+        .arg(
+            Arg::new("commit-ms")
+                .long("commit-ms")
+                .value_name("MS")
+                .default_value("100")
+                .help("Longest a record of history waits to be committed with --fsync interval"),
+        )
+        .arg(
+            Arg::new("checkpoint-dir")
+                .long("checkpoint-dir")
+                .value_name("DIR")
+                .default_value("checkpoints")
+                .help("Directory checkpoints are written to and resumed from"),
+        )
+        .arg(
+            Arg::new("resume")
+                .long("resume")
+                .help("Carry on from the last checkpoint instead of the first row"),
+        )
+        .arg(
+            Arg::new("no-history")
+                .long("no-history")
+                .help("Keep only what disputes need of each transaction, not its records, to save memory"),
+        )
+        .arg(
+            Arg::new("max-memory")
+                .long("max-memory")
+                .value_name("BYTES")
+                .conflicts_with("no-history")
+                .help("Write the records kept of transactions out to a temporary file once they take more than BYTES, and read them back when asked for. What disputes need of each transaction is still kept in memory"),
+        )
+        .arg(
+            Arg::new("spill-dir")
+                .long("spill-dir")
+                .value_name("DIR")
+                .requires("max-memory")
+                .help("Directory for the --max-memory file, the system's temporary directory by default"),
+        )
+        .arg(
+            Arg::new("processed-inputs")
+                .long("processed-inputs")
+                .value_name("CSV")
+                .help("File recording the content hash of each input processed, to catch one fed in twice, processed.csv in --checkpoint-dir when checkpointing"),
+        )
+        .arg(
+            Arg::new("duplicate-inputs")
+                .long("duplicate-inputs")
+                .value_name("POLICY")
+                .possible_values(["refuse", "skip"])
+                .default_value("refuse")
+                .help("Whether an input already in --processed-inputs fails the run or is skipped with a warning"),
+        )
+        .arg(
+            Arg::new("trailer")
+                .long("trailer")
+                .help("Fail the batch unless its record count and amount total match its closing trailer row"),
+        )
+        .arg(
+            Arg::new("suspense")
+                .long("suspense")
+                .help("Park disputes, resolves and chargebacks for unknown transactions until they arrive"),
+        )
+        .arg(
+            Arg::new("foreign-disputes")
+                .long("foreign-disputes")
+                .value_name("POLICY")
+                .possible_values(["reject", "route", "suspense"])
+                .default_value("reject")
+                .help("What to do with disputes, resolves and chargebacks for another client's transaction"),
+        )
+        .arg(
+            Arg::new("unlock-on-representment")
+                .long("unlock-on-representment")
+                .help("Unfreeze an account once won representments reversed all its chargebacks"),
+        )
+        .arg(
+            Arg::new("fraud-lock-after")
+                .long("fraud-lock-after")
+                .value_name("N")
+                .help("Freeze an account once N of its transactions were disputed with reason_code fraud"),
+        )
+        .arg(
+            Arg::new("locked-deposits")
+                .long("locked-deposits")
+                .value_name("POLICY")
+                .possible_values(["accept", "reject", "hold"])
+                .default_value("accept")
+                .help("What to do with deposits to a frozen account: apply them, turn them down, or apply them on admin hold"),
+        )
+        .arg(
+            Arg::new("velocity")
+                .long("velocity")
+                .value_name("N/WINDOW")
+                .help("Freeze an account at N disputes and chargebacks within WINDOW records, or WINDOWs seconds, unless its tier or --clients row sets its own; :flag only warns, e.g. 3/86400s:flag"),
+        )
+        .arg(
+            Arg::new("reserve")
+                .long("reserve")
+                .value_name("AMOUNT")
+                .default_value("0")
+                .help("Reject withdrawals that would leave a client less than AMOUNT available"),
+        )
+        .arg(
+            Arg::new("reserves")
+                .long("reserves")
+                .value_name("CSV")
+                .help("Per client reserves overriding --reserve, with columns client,reserve"),
+        )
+        .arg(
+            Arg::new("credit-limit")
+                .long("credit-limit")
+                .value_name("AMOUNT")
+                .default_value("0")
+                .help("Let withdrawals overdraw a client's available funds by up to AMOUNT"),
+        )
+        .arg(
+            Arg::new("credit-limits")
+                .long("credit-limits")
+                .value_name("CSV")
+                .help("Per client credit limits overriding --credit-limit, with columns client,credit_limit"),
+        )
+        .arg(
+            Arg::new("clients")
+                .long("clients")
+                .value_name("CSV")
+                .help("Client tiers, currencies and limits, with columns client,tier,currency,reserve,credit_limit,max_amount,velocity"),
+        )
+        .arg(
+            Arg::new("id-map")
+                .long("id-map")
+                .value_name("CSV")
+                .help("Canonical ids of migrated clients, with columns client,canonical"),
+        )
+        .arg(
+            Arg::new("enrich")
+                .long("enrich")
+                .value_name("CSV")
+                .help("Merchant, channel and country of transactions for the journal and --deny, with columns tx (an id, or digits and * for a prefix) and any of merchant,channel,country"),
+        )
+        .arg(
+            Arg::new("deny")
+                .long("deny")
+                .value_name("FIELD=VALUE")
+                .multiple_occurrences(true)
+                .requires("enrich")
+                .help("Turn down records enriched with VALUE as their merchant, channel or country, e.g. country=KP"),
+        )
+        .arg(
+            Arg::new("screen")
+                .long("screen")
+                .value_name("CSV")
+                .help("Clients to screen, with columns client,action: block turns down their withdrawals and holds their deposits, flag only reports them"),
+        )
+        .arg(
+            Arg::new("compliance")
+                .long("compliance")
+                .value_name("PATH")
+                .requires("screen")
+                .help("CSV file to write every record of a screened client to, with what became of it"),
+        )
+        .arg(
+            Arg::new("tiers")
+                .long("tiers")
+                .value_name("CSV")
+                .requires("clients")
+                .help("Limits of the tiers in --clients, with columns tier,reserve,credit_limit,max_amount,velocity"),
+        )
+        .arg(
+            Arg::new("dead-letter")
+                .long("dead-letter")
+                .value_name("PATH")
+                .help("CSV file to write unparsable and rejected records to, with the reason"),
+        )
+        .arg(
+            Arg::new("lenient")
+                .long("lenient")
+                .help("Accept amounts like $1,234.56 or (12.50), any case type names and blank rows"),
+        )
+        .arg(
+            Arg::new("scientific")
+                .long("scientific")
+                .value_name("POLICY")
+                .possible_values(["reject", "accept"])
+                .default_value("reject")
+                .help("Whether amounts in scientific notation (1e4) are accepted"),
+        )
+        .arg(
+            Arg::new("excess-precision")
+                .long("excess-precision")
+                .value_name("POLICY")
+                .possible_values(["round", "truncate", "reject"])
+                .default_value("round")
+                .help("What to do with amounts of more than 4 decimal places"),
+        )
+        .arg(
+            Arg::new("max-amount")
+                .long("max-amount")
+                .value_name("AMOUNT")
+                .help("Reject deposits and withdrawals larger than AMOUNT as out of bounds"),
+        )
+        .arg(
+            Arg::new("columns")
+                .long("columns")
+                .value_name("COLUMN=HEADER")
+                .use_value_delimiter(true)
+                .help("Read a column from a differently named input header, e.g. tx=transaction_id"),
+        )
+        .subcommand(
+            Command::new("replay-rejects")
+                .about("Re-submit records from a --dead-letter file and report which now apply")
+                .arg(arg!(<rejects_csv>).help("Dead letter file written by --dead-letter"))
+                .arg(
+                    Arg::new("history")
+                        .long("history")
+                        .value_name("CSV")
+                        .help("Transactions to rebuild client state from first, rather than the state in --checkpoint-dir; dead lettered rows in it are held back"),
+                ),
+        )
+        .subcommand(
+            Command::new("shadow")
+                .about("Run the input with these flags and again with other policies, and list the clients that end up differently")
+                .arg(arg!(<transactions_csv>).help("Input file to run twice"))
+                .arg(
+                    Arg::new("against")
+                        .long("against")
+                        .value_name("KEY=VALUE")
+                        .required(true)
+                        .multiple_occurrences(true)
+                        .use_value_delimiter(true)
+                        .help("Policy flag to set differently for the shadow run, e.g. foreign-disputes=route or fraud-lock-after=none"),
+                ),
+        )
+        .subcommand(
+            Command::new("analyze")
+                .about("Run the input and write percentiles of balances, transaction sizes and dispute latencies across clients")
+                .arg(arg!(<transactions_csv>).help("Input file to analyze")),
+        )
+        .subcommand(
+            Command::new("merge")
+                .about("Write the rows of several inputs as one, with deposits and withdrawals resent in a later file kept only once")
+                .arg(arg!(<inputs>).multiple_values(true).help("Input files (or http:// or https:// URLs), in the order they're applied"))
+                .arg(
+                    Arg::new("cross-file-duplicates")
+                        .long("cross-file-duplicates")
+                        .value_name("POLICY")
+                        .default_value("first-wins")
+                        .help("What to do with a tx id in more than one input: first-wins, error, or latest-wins by timestamp"),
+                )
+                .arg(
+                    Arg::new("duplicates-report")
+                        .long("duplicates-report")
+                        .value_name("PATH")
+                        .help("Where to write how many duplicates each input had, stderr by default"),
+                ),
+        )
+        .subcommand(
+            Command::new("loadtest")
+                .about("Drive the embedded engine with generated records and report throughput, apply latency and memory growth")
+                .arg(
+                    Arg::new("tps")
+                        .long("tps")
+                        .value_name("N")
+                        .default_value("0")
+                        .help("Records a second to submit, 0 for as fast as they're applied"),
+                )
+                .arg(
+                    Arg::new("duration")
+                        .long("duration")
+                        .value_name("SECONDS")
+                        .default_value("10")
+                        .help("How long to keep submitting for"),
+                )
+                .arg(
+                    Arg::new("clients")
+                        .long("clients")
+                        .value_name("N")
+                        .default_value("1000")
+                        .help("Clients to spread the records over"),
+                )
+                .arg(
+                    Arg::new("seed")
+                        .long("seed")
+                        .value_name("N")
+                        .default_value("1")
+                        .help("Seed of the generated records, the same seed makes the same records"),
+                ),
+        )
+        .subcommand(
+            Command::new("verify-db")
+                .about("Apply the input on top of the state in --checkpoint-dir without writing to it, and list the clients it would change")
+                .arg(arg!(<transactions_csv>).help("Input file to try out")),
+        )
+        .subcommand(
+            Command::new("migrate-state")
+                .about("Rewrite the checkpoint files in --checkpoint-dir an older version wrote in the current layout"),
+        )
+        .subcommand(
+            Command::new("export-state")
+                .about("Write the state in --checkpoint-dir to stdout as versioned JSON lines, see the README"),
+        )
+        .subcommand(
+            Command::new("import-state")
+                .about("Checkpoint state exported by export-state into an empty --checkpoint-dir")
+                .arg(arg!(<state_jsonl>).help("Export to import, - for stdin")),
+        )
+        .subcommand(
+            Command::new("apply-decisions")
+                .about("Settle disputes decided in a pending report against the state in --checkpoint-dir")
+                .arg(arg!(<decisions_csv>).help("Report written by --report pending=PATH, with its decision column filled in")),
+        )
+        .subcommand(
+            Command::new("query")
+                .about("Look into the history kept in --checkpoint-dir without re-reading the input")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("client")
+                        .about("A client's balances and disputes as of a point in the run")
+                        .arg(arg!(<client_id>).help("Client to rebuild"))
+                        .arg(
+                            Arg::new("as-of")
+                                .long("as-of")
+                                .value_name("WHEN")
+                                .help("counter=N for just before row N, timestamp=T for after the last record stamped T or earlier; the end of the history otherwise"),
+                        ),
+                ),
+        );
+    let given = command.clone().get_matches();
+    let matches = match configured(command, &given) {
+        Ok(matches) => {
+            init_logger(&matches);
+            matches
         }
-    } else {
-        Err(io::Error::new(io::ErrorKind::InvalidInput, err_str))
-    }
-}
-
-const PRECISION: u32 = 4u32;
-//TODO you've hardcoded a value, if you had more time, you'd make this configurable via clap
-pub fn deserialize_with_precision_of_4<'de, D>(deserializer: D) -> Result<Decimal, D::Error>
-where
-    D: de::Deserializer<'de>,
-{
-    let buf = String::deserialize(deserializer)?;
-    from_string_with_precision(&buf, PRECISION).map_err(de::Error::custom)
-}
-
-fn from_string_with_precision(val: &str, precision: u32) -> Result<Decimal, rust_decimal::Error> {
-    if val.is_empty() {
-        Ok(Decimal::ZERO)
-    } else {
-        Decimal::from_str(val).map(|decimal| decimal.round_dp(precision))
-    }
-}
-
-#[derive(Debug, Copy, Clone, Deserialize)]
-#[serde(rename_all = "lowercase")]
-enum TransactionType {
-    Withdrawal,
-    Deposit,
-    Dispute,
-    Resolve,
-    Chargeback,
-}
-
-#[derive(Deserialize, Debug, Copy, Clone)]
-struct Record {
-    #[serde(rename = "type")]
-    transaction_type: TransactionType,
-    #[serde(rename = "client")]
-    client_id: u16,
-    #[serde(rename = "tx")]
-    transaction_id: u32,
-    #[serde(deserialize_with = "deserialize_with_precision_of_4")]
-    amount: Decimal,
-}
-
-/// as in, a record that has some context. In this case, embedding a "chronological" element.
-/// The app is currently not "stateful" a full implementation would track monotonic_counter offsets
-/// in some crash-safe persistent store to guarantee monotonicty.
-#[derive(Debug, Copy, Clone)]
-struct SituatedRecord {
-    monotonic_counter: usize,
-    record: Record,
-}
-
-#[derive(Debug)]
-struct ClientState {
-    client_id: u16,
-    available_funds: Decimal,
-    held_funds: Decimal,
-    locked: bool,
-    // TODO Vec<SituatedRecord> by convention stores records with the same transaction_id like
-    // [(Withdrawal|Deposit),(Dispute),(Resolution|Chargeback)] in a Vec in that order,
-    // this convention would be better understood with an API
-    client_transactions: HashMap<u32, Vec<SituatedRecord>>,
-}
-
-impl ClientState {
-    fn new(client_id: u16) -> Self {
-        ClientState {
-            client_id,
-            available_funds: Decimal::default(),
-            held_funds: Decimal::default(),
-            locked: false,
-            client_transactions: HashMap::new(),
+        Err(e) => {
+            init_logger(&given);
+            error!("Unable to read the config!\n{}", e);
+            return ExitCode::from(2);
+        }
+    };
+    let spill = match matches.is_present("max-memory") {
+        true => {
+            let dir = matches
+                .value_of("spill-dir")
+                .map_or_else(env::temp_dir, PathBuf::from);
+            match Spill::create(matches.value_of_t_or_exit("max-memory"), &dir) {
+                Ok(spill) => Some(Arc::new(spill)),
+                Err(e) => {
+                    error!("Unable to create spill file in ({:?})!\n{}", dir, e);
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+        false => None,
+    };
+    let id_map = match matches
+        .value_of("id-map")
+        .map(|path| IdMap::read_file(path.as_ref()))
+    {
+        Some(Ok(id_map)) => Some(Arc::new(id_map)),
+        Some(Err(e)) => {
+            error!("Unable to read the id map!\n{}", e);
+            return ExitCode::FAILURE;
+        }
+        None => None,
+    };
+    let enricher = match matches
+        .value_of("enrich")
+        .map(|path| Lookup::read_file(path.as_ref()))
+    {
+        Some(Ok(lookup)) => Some(Arc::new(Enricher {
+            lookup,
+            rules: match matches.is_present("deny") {
+                true => matches
+                    .values_of_t::<Rule>("deny")
+                    .unwrap_or_else(|e| e.exit()),
+                false => vec![],
+            },
+        })),
+        Some(Err(e)) => {
+            error!("Unable to read the enrichment!\n{}", e);
+            return ExitCode::FAILURE;
         }
+        None => None,
+    };
+    let screening = match matches
+        .value_of("screen")
+        .map(|path| Screening::read_file(path.as_ref()))
+    {
+        Some(Ok(screening)) => Some(Arc::new(screening)),
+        Some(Err(e)) => {
+            error!("Unable to read the screening list!\n{}", e);
+            return ExitCode::FAILURE;
+        }
+        None => None,
+    };
+    let messages = match Messages::for_lang(matches.value_of("lang").unwrap()) {
+        Ok(messages) => messages,
+        Err(e) => {
+            error!("Unable to read the --lang catalog!\n{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let shared = Shared {
+        spill,
+        id_map,
+        enricher,
+        screening,
+        messages,
+        limits: match client_limits(&matches) {
+            Ok(client_limits) => Arc::new(client_limits),
+            Err(e) => {
+                error!("Unable to read client limits!\n{}", e);
+                return ExitCode::FAILURE;
+            }
+        },
+    };
+    if let Some(("query", query_matches)) = matches.subcommand() {
+        if let Err(e) = query(&matches, query_matches, &shared) {
+            error!("Unable to query the checkpoint history!\n{}", e);
+            return ExitCode::FAILURE;
+        }
+        return ExitCode::SUCCESS;
     }
-
-    fn get_available_funds(&self) -> Decimal {
-        self.available_funds
+    if let Some(("shadow", shadow_matches)) = matches.subcommand() {
+        match shadow(&matches, shadow_matches, &shared) {
+            Ok(0) => {}
+            Ok(_) => return ExitCode::FAILURE,
+            Err(e) => {
+                error!("Unable to shadow the run!\n{}", e);
+                return ExitCode::from(2);
+            }
+        }
+        return ExitCode::SUCCESS;
     }
-
-    fn get_held_funds(&self) -> Decimal {
-        self.held_funds
+    if let Some(("analyze", analyze_matches)) = matches.subcommand() {
+        if let Err(e) = analyze(&matches, analyze_matches, &shared) {
+            error!("Unable to analyze the run!\n{}", e);
+            return ExitCode::FAILURE;
+        }
+        return ExitCode::SUCCESS;
     }
-
-    fn get_total_funds(&self) -> Decimal {
-        self.held_funds + self.available_funds
+    if let Some(("merge", merge_matches)) = matches.subcommand() {
+        if let Err(e) = merge(&matches, merge_matches) {
+            error!("Unable to merge the inputs!\n{}", e);
+            return ExitCode::FAILURE;
+        }
+        return ExitCode::SUCCESS;
     }
-
-    fn is_locked(&self) -> bool {
-        self.locked
+    if let Some(("loadtest", load_matches)) = matches.subcommand() {
+        let load = Load {
+            tps: Some(load_matches.value_of_t_or_exit("tps")).filter(|tps| *tps > 0),
+            duration: Duration::from_secs_f64(load_matches.value_of_t_or_exit("duration")),
+            clients: load_matches.value_of_t_or_exit("clients"),
+            seed: load_matches.value_of_t_or_exit("seed"),
+        };
+        print!(
+            "{}",
+            loadtest::run(&load, &pipeline_config(&matches, &shared))
+        );
+        return ExitCode::SUCCESS;
     }
-
-    /// return the last processed counter, in a persistent system, this means said record is now durable.
-    /// a crash safe persistent system should indicate the last record it actually processed
-    /// so restarts are possible.
-    fn add_transaction(&mut self, situated_record: SituatedRecord) -> usize {
-        let tx_id = situated_record.record.transaction_id;
-        let transact = self.transact(situated_record);
-        if transact {
-            self.push_transaction(tx_id, situated_record);
+    if let Some(("verify-db", verify_matches)) = matches.subcommand() {
+        if let Err(e) = verify_db(&matches, verify_matches, &shared) {
+            error!(
+                "Unable to verify the input against the checkpointed state!\n{}",
+                e
+            );
+            return ExitCode::FAILURE;
         }
-        situated_record.monotonic_counter
+        return ExitCode::SUCCESS;
     }
-
-    fn transact_withdrawal_or_deposit(&mut self, situated_record: SituatedRecord) -> bool {
-        let amount = situated_record.record.amount;
-        let tx_type = situated_record.record.transaction_type;
-        let tx_id = situated_record.record.transaction_id;
-        match (tx_type, self.locked) {
-            (TransactionType::Withdrawal, false) => {
-                if amount <= self.available_funds {
-                    self.available_funds -= amount;
-                } else {
-                    warn!(
-                        "Withdrawal ({}) failed to withdraw due to insufficient funds.",
-                        tx_id
+    if let Some(("migrate-state", _)) = matches.subcommand() {
+        let dir = PathBuf::from(matches.value_of("checkpoint-dir").unwrap_or("checkpoints"));
+        match migrate::upgrade(&dir) {
+            Ok(upgraded) => {
+                for (artifact, version) in &upgraded {
+                    eprintln!(
+                        "Migrated {:?} from version {} to {}.",
+                        artifact,
+                        version,
+                        migrate::VERSION
                     );
                 }
-                true
+                if upgraded.is_empty() {
+                    eprintln!("Already at version {}.", migrate::VERSION);
+                }
             }
-            (TransactionType::Withdrawal, true) => {
-                warn!(
-                    "Withdrawal ({}) failed to process because client account ({}) is frozen.",
-                    tx_id, self.client_id
-                );
-                true
+            Err(e) => {
+                error!("Unable to migrate the checkpointed state!\n{}", e);
+                return ExitCode::FAILURE;
             }
-            (TransactionType::Deposit, _) => {
-                self.available_funds += amount;
-                true
+        }
+        return ExitCode::SUCCESS;
+    }
+    if let Some(("export-state", _)) = matches.subcommand() {
+        let dir = PathBuf::from(matches.value_of("checkpoint-dir").unwrap_or("checkpoints"));
+        match state::export(
+            &dir,
+            &pipeline_config(&matches, &shared),
+            io::stdout().lock(),
+        ) {
+            Ok(clients) => eprintln!("Exported {} clients.", clients),
+            Err(e) => {
+                error!("Unable to export the checkpointed state!\n{}", e);
+                return ExitCode::FAILURE;
             }
-            (_, _) => false,
         }
+        return ExitCode::SUCCESS;
     }
-
-    fn push_transaction(&mut self, tx_id: u32, record: SituatedRecord) {
-        self.client_transactions
-            .entry(tx_id)
-            .or_insert_with(Vec::new)
-            .push(record);
+    if let Some(("import-state", import_matches)) = matches.subcommand() {
+        if let Err(e) = import_state(&matches, import_matches, &shared) {
+            error!("Unable to import state!\n{}", e);
+            return ExitCode::FAILURE;
+        }
+        return ExitCode::SUCCESS;
+    }
+    if let Some(("apply-decisions", decisions_matches)) = matches.subcommand() {
+        if let Err(e) = apply_decisions(&matches, decisions_matches, &shared) {
+            error!("Unable to apply decisions!\n{}", e);
+            return ExitCode::FAILURE;
+        }
+        return ExitCode::SUCCESS;
     }
+    if let Some(("replay-rejects", replay_matches)) = matches.subcommand() {
+        if let Err(e) = replay_rejects(&matches, replay_matches, &shared) {
+            error!("Encountered error while replaying rejects!\n{}", e);
+            return ExitCode::FAILURE;
+        }
+        return ExitCode::SUCCESS;
+    }
+    let key = match matches.value_of("sign-key").map(read_key).transpose() {
+        Ok(key) => key,
+        Err(e) => {
+            error!("Unable to read signing key!\n{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Some(path) = matches.value_of("verify") {
+        match journal::verify_file(path.as_ref(), key.as_deref()) {
+            Ok(verified) => println!("{} journal entries verified.", verified),
+            Err(e) => {
+                eprintln!("Journal verification failed!\n{}", e);
+                return ExitCode::FAILURE;
+            }
+        }
+        return ExitCode::SUCCESS;
+    }
+    let mut pipeline_config = pipeline_config(&matches, &shared);
+    let filter = match matches
+        .value_of("where")
+        .map(str::parse::<Filter>)
+        .transpose()
+    {
+        Ok(filter) => filter,
+        Err(e) => {
+            error!("Invalid --where filter!\n{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let amounts = amount_format(&matches);
+    let output_columns = match output_columns(&matches) {
+        Ok(output_columns) => output_columns,
+        Err(e) => {
+            error!("Invalid output columns!\n{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let columns = match ColumnMap::parse(matches.values_of("columns").into_iter().flatten()) {
+        Ok(columns) => columns,
+        Err(e) => {
+            error!("Invalid column mapping!\n{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
 
-    fn transact(&mut self, situated_record: SituatedRecord) -> bool {
-        let tx_id = situated_record.record.transaction_id;
-        let client_id = situated_record.record.client_id;
-        let len = if let Some(vec) = self
-            .client_transactions
-            .get(&situated_record.record.transaction_id)
-        {
-            vec.len()
-        } else {
-            0
-        };
-        trace!(
-            "Type {:?}, id {}, len of transactions vec is {}.",
-            situated_record.record.transaction_type,
-            tx_id,
-            len
-        );
-        match (situated_record.record.transaction_type, self.locked) {
-            (TransactionType::Withdrawal | TransactionType::Deposit, _) => {
-                // must have original withdrawal/deposit transaction ids
-                if len == 0 {
-                    self.transact_withdrawal_or_deposit(situated_record)
-                } else {
-                    warn!("Record of type ({:?}) is re-using existent transaction id ({}), this is not allowed!)", situated_record.record.transaction_type, tx_id);
-                    false
+    let reports = match matches
+        .values_of("report")
+        .into_iter()
+        .flatten()
+        .map(str::parse)
+        .collect::<Result<Vec<Report>, String>>()
+    {
+        Ok(reports) => reports,
+        Err(e) => {
+            error!("Invalid report!\n{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let notifier = match webhooks(&matches) {
+        Ok(notifier) => notifier,
+        Err(e) => {
+            error!("Invalid webhook configuration!\n{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    // what could still exit over a malformed flag is read before the input is spooled, as exiting
+    // leaves the spool behind
+    let duplicates: DuplicatePolicy = matches.value_of_t_or_exit("duplicate-inputs");
+    let thresholds = Thresholds {
+        z: matches.value_of_t_or_exit("anomaly-z"),
+        alpha: matches.value_of_t_or_exit("anomaly-alpha"),
+        warmup: matches.value_of_t_or_exit("anomaly-warmup"),
+    };
+    let top: usize = matches.value_of_t_or_exit("top");
+    let input = matches.value_of("transactions_csv").map(|s| s.as_ref());
+    // checkpointed runs keep them with the rest of their state unless told otherwise
+    let processed_inputs = match matches.value_of("processed-inputs") {
+        Some(path) => Some(PathBuf::from(path)),
+        None if matches.is_present("checkpoint-every") => Some(
+            PathBuf::from(matches.value_of("checkpoint-dir").unwrap_or("checkpoints"))
+                .join(PROCESSED),
+        ),
+        None => None,
+    };
+    let mut processed = match processed_inputs {
+        Some(path) => {
+            match Processed::open(&path).and_then(|processed| {
+                Ok((
+                    processed,
+                    Spooled::create(source::open(input)?, &env::temp_dir())?,
+                ))
+            }) {
+                Ok(processed) => Some(processed),
+                Err(e) => {
+                    error!("Unable to check the input against processed inputs!\n{}", e);
+                    return ExitCode::FAILURE;
                 }
             }
-            //TOD0 self.locked needs to behave differently for disputes/resolves/chargebacks
-            (TransactionType::Dispute, false) => {
-                if len == 1 {
-                    self.transact_dispute(situated_record)
-                } else {
-                    warn!("Dispute [transaction_id={}, client_id={}] will be ignored as it either does not exist or has already been addressed.", tx_id, client_id);
-                    false
+        }
+        None => None,
+    };
+    if let Some((processed, spooled)) = &processed {
+        if processed.contains(&spooled.hash) {
+            match duplicates {
+                DuplicatePolicy::Refuse => {
+                    eprintln!(
+                        "Input ({}) was already processed, refusing to apply it again.",
+                        spooled.hash
+                    );
+                    return ExitCode::FAILURE;
                 }
-            }
-            (TransactionType::Resolve | TransactionType::Chargeback, false) => {
-                if len == 2 {
-                    self.transaction_resolution(situated_record)
-                } else {
-                    warn!("Resolution/Chargeback for transaction ({}) will be ignored as it has already been addressed.", tx_id);
-                    false
+                DuplicatePolicy::Skip => {
+                    warn!(
+                        "Input ({}) was already processed, skipping it.",
+                        spooled.hash
+                    );
+                    return ExitCode::SUCCESS;
                 }
             }
-            (
-                TransactionType::Resolve | TransactionType::Chargeback | TransactionType::Dispute,
-                true,
-            ) => {
-                warn!(
-                    "Resolution/Chargeback/Dispute  ({}) failed to process because client account ({}) is frozen.",
-                    tx_id, client_id);
-                false
-            }
         }
     }
+    // the run reads the copy that was hashed
+    let str = match &processed {
+        Some((_, spooled)) => Some(spooled.path.as_os_str()),
+        None => input,
+    };
 
-    fn transact_dispute(&mut self, dispute: SituatedRecord) -> bool {
-        let tx_id = dispute.record.transaction_id;
-        if let Some(all_prev_record) = self.client_transactions.get(&tx_id) {
-            let disputed_target = all_prev_record.iter().find(|record| {
-                matches!(record.record.transaction_type, TransactionType::Withdrawal)
-                    || matches!(record.record.transaction_type, TransactionType::Deposit)
-            });
-            if let Some(disputed_target) = disputed_target {
-                match disputed_target.record.transaction_type {
-                    TransactionType::Withdrawal => {
-                        let prev_amount = disputed_target.record.amount;
-                        self.held_funds += prev_amount;
-                    }
-                    TransactionType::Deposit => {
-                        let prev_amount = disputed_target.record.amount;
-                        self.available_funds -= prev_amount;
-                        self.held_funds += prev_amount;
-                    }
-                    _ => {}
-                }
-                true
-            } else {
-                warn!("Dispute for transaction id ({:?}) will be ignored as it does not refer to an extant withdrawal or deposit.", tx_id);
-                false
+    let dead_letters = match matches.value_of("dead-letter").map(PathBuf::from) {
+        Some(path) => match DeadLetterQueue::create(&path) {
+            Ok(queue) => Some(queue),
+            Err(e) => {
+                error!("Unable to create dead letter file ({:?})!\n{}", path, e);
+                return ExitCode::FAILURE;
             }
-        } else {
-            error!("Internal state of records for transaction id ({}) is incorrect, offending transaction history: {:?}.", tx_id, self.client_transactions.get(&tx_id));
-            false
-        }
-    }
+        },
+        None => None,
+    };
 
-    fn transaction_resolution(&mut self, resolution: SituatedRecord) -> bool {
-        let tx_id = resolution.record.transaction_id;
-        if let Some(all_prev_record) = self.client_transactions.get(&tx_id) {
-            let prev_record = all_prev_record.iter().find(|record| {
-                matches!(record.record.transaction_type, TransactionType::Withdrawal)
-                    || matches!(record.record.transaction_type, TransactionType::Deposit)
-            });
-            let transact = if let Some(prev_record) = prev_record {
-                (
-                    Some(prev_record.record.transaction_type),
-                    Some(prev_record.record.amount),
-                )
-            } else {
-                error!("Resolve for transaction id ({}) will be ignored as it does not refer to an existing withdrawal or deposit.", tx_id);
-                (None, None)
-            };
-            match transact {
-                (Some(tx_type), Some(tx_amount)) => match resolution.record.transaction_type {
-                    TransactionType::Resolve => self.transact_resolve(tx_type, tx_amount),
-                    TransactionType::Chargeback => self.transact_chargeback(tx_type, tx_amount),
-                    _ => false,
-                },
-                (_, _) => false,
+    let journal = match matches.value_of("journal").map(PathBuf::from) {
+        Some(path) => match Journal::create(
+            &path,
+            key,
+            pseudonyms(&matches),
+            pipeline_config.enricher.clone(),
+        ) {
+            Ok(journal) => Some(journal),
+            Err(e) => {
+                error!("Unable to create journal file ({:?})!\n{}", path, e);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+
+    let changes = match matches.value_of("changes").map(PathBuf::from) {
+        Some(path) => match ChangeLog::create(&path, pseudonyms(&matches)) {
+            Ok(changes) => Some(changes),
+            Err(e) => {
+                error!("Unable to create change file ({:?})!\n{}", path, e);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+
+    let anomalies = match matches.value_of("anomalies").map(PathBuf::from) {
+        Some(path) => match Anomalies::create(&path, thresholds, pseudonyms(&matches)) {
+            Ok(anomalies) => Some(anomalies),
+            Err(e) => {
+                error!("Unable to create anomalies file ({:?})!\n{}", path, e);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+
+    let compliance = match matches.value_of("compliance").map(PathBuf::from) {
+        Some(path) => match Compliance::create(&path, pseudonyms(&matches)) {
+            Ok(compliance) => Some(compliance),
+            Err(e) => {
+                error!("Unable to create compliance file ({:?})!\n{}", path, e);
+                return ExitCode::FAILURE;
             }
-        } else {
-            error!("Internal state of records for transaction id ({}) is incorrect, offending transaction history: {:?}.", tx_id, self.client_transactions.get(&tx_id));
-            false
+        },
+        None => None,
+    };
+
+    debug!("Given filepath: {:?}.", &str);
+    let mut clients = HashMap::new();
+    let mut checkpoints = match checkpoints(&matches, &mut clients) {
+        Ok(Some((checkpoints, resume_from))) => {
+            pipeline_config.resume_from = resume_from;
+            Some(checkpoints)
+        }
+        Ok(None) => None,
+        Err(e) => {
+            error!("Unable to set up checkpoints!\n{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let tally = matches
+        .is_present("manifest")
+        .then(|| Arc::new(Tally::default()));
+    let sinks = Sinks {
+        events: notifier.as_ref().map(|notifier| notifier.sender().clone()),
+        dead_letters: dead_letters.as_ref().map(|queue| queue.sender().clone()),
+        journal: journal.as_ref().map(|journal| journal.sender().clone()),
+        history: checkpoints
+            .as_ref()
+            .map(|checkpoints| checkpoints.sender().clone()),
+        changes: changes.as_ref().map(|changes| changes.sender().clone()),
+        anomalies: anomalies
+            .as_ref()
+            .map(|anomalies| anomalies.sender().clone()),
+        tally: tally.clone(),
+        compliance: compliance
+            .as_ref()
+            .map(|compliance| compliance.sender().clone()),
+    };
+    shutdown::install();
+    let played = play_with_money(
+        str,
+        &pipeline_config,
+        &columns,
+        &sinks,
+        checkpoints.as_mut(),
+        &mut clients,
+    );
+    drop(sinks);
+    if let Some(checkpoints) = checkpoints {
+        if let Err(e) = checkpoints.finish() {
+            error!("Unable to write checkpoint history!\n{}", e);
+        }
+    }
+    if let Some(notifier) = notifier {
+        notifier.finish();
+    }
+    if let Some(queue) = dead_letters {
+        match queue.finish() {
+            Ok(written) => debug!("Dead lettered {} records.", written),
+            Err(e) => error!("Unable to write dead letter file!\n{}", e),
+        }
+    }
+    if let Some(journal) = journal {
+        match journal.finish() {
+            Ok(written) => debug!("Journaled {} records.", written),
+            Err(e) => error!("Unable to write journal file!\n{}", e),
+        }
+    }
+    if let Some(changes) = changes {
+        match changes.finish() {
+            Ok(written) => debug!("Wrote {} balance changes.", written),
+            Err(e) => error!("Unable to write change file!\n{}", e),
+        }
+    }
+    if let Some(anomalies) = anomalies {
+        match anomalies.finish() {
+            Ok(written) => debug!("Flagged {} anomalies.", written),
+            Err(e) => error!("Unable to write anomalies file!\n{}", e),
+        }
+    }
+    if let Some(compliance) = compliance {
+        match compliance.finish() {
+            Ok(written) => debug!("Reported {} records of screened clients.", written),
+            Err(e) => error!("Unable to write compliance file!\n{}", e),
+        }
+    }
+    if let (Ok(_), None, Some((processed, spooled))) =
+        (&played, shutdown::requested(), processed.as_mut())
+    {
+        let input = matches.value_of("transactions_csv").unwrap_or_default();
+        if let Err(e) = processed.record(&spooled.hash, input) {
+            error!("Unable to record the input as processed!\n{}", e);
         }
     }
-    fn transact_resolve(&mut self, prev_type: TransactionType, tx_amount: Decimal) -> bool {
-        match prev_type {
-            TransactionType::Withdrawal | TransactionType::Deposit => {
-                self.held_funds -= tx_amount;
-                self.available_funds += tx_amount;
-                true
+    // --where narrows what's written, not the checks and totals of the whole run
+    let selected = filter
+        .as_ref()
+        .filter(|_| played.is_ok())
+        .map(|filter| filter.select(&clients));
+    let shown = |clients| selected.as_ref().unwrap_or(clients);
+    let mut written = vec![];
+    let status = match played {
+        Ok(_) => match write_output(
+            &matches,
+            shown(&clients),
+            &output_columns,
+            amounts,
+            &pipeline_config.pseudonyms,
+        ) {
+            Ok(outputs) => {
+                written = outputs;
+                Conservation::check(&clients).warn(&pipeline_config.pseudonyms);
+                let parked: usize = clients.values().map(|c| c.parked().len()).sum();
+                if parked > 0 {
+                    warn!(
+                        "{} records are still parked waiting for their transaction, see --report suspense=PATH.",
+                        parked
+                    );
+                }
+                let quiet = matches.is_present("quiet");
+                if !quiet
+                    && (matches.is_present("summary") || matches.occurrences_of("verbose") > 1)
+                {
+                    eprint!(
+                        "{}",
+                        Summary::of(&clients, top, pipeline_config.pseudonyms.clone())
+                            .localized(&pipeline_config.messages)
+                    );
+                }
+                if matches.is_present("run-hash") {
+                    eprint!("{}", RunHash::of(&clients));
+                }
+                for report in &reports {
+                    if let Err(e) =
+                        report.write_file(shown(&clients), amounts, &pipeline_config.pseudonyms)
+                    {
+                        error!("Unable to write {} report!\n{}", report.kind.name(), e);
+                    }
+                }
+                if let Some(profiler) = pipeline_config.profiler.as_ref().filter(|_| !quiet) {
+                    eprint!("{}", profiler);
+                }
+                debug!("done processing!");
+                "ok"
+            }
+            Err(e) => {
+                error!("Encountered error while processing data!\n{}", e);
+                "failed"
             }
-            _ => false,
+        },
+        Err(e) => {
+            error!("Encountered error while processing data!\n{}", e);
+            "failed"
+        }
+    };
+    if let Some(path) = matches.value_of("manifest") {
+        let status = match shutdown::requested() {
+            Some(_) => "interrupted",
+            None => status,
+        };
+        if let Err(e) = write_manifest(&matches, path, tally.as_deref(), &reports, written, status)
+        {
+            error!("Unable to write the manifest!\n{}", e);
         }
     }
+    if let Some(signal) = shutdown::requested() {
+        // the balances written are only as of the row reading stopped at
+        error!(
+            "Interrupted by signal ({}) before the end of the input.",
+            signal
+        );
+        return ExitCode::from((128 + signal) as u8);
+    }
+    match status {
+        "ok" => ExitCode::SUCCESS,
+        _ => ExitCode::FAILURE,
+    }
+}
 
-    fn transact_chargeback(&mut self, prev_type: TransactionType, tx_amount: Decimal) -> bool {
-        match prev_type {
-            TransactionType::Withdrawal | TransactionType::Deposit => {
-                self.held_funds -= tx_amount;
-                self.locked = true;
-                true
-            }
-            _ => false,
+/// Log at the level -q or -v asks for, or as RUST_LOG says without either, nothing by default.
+/// Someone watching a terminal gets it laid out for reading, anything else the usual lines.
+fn init_logger(matches: &clap::ArgMatches) {
+    let level = match (
+        matches.is_present("quiet"),
+        matches.occurrences_of("verbose"),
+    ) {
+        (true, _) => Some(LevelFilter::Off),
+        (false, 0) => None,
+        (false, 1) => Some(LevelFilter::Warn),
+        (false, 2) => Some(LevelFilter::Info),
+        (false, 3) => Some(LevelFilter::Debug),
+        (false, _) => Some(LevelFilter::Trace),
+    };
+    let mut builder = match level {
+        Some(level) => {
+            let mut builder = Builder::new();
+            builder.filter_level(level);
+            builder
         }
+        None => Builder::from_env(Env::default().default_filter_or("off")),
+    };
+    if console::is_terminal() {
+        builder.format(console::format);
     }
+    builder.init();
 }
 
-fn process_record(situated_record: SituatedRecord, clients: &mut HashMap<u16, ClientState>) {
-    let client_id = situated_record.record.client_id;
-    if let Some(client_state) = clients.get_mut(&client_id) {
-        client_state.add_transaction(situated_record);
-    } else {
-        let mut client_state = ClientState::new(client_id);
-        client_state.add_transaction(situated_record);
-        clients.insert(client_id, client_state);
+/// The command line parsed again with the options of the --config file, and its --config-profile,
+/// that it doesn't give ahead of it; as it is without --config.
+fn configured(command: Command, matches: &clap::ArgMatches) -> io::Result<clap::ArgMatches> {
+    let Some(path) = matches.value_of("config") else {
+        return Ok(matches.clone());
+    };
+    let config = Config::read_file(Path::new(path))?;
+    let args = config.args(matches.value_of("config-profile"), |key| {
+        matches.occurrences_of(key) > 0
+    })?;
+    let mut argv = env::args_os();
+    Ok(command.get_matches_from(
+        argv.next()
+            .into_iter()
+            .chain(args.into_iter().map(OsString::from))
+            .chain(argv),
+    ))
+}
+
+/// the balances to stdout, or split over --output-shards files, and what was written with its
+/// hash when there's a --manifest to list it in.
+fn write_output(
+    matches: &clap::ArgMatches,
+    clients: &HashMap<ClientId, ClientState>,
+    output_columns: &OutputColumns,
+    amounts: AmountFormat,
+    pseudonyms: &Pseudonyms,
+) -> io::Result<Vec<Hashed>> {
+    match matches.is_present("output-shards") {
+        true => {
+            let dir = PathBuf::from(matches.value_of("output-dir").unwrap_or("balances"));
+            let parts = partition::write(
+                &dir,
+                flag(matches, "output-shards")?,
+                clients,
+                output_columns,
+                amounts,
+                pseudonyms,
+            )?;
+            debug!("Wrote {} balance files to ({:?}).", parts.len(), dir);
+            let mut written: Vec<Hashed> = parts
+                .into_iter()
+                .map(|part| Hashed {
+                    path: dir.join(part.file).to_string_lossy().to_string(),
+                    sha256: part.sha256,
+                })
+                .collect();
+            let manifest = dir.join(partition::MANIFEST);
+            written.push(Hashed::of(&manifest.to_string_lossy())?);
+            Ok(written)
+        }
+        false if matches.is_present("manifest") => {
+            let mut balances = vec![];
+            write_client_state_to(&mut balances, clients, output_columns, amounts, pseudonyms)?;
+            io::Write::write_all(&mut io::stdout().lock(), &balances)?;
+            Ok(vec![Hashed::read(
+                "-",
+                Box::new(io::Cursor::new(balances)),
+            )?])
+        }
+        false => {
+            write_client_state(clients, output_columns, amounts, pseudonyms)?;
+            Ok(vec![])
+        }
+    }
+}
+
+/// Describe the run in the --manifest file: the input, the balances `written` and every other
+/// file the run wrote, with their hashes.
+fn write_manifest(
+    matches: &clap::ArgMatches,
+    path: &str,
+    tally: Option<&Tally>,
+    reports: &[Report],
+    mut written: Vec<Hashed>,
+    status: &'static str,
+) -> io::Result<()> {
+    let inputs = ["transactions_csv", "config"]
+        .into_iter()
+        .filter_map(|name| matches.value_of(name))
+        .map(Hashed::of)
+        .collect::<io::Result<Vec<_>>>()?;
+    let reports = reports
+        .iter()
+        .map(|report| report.path.to_string_lossy().to_string());
+    let files = [
+        "dead-letter",
+        "journal",
+        "changes",
+        "anomalies",
+        "compliance",
+    ]
+    .into_iter()
+    .filter_map(|name| matches.value_of(name).map(str::to_string));
+    for file in reports.chain(files) {
+        if Path::new(&file).exists() {
+            written.push(Hashed::of(&file)?);
+        }
     }
+    let manifest = Manifest {
+        args: env::args().skip(1).collect(),
+        inputs,
+        tally: tally.map(Tally::counts).unwrap_or_default(),
+        outputs: written,
+        status,
+    };
+    std::fs::write(path, manifest.to_json() + "\n")
 }
 
-fn get_reader(path: &Path) -> Result<Reader<File>, csv::Error> {
-    let reader = ReaderBuilder::new().trim(Trim::All).from_path(path);
-    reader
+fn replay_rejects(
+    matches: &clap::ArgMatches,
+    replay_matches: &clap::ArgMatches,
+    shared: &Shared,
+) -> io::Result<()> {
+    let rejects_input = validate_input(replay_matches.value_of("rejects_csv").map(OsStr::new))?;
+    let history = match replay_matches.value_of("history") {
+        Some(history) => Some(validate_input(Some(OsStr::new(history)))?),
+        None => None,
+    };
+    let columns = ColumnMap::parse(matches.values_of("columns").into_iter().flatten())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let pipeline_config = pipeline_config(matches, shared);
+    let dir = PathBuf::from(matches.value_of("checkpoint-dir").unwrap_or("checkpoints"));
+    let replayed = replay::replay_file(rejects_input, history, &dir, &pipeline_config, &columns)?;
+    replay::write_report(io::stdout(), &replayed, &pipeline_config.pseudonyms)?;
+    Ok(())
 }
 
-fn play_with_money(
-    input: Option<&OsStr>,
-    clients: &mut HashMap<u16, ClientState>,
+fn query(
+    matches: &clap::ArgMatches,
+    query_matches: &clap::ArgMatches,
+    shared: &Shared,
 ) -> io::Result<()> {
-    let records_input = validate_input(input)?;
-    let reader = get_reader(records_input)?;
-    for (monotonic_counter, record) in reader.into_deserialize().enumerate() {
-        let record = record?;
-        let situated_record = SituatedRecord {
-            monotonic_counter,
-            record,
+    let dir = PathBuf::from(matches.value_of("checkpoint-dir").unwrap_or("checkpoints"));
+    if let Some(("client", client_matches)) = query_matches.subcommand() {
+        let client_id = client_matches.value_of_t_or_exit("client_id");
+        let as_of = match client_matches.is_present("as-of") {
+            true => client_matches.value_of_t_or_exit("as-of"),
+            false => AsOf::Counter(usize::MAX),
         };
-        process_record(situated_record, clients);
+        let client =
+            query::client_as_of(&dir, client_id, as_of, &pipeline_config(matches, shared))?;
+        query::write_client(
+            io::stdout(),
+            &client,
+            amount_format(matches),
+            &pseudonyms(matches),
+        )?;
     }
     Ok(())
 }
 
-fn main() {
-    Builder::from_env(Env::default().default_filter_or("off")).init();
+/// run the input as the flags say and again with the shadow settings, write the clients that
+/// diverge and return how many do.
+fn shadow(
+    matches: &clap::ArgMatches,
+    shadow_matches: &clap::ArgMatches,
+    shared: &Shared,
+) -> io::Result<usize> {
+    let input = validate_input(shadow_matches.value_of("transactions_csv").map(OsStr::new))?;
+    let columns = ColumnMap::parse(matches.values_of("columns").into_iter().flatten())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let primary = pipeline_config(matches, shared);
+    let mut against = primary.clone();
+    for setting in shadow_matches.values_of("against").into_iter().flatten() {
+        setting
+            .parse::<Setting>()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?
+            .apply(&mut against);
+    }
+    let (clients, divergences) = shadow::run(input.as_os_str(), &primary, &against, &columns)?;
+    shadow::write_report(
+        io::stdout(),
+        &divergences,
+        "shadow_",
+        amount_format(matches),
+        &primary.pseudonyms,
+    )?;
+    eprintln!("{} of {} clients diverge.", divergences.len(), clients);
+    Ok(divergences.len())
+}
 
-    let matches = command!()
-        .arg(
-            arg!([transactions_csv])
-                .help("CSV file containing chronological list of client transactions"),
-        )
-        .get_matches();
-    let str = matches.value_of("transactions_csv").map(|s| s.as_ref());
+fn analyze(
+    matches: &clap::ArgMatches,
+    analyze_matches: &clap::ArgMatches,
+    shared: &Shared,
+) -> io::Result<()> {
+    let input = validate_input(analyze_matches.value_of("transactions_csv").map(OsStr::new))?;
+    let columns = ColumnMap::parse(matches.values_of("columns").into_iter().flatten())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let analysis = analyze::run(
+        input.as_os_str(),
+        &pipeline_config(matches, shared),
+        &columns,
+    )?;
+    analysis.write(io::stdout())?;
+    Ok(())
+}
 
-    debug!("Given filepath: {:?}.", &str);
-    let mut clients = HashMap::new();
-    match play_with_money(str, &mut clients) {
-        Ok(_) => match write_client_state(&clients) {
-            Ok(_) => {
-                debug!("done processing!");
-            }
-            Err(e) => {
-                error!("Encountered error while processing data!\n{}", e);
-            }
+/// --output-columns, or the standard columns and with --extended-output the extended ones.
+fn output_columns(matches: &clap::ArgMatches) -> Result<OutputColumns, String> {
+    match matches.values_of("output-columns") {
+        Some(names) => OutputColumns::parse(names),
+        None => Ok(OutputColumns::standard(
+            matches.is_present("extended-output"),
+        )),
+    }
+}
+
+/// how amounts are written in the balances and reports.
+fn amount_format(matches: &clap::ArgMatches) -> AmountFormat {
+    AmountFormat {
+        scale: matches
+            .is_present("output-scale")
+            .then(|| matches.value_of_t_or_exit("output-scale")),
+        trim_zeros: matches.is_present("trim-zeros"),
+    }
+}
+
+/// the pipeline as the flags set it up, for a run that starts at the first row.
+fn pipeline_config(matches: &clap::ArgMatches, shared: &Shared) -> PipelineConfig {
+    PipelineConfig {
+        read_queue_capacity: matches.value_of_t_or_exit("read-queue-capacity"),
+        parse_queue_capacity: matches.value_of_t_or_exit("parse-queue-capacity"),
+        workers: matches.value_of_t_or_exit("workers"),
+        reorder_window: matches.value_of_t_or_exit("reorder-window"),
+        max_skew: matches
+            .is_present("max-skew")
+            .then(|| matches.value_of_t_or_exit("max-skew")),
+        lenient: matches.is_present("lenient"),
+        id_map: shared.id_map.clone(),
+        max_amount: matches
+            .is_present("max-amount")
+            .then(|| matches.value_of_t_or_exit("max-amount")),
+        disallowed: match matches.is_present("disallow") {
+            true => matches
+                .values_of_t::<TransactionType>("disallow")
+                .unwrap_or_else(|e| e.exit())
+                .into_iter()
+                .collect(),
+            false => TransactionTypes::default(),
         },
-        Err(e) => {
-            error!("Encountered error while processing data!\n{}", e);
-        }
+        enricher: shared.enricher.clone(),
+        screening: shared.screening.clone(),
+        amount_policy: AmountPolicy {
+            scientific: matches.value_of_t_or_exit("scientific"),
+            excess_precision: matches.value_of_t_or_exit("excess-precision"),
+        },
+        resume_from: 0,
+        retain_history: !matches.is_present("no-history"),
+        suspense: matches.is_present("suspense"),
+        foreign_disputes: matches.value_of_t_or_exit("foreign-disputes"),
+        unlock_on_representment: matches.is_present("unlock-on-representment"),
+        fraud_lock_after: matches
+            .is_present("fraud-lock-after")
+            .then(|| matches.value_of_t_or_exit("fraud-lock-after")),
+        locked_deposits: matches.value_of_t_or_exit("locked-deposits"),
+        velocity: matches
+            .is_present("velocity")
+            .then(|| matches.value_of_t_or_exit("velocity")),
+        limits: shared.limits.clone(),
+        spill: shared.spill.clone(),
+        trailer: matches.is_present("trailer"),
+        chaos: matches
+            .is_present("chaos")
+            .then(|| matches.value_of_t_or_exit("chaos")),
+        pseudonyms: pseudonyms(matches),
+        messages: shared.messages.clone(),
+        profiler: matches.is_present("profile").then(|| {
+            Arc::new(Profiler::new(
+                matches.value_of_t_or_exit::<Sampling>("profile"),
+            ))
+        }),
+        interrupted: shutdown::is_requested,
     }
 }
 
-fn write_client_state(clients: &HashMap<u16, ClientState>) -> Result<(), csv::Error> {
-    let mut wtr = csv::Writer::from_writer(io::stdout());
-    wtr.write_record(&["client", "available", "held", "total", "locked"])?;
-    for x in clients.keys() {
-        let client = clients.get(x);
-        if let Some(client) = client {
-            wtr.write_record(&[
-                format!("{}", client.client_id),
-                format!("{}", client.get_available_funds()),
-                format!("{}", client.get_held_funds()),
-                format!("{}", client.get_total_funds()),
-                format!("{}", client.is_locked()),
-            ])?;
-        }
+fn pseudonyms(matches: &clap::ArgMatches) -> Pseudonyms {
+    matches
+        .value_of("pseudonymize")
+        .map_or_else(Pseudonyms::default, Pseudonyms::salted)
+}
+
+/// settle the decided disputes against the state checkpointed in --checkpoint-dir, checkpoint
+/// the result and write the balances as a run would.
+fn apply_decisions(
+    matches: &clap::ArgMatches,
+    decisions_matches: &clap::ArgMatches,
+    shared: &Shared,
+) -> io::Result<()> {
+    let pipeline_config = &pipeline_config(matches, shared);
+    let dir = PathBuf::from(matches.value_of("checkpoint-dir").unwrap_or("checkpoints"));
+    let mut clients = HashMap::new();
+    let (mut checkpoints, next) =
+        Checkpoints::reopen(&dir, pipeline_config, amount_format(matches), &mut clients)?;
+    let decided = decisions::read(
+        File::open(
+            decisions_matches
+                .value_of("decisions_csv")
+                .unwrap_or_default(),
+        )?,
+        &clients,
+        &pipeline_config.pseudonyms,
+    )?;
+    let sinks = Sinks {
+        history: Some(checkpoints.sender().clone()),
+        ..Sinks::default()
+    };
+    let rejected = decisions::apply(&decided, next, &mut clients, pipeline_config, &sinks);
+    drop(sinks);
+    let saved: Vec<_> = clients.values().map(ClientState::save).collect();
+    checkpoints.save(next + decided.len(), &saved)?;
+    checkpoints.finish()?;
+    let output_columns =
+        output_columns(matches).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    write_client_state(
+        &clients,
+        &output_columns,
+        amount_format(matches),
+        &pipeline_config.pseudonyms,
+    )?;
+    eprintln!(
+        "{} decisions applied, {} turned down.",
+        decided.len() - rejected.len(),
+        rejected.len()
+    );
+    Ok(())
+}
+
+fn merge(matches: &clap::ArgMatches, merge_matches: &clap::ArgMatches) -> io::Result<()> {
+    let inputs: Vec<&OsStr> = merge_matches
+        .values_of("inputs")
+        .into_iter()
+        .flatten()
+        .map(OsStr::new)
+        .collect();
+    let columns = ColumnMap::parse(matches.values_of("columns").into_iter().flatten())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let contributions = merge::merge(
+        &inputs,
+        merge_matches.value_of_t_or_exit("cross-file-duplicates"),
+        &columns,
+        io::stdout().lock(),
+    )?;
+    match merge_matches.value_of("duplicates-report") {
+        Some(path) => merge::write_report(File::create(path)?, &contributions)?,
+        None => merge::write_report(io::stderr(), &contributions)?,
     }
     Ok(())
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use std::path::PathBuf;
+fn import_state(
+    matches: &clap::ArgMatches,
+    import_matches: &clap::ArgMatches,
+    shared: &Shared,
+) -> io::Result<()> {
+    let dir = PathBuf::from(matches.value_of("checkpoint-dir").unwrap_or("checkpoints"));
+    let config = pipeline_config(matches, shared);
+    let (clients, resume_from) = match import_matches.value_of("state_jsonl") {
+        Some("-") | None => state::import(io::stdin().lock(), &dir, &config)?,
+        Some(path) => state::import(io::BufReader::new(File::open(path)?), &dir, &config)?,
+    };
+    eprintln!(
+        "Imported {} clients, records go on from row ({}).",
+        clients, resume_from
+    );
+    Ok(())
+}
 
-    /// Return the repo root directory path.
-    fn repo_dir() -> PathBuf {
-        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-    }
+fn verify_db(
+    matches: &clap::ArgMatches,
+    verify_matches: &clap::ArgMatches,
+    shared: &Shared,
+) -> io::Result<()> {
+    let input = validate_input(verify_matches.value_of("transactions_csv").map(OsStr::new))?;
+    let columns = ColumnMap::parse(matches.values_of("columns").into_iter().flatten())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let dir = PathBuf::from(matches.value_of("checkpoint-dir").unwrap_or("checkpoints"));
+    let (clients, divergences) = dry_run::run(
+        input.as_os_str(),
+        &dir,
+        &pipeline_config(matches, shared),
+        &columns,
+    )?;
+    shadow::write_report(
+        io::stdout(),
+        &divergences,
+        "new_",
+        amount_format(matches),
+        &pseudonyms(matches),
+    )?;
+    eprintln!(
+        "{} of {} clients would change, nothing was written.",
+        divergences.len(),
+        clients
+    );
+    Ok(())
+}
 
-    /// Return the directory containing the example data.
-    fn data_dir() -> PathBuf {
-        repo_dir().join("examples").join("data")
-    }
+/// the value of flag `name`, an error rather than an exit if it's malformed.
+fn flag<T: std::str::FromStr>(matches: &clap::ArgMatches, name: &str) -> io::Result<T>
+where
+    T::Err: std::fmt::Display,
+{
+    matches
+        .value_of_t(name)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))
+}
 
-    fn read_into_memory(reader: Reader<File>) -> io::Result<Vec<SituatedRecord>> {
-        let mut all_records = vec![];
-        for (monotonic_counter, record) in reader.into_deserialize().enumerate() {
-            let record = record?;
-            all_records.push(SituatedRecord {
-                monotonic_counter,
-                record,
-            });
+/// with --checkpoint-every, where to checkpoint to and the row to start from: the first for a new
+/// run, or wherever the last checkpoint left off with --resume.
+fn checkpoints(
+    matches: &clap::ArgMatches,
+    clients: &mut HashMap<ClientId, ClientState>,
+) -> io::Result<Option<(Checkpoints, usize)>> {
+    let every: usize = match matches.is_present("checkpoint-every") {
+        true => flag(matches, "checkpoint-every")?,
+        false if matches.is_present("resume") => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--resume needs --checkpoint-every",
+            ))
         }
-        Ok(all_records)
-    }
-
-    fn read_records_into_memory(path: &Path) -> io::Result<Vec<SituatedRecord>> {
-        let reader = get_reader(path)?;
-        read_into_memory(reader)
+        false => return Ok(None),
+    };
+    let dir = PathBuf::from(matches.value_of("checkpoint-dir").unwrap_or("checkpoints"));
+    let commit = Commit {
+        fsync: flag(matches, "fsync")?,
+        records: flag(matches, "commit-records")?,
+        window: Duration::from_millis(flag(matches, "commit-ms")?),
+    };
+    if matches.is_present("resume") {
+        Checkpoints::resume(
+            &dir,
+            every,
+            commit,
+            amount_format(matches),
+            pseudonyms(matches),
+            clients,
+        )
+        .map(Some)
+    } else {
+        Ok(Some((
+            Checkpoints::start(
+                &dir,
+                every,
+                commit,
+                amount_format(matches),
+                pseudonyms(matches),
+            )?,
+            0,
+        )))
     }
+}
 
-    #[test]
-    fn test_reader() {
-        let p = data_dir().join("sample.csv");
-        let valid_input = validate_input(Some(p.as_os_str()));
-        assert!(valid_input.is_ok());
-        assert!(valid_input.unwrap().exists());
-        let invalid_input1 = validate_input(None);
-        assert!(invalid_input1.is_err());
-        let invalid_input2 = validate_input(Some("this is nota filepath at all!".as_ref()));
-        assert!(invalid_input2.is_err());
+/// the key is the file's contents, minus surrounding whitespace such as a trailing newline.
+fn read_key(path: &str) -> io::Result<Vec<u8>> {
+    let key = std::fs::read(path)?;
+    let start = key.iter().position(|b| !b.is_ascii_whitespace());
+    let end = key.iter().rposition(|b| !b.is_ascii_whitespace());
+    match (start, end) {
+        (Some(start), Some(end)) => Ok(key[start..=end].to_vec()),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "signing key file is empty",
+        )),
     }
+}
 
-    #[test]
-    fn test_read_in_records_whitespace() {
-        let p = data_dir().join("whitespace-sample.csv");
-        let vec = read_records_into_memory(&*p).unwrap();
-        assert_eq!(5, vec.len());
-        let mut test_amounts: Decimal = Decimal::ZERO;
-        for x in vec {
-            test_amounts += x.record.amount;
-        }
-        assert_eq!(Decimal::new(96214, 4), test_amounts);
-    }
+/// What the flags set up before anything runs, the side files they point at and the spill file,
+/// shared by every pipeline of the run.
+struct Shared {
+    spill: Option<Arc<Spill>>,
+    id_map: Option<Arc<IdMap>>,
+    enricher: Option<Arc<Enricher>>,
+    screening: Option<Arc<Screening>>,
+    limits: Arc<Limits>,
+    messages: Messages,
+}
 
-    #[test]
-    fn test_sample_csv() {
-        let p = data_dir().join("sample.csv");
-        let mut clients = HashMap::new();
-        play_with_money(Some(p.as_os_str()), &mut clients).unwrap();
-        for client_id in clients.keys() {
-            let state = clients.get(client_id).unwrap();
-            match client_id {
-                1 => {
-                    assert_eq!(Decimal::new(14848, 4), state.available_funds);
-                    assert_eq!(Decimal::ZERO, state.held_funds);
-                    assert!(!state.locked);
-                }
-                2 => {
-                    assert_eq!(Decimal::new(80290, 4), state.available_funds);
-                    assert_eq!(Decimal::ZERO, state.held_funds);
-                    assert!(!state.locked);
-                }
-                3 => {
-                    assert_eq!(Decimal::new(1000, 1), state.available_funds);
-                    assert_eq!(Decimal::ZERO, state.held_funds);
-                    assert!(state.locked);
-                }
-                4 => {
-                    assert_eq!(Decimal::ZERO, state.available_funds);
-                    assert_eq!(Decimal::new(-100, 0), state.held_funds);
-                    assert!(!state.locked);
-                }
-                5 => {
-                    assert_eq!(Decimal::new(10000, 2), state.available_funds);
-                    assert_eq!(Decimal::ZERO, state.held_funds);
-                    assert!(!state.locked);
-                }
-                _ => unreachable!(),
-            }
+fn client_limits(matches: &clap::ArgMatches) -> io::Result<Limits> {
+    let per_client = |default: &str, file: &str| {
+        let default = matches.value_of_t_or_exit(default);
+        match matches.value_of(file) {
+            Some(path) => PerClient::read_file(path.as_ref(), default),
+            None => Ok(PerClient::uniform(default)),
         }
-    }
+    };
+    let tiers = match matches.value_of("tiers") {
+        Some(path) => limits::read_tiers(File::open(path)?)?,
+        None => HashMap::new(),
+    };
+    let profiles = match matches.value_of("clients") {
+        Some(path) => limits::read_profiles(File::open(path)?, &tiers)?,
+        None => HashMap::new(),
+    };
+    Ok(Limits {
+        reserve: per_client("reserve", "reserves")?,
+        credit: per_client("credit-limit", "credit-limits")?,
+        profiles,
+    })
 }
 
-// https://rust-lang-nursery.github.io/rust-cookbook/encoding/csv.html
-// https://docs.rs/csv/1.1.6/csv/struct.Reader.html#method.deserialize
-// https://crates.io/crates/serde
-// https://docs.rs/serial_int/latest/serial_int/
+fn webhooks(matches: &clap::ArgMatches) -> io::Result<Option<Notifier>> {
+    let urls = match matches.values_of("webhook") {
+        Some(urls) => urls.map(HttpUrl::parse).collect::<io::Result<Vec<_>>>()?,
+        None => return Ok(None),
+    };
+    let kinds = matches
+        .values_of("webhook-events")
+        .into_iter()
+        .flatten()
+        .map(|kind| {
+            kind.parse::<EventKind>()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    Ok(Some(Notifier::spawn(urls, kinds, pseudonyms(matches))))
+}
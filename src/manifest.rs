@@ -0,0 +1,145 @@
+use crate::processed;
+use crate::source::{self, Source};
+use std::ffi::OsStr;
+use std::fmt::Write as _;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// How many records of a run were applied, turned down, or couldn't be parsed, counted by the
+/// [`crate::Sinks`] as they're published.
+#[derive(Debug, Default)]
+pub struct Tally {
+    applied: AtomicUsize,
+    rejected: AtomicUsize,
+    unparsable: AtomicUsize,
+}
+
+impl Tally {
+    pub fn applied(&self) {
+        self.applied.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn rejected(&self) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn unparsable(&self) {
+        self.unparsable.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// applied, rejected and unparsable so far.
+    pub fn counts(&self) -> (usize, usize, usize) {
+        (
+            self.applied.load(Ordering::Relaxed),
+            self.rejected.load(Ordering::Relaxed),
+            self.unparsable.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// A file a run read or wrote and the SHA-256 of its content, hex encoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hashed {
+    pub path: String,
+    pub sha256: String,
+}
+
+impl Hashed {
+    /// `path`, read through once more to hash it, which for a remote input means downloading it
+    /// again.
+    pub fn of(path: &str) -> io::Result<Self> {
+        Self::read(path, source::open(Some(OsStr::new(path)))?)
+    }
+
+    pub fn read(path: &str, source: Source) -> io::Result<Self> {
+        Ok(Hashed {
+            path: path.to_string(),
+            sha256: processed::hash(source)?,
+        })
+    }
+}
+
+/// What a run was given and what it made of it, see --manifest: enough to tell two runs apart
+/// or to run one again and check it comes out the same.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    /// the command line, without the program
+    pub args: Vec<String>,
+    pub inputs: Vec<Hashed>,
+    pub tally: (usize, usize, usize),
+    /// the balances as `-` when they went to stdout, then every other file written
+    pub outputs: Vec<Hashed>,
+    /// `ok`, `failed` or `interrupted`
+    pub status: &'static str,
+}
+
+fn string(value: &str) -> String {
+    format!(r#""{}""#, value.replace('\\', r"\\").replace('"', r#"\""#))
+}
+
+fn files(hashed: &[Hashed]) -> String {
+    let files: Vec<String> = hashed
+        .iter()
+        .map(|hashed| {
+            format!(
+                r#"{{"path":{},"sha256":"{}"}}"#,
+                string(&hashed.path),
+                hashed.sha256
+            )
+        })
+        .collect();
+    format!("[{}]", files.join(","))
+}
+
+impl Manifest {
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        let args: Vec<String> = self.args.iter().map(|arg| string(arg)).collect();
+        let (applied, rejected, unparsable) = self.tally;
+        let _ = write!(
+            json,
+            r#"{{"engine":"{}","version":"{}","args":[{}],"inputs":{},"records":{{"applied":{},"rejected":{},"unparsable":{}}},"outputs":{},"status":"{}"}}"#,
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+            args.join(","),
+            files(&self.inputs),
+            applied,
+            rejected,
+            unparsable,
+            files(&self.outputs),
+            self.status
+        );
+        json
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_manifest_json() {
+        let tally = Tally::default();
+        tally.applied();
+        tally.applied();
+        tally.rejected();
+        let manifest = Manifest {
+            args: vec![
+                "--workers".to_string(),
+                "2".to_string(),
+                "in \"1\".csv".to_string(),
+            ],
+            inputs: vec![Hashed::read("in \"1\".csv", Box::new("abc".as_bytes())).unwrap()],
+            tally: tally.counts(),
+            outputs: vec![],
+            status: "ok",
+        };
+        assert_eq!(
+            format!(
+                r#"{{"engine":"playing-with-money","version":"{}","args":["--workers","2","in \"1\".csv"],"inputs":[{{"path":"in \"1\".csv","sha256":"ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"}}],"records":{{"applied":2,"rejected":1,"unparsable":0}},"outputs":[],"status":"ok"}}"#,
+                env!("CARGO_PKG_VERSION")
+            ),
+            manifest.to_json()
+        );
+    }
+}
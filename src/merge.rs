@@ -0,0 +1,265 @@
+use crate::schema::{self, ColumnMap};
+use crate::{get_source_reader, source, Record, TransactionType, TxId};
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::str::FromStr;
+
+/// What to do with a deposit or withdrawal whose tx id is in more than one of the files merged,
+/// as when a provider resends part of an earlier day.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum CrossFilePolicy {
+    /// keep it in the first file it's in
+    #[default]
+    FirstWins,
+    /// fail the merge
+    Error,
+    /// keep it in the file where it has the latest timestamp, which every copy needs
+    LatestWins,
+}
+
+impl FromStr for CrossFilePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first-wins" => Ok(CrossFilePolicy::FirstWins),
+            "error" => Ok(CrossFilePolicy::Error),
+            "latest-wins" => Ok(CrossFilePolicy::LatestWins),
+            _ => Err(format!(
+                "Unknown cross-file duplicate policy ({}), expected first-wins, error or latest-wins.",
+                s
+            )),
+        }
+    }
+}
+
+/// How many rows a merged file had, how many of them repeat a tx id of an earlier file, and how
+/// many were left out of the merge as copies kept from another file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Contribution {
+    pub input: OsString,
+    pub rows: usize,
+    pub duplicates: usize,
+    pub dropped: usize,
+}
+
+/// where each tx id was seen first and which file's copy is kept.
+struct Seen {
+    first: usize,
+    kept: usize,
+    timestamp: Option<u64>,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// the deposit or withdrawal tx id of a row, None for references and rows that don't parse,
+/// which are merged as they are.
+fn keyed(row: &csv::StringRecord, headers: &csv::StringRecord) -> Option<Record> {
+    row.deserialize::<Record>(Some(headers))
+        .ok()
+        .filter(|record| {
+            matches!(
+                record.transaction_type,
+                TransactionType::Deposit | TransactionType::Withdrawal
+            )
+        })
+}
+
+/// Write the rows of every one of `inputs` to `writer` one file after another, leaving out the
+/// deposits and withdrawals whose tx id another file's copy is kept of under `policy`. Disputes,
+/// resolves and chargebacks are all kept, as are repeats within a file, which the engine turns
+/// down as it does in any input. Every input needs the same headers; the first one's are written.
+pub fn merge<W: io::Write>(
+    inputs: &[&OsStr],
+    policy: CrossFilePolicy,
+    columns: &ColumnMap,
+    writer: W,
+) -> io::Result<Vec<Contribution>> {
+    let mut contributions: Vec<Contribution> = inputs
+        .iter()
+        .map(|input| Contribution {
+            input: input.to_os_string(),
+            rows: 0,
+            duplicates: 0,
+            dropped: 0,
+        })
+        .collect();
+    let mut first_headers = None;
+    let mut seen: HashMap<TxId, Seen> = HashMap::new();
+    // which file's copy of every tx id is kept, before anything is written
+    for (file, input) in inputs.iter().enumerate() {
+        let mut reader = get_source_reader(source::open(Some(input))?, false);
+        let raw = reader.headers()?.clone();
+        match &first_headers {
+            None => first_headers = Some(raw),
+            Some(first) if *first != raw => {
+                return Err(invalid(format!(
+                    "Input ({}) has other headers than the first, expected ({}).",
+                    input.to_string_lossy(),
+                    first.iter().collect::<Vec<_>>().join(",")
+                )))
+            }
+            Some(_) => {}
+        }
+        schema::prepare(&mut reader, columns)?;
+        let headers = reader.headers()?.clone();
+        for row in reader.records() {
+            let row = row?;
+            contributions[file].rows += 1;
+            let Some(record) = keyed(&row, &headers) else {
+                continue;
+            };
+            let entry = seen.entry(record.transaction_id).or_insert(Seen {
+                first: file,
+                kept: file,
+                timestamp: record.timestamp,
+            });
+            if entry.first == file {
+                continue;
+            }
+            contributions[file].duplicates += 1;
+            match policy {
+                CrossFilePolicy::FirstWins => {}
+                CrossFilePolicy::Error => {
+                    return Err(invalid(format!(
+                        "Transaction ({}) of input ({}) is in input ({}) too.",
+                        record.transaction_id,
+                        input.to_string_lossy(),
+                        inputs[entry.first].to_string_lossy()
+                    )))
+                }
+                CrossFilePolicy::LatestWins => match (entry.timestamp, record.timestamp) {
+                    // a later file's copy wins a tie
+                    (Some(kept), Some(timestamp)) if timestamp >= kept => {
+                        entry.kept = file;
+                        entry.timestamp = Some(timestamp);
+                    }
+                    (Some(_), Some(_)) => {}
+                    _ => {
+                        return Err(invalid(format!(
+                            "Transaction ({}) is in more than one input without a timestamp, \
+                            which latest-wins needs.",
+                            record.transaction_id
+                        )))
+                    }
+                },
+            }
+        }
+    }
+    let mut wtr = csv::Writer::from_writer(writer);
+    if let Some(headers) = &first_headers {
+        wtr.write_record(headers)?;
+    }
+    for (file, input) in inputs.iter().enumerate() {
+        let mut reader = get_source_reader(source::open(Some(input))?, false);
+        schema::prepare(&mut reader, columns)?;
+        let headers = reader.headers()?.clone();
+        for row in reader.records() {
+            let row = row?;
+            let kept = keyed(&row, &headers)
+                .and_then(|record| seen.get(&record.transaction_id))
+                .is_none_or(|seen| seen.kept == file);
+            match kept {
+                true => wtr.write_record(&row)?,
+                false => contributions[file].dropped += 1,
+            }
+        }
+    }
+    wtr.flush()?;
+    Ok(contributions)
+}
+
+/// A row per input with its [`Contribution`].
+pub fn write_report<W: io::Write>(writer: W, contributions: &[Contribution]) -> csv::Result<()> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    wtr.write_record(["input", "rows", "duplicates", "dropped"])?;
+    for contribution in contributions {
+        wtr.write_record([
+            contribution.input.to_string_lossy().to_string(),
+            contribution.rows.to_string(),
+            contribution.duplicates.to_string(),
+            contribution.dropped.to_string(),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_merge() {
+        let dir = env::temp_dir().join(format!("merge-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let monday = dir.join("monday.csv");
+        let tuesday = dir.join("tuesday.csv");
+        fs::write(
+            &monday,
+            "type,client,tx,amount,timestamp\n\
+            deposit,1,1,10,100\n\
+            deposit,1,2,20,110\n\
+            dispute,1,1,,120\n",
+        )
+        .unwrap();
+        // a resend of tx 2, corrected later in the day, and a resend of the dispute
+        fs::write(
+            &tuesday,
+            "type,client,tx,amount,timestamp\n\
+            deposit,1,2,25,200\n\
+            dispute,1,1,,120\n\
+            withdrawal,1,3,5,210\n",
+        )
+        .unwrap();
+        let inputs = [monday.as_os_str(), tuesday.as_os_str()];
+        let merged = |policy| {
+            let mut output = vec![];
+            merge(&inputs, policy, &ColumnMap::default(), &mut output)
+                .map(|contributions| (String::from_utf8(output).unwrap(), contributions))
+        };
+
+        let (output, contributions) = merged(CrossFilePolicy::FirstWins).unwrap();
+        assert_eq!(
+            "type,client,tx,amount,timestamp\n\
+            deposit,1,1,10,100\n\
+            deposit,1,2,20,110\n\
+            dispute,1,1,,120\n\
+            dispute,1,1,,120\n\
+            withdrawal,1,3,5,210\n",
+            output
+        );
+        assert_eq!(
+            vec![(3, 0, 0), (3, 1, 1)],
+            contributions
+                .iter()
+                .map(|c| (c.rows, c.duplicates, c.dropped))
+                .collect::<Vec<_>>()
+        );
+
+        let (output, contributions) = merged(CrossFilePolicy::LatestWins).unwrap();
+        assert!(output.contains("deposit,1,2,25,200\n") && !output.contains("deposit,1,2,20"));
+        assert_eq!((1, 0), (contributions[0].dropped, contributions[1].dropped));
+        let mut report = vec![];
+        write_report(&mut report, &contributions).unwrap();
+        assert!(String::from_utf8(report)
+            .unwrap()
+            .starts_with("input,rows,duplicates,dropped\n"));
+
+        assert!(merged(CrossFilePolicy::Error).is_err());
+        fs::write(
+            &tuesday,
+            "type,client,tx,amount,timestamp\ndeposit,1,2,25,\n",
+        )
+        .unwrap();
+        assert!(merged(CrossFilePolicy::LatestWins).is_err());
+        fs::write(&tuesday, "type,client,tx,amount\ndeposit,1,4,25\n").unwrap();
+        assert!(merged(CrossFilePolicy::FirstWins).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
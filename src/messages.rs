@@ -0,0 +1,188 @@
+use crate::Rejection;
+use fluent::concurrent::FluentBundle;
+use fluent::{FluentArgs, FluentResource};
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+use unic_langid::LanguageIdentifier;
+
+type Bundle = FluentBundle<FluentResource>;
+
+const ENGLISH: &str = include_str!("messages/en.ftl");
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// `source` as a catalog in `lang`, failing on the first syntax error or repeated message.
+fn bundle(lang: LanguageIdentifier, source: String) -> io::Result<Bundle> {
+    let resource = FluentResource::try_new(source).map_err(|(_, errors)| {
+        invalid(format!(
+            "The catalog isn't valid Fluent: {}",
+            errors
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+    })?;
+    let mut bundle = FluentBundle::new_concurrent(vec![lang]);
+    // messages go to logs and files, where isolation marks around arguments are noise
+    bundle.set_use_isolating(false);
+    bundle.add_resource(resource).map_err(|errors| {
+        invalid(format!(
+            "The catalog repeats messages: {}",
+            errors
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+    })?;
+    Ok(bundle)
+}
+
+fn english() -> &'static Bundle {
+    static BUNDLE: OnceLock<Bundle> = OnceLock::new();
+    BUNDLE.get_or_init(|| {
+        let lang = "en".parse().expect("en is a language");
+        bundle(lang, ENGLISH.to_string()).expect("the English catalog is valid")
+    })
+}
+
+/// The words rejections are described in, in dead letters, and the --summary is written in: the
+/// English catalog in `src/messages/en.ftl`, or a translation of it given with --lang. Rejections
+/// are keyed on their reason code, so a translation writes one message per code, and anything a
+/// translation leaves out is in English.
+#[derive(Clone, Default)]
+pub struct Messages {
+    translation: Option<Arc<Bundle>>,
+}
+
+impl fmt::Debug for Messages {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Messages({})", self.lang())
+    }
+}
+
+impl Messages {
+    /// `en` for the English catalog, otherwise a Fluent file named for the language it's in, e.g.
+    /// `fr.ftl` or `pt-BR.ftl`.
+    pub fn for_lang(lang: &str) -> io::Result<Self> {
+        match lang {
+            "en" => Ok(Messages::default()),
+            path => Self::read_file(path.as_ref()),
+        }
+    }
+
+    pub fn read_file(path: &Path) -> io::Result<Self> {
+        let tag = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or_default();
+        let lang = tag.parse().map_err(|_| {
+            invalid(format!(
+                "({}) isn't named for a language, such as fr.ftl.",
+                path.display()
+            ))
+        })?;
+        Ok(Messages {
+            translation: Some(Arc::new(bundle(lang, std::fs::read_to_string(path)?)?)),
+        })
+    }
+
+    /// the language messages are written in, where there's a translation for them.
+    pub fn lang(&self) -> String {
+        match &self.translation {
+            Some(bundle) => bundle.locales[0].to_string(),
+            None => "en".to_string(),
+        }
+    }
+
+    /// message `id` with `args`, from the translation when it has it.
+    pub fn get(&self, id: &str, args: Option<&FluentArgs>) -> String {
+        let bundles = self.translation.as_deref().into_iter().chain([english()]);
+        for bundle in bundles {
+            if let Some(pattern) = bundle.get_message(id).and_then(|message| message.value()) {
+                // a missing argument is written as its name, which is the best left to do
+                let mut errors = vec![];
+                return bundle
+                    .format_pattern(pattern, args, &mut errors)
+                    .into_owned();
+            }
+        }
+        id.to_string()
+    }
+
+    pub fn rejection(&self, rejection: &Rejection) -> String {
+        self.get(rejection.code(), None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_english() {
+        let messages = Messages::default();
+        for rejection in Rejection::ALL {
+            assert_eq!(rejection.to_string(), messages.rejection(&rejection));
+        }
+        let mut args = FluentArgs::new();
+        args.set("count", 3);
+        assert_eq!(
+            "top 3 clients by total funds:",
+            messages.get("summary-top", Some(&args))
+        );
+        assert_eq!("nonexistent", messages.get("nonexistent", None));
+    }
+
+    #[test]
+    fn test_translation() {
+        let dir = std::env::temp_dir().join(format!("messages-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("fr.ftl");
+        fs::write(
+            &path,
+            "insufficient_funds = fonds disponibles insuffisants\n\
+             summary-top = { $count ->\n    [one] le client\n   *[other] les { $count } clients\n} les mieux dotés :\n",
+        )
+        .unwrap();
+        let messages = Messages::for_lang(path.to_str().unwrap()).unwrap();
+        assert_eq!("fr", messages.lang());
+        assert_eq!(
+            "fonds disponibles insuffisants",
+            messages.rejection(&Rejection::InsufficientFunds)
+        );
+        // left out of the translation
+        assert_eq!(
+            "client account is frozen",
+            messages.rejection(&Rejection::AccountLocked)
+        );
+        let mut args = FluentArgs::new();
+        args.set("count", 1);
+        assert_eq!(
+            "le client les mieux dotés :",
+            messages.get("summary-top", Some(&args))
+        );
+        args.set("count", 3);
+        assert_eq!(
+            "les 3 clients les mieux dotés :",
+            messages.get("summary-top", Some(&args))
+        );
+
+        fs::write(&path, "insufficient_funds = {\n").unwrap();
+        assert!(Messages::read_file(&path).is_err());
+        fs::write(&path, "screened = a\nscreened = b\n").unwrap();
+        assert!(Messages::read_file(&path).is_err());
+        let unnamed = dir.join("translation.ftl");
+        fs::write(&unnamed, "screened = a\n").unwrap();
+        assert!(Messages::read_file(&unnamed).is_err());
+        assert!(Messages::for_lang(dir.join("de.ftl").to_str().unwrap()).is_err());
+        assert_eq!("en", Messages::for_lang("en").unwrap().lang());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,52 @@
+### The English catalog, and the ids a translation given with --lang writes its own of.
+### Rejections are keyed on their reason code, with their PWM-E number alongside.
+
+# PWM-E001
+insufficient_funds = insufficient available funds
+# PWM-E002
+duplicate_transaction = transaction id is already in use
+# PWM-E003
+account_locked = client account is frozen
+# PWM-E004
+unknown_transaction = no withdrawal or deposit with this transaction id
+# PWM-E005
+already_disputed = transaction is already disputed
+# PWM-E006
+not_disputed = transaction is not under dispute
+# PWM-E007
+already_settled = dispute was already resolved or charged back
+# PWM-E008
+not_charged_back = transaction isn't charged back or represented
+# PWM-E009
+exceeds_admin_hold = release is more than the funds on admin hold
+# PWM-E010
+below_reserve = available funds would drop below the reserve
+# PWM-E011
+foreign_transaction = transaction belongs to another client
+# PWM-E012
+amount_out_of_bounds = amount is beyond the allowed maximum
+# PWM-E013
+overflow = balance would overflow or lose precision
+# PWM-E014
+vetoed = turned down by a registered hook
+# PWM-E015
+id_collision = client id is the canonical id of another one
+# PWM-E016
+type_disabled = transaction type is disabled for this run
+# PWM-E017
+missing_amount = amount is required for this transaction type
+# PWM-E018
+denied = turned down by a rule on its merchant, channel or country
+# PWM-E019
+screened = client is blocked by screening
+
+## The --summary, one line each.
+
+summary-clients = clients: { $count }
+summary-locked = locked: { $count }
+summary-available = available: { $amount }
+summary-held = held: { $amount }
+summary-total = total: { $amount }
+summary-disputes = disputes: { $open } open, { $resolved } resolved, { $cancelled } cancelled, { $charged_back } charged back, { $reversed } reversed
+summary-overflowed = (sums overflowed and are pinned to the Decimal limits)
+summary-top = top { $count } clients by total funds:
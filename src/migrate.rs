@@ -0,0 +1,315 @@
+use crate::checkpoint::{self, HISTORY, SNAPSHOT};
+use csv::{ReaderBuilder, StringRecord, StringRecordsIntoIter};
+use log::info;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// The version of the checkpoint files this build writes, and the newest one it reads.
+pub const VERSION: u32 = 6;
+
+// the first line of a checkpoint file from version 6 on, which CSV readers skip as a comment
+const PREFIX: &str = "# version ";
+
+/// A checkpoint file, see [`checkpoint::Checkpoints`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Artifact {
+    Snapshot,
+    History,
+}
+
+/// What changed in the layout of the checkpoint files from the version before `to`: columns
+/// added at the end, with the value rows written before get.
+struct Migration {
+    to: u32,
+    snapshot: &'static [(&'static str, &'static str)],
+    history: &'static [(&'static str, &'static str)],
+}
+
+const MIGRATIONS: [Migration; 5] = [
+    // the activity behind --extended-output
+    Migration {
+        to: 2,
+        snapshot: &[
+            ("transactions", "0"),
+            ("last_counter", ""),
+            ("last_timestamp", ""),
+        ],
+        history: &[],
+    },
+    // dispute reason codes
+    Migration {
+        to: 3,
+        snapshot: &[],
+        history: &[("reason_code", "")],
+    },
+    // admin holds
+    Migration {
+        to: 4,
+        snapshot: &[("admin_held", "0")],
+        history: &[],
+    },
+    // credit lines
+    Migration {
+        to: 5,
+        snapshot: &[("credit_used", "0")],
+        history: &[],
+    },
+    // the version line itself
+    Migration {
+        to: 6,
+        snapshot: &[],
+        history: &[],
+    },
+];
+
+impl Artifact {
+    fn file_name(self) -> &'static str {
+        match self {
+            Artifact::Snapshot => SNAPSHOT,
+            Artifact::History => HISTORY,
+        }
+    }
+
+    fn added(self, migration: &Migration) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Artifact::Snapshot => migration.snapshot,
+            Artifact::History => migration.history,
+        }
+    }
+
+    /// rows ahead of the header, like the snapshot's resume_from
+    fn preamble(self) -> usize {
+        match self {
+            Artifact::Snapshot => 1,
+            Artifact::History => 0,
+        }
+    }
+
+    /// The columns `version` wrote.
+    pub fn columns(self, version: u32) -> Vec<&'static str> {
+        let mut columns = match self {
+            Artifact::Snapshot => vec![
+                "client",
+                "available",
+                "held",
+                "locked",
+                "deposited",
+                "withdrawn",
+                "charged_back",
+                "applied",
+            ],
+            Artifact::History => vec!["counter", "type", "client", "tx", "amount", "timestamp"],
+        };
+        for migration in MIGRATIONS.iter().filter(|m| m.to <= version) {
+            columns.extend(self.added(migration).iter().map(|(column, _)| *column));
+        }
+        columns
+    }
+}
+
+/// The line every checkpoint file starts with.
+pub(crate) fn version_line() -> String {
+    format!("{}{}\n", PREFIX, VERSION)
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The rows of a checkpoint file as the current version lays them out, whichever version wrote
+/// it: migrated as they're read, so reading an old file doesn't change it.
+pub(crate) struct Rows {
+    /// the version that wrote the file
+    pub version: u32,
+    artifact: Artifact,
+    read: usize,
+    // rows read ahead to tell the version of a file from before the version line
+    ahead: VecDeque<StringRecord>,
+    records: StringRecordsIntoIter<File>,
+}
+
+impl Rows {
+    pub(crate) fn open(path: &Path, artifact: Artifact) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let mut first = String::new();
+        BufReader::new(&file).read_line(&mut first)?;
+        file.seek(SeekFrom::Start(0))?;
+        let versioned = first
+            .strip_prefix(PREFIX)
+            .map(|version| version.trim().parse::<u32>());
+        let mut records = ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .comment(Some(b'#'))
+            .from_reader(file)
+            .into_records();
+        let mut ahead = VecDeque::new();
+        let version = match versioned {
+            Some(Ok(version)) if version <= VERSION => version,
+            Some(version) => {
+                return Err(invalid(format!(
+                    "Checkpoint file ({:?}) has version ({}), this build reads up to {}.",
+                    path,
+                    version.map(|v| v.to_string()).unwrap_or_default(),
+                    VERSION
+                )))
+            }
+            // before the version line, so told apart by the columns it has
+            None => {
+                for _ in 0..=artifact.preamble() {
+                    ahead.extend(records.next().transpose()?);
+                }
+                let header = ahead.back().filter(|_| ahead.len() > artifact.preamble());
+                (1..VERSION)
+                    .rev()
+                    .find(|&version| {
+                        header.is_some_and(|header| header.iter().eq(artifact.columns(version)))
+                    })
+                    .ok_or_else(|| {
+                        invalid(format!(
+                            "Checkpoint file ({:?}) has no version and unexpected columns.",
+                            path
+                        ))
+                    })?
+            }
+        };
+        Ok(Rows {
+            version,
+            artifact,
+            read: 0,
+            ahead,
+            records,
+        })
+    }
+}
+
+impl Iterator for Rows {
+    type Item = io::Result<StringRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut row = match self.ahead.pop_front() {
+            Some(row) => row,
+            None => match self.records.next()? {
+                Ok(row) => row,
+                Err(e) => return Some(Err(e.into())),
+            },
+        };
+        let preamble = self.artifact.preamble();
+        for migration in MIGRATIONS.iter().filter(|m| m.to > self.version) {
+            for (column, default) in self.artifact.added(migration) {
+                match self.read {
+                    read if read < preamble => {}
+                    read if read == preamble => row.push_field(column),
+                    _ => row.push_field(default),
+                }
+            }
+        }
+        self.read += 1;
+        Some(Ok(row))
+    }
+}
+
+/// Rewrite every checkpoint file in `dir` an older version wrote in the current layout, and
+/// return the files that were along with the version they were. Each file is replaced whole, so
+/// one interrupted part way is still read as its old version.
+pub fn upgrade(dir: &Path) -> io::Result<Vec<(Artifact, u32)>> {
+    let mut upgraded = vec![];
+    for artifact in [Artifact::History, Artifact::Snapshot] {
+        let path = dir.join(artifact.file_name());
+        if !path.exists() {
+            continue;
+        }
+        let rows = Rows::open(&path, artifact)?;
+        let version = rows.version;
+        if version == VERSION {
+            continue;
+        }
+        checkpoint::replace(&path, |file| {
+            file.write_all(version_line().as_bytes())?;
+            let mut writer = csv::WriterBuilder::new().flexible(true).from_writer(file);
+            for row in rows {
+                writer.write_record(&row?)?;
+            }
+            writer.flush()
+        })?;
+        info!(
+            "Migrated checkpoint file ({:?}) from version {} to {}.",
+            path, version, VERSION
+        );
+        upgraded.push((artifact, version));
+    }
+    Ok(upgraded)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::checkpoint::{HISTORY_COLUMNS, SNAPSHOT_COLUMNS};
+    use crate::ClientId;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_columns_match_current_layout() {
+        assert!(Artifact::Snapshot
+            .columns(VERSION)
+            .iter()
+            .eq(SNAPSHOT_COLUMNS.iter()));
+        assert!(Artifact::History
+            .columns(VERSION)
+            .iter()
+            .eq(HISTORY_COLUMNS.iter()));
+    }
+
+    #[test]
+    fn test_upgrade() {
+        let dir = env::temp_dir().join(format!("migrate-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        // as the first checkpoints were written
+        fs::write(
+            dir.join(SNAPSHOT),
+            "resume_from,2\n\
+            client,available,held,locked,deposited,withdrawn,charged_back,applied\n\
+            1,0,10,false,10,0,0,0:6a09e667bb67ae853c6ef372a54ff53a510e527f9b05688c1f83d9ab5be0cd19:\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join(HISTORY),
+            "counter,type,client,tx,amount,timestamp\n\
+            0,deposit,1,1,10,\n\
+            1,dispute,1,1,0,5\n",
+        )
+        .unwrap();
+        let rows = Rows::open(&dir.join(SNAPSHOT), Artifact::Snapshot).unwrap();
+        assert_eq!(1, rows.version);
+        let rows: Vec<StringRecord> = rows.map(Result::unwrap).collect();
+        assert!(rows[1].iter().eq(SNAPSHOT_COLUMNS));
+        assert_eq!(Some("0"), rows[2].get(8));
+        assert_eq!(Some(""), rows[2].get(9));
+        assert_eq!(Some("0"), rows[2].get(12));
+
+        assert_eq!(
+            vec![(Artifact::History, 2), (Artifact::Snapshot, 1)],
+            upgrade(&dir).unwrap()
+        );
+        assert_eq!(
+            "# version 6\n\
+            counter,type,client,tx,amount,timestamp,reason_code\n\
+            0,deposit,1,1,10,,\n\
+            1,dispute,1,1,0,5,\n",
+            fs::read_to_string(dir.join(HISTORY)).unwrap()
+        );
+        assert!(upgrade(&dir).unwrap().is_empty());
+        let mut clients = std::collections::HashMap::new();
+        let next = checkpoint::load(&dir, &Default::default(), &mut clients).unwrap();
+        assert_eq!(2, next);
+        assert_eq!(1, clients[&ClientId(1)].open_disputes());
+
+        let newer = format!("{}{}\n", PREFIX, VERSION + 1);
+        fs::write(dir.join(HISTORY), newer + &HISTORY_COLUMNS.join(",")).unwrap();
+        assert!(Rows::open(&dir.join(HISTORY), Artifact::History).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,223 @@
+use rust_decimal::Decimal;
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+/// An ISO 4217 code, e.g. `EUR`, as a client's profile in --clients gives it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    pub fn as_str(&self) -> &str {
+        // only ever built from ASCII letters
+        std::str::from_utf8(&self.0).unwrap_or_default()
+    }
+}
+
+impl FromStr for Currency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match <[u8; 3]>::try_from(s.as_bytes()) {
+            Ok(code) if code.iter().all(u8::is_ascii_alphabetic) => {
+                Ok(Currency(code.map(|b| b.to_ascii_uppercase())))
+            }
+            _ => Err(format!(
+                "Unknown currency ({}), expected a three letter code such as EUR",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// An amount of money: a `Decimal`, which carries its own scale, and the currency it's in when
+/// that's known. Record amounts come without one and take on the currency of the balance they're
+/// applied to, balances are in the currency of their client's profile, if it has one. It's
+/// (de)serialized as the bare amount, so files read and written don't change.
+///
+/// ```
+/// use playing_with_money::money::{Currency, Money};
+///
+/// let eur: Currency = "EUR".parse().unwrap();
+/// let balance = Money::new(1050, 2).in_currency(Some(eur));
+/// let deposit = Money::new(25, 1);
+/// assert_eq!(Some(Money::new(1300, 2).in_currency(Some(eur))), balance.checked_add(deposit));
+/// assert_eq!(2, balance.scale());
+///
+/// // amounts in different currencies don't add up
+/// let usd = Money::new(1, 0).in_currency(Some("USD".parse().unwrap()));
+/// assert_eq!(None, balance.checked_sub(usd));
+/// ```
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Money {
+    amount: Decimal,
+    #[serde(skip)]
+    currency: Option<Currency>,
+}
+
+impl Money {
+    pub const ZERO: Money = Money {
+        amount: Decimal::ZERO,
+        currency: None,
+    };
+
+    /// `num` at `scale` decimal places, as [`Decimal::new`], in no currency in particular.
+    pub fn new(num: i64, scale: u32) -> Self {
+        Decimal::new(num, scale).into()
+    }
+
+    /// the same amount, in `currency`.
+    pub fn in_currency(self, currency: Option<Currency>) -> Self {
+        Money { currency, ..self }
+    }
+
+    pub fn amount(&self) -> Decimal {
+        self.amount
+    }
+
+    pub fn scale(&self) -> u32 {
+        self.amount.scale()
+    }
+
+    pub fn currency(&self) -> Option<Currency> {
+        self.currency
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.amount.is_zero()
+    }
+
+    /// the larger of the two by amount, whatever their currencies.
+    pub fn max(self, other: Money) -> Money {
+        match other.amount > self.amount {
+            true => other,
+            false => self,
+        }
+    }
+
+    /// the smaller of the two by amount, whatever their currencies.
+    pub fn min(self, other: Money) -> Money {
+        match other.amount < self.amount {
+            true => other,
+            false => self,
+        }
+    }
+
+    /// the same amount without trailing zeros, see [`Decimal::normalize`].
+    pub fn normalize(self) -> Self {
+        Money {
+            amount: self.amount.normalize(),
+            ..self
+        }
+    }
+
+    /// the currency of a result of `self` and `other`: theirs if they're in the same one or one
+    /// of them is in none, otherwise there isn't one.
+    fn currency_with(self, other: Money) -> Option<Option<Currency>> {
+        match (self.currency, other.currency) {
+            (Some(a), Some(b)) if a != b => None,
+            (a, b) => Some(a.or(b)),
+        }
+    }
+
+    /// `Decimal` rounds a result that needs more than 28 significant digits, which would quietly
+    /// create or destroy money, so an inexact result is None like an overflow. Exact results keep
+    /// the larger scale of their operands, rounding is what lowers it.
+    fn exact(self, other: Money, result: Option<Decimal>) -> Option<Money> {
+        let amount = result.filter(|result| result.scale() >= self.scale().max(other.scale()))?;
+        Some(Money {
+            amount,
+            currency: self.currency_with(other)?,
+        })
+    }
+
+    /// `self + other`, or None if it overflows, can't be represented exactly or the two are in
+    /// different currencies.
+    pub fn checked_add(self, other: Money) -> Option<Money> {
+        self.exact(other, self.amount.checked_add(other.amount))
+    }
+
+    /// `self - other`, or None as for [`Money::checked_add`].
+    pub fn checked_sub(self, other: Money) -> Option<Money> {
+        self.exact(other, self.amount.checked_sub(other.amount))
+    }
+}
+
+impl From<Decimal> for Money {
+    fn from(amount: Decimal) -> Self {
+        Money {
+            amount,
+            currency: None,
+        }
+    }
+}
+
+impl From<Money> for Decimal {
+    fn from(money: Money) -> Self {
+        money.amount
+    }
+}
+
+/// by amount, between amounts in the same currency or both in none.
+impl PartialOrd for Money {
+    fn partial_cmp(&self, other: &Money) -> Option<Ordering> {
+        (self.currency == other.currency).then(|| self.amount.cmp(&other.amount))
+    }
+}
+
+/// the amount alone, as it's written to every output.
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.amount.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_money() {
+        let code = "eur".parse::<Currency>().unwrap();
+        assert_eq!("EUR", code.to_string());
+        let eur = Some(code);
+        assert!("EURO".parse::<Currency>().is_err());
+        assert!("E1R".parse::<Currency>().is_err());
+
+        let a = Money::new(15, 1).in_currency(eur);
+        assert_eq!(
+            Some(Money::new(5, 1).in_currency(eur)),
+            a.checked_sub(Money::new(1, 0))
+        );
+        // the larger scale is kept
+        assert_eq!(4, a.checked_add(Money::new(1, 4)).unwrap().scale());
+        assert_eq!(
+            None,
+            Money::from(Decimal::MAX).checked_add(Money::new(1, 0))
+        );
+        // more significant digits than a Decimal has
+        let large = Money::from("79228162514264337593543950".parse::<Decimal>().unwrap());
+        assert_eq!(None, large.checked_add(Money::new(1, 4)));
+
+        assert_eq!("1.50", Money::new(150, 2).in_currency(eur).to_string());
+        assert_eq!(0, Money::new(300, 2).normalize().scale());
+        assert!(Money::new(1, 0) < Money::new(15, 1));
+        assert_eq!(None, a.partial_cmp(&Money::new(15, 1)));
+    }
+}
@@ -0,0 +1,206 @@
+use crate::SituatedRecord;
+use log::warn;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+/// Flags records whose timestamp lags the latest timestamp seen so far by more than `max_skew`,
+/// a sign the upstream feed isn't as chronological as the engine assumes. Records without a
+/// timestamp are never flagged.
+#[derive(Debug)]
+pub struct SkewDetector {
+    max_skew: u64,
+    latest: Option<u64>,
+    flagged: usize,
+}
+
+impl SkewDetector {
+    pub fn new(max_skew: u64) -> Self {
+        SkewDetector {
+            max_skew,
+            latest: None,
+            flagged: 0,
+        }
+    }
+
+    /// returns whether the record arrived further out of order than allowed.
+    pub fn observe(&mut self, situated_record: &SituatedRecord) -> bool {
+        let timestamp = match situated_record.record.timestamp {
+            Some(timestamp) => timestamp,
+            None => return false,
+        };
+        let latest = *self.latest.get_or_insert(timestamp);
+        if timestamp > latest {
+            self.latest = Some(timestamp);
+            false
+        } else if latest - timestamp > self.max_skew {
+            self.flagged += 1;
+            warn!(
+                "Record ({}) for transaction ({}) has timestamp {} which is {} behind the latest seen ({}), more than the allowed skew of {}.",
+                situated_record.monotonic_counter,
+                situated_record.record.transaction_id,
+                timestamp,
+                latest - timestamp,
+                latest,
+                self.max_skew
+            );
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn flagged(&self) -> usize {
+        self.flagged
+    }
+}
+
+/// Heap entry ordered by timestamp then arrival, so equal timestamps keep their input order.
+#[derive(Debug)]
+struct Pending {
+    timestamp: u64,
+    situated_record: SituatedRecord,
+}
+
+impl Pending {
+    fn key(&self) -> (u64, usize) {
+        (self.timestamp, self.situated_record.monotonic_counter)
+    }
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Pending {}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Pending {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+/// Holds back up to `window` records and releases the earliest by timestamp once full, so a
+/// record that arrives a few rows ahead of one it depends on (e.g. a dispute ahead of its
+/// deposit) is applied after it. Records without a timestamp sort as if stamped with the latest
+/// timestamp seen, keeping their place in the arrival order.
+#[derive(Debug)]
+pub struct ReorderBuffer {
+    window: usize,
+    latest: u64,
+    pending: BinaryHeap<Reverse<Pending>>,
+}
+
+impl ReorderBuffer {
+    pub fn new(window: usize) -> Self {
+        ReorderBuffer {
+            window,
+            latest: 0,
+            pending: BinaryHeap::with_capacity(window + 1),
+        }
+    }
+
+    /// buffer the record and return the earliest one if the window is now over capacity.
+    pub fn push(&mut self, situated_record: SituatedRecord) -> Option<SituatedRecord> {
+        let timestamp = situated_record.record.timestamp.unwrap_or(self.latest);
+        self.latest = self.latest.max(timestamp);
+        self.pending.push(Reverse(Pending {
+            timestamp,
+            situated_record,
+        }));
+        if self.pending.len() > self.window {
+            self.pop()
+        } else {
+            None
+        }
+    }
+
+    /// release the earliest buffered record, used to drain the buffer once input ends.
+    pub fn pop(&mut self) -> Option<SituatedRecord> {
+        self.pending
+            .pop()
+            .map(|Reverse(pending)| pending.situated_record)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::money::Money;
+    use crate::{ClientId, Record, TransactionType, TxId};
+
+    fn at(monotonic_counter: usize, timestamp: Option<u64>) -> SituatedRecord {
+        SituatedRecord {
+            monotonic_counter,
+            record: Record {
+                transaction_type: TransactionType::Deposit,
+                client_id: ClientId(1),
+                transaction_id: TxId(monotonic_counter as u32),
+                amount: Some(Money::new(1, 0)),
+                timestamp,
+                reason: None,
+            },
+        }
+    }
+
+    #[test]
+    fn test_skew_detection() {
+        let mut detector = SkewDetector::new(10);
+        assert!(!detector.observe(&at(0, Some(100))));
+        assert!(!detector.observe(&at(1, Some(120))));
+        // within the allowed skew of the latest timestamp
+        assert!(!detector.observe(&at(2, Some(110))));
+        assert!(detector.observe(&at(3, Some(109))));
+        assert!(!detector.observe(&at(4, None)));
+        assert!(!detector.observe(&at(5, Some(121))));
+        assert!(detector.observe(&at(6, Some(100))));
+        assert_eq!(2, detector.flagged());
+    }
+
+    fn drain(window: usize, arrivals: Vec<SituatedRecord>) -> Vec<usize> {
+        let mut buffer = ReorderBuffer::new(window);
+        let mut released = vec![];
+        for situated_record in arrivals {
+            released.extend(buffer.push(situated_record));
+        }
+        while let Some(situated_record) = buffer.pop() {
+            released.push(situated_record);
+        }
+        released
+            .iter()
+            .map(|situated_record| situated_record.monotonic_counter)
+            .collect()
+    }
+
+    #[test]
+    fn test_reorder_within_window() {
+        let arrivals = vec![
+            at(0, Some(10)),
+            at(1, Some(30)),
+            at(2, Some(20)),
+            at(3, None),
+            at(4, Some(30)),
+            at(5, Some(40)),
+        ];
+        assert_eq!(vec![0, 2, 1, 3, 4, 5], drain(2, arrivals));
+    }
+
+    #[test]
+    fn test_reorder_beyond_window() {
+        let arrivals = vec![at(0, Some(30)), at(1, Some(40)), at(2, Some(10))];
+        // the window of 1 releases 30 before 10 arrives, so 10 can only come next
+        assert_eq!(vec![0, 2, 1], drain(1, arrivals));
+        let arrivals = vec![at(0, Some(30)), at(1, Some(40)), at(2, Some(10))];
+        assert_eq!(vec![2, 0, 1], drain(2, arrivals));
+        // a zero window passes records straight through
+        let arrivals = vec![at(0, Some(30)), at(1, Some(10))];
+        assert_eq!(vec![0, 1], drain(0, arrivals));
+    }
+}
@@ -0,0 +1,204 @@
+use crate::EXTENDED_COLUMNS;
+use rust_decimal::prelude::RoundingStrategy;
+use rust_decimal::Decimal;
+
+/// The columns of the balances output, before the extended ones.
+pub const STANDARD_COLUMNS: [&str; 5] = ["client", "available", "held", "total", "locked"];
+
+fn all_columns() -> impl Iterator<Item = &'static str> {
+    STANDARD_COLUMNS.into_iter().chain(EXTENDED_COLUMNS)
+}
+
+/// Which columns of the balances output are written and in what order, as indexes into the
+/// standard columns followed by the extended ones, see --output-columns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputColumns(Vec<usize>);
+
+impl OutputColumns {
+    /// the standard columns, then the extended ones with `extended`.
+    pub fn standard(extended: bool) -> Self {
+        let count = match extended {
+            true => STANDARD_COLUMNS.len() + EXTENDED_COLUMNS.len(),
+            false => STANDARD_COLUMNS.len(),
+        };
+        OutputColumns((0..count).collect())
+    }
+
+    /// columns by name, in the order given.
+    pub fn parse<'a, I: IntoIterator<Item = &'a str>>(names: I) -> Result<Self, String> {
+        let mut columns = vec![];
+        for name in names {
+            let index = all_columns()
+                .position(|column| column == name)
+                .ok_or_else(|| {
+                    format!(
+                        "Unknown output column ({}), expected any of {:?}.",
+                        name,
+                        all_columns().collect::<Vec<_>>()
+                    )
+                })?;
+            if columns.contains(&index) {
+                return Err(format!("Output column ({}) is given twice.", name));
+            }
+            columns.push(index);
+        }
+        match columns.is_empty() {
+            true => Err("No output columns given.".to_string()),
+            false => Ok(OutputColumns(columns)),
+        }
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        let all: Vec<&str> = all_columns().collect();
+        self.0.iter().map(|&index| all[index]).collect()
+    }
+
+    /// whether any column past the standard ones is written, which are dearer to work out.
+    pub fn is_extended(&self) -> bool {
+        self.0.iter().any(|&index| index >= STANDARD_COLUMNS.len())
+    }
+
+    /// the selected fields of `row`, which has every standard column and, if
+    /// [`OutputColumns::is_extended`], every extended one.
+    pub fn select<'r>(&'r self, row: &'r [String]) -> impl Iterator<Item = &'r str> + 'r {
+        self.0.iter().map(|&index| row[index].as_str())
+    }
+}
+
+/// How amounts in the balances output are written. By default as the engine holds them, with
+/// whatever scale the inputs left them at, e.g. `1.4848` next to `100.00`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct AmountFormat {
+    /// decimal places, rounded to the nearest with ties to even or padded with zeros
+    pub scale: Option<u32>,
+    /// drop trailing zeros, after rounding to `scale`
+    pub trim_zeros: bool,
+}
+
+impl AmountFormat {
+    pub fn format(&self, amount: impl Into<Decimal>) -> String {
+        let amount = amount.into();
+        let mut amount = match self.scale {
+            Some(scale) => {
+                let mut rounded =
+                    amount.round_dp_with_strategy(scale, RoundingStrategy::MidpointNearestEven);
+                rounded.rescale(scale);
+                rounded
+            }
+            None => amount,
+        };
+        if self.trim_zeros {
+            amount = amount.normalize();
+        }
+        amount.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::money::Money;
+    use crate::pseudonym::Pseudonyms;
+    use crate::{
+        process_record, write_client_state_to, ClientId, ClientState, Record, SituatedRecord,
+        TransactionType, TxId,
+    };
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_parse_output_columns() {
+        assert_eq!(
+            OutputColumns::standard(false),
+            OutputColumns::parse(STANDARD_COLUMNS).unwrap()
+        );
+        let columns = OutputColumns::parse(["total", "client", "open_disputes"]).unwrap();
+        assert_eq!(vec!["total", "client", "open_disputes"], columns.names());
+        assert!(columns.is_extended());
+        assert!(!OutputColumns::parse(["locked"]).unwrap().is_extended());
+        assert!(OutputColumns::parse(["client", "balance"]).is_err());
+        assert!(OutputColumns::parse(["client", "client"]).is_err());
+        assert!(OutputColumns::parse([]).is_err());
+    }
+
+    #[test]
+    fn test_amount_format() {
+        let amounts = [
+            Money::new(14848, 4),
+            Money::new(10000, 2),
+            Money::new(125, 3),
+            Money::new(-5, 0),
+        ];
+        let formatted = |format: AmountFormat| -> Vec<String> {
+            amounts
+                .iter()
+                .map(|&amount| format.format(amount))
+                .collect()
+        };
+        assert_eq!(
+            vec!["1.4848", "100.00", "0.125", "-5"],
+            formatted(AmountFormat::default())
+        );
+        assert_eq!(
+            vec!["1.48", "100.00", "0.12", "-5.00"],
+            formatted(AmountFormat {
+                scale: Some(2),
+                trim_zeros: false
+            })
+        );
+        assert_eq!(
+            vec!["1.4848", "100", "0.125", "-5"],
+            formatted(AmountFormat {
+                scale: None,
+                trim_zeros: true
+            })
+        );
+        assert_eq!(
+            vec!["1.5", "100", "0.1", "-5"],
+            formatted(AmountFormat {
+                scale: Some(1),
+                trim_zeros: true
+            })
+        );
+    }
+
+    #[test]
+    fn test_selected_output() {
+        let mut clients: HashMap<ClientId, ClientState> = HashMap::new();
+        for (monotonic_counter, transaction_type) in
+            [TransactionType::Deposit, TransactionType::Dispute]
+                .into_iter()
+                .enumerate()
+        {
+            let record = Record {
+                transaction_type,
+                client_id: ClientId(4),
+                transaction_id: TxId(1),
+                amount: Some(Money::new(25, 1)),
+                timestamp: None,
+                reason: None,
+            };
+            process_record(
+                SituatedRecord {
+                    monotonic_counter,
+                    record,
+                },
+                &mut clients,
+            )
+            .unwrap();
+        }
+        let mut output = vec![];
+        let columns = OutputColumns::parse(["client", "total", "open_disputes", "locked"]).unwrap();
+        write_client_state_to(
+            &mut output,
+            &clients,
+            &columns,
+            AmountFormat::default(),
+            &Pseudonyms::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            "client,total,open_disputes,locked\n4,2.5,1,false\n",
+            String::from_utf8(output).unwrap()
+        );
+    }
+}
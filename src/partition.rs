@@ -0,0 +1,136 @@
+use crate::digest::{self, Sha256};
+use crate::output::{AmountFormat, OutputColumns};
+use crate::pseudonym::Pseudonyms;
+use crate::{write_clients_to, ClientId, ClientState};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+/// The file listing the balance files of a partitioned output, written once they all are.
+pub const MANIFEST: &str = "manifest.csv";
+
+/// Which of `shards` balance files `client`, as the balances output writes it, goes to: FNV-1a
+/// (64 bit) of its UTF-8 bytes modulo `shards`, so a loader can tell from the output alone.
+pub fn shard_of(client: &str, shards: usize) -> usize {
+    let hash = client.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    (hash % shards.max(1) as u64) as usize
+}
+
+/// One of the balance files of a partitioned output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Part {
+    pub shard: usize,
+    pub file: String,
+    pub clients: usize,
+    /// of the file's content, hex encoded
+    pub sha256: String,
+}
+
+/// Write the balances of `clients` to `shards` files in `dir` by [`shard_of`], each with the
+/// header and in the order of client id, then [`MANIFEST`] with a `shard,file,clients,sha256` row
+/// per file. A shard without clients still gets a file with just the header, so a loader can
+/// count on all of them being there once the manifest is.
+pub fn write(
+    dir: &Path,
+    shards: usize,
+    clients: &HashMap<ClientId, ClientState>,
+    columns: &OutputColumns,
+    amounts: AmountFormat,
+    pseudonyms: &Pseudonyms,
+) -> io::Result<Vec<Part>> {
+    let shards = shards.max(1);
+    fs::create_dir_all(dir)?;
+    let mut partitioned: Vec<Vec<&ClientState>> = vec![vec![]; shards];
+    for client in clients.values() {
+        partitioned[shard_of(&pseudonyms.client(client.client_id), shards)].push(client);
+    }
+    let width = (shards - 1).to_string().len();
+    let mut parts = vec![];
+    for (shard, mut clients) in partitioned.into_iter().enumerate() {
+        clients.sort_unstable_by_key(|client| client.client_id);
+        let file = format!("balances-{:0width$}.csv", shard, width = width);
+        let mut content = vec![];
+        write_clients_to(
+            &mut content,
+            clients.iter().copied(),
+            columns,
+            amounts,
+            pseudonyms,
+        )?;
+        fs::write(dir.join(&file), &content)?;
+        let mut sha = Sha256::new();
+        sha.update(&content);
+        parts.push(Part {
+            shard,
+            file,
+            clients: clients.len(),
+            sha256: digest::hex(&sha.finish()),
+        });
+    }
+    let mut manifest = csv::Writer::from_writer(BufWriter::new(File::create(dir.join(MANIFEST))?));
+    manifest.write_record(["shard", "file", "clients", "sha256"])?;
+    for part in &parts {
+        manifest.write_record([
+            part.shard.to_string(),
+            part.file.clone(),
+            part.clients.to_string(),
+            part.sha256.clone(),
+        ])?;
+    }
+    manifest.flush()?;
+    Ok(parts)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_partition() {
+        // FNV-1a of "1" is 0xaf63ac4c86019afc
+        assert_eq!((0xaf63ac4c86019afcu64 % 7) as usize, shard_of("1", 7));
+        let dir = env::temp_dir().join(format!("partition-{}", std::process::id()));
+        let clients: HashMap<ClientId, ClientState> = (0..100)
+            .map(|client_id| (ClientId(client_id), ClientState::new(ClientId(client_id))))
+            .collect();
+        let parts = write(
+            &dir,
+            4,
+            &clients,
+            &OutputColumns::standard(false),
+            AmountFormat::default(),
+            &Pseudonyms::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                "balances-0.csv",
+                "balances-1.csv",
+                "balances-2.csv",
+                "balances-3.csv"
+            ],
+            parts
+                .iter()
+                .map(|part| part.file.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(100, parts.iter().map(|part| part.clients).sum::<usize>());
+        for part in &parts {
+            let content = fs::read_to_string(dir.join(&part.file)).unwrap();
+            let rows: Vec<&str> = content.lines().skip(1).collect();
+            assert_eq!(part.clients, rows.len());
+            for row in rows {
+                let client = row.split(',').next().unwrap();
+                assert_eq!(part.shard, shard_of(client, 4));
+            }
+        }
+        let manifest = fs::read_to_string(dir.join(MANIFEST)).unwrap();
+        assert_eq!(5, manifest.lines().count());
+        assert!(manifest.starts_with("shard,file,clients,sha256\n0,balances-0.csv,"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,712 @@
+use crate::amount::{AmountPolicy, Refused};
+use crate::enrich::Enricher;
+use crate::foreign::ForeignPolicy;
+use crate::id_map::IdMap;
+use crate::lenient;
+use crate::limits::Limits;
+use crate::messages::Messages;
+use crate::ordering::{ReorderBuffer, SkewDetector};
+use crate::profile::{self, Profiler, Stage};
+use crate::pseudonym::Pseudonyms;
+use crate::screening::Screening;
+use crate::shutdown;
+use crate::spill::Spill;
+use crate::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use crate::sync::mpsc::{self, Receiver, SendError, SyncSender, TrySendError};
+use crate::velocity::Velocity;
+use crate::{LockedDeposits, Record, SituatedRecord, TransactionTypes};
+use csv::{Reader, StringRecord};
+use log::{info, warn};
+use rust_decimal::Decimal;
+use std::fmt;
+use std::io::{self, Read};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_QUEUE_CAPACITY: usize = 1024;
+
+/// Capacities of the queues sitting between the reader, parser and engine stages. A full queue
+/// blocks the stage feeding it, so memory stays bounded when a source outpaces the engine.
+/// `workers` above one shards the engine stage by client id, see [`crate::shards`]. A non zero
+/// `reorder_window` restores timestamp order within that many records, see [`ReorderBuffer`],
+/// and `max_skew` flags whatever still arrives out of order, see [`SkewDetector`]. `lenient`
+/// cleans up messy rows before they're parsed, see [`crate::lenient`], and `amount_policy` decides
+/// what the parser makes of unusual amounts. `max_amount` bounds deposits and withdrawals before
+/// they reach the engine, see `check_bounds`. Rows before `resume_from` are read but skipped, as
+/// they were applied before a checkpoint, see [`crate::checkpoint`]. Without `retain_history`
+/// clients keep only what disputes need of each transaction, see [`crate::Disputable`]. Reading
+/// stops once `interrupted` returns true, and the rows already read are still applied.
+#[derive(Debug, Clone)]
+pub struct PipelineConfig {
+    pub read_queue_capacity: usize,
+    pub parse_queue_capacity: usize,
+    pub workers: usize,
+    pub reorder_window: usize,
+    pub max_skew: Option<u64>,
+    pub lenient: bool,
+    /// the canonical ids records of migrated clients are applied to, see [`crate::id_map`]
+    pub id_map: Option<Arc<IdMap>>,
+    pub amount_policy: AmountPolicy,
+    pub max_amount: Option<Decimal>,
+    /// types of records turned down with [`crate::Rejection::TypeDisabled`], see `check_bounds`
+    pub disallowed: TransactionTypes,
+    /// the side tables records are enriched from and the rules turning them down with
+    /// [`crate::Rejection::Denied`], see [`crate::enrich`]
+    pub enricher: Option<Arc<Enricher>>,
+    /// the clients whose records are screened, see [`crate::screening`]
+    pub screening: Option<Arc<Screening>>,
+    pub resume_from: usize,
+    pub retain_history: bool,
+    /// where clients write out their history once it's over budget, see [`crate::spill`]
+    pub spill: Option<Arc<Spill>>,
+    /// park references to transactions that haven't arrived yet, see [`crate::apply_and_publish`]
+    pub suspense: bool,
+    pub foreign_disputes: ForeignPolicy,
+    /// unfreeze an account once representments reversed every chargeback it had
+    pub unlock_on_representment: bool,
+    /// freeze an account once this many of its transactions were disputed as fraud
+    pub fraud_lock_after: Option<usize>,
+    /// lock or flag an account with too many disputes and chargebacks in a window, see
+    /// [`crate::velocity`]
+    pub velocity: Option<Velocity>,
+    /// the reserves, credit limits and profiles clients are held to, see [`crate::limits`]
+    pub limits: Arc<Limits>,
+    pub locked_deposits: LockedDeposits,
+    /// check the input against its trailer row before applying any of it, see [`crate::trailer`]
+    pub trailer: bool,
+    /// stall the shard workers at random points drawn from this seed, see [`crate::shards`]
+    pub chaos: Option<u64>,
+    /// how client ids are written in the dead letters, journal and logs of the run
+    pub pseudonyms: Pseudonyms,
+    /// the words rejections are described in in the dead letters, see [`crate::messages`]
+    pub messages: Messages,
+    /// where the stages account the time they spend on sampled records, see [`crate::profile`]
+    pub profiler: Option<Arc<Profiler>>,
+    pub interrupted: fn() -> bool,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        PipelineConfig {
+            read_queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            parse_queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            workers: 1,
+            reorder_window: 0,
+            max_skew: None,
+            lenient: false,
+            id_map: None,
+            amount_policy: AmountPolicy::default(),
+            max_amount: None,
+            disallowed: TransactionTypes::default(),
+            enricher: None,
+            screening: None,
+            resume_from: 0,
+            retain_history: true,
+            spill: None,
+            suspense: false,
+            foreign_disputes: ForeignPolicy::default(),
+            unlock_on_representment: false,
+            fraud_lock_after: None,
+            velocity: None,
+            limits: Arc::default(),
+            locked_deposits: LockedDeposits::default(),
+            trailer: false,
+            chaos: None,
+            pseudonyms: Pseudonyms::default(),
+            messages: Messages::default(),
+            profiler: None,
+            interrupted: shutdown::is_requested,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct QueueMetrics {
+    depth: AtomicUsize,
+    max_depth: AtomicUsize,
+    sent: AtomicUsize,
+    stalls: AtomicUsize,
+    stall_nanos: AtomicU64,
+}
+
+/// Point in time view of a queue's metrics, logged once the pipeline drains.
+#[derive(Debug, Clone)]
+pub struct QueueStats {
+    pub name: &'static str,
+    pub capacity: usize,
+    pub sent: usize,
+    pub max_depth: usize,
+    pub stalls: usize,
+    pub stall_time: Duration,
+}
+
+impl fmt::Display for QueueStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} queue: capacity={} sent={} max_depth={} stalls={} stall_time={:?}",
+            self.name, self.capacity, self.sent, self.max_depth, self.stalls, self.stall_time
+        )
+    }
+}
+
+pub(crate) struct Queue {
+    name: &'static str,
+    capacity: usize,
+    metrics: Arc<QueueMetrics>,
+}
+
+impl Queue {
+    pub(crate) fn stats(&self) -> QueueStats {
+        QueueStats {
+            name: self.name,
+            capacity: self.capacity,
+            sent: self.metrics.sent.load(Ordering::Relaxed),
+            max_depth: self.metrics.max_depth.load(Ordering::Relaxed),
+            stalls: self.metrics.stalls.load(Ordering::Relaxed),
+            stall_time: Duration::from_nanos(self.metrics.stall_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+pub(crate) struct BoundedSender<T> {
+    inner: SyncSender<T>,
+    metrics: Arc<QueueMetrics>,
+}
+
+impl<T> BoundedSender<T> {
+    /// Send without blocking when there is room, otherwise block and account the wait as a stall.
+    pub(crate) fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let depth = self.metrics.depth.fetch_add(1, Ordering::Relaxed) + 1;
+        self.metrics.max_depth.fetch_max(depth, Ordering::Relaxed);
+        let sent = match self.inner.try_send(value) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(value)) => {
+                let stalled_at = Instant::now();
+                let sent = self.inner.send(value);
+                self.metrics.stalls.fetch_add(1, Ordering::Relaxed);
+                self.metrics
+                    .stall_nanos
+                    .fetch_add(stalled_at.elapsed().as_nanos() as u64, Ordering::Relaxed);
+                sent
+            }
+            Err(TrySendError::Disconnected(value)) => Err(SendError(value)),
+        };
+        match sent {
+            Ok(()) => {
+                self.metrics.sent.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(_) => {
+                self.metrics.depth.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+        sent
+    }
+}
+
+pub(crate) struct BoundedReceiver<T> {
+    inner: Receiver<T>,
+    metrics: Arc<QueueMetrics>,
+}
+
+impl<T> Iterator for BoundedReceiver<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        let value = self.inner.recv().ok()?;
+        self.metrics.depth.fetch_sub(1, Ordering::Relaxed);
+        Some(value)
+    }
+}
+
+pub(crate) fn bounded<T>(
+    name: &'static str,
+    capacity: usize,
+) -> (BoundedSender<T>, BoundedReceiver<T>, Queue) {
+    let (tx, rx) = mpsc::sync_channel(capacity);
+    let metrics = Arc::new(QueueMetrics::default());
+    (
+        BoundedSender {
+            inner: tx,
+            metrics: metrics.clone(),
+        },
+        BoundedReceiver {
+            inner: rx,
+            metrics: metrics.clone(),
+        },
+        Queue {
+            name,
+            capacity,
+            metrics,
+        },
+    )
+}
+
+type RawRow = (usize, csv::Result<StringRecord>);
+type Parsed = Result<SituatedRecord, ParseFailure>;
+
+/// A row that couldn't be read or deserialized into a [`Record`].
+#[derive(Debug)]
+pub struct ParseFailure {
+    pub monotonic_counter: usize,
+    headers: Arc<StringRecord>,
+    /// the raw row, when it could be read at all
+    pub row: Option<StringRecord>,
+    pub error: RowError,
+    /// how the row's client is written out
+    pseudonyms: Pseudonyms,
+}
+
+impl ParseFailure {
+    /// the `PWM-E` number of a row set aside as unparsable, after those of [`crate::Rejection`].
+    pub const ERROR_CODE: &'static str = "PWM-E100";
+
+    /// raw value of the named column, empty when the row or column is missing.
+    pub fn field(&self, column: &str) -> &str {
+        let index = self.headers.iter().position(|header| header == column);
+        match (index, &self.row) {
+            (Some(index), Some(row)) => row.get(index).unwrap_or(""),
+            _ => "",
+        }
+    }
+
+    /// the named column as it's written out, which pseudonymizes the client.
+    pub fn written(&self, column: &str) -> String {
+        match column {
+            "client" => self.pseudonyms.client(self.field(column)),
+            _ => self.field(column).to_string(),
+        }
+    }
+
+    /// the raw row as a line of CSV with its client pseudonymized, when it could be read.
+    pub fn line(&self) -> Option<String> {
+        self.row
+            .as_ref()
+            .map(|row| to_csv_line(&self.pseudonyms.row(&self.headers, row)))
+    }
+}
+
+/// Why a row couldn't be made a [`Record`]: reading or deserializing it failed, or the amount
+/// policy turned its amount down.
+#[derive(Debug)]
+pub enum RowError {
+    Csv(csv::Error),
+    Amount(Refused),
+}
+
+impl RowError {
+    /// whether reading the input failed, rather than the row being wrong.
+    pub fn is_io_error(&self) -> bool {
+        match self {
+            RowError::Csv(error) => error.is_io_error(),
+            RowError::Amount(_) => false,
+        }
+    }
+
+    pub fn position(&self) -> Option<&csv::Position> {
+        match self {
+            RowError::Csv(error) => error.position(),
+            RowError::Amount(refused) => refused.position.as_ref(),
+        }
+    }
+}
+
+impl From<csv::Error> for RowError {
+    fn from(error: csv::Error) -> Self {
+        RowError::Csv(error)
+    }
+}
+
+impl From<Refused> for RowError {
+    fn from(refused: Refused) -> Self {
+        RowError::Amount(refused)
+    }
+}
+
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RowError::Csv(error) => write!(f, "{}", error),
+            RowError::Amount(refused) => write!(f, "{}", refused),
+        }
+    }
+}
+
+impl std::error::Error for RowError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RowError::Csv(error) => Some(error),
+            RowError::Amount(refused) => Some(refused),
+        }
+    }
+}
+
+/// A parse failure that stopped the pipeline, with enough context to find the row in the input.
+#[derive(Debug)]
+pub struct ParseError {
+    /// 1-based line and 0-based byte offset the row starts at, when the reader got that far
+    pub line: Option<u64>,
+    pub byte: Option<u64>,
+    /// the offending row as read, re-quoted where a field needs it
+    pub row: Option<String>,
+    pub source: RowError,
+}
+
+impl From<csv::Error> for ParseError {
+    fn from(source: csv::Error) -> Self {
+        let position = source.position().cloned();
+        ParseError {
+            line: position.as_ref().map(csv::Position::line),
+            byte: position.as_ref().map(csv::Position::byte),
+            row: None,
+            source: RowError::Csv(source),
+        }
+    }
+}
+
+impl From<ParseFailure> for ParseError {
+    fn from(failure: ParseFailure) -> Self {
+        let position = failure
+            .error
+            .position()
+            .or_else(|| failure.row.as_ref().and_then(StringRecord::position))
+            .cloned();
+        ParseError {
+            line: position.as_ref().map(csv::Position::line),
+            byte: position.as_ref().map(csv::Position::byte),
+            row: failure.line(),
+            source: failure.error,
+        }
+    }
+}
+
+fn to_csv_line(row: &StringRecord) -> String {
+    let mut writer = csv::WriterBuilder::new()
+        .terminator(csv::Terminator::Any(b'\n'))
+        .from_writer(vec![]);
+    // writing to a Vec can't fail
+    let _ = writer.write_record(row);
+    let line = writer.into_inner().unwrap_or_default();
+    String::from_utf8_lossy(&line).trim_end().to_string()
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.line, self.byte) {
+            (Some(line), Some(byte)) => write!(f, "line {} (byte {}): ", line, byte)?,
+            (Some(line), None) => write!(f, "line {}: ", line)?,
+            _ => {}
+        }
+        // deserialize errors repeat the position, only keep what went wrong
+        match &self.source {
+            RowError::Csv(source) => match source.kind() {
+                csv::ErrorKind::Deserialize { err, .. } => write!(f, "{}", err)?,
+                _ => write!(f, "{}", source)?,
+            },
+            RowError::Amount(refused) => write!(f, "{}", refused.message)?,
+        }
+        if let Some(row) = &self.row {
+            write!(f, "\n  row: {}", row)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+impl From<ParseError> for io::Error {
+    fn from(error: ParseError) -> Self {
+        let kind = match &error.source {
+            RowError::Csv(source) => match source.kind() {
+                csv::ErrorKind::Io(e) => e.kind(),
+                _ => io::ErrorKind::InvalidData,
+            },
+            RowError::Amount(_) => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, error)
+    }
+}
+
+/// Stream records through reader -> parser -> engine stages, each on its own thread and connected
+/// by bounded queues. `apply` runs on the calling thread in input order, or timestamp order within
+/// the reorder window. Rows that fail to read or parse go to `reject`, which either sets them
+/// aside and returns Ok, or returns an error that stops the pipeline. That error is returned
+/// after the stages have wound down.
+pub fn run<R, F, E>(
+    reader: Reader<R>,
+    config: &PipelineConfig,
+    apply: F,
+    reject: E,
+) -> Result<Vec<QueueStats>, ParseError>
+where
+    R: Read + Send + 'static,
+    F: FnMut(SituatedRecord),
+    E: FnMut(ParseFailure) -> Result<(), ParseError>,
+{
+    let mut reader = reader;
+    let headers = Arc::new(reader.headers()?.clone());
+    let (raw_tx, raw_rx, read_queue) = bounded::<RawRow>("read", config.read_queue_capacity);
+    let (parsed_tx, parsed_rx, parse_queue) =
+        bounded::<Parsed>("parse", config.parse_queue_capacity);
+
+    let resume_from = config.resume_from;
+    let interrupted = config.interrupted;
+    let profiler = config.profiler.clone();
+    let reader_stage = thread::spawn(move || {
+        let mut rows = reader.into_records().enumerate().skip(resume_from);
+        loop {
+            let started = profile::start(profiler.as_deref());
+            let row = match rows.next() {
+                Some(row) => row,
+                None => break,
+            };
+            profile::finish(profiler.as_deref(), started, Stage::Read, "*", row.0);
+            if interrupted() {
+                warn!("Interrupted, no longer reading from row ({}).", row.0);
+                break;
+            }
+            if raw_tx.send(row).is_err() {
+                break;
+            }
+        }
+    });
+    let lenient = config.lenient;
+    let pseudonyms = config.pseudonyms.clone();
+    let amount_policy = config.amount_policy;
+    let profiler = config.profiler.clone();
+    let parser_stage = thread::spawn(move || {
+        for (monotonic_counter, row) in raw_rx {
+            let started = profile::start(profiler.as_deref());
+            let row = match row {
+                Ok(row) if lenient => match lenient::normalize(&headers, &row, &pseudonyms) {
+                    Some(row) => Ok(row),
+                    None => continue,
+                },
+                row => row,
+            };
+            let parsed = match row {
+                Ok(row) => {
+                    match amount_policy
+                        .apply(&headers, &row)
+                        .map_err(RowError::from)
+                        .and_then(|(policed, adjustment)| {
+                            let record = policed.deserialize::<Record>(Some(&headers))?;
+                            Ok((record, adjustment))
+                        }) {
+                        Ok((record, adjustment)) => {
+                            if let Some(adjustment) = adjustment {
+                                warn!(
+                                "Amount ({}) of record ({}) was adjusted to {} per the amount policy.",
+                                adjustment.raw, monotonic_counter, adjustment.adjusted
+                            );
+                            }
+                            Ok(SituatedRecord {
+                                monotonic_counter,
+                                record,
+                            })
+                        }
+                        Err(error) => Err(ParseFailure {
+                            monotonic_counter,
+                            headers: headers.clone(),
+                            row: Some(row),
+                            error,
+                            pseudonyms: pseudonyms.clone(),
+                        }),
+                    }
+                }
+                Err(error) => Err(ParseFailure {
+                    monotonic_counter,
+                    headers: headers.clone(),
+                    row: None,
+                    error: RowError::Csv(error),
+                    pseudonyms: pseudonyms.clone(),
+                }),
+            };
+            let kind = match &parsed {
+                Ok(situated_record) => situated_record.record.transaction_type.as_str(),
+                Err(_) => "unparsable",
+            };
+            profile::finish(
+                profiler.as_deref(),
+                started,
+                Stage::Parse,
+                kind,
+                monotonic_counter,
+            );
+            if parsed_tx.send(parsed).is_err() {
+                break;
+            }
+        }
+    });
+
+    let applied = apply_all(parsed_rx, config, apply, reject);
+    for stage in [reader_stage, parser_stage] {
+        if let Err(panic) = stage.join() {
+            std::panic::resume_unwind(panic);
+        }
+    }
+    let stats = vec![read_queue.stats(), parse_queue.stats()];
+    for queue_stats in &stats {
+        info!("{}", queue_stats);
+    }
+    applied.map(|_| stats)
+}
+
+/// Takes the receiver by value so an early error hangs up on the parser stage, unblocking it.
+fn apply_all<F, E>(
+    parsed: BoundedReceiver<Parsed>,
+    config: &PipelineConfig,
+    mut apply: F,
+    mut reject: E,
+) -> Result<(), ParseError>
+where
+    F: FnMut(SituatedRecord),
+    E: FnMut(ParseFailure) -> Result<(), ParseError>,
+{
+    let mut reorder = ReorderBuffer::new(config.reorder_window);
+    let mut skew = config.max_skew.map(SkewDetector::new);
+    let mut release = |situated_record: SituatedRecord| {
+        if let Some(skew) = skew.as_mut() {
+            skew.observe(&situated_record);
+        }
+        apply(situated_record);
+    };
+    for parsed in parsed {
+        match parsed {
+            Ok(situated_record) => {
+                if let Some(ready) = reorder.push(situated_record) {
+                    release(ready);
+                }
+            }
+            Err(failure) => reject(failure)?,
+        }
+    }
+    while let Some(ready) = reorder.pop() {
+        release(ready);
+    }
+    if let Some(skew) = skew.filter(|skew| skew.flagged() > 0) {
+        warn!(
+            "{} records arrived further out of timestamp order than the allowed skew, the feed may not be chronological.",
+            skew.flagged()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::TxId;
+    use csv::{ReaderBuilder, Trim};
+    use std::io::Cursor;
+
+    fn abort(failure: ParseFailure) -> Result<(), ParseError> {
+        Err(failure.into())
+    }
+
+    fn reader_for(data: &str) -> Reader<Cursor<Vec<u8>>> {
+        ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(Cursor::new(data.as_bytes().to_vec()))
+    }
+
+    fn deposits(count: usize) -> String {
+        let mut data = String::from("type,client,tx,amount\n");
+        for tx in 0..count {
+            data.push_str(&format!("deposit,1,{},1.0\n", tx));
+        }
+        data
+    }
+
+    #[test]
+    fn test_records_arrive_in_order() {
+        let mut seen = vec![];
+        let config = PipelineConfig::default();
+        run(
+            reader_for(&deposits(50)),
+            &config,
+            |situated| seen.push((situated.monotonic_counter, situated.record.transaction_id)),
+            abort,
+        )
+        .unwrap();
+        let expected: Vec<(usize, TxId)> = (0..50).map(|i| (i, TxId(i as u32))).collect();
+        assert_eq!(expected, seen);
+    }
+
+    #[test]
+    fn test_slow_engine_stalls_upstream() {
+        let config = PipelineConfig {
+            read_queue_capacity: 1,
+            parse_queue_capacity: 1,
+            ..PipelineConfig::default()
+        };
+        let mut applied = 0;
+        let apply = |_| {
+            thread::sleep(Duration::from_millis(2));
+            applied += 1;
+        };
+        let stats = run(reader_for(&deposits(20)), &config, apply, abort).unwrap();
+        assert_eq!(20, applied);
+        let parse_stats = stats.iter().find(|s| s.name == "parse").unwrap();
+        assert_eq!(20, parse_stats.sent);
+        // the buffered slot, plus one blocked sender and one record mid hand-off to the engine
+        assert!(parse_stats.max_depth <= config.parse_queue_capacity + 2);
+        assert!(parse_stats.stalls > 0);
+        assert!(parse_stats.stall_time > Duration::ZERO);
+    }
+
+    #[test]
+    fn test_parse_error_stops_pipeline() {
+        let config = PipelineConfig {
+            read_queue_capacity: 1,
+            parse_queue_capacity: 1,
+            ..PipelineConfig::default()
+        };
+        let mut data = deposits(5);
+        data.push_str("deposit,not-a-client,6,1.0\n");
+        for tx in 7..100 {
+            data.push_str(&format!("deposit,1,{},1.0\n", tx));
+        }
+        let mut applied = 0;
+        let error = run(reader_for(&data), &config, |_| applied += 1, abort).unwrap_err();
+        assert_eq!(5, applied);
+        assert_eq!(Some(7), error.line);
+        assert_eq!(Some("deposit,not-a-client,6,1.0"), error.row.as_deref());
+        let message = error.to_string();
+        assert!(message.starts_with("line 7 (byte "));
+        assert!(message.ends_with("\n  row: deposit,not-a-client,6,1.0"));
+    }
+
+    #[test]
+    fn test_rejected_parse_failure_continues() {
+        let mut data = deposits(2);
+        data.push_str("deposit,not-a-client,2,1.0\n");
+        data.push_str("deposit,1,3,1.0\n");
+        let mut applied = vec![];
+        let mut rejected = vec![];
+        run(
+            reader_for(&data),
+            &PipelineConfig::default(),
+            |situated| applied.push(situated.monotonic_counter),
+            |failure| {
+                rejected.push((
+                    failure.monotonic_counter,
+                    failure.field("client").to_string(),
+                    failure.field("no-such-column").to_string(),
+                ));
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(vec![0, 1, 3], applied);
+        assert_eq!(
+            vec![(2, "not-a-client".to_string(), String::new())],
+            rejected
+        );
+    }
+}
@@ -0,0 +1,218 @@
+use crate::digest::{self, Sha256};
+use crate::source::Source;
+use csv::{ReaderBuilder, WriterBuilder};
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const COLUMNS: [&str; 2] = ["sha256", "input"];
+
+/// The file in a checkpoint directory the processed inputs are kept in, when checkpointing and
+/// not given another.
+pub const PROCESSED: &str = "processed.csv";
+
+/// What to do with an input whose content was already processed once.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum DuplicatePolicy {
+    /// fail the run
+    #[default]
+    Refuse,
+    /// warn and process nothing
+    Skip,
+}
+
+impl FromStr for DuplicatePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "refuse" => Ok(DuplicatePolicy::Refuse),
+            "skip" => Ok(DuplicatePolicy::Skip),
+            _ => Err(format!(
+                "Unknown duplicate input policy ({}), expected refuse or skip.",
+                s
+            )),
+        }
+    }
+}
+
+/// SHA-256 of everything `source` reads, hex encoded.
+pub fn hash(source: Source) -> io::Result<String> {
+    tee(source, &mut io::sink())
+}
+
+/// hash everything `source` reads, as [`hash`] does, while writing it to `copy`.
+fn tee<W: Write>(mut source: Source, copy: &mut W) -> io::Result<String> {
+    let mut sha = Sha256::new();
+    let mut buf = [0; 64 * 1024];
+    loop {
+        match source.read(&mut buf)? {
+            0 => {
+                copy.flush()?;
+                return Ok(digest::hex(&sha.finish()));
+            }
+            read => {
+                sha.update(&buf[..read]);
+                copy.write_all(&buf[..read])?;
+            }
+        }
+    }
+}
+
+/// An input copied to a file as it was hashed, so a run reads the very bytes that were checked
+/// against [`Processed`], and a remote input is downloaded once. The file is one of its own,
+/// created where nothing was, and removed on drop.
+#[derive(Debug)]
+pub struct Spooled {
+    pub path: PathBuf,
+    pub hash: String,
+}
+
+impl Spooled {
+    /// spool `source` to a new file in `dir`.
+    pub fn create(source: Source, dir: &Path) -> io::Result<Self> {
+        let (file, path) = create_new(dir)?;
+        let mut copy = BufWriter::new(file);
+        // made first, so a failed copy is cleaned up as well
+        let mut spooled = Spooled {
+            path,
+            hash: String::new(),
+        };
+        spooled.hash = tee(source, &mut copy)?;
+        Ok(spooled)
+    }
+}
+
+/// a file in `dir` under a name no other file has, created only if nothing is there yet, so it
+/// can't be a file or symlink planted under a name guessed in advance. Only the owner can read
+/// it where permissions allow.
+fn create_new(dir: &Path) -> io::Result<(File, PathBuf)> {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.subsec_nanos());
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    for _ in 0..16 {
+        let path = dir.join(format!(
+            "playing-with-money-{}-{:08x}-{}.csv",
+            std::process::id(),
+            nanos,
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        match options.open(&path) {
+            Ok(file) => return Ok((file, path)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("No spool file could be created in ({:?}).", dir),
+    ))
+}
+
+impl Drop for Spooled {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// The content hashes of the inputs processed so far, kept in a `sha256,input` CSV that each
+/// finished run appends to, so the same day's batch isn't applied twice under another name.
+#[derive(Debug)]
+pub struct Processed {
+    path: PathBuf,
+    hashes: HashSet<String>,
+}
+
+impl Processed {
+    /// the inputs recorded at `path`, none if there's no file there yet.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut hashes = HashSet::new();
+        if path.exists() {
+            let mut reader = ReaderBuilder::new().from_path(path)?;
+            for row in reader.records() {
+                let row = row?;
+                let hash = row.get(0).filter(|hash| hash.len() == 64).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Malformed processed input ({:?}) in ({:?}).", row, path),
+                    )
+                })?;
+                hashes.insert(hash.to_string());
+            }
+        }
+        Ok(Processed {
+            path: path.to_path_buf(),
+            hashes,
+        })
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.hashes.contains(hash)
+    }
+
+    /// note that the input named `input` with content `hash` was processed.
+    pub fn record(&mut self, hash: &str, input: &str) -> io::Result<()> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        let new = file.metadata()?.len() == 0;
+        let mut writer = WriterBuilder::new().from_writer(file);
+        if new {
+            writer.write_record(COLUMNS)?;
+        }
+        writer.write_record([hash, input])?;
+        writer.flush()?;
+        self.hashes.insert(hash.to_string());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_processed() {
+        let path = env::temp_dir().join(format!("processed-{}.csv", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let first = hash(Box::new("type,client,tx,amount\n".as_bytes())).unwrap();
+        let second = hash(Box::new("type,client,tx,amount\n\n".as_bytes())).unwrap();
+        assert_ne!(first, second);
+        let mut processed = Processed::open(&path).unwrap();
+        assert!(!processed.contains(&first));
+        processed.record(&first, "monday.csv").unwrap();
+        processed.record(&second, "tuesday.csv").unwrap();
+        let reopened = Processed::open(&path).unwrap();
+        assert!(reopened.contains(&first) && reopened.contains(&second));
+        assert_eq!(3, std::fs::read_to_string(&path).unwrap().lines().count());
+        std::fs::remove_file(&path).unwrap();
+
+        let input = "type,client,tx,amount\ndeposit,1,1,5\n";
+        let spooled = Spooled::create(Box::new(input.as_bytes()), &env::temp_dir()).unwrap();
+        assert_eq!(hash(Box::new(input.as_bytes())).unwrap(), spooled.hash);
+        assert_eq!(input, std::fs::read_to_string(&spooled.path).unwrap());
+        // each spool gets a file of its own
+        let other = Spooled::create(Box::new(input.as_bytes()), &env::temp_dir()).unwrap();
+        assert_ne!(spooled.path, other.path);
+        let path = spooled.path.clone();
+        drop(spooled);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_parse_policy() {
+        assert_eq!(Ok(DuplicatePolicy::Skip), "skip".parse());
+        assert!("ignore".parse::<DuplicatePolicy>().is_err());
+    }
+}
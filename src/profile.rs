@@ -0,0 +1,176 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Time N of every M records, picked by monotonic counter so every stage times the same ones.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Sampling {
+    pub n: usize,
+    pub m: usize,
+}
+
+impl Sampling {
+    pub fn samples(&self, monotonic_counter: usize) -> bool {
+        monotonic_counter % self.m < self.n
+    }
+}
+
+impl FromStr for Sampling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid sampling ({}), expected N/M like 1/100.", s);
+        let (n, m) = s.split_once('/').ok_or_else(invalid)?;
+        let n: usize = n.trim().parse().map_err(|_| invalid())?;
+        let m: usize = m.trim().parse().map_err(|_| invalid())?;
+        if n == 0 || m < n {
+            return Err(invalid());
+        }
+        Ok(Sampling { n, m })
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    /// reading the row off the input, before its type is known
+    Read,
+    /// deserializing the row into a record
+    Parse,
+    /// bounds checks and the engine
+    Apply,
+}
+
+impl Stage {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::Read => "read",
+            Stage::Parse => "parse",
+            Stage::Apply => "apply",
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+struct Timing {
+    samples: usize,
+    total: Duration,
+    max: Duration,
+}
+
+/// Time spent per stage and transaction type in the sampled records.
+#[derive(Debug)]
+pub struct Profiler {
+    sampling: Sampling,
+    timings: Mutex<BTreeMap<(Stage, &'static str), Timing>>,
+}
+
+impl Profiler {
+    pub fn new(sampling: Sampling) -> Self {
+        Profiler {
+            sampling,
+            timings: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// account `elapsed` to `stage` and `kind` (a transaction type, or "*" before it's known) if
+    /// the record is sampled.
+    pub fn record(
+        &self,
+        stage: Stage,
+        kind: &'static str,
+        monotonic_counter: usize,
+        elapsed: Duration,
+    ) {
+        if !self.sampling.samples(monotonic_counter) {
+            return;
+        }
+        let mut timings = self.timings.lock().unwrap_or_else(|e| e.into_inner());
+        let timing = timings.entry((stage, kind)).or_default();
+        timing.samples += 1;
+        timing.total += elapsed;
+        timing.max = timing.max.max(elapsed);
+    }
+}
+
+impl fmt::Display for Profiler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let timings = self.timings.lock().unwrap_or_else(|e| e.into_inner());
+        let total: Duration = timings.values().map(|timing| timing.total).sum();
+        writeln!(
+            f,
+            "profile of {} in every {} records:",
+            self.sampling.n, self.sampling.m
+        )?;
+        writeln!(
+            f,
+            "{:<6} {:<11} {:>8} {:>12} {:>12} {:>6}",
+            "stage", "type", "samples", "mean", "max", "share"
+        )?;
+        for ((stage, kind), timing) in timings.iter() {
+            writeln!(
+                f,
+                "{:<6} {:<11} {:>8} {:>12} {:>12} {:>5.1}%",
+                stage.name(),
+                kind,
+                timing.samples,
+                format!("{:?}", timing.total / timing.samples as u32),
+                format!("{:?}", timing.max),
+                100.0 * timing.total.as_secs_f64() / total.as_secs_f64().max(f64::MIN_POSITIVE)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// when a timed section started, if there's a profiler to account it to.
+pub fn start(profiler: Option<&Profiler>) -> Option<Instant> {
+    profiler.map(|_| Instant::now())
+}
+
+/// account the time since `started` (see [`start`]) to `stage` and `kind` of `profiler`.
+pub fn finish(
+    profiler: Option<&Profiler>,
+    started: Option<Instant>,
+    stage: Stage,
+    kind: &'static str,
+    monotonic_counter: usize,
+) {
+    if let (Some(started), Some(profiler)) = (started, profiler) {
+        profiler.record(stage, kind, monotonic_counter, started.elapsed());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sampling() {
+        let sampling: Sampling = "2/10".parse().unwrap();
+        let sampled: Vec<usize> = (0..25).filter(|c| sampling.samples(*c)).collect();
+        assert_eq!(vec![0, 1, 10, 11, 20, 21], sampled);
+        assert!("0/10".parse::<Sampling>().is_err());
+        assert!("5/2".parse::<Sampling>().is_err());
+        assert!("10".parse::<Sampling>().is_err());
+    }
+
+    #[test]
+    fn test_profiler() {
+        let profiler = Profiler::new(Sampling { n: 1, m: 2 });
+        profiler.record(Stage::Apply, "deposit", 0, Duration::from_micros(3));
+        profiler.record(Stage::Apply, "deposit", 1, Duration::from_secs(9));
+        profiler.record(Stage::Apply, "deposit", 2, Duration::from_micros(1));
+        profiler.record(Stage::Parse, "dispute", 4, Duration::from_micros(4));
+        let report = profiler.to_string();
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(4, lines.len());
+        assert!(lines[2].starts_with("parse  dispute"));
+        assert!(lines[2].ends_with("50.0%"));
+        assert!(lines[3].starts_with("apply  deposit"));
+        assert!(lines[3].contains(" 2 "));
+        assert!(lines[3].contains("2µs"));
+        assert!(lines[3].contains("3µs"));
+    }
+}
@@ -0,0 +1,90 @@
+use crate::digest::{hex, hmac};
+use csv::StringRecord;
+use std::fmt::{self, Display};
+use std::sync::Arc;
+
+/// How client ids are written in outputs, reports and logs: as they are, or as salted hashes once
+/// [`Pseudonyms::salted`]. Processing still uses the real ids. Cloning shares the salt.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Pseudonyms {
+    salt: Option<Arc<[u8]>>,
+}
+
+impl Pseudonyms {
+    pub fn salted(salt: &str) -> Self {
+        Pseudonyms {
+            salt: Some(salt.as_bytes().into()),
+        }
+    }
+
+    pub fn is_salted(&self) -> bool {
+        self.salt.is_some()
+    }
+
+    /// how `client` is written out: the id itself, or its [`pseudonym`] once salted.
+    pub fn client<T: Display>(&self, client: T) -> String {
+        match &self.salt {
+            Some(salt) => pseudonym(salt, &client.to_string()),
+            None => client.to_string(),
+        }
+    }
+
+    /// `row` with its client column, if `headers` has one, passed through [`Pseudonyms::client`].
+    pub fn row(&self, headers: &StringRecord, row: &StringRecord) -> StringRecord {
+        if !self.is_salted() {
+            return row.clone();
+        }
+        let mut pseudonymized: StringRecord = row
+            .iter()
+            .enumerate()
+            .map(|(index, field)| match headers.get(index) {
+                Some("client") => self.client(field),
+                _ => field.to_string(),
+            })
+            .collect();
+        pseudonymized.set_position(row.position().cloned());
+        pseudonymized
+    }
+}
+
+// by hand so the salt doesn't end up in a log of the config
+impl fmt::Debug for Pseudonyms {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pseudonyms")
+            .field("salted", &self.is_salted())
+            .finish()
+    }
+}
+
+/// 16 hex digits of HMAC-SHA256(salt, client id), so the same id always maps to the same
+/// pseudonym for a given salt. Ids are few enough to guess, so the salt has to stay secret.
+pub fn pseudonym(salt: &[u8], client: &str) -> String {
+    hex(&hmac(salt, &[client.trim().as_bytes()])[..8])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pseudonym() {
+        let one = pseudonym(b"salt", "1");
+        assert_eq!(16, one.len());
+        assert_eq!(one, pseudonym(b"salt", " 1"));
+        assert_ne!(one, pseudonym(b"salt", "2"));
+        assert_ne!(one, pseudonym(b"pepper", "1"));
+    }
+
+    #[test]
+    fn test_pseudonyms() {
+        let (salt, pepper) = (Pseudonyms::salted("salt"), Pseudonyms::salted("pepper"));
+        assert_eq!("1", Pseudonyms::default().client(1));
+        assert_eq!(pseudonym(b"salt", "1"), salt.client(1));
+        // two salts side by side in one process
+        assert_ne!(salt.client(1), pepper.client(1));
+        let headers = StringRecord::from(vec!["type", "client"]);
+        let row = StringRecord::from(vec!["deposit", "1"]);
+        assert_eq!(row, Pseudonyms::default().row(&headers, &row));
+        assert_eq!(salt.client(1), salt.row(&headers, &row)[1]);
+    }
+}
@@ -0,0 +1,136 @@
+use crate::checkpoint;
+use crate::output::AmountFormat;
+use crate::pipeline::PipelineConfig;
+use crate::pseudonym::Pseudonyms;
+use crate::{ClientId, ClientState, Disputable, DisputeReason, DisputeStatus, TxId};
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+/// The point in a run to look at a client as of: just before the record at a counter, or after
+/// the last record stamped at or before a timestamp.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AsOf {
+    Counter(usize),
+    Timestamp(u64),
+}
+
+impl FromStr for AsOf {
+    type Err = String;
+
+    /// `counter=N`, `timestamp=T`, or a bare counter.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Invalid point in time ({}), expected counter=N, timestamp=T or N.",
+                s
+            )
+        };
+        match s.split_once('=') {
+            Some(("counter", counter)) => counter.parse().map(AsOf::Counter),
+            Some(("timestamp", timestamp)) => timestamp.parse().map(AsOf::Timestamp),
+            Some(_) => return Err(invalid()),
+            None => s.parse().map(AsOf::Counter),
+        }
+        .map_err(|_| invalid())
+    }
+}
+
+/// Client `client_id` as it was at `as_of`, rebuilt from the checkpoint history in `dir` rather
+/// than the input, see [`ClientState::as_of`]. Its records keep the order they were applied in,
+/// so a timestamp means the first of them stamped later than it and everything after is left out.
+/// The client is held to the limits and settings of `pipeline_config`.
+pub fn client_as_of(
+    dir: &Path,
+    client_id: ClientId,
+    as_of: AsOf,
+    pipeline_config: &PipelineConfig,
+) -> io::Result<ClientState> {
+    let mut client = checkpoint::client_history(dir, client_id)?.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "No history of client ({}) in checkpoints ({:?}).",
+                client_id, dir
+            ),
+        )
+    })?;
+    client.configure(pipeline_config);
+    let counter = match as_of {
+        AsOf::Counter(counter) => counter,
+        AsOf::Timestamp(timestamp) => client.first_after(timestamp).unwrap_or(usize::MAX),
+    };
+    Ok(client
+        .as_of(counter)
+        .expect("clients rebuilt from history retain it"))
+}
+
+/// The client's balances, then a row per transaction it ever disputed with where its dispute
+/// stands, or a single row with blank dispute columns if it disputed none.
+pub fn write_client<W: io::Write>(
+    writer: W,
+    client: &ClientState,
+    amounts: AmountFormat,
+    pseudonyms: &Pseudonyms,
+) -> csv::Result<()> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    wtr.write_record([
+        "client",
+        "available",
+        "held",
+        "total",
+        "locked",
+        "tx",
+        "tx_type",
+        "amount",
+        "status",
+        "reason_code",
+    ])?;
+    let balances = [
+        pseudonyms.client(client.client_id),
+        amounts.format(client.get_available_funds()),
+        amounts.format(client.get_held_funds()),
+        amounts.format(client.get_total_funds()),
+        client.is_locked().to_string(),
+    ];
+    let mut disputed: Vec<(TxId, &Disputable)> = client
+        .disputables()
+        .filter(|(_, disputable)| disputable.status != DisputeStatus::Undisputed)
+        .collect();
+    disputed.sort_unstable_by_key(|(tx_id, _)| *tx_id);
+    if disputed.is_empty() {
+        let mut row = balances.to_vec();
+        row.resize(10, String::new());
+        wtr.write_record(&row)?;
+    }
+    for (tx_id, disputable) in disputed {
+        let mut row = balances.to_vec();
+        row.extend([
+            tx_id.to_string(),
+            disputable.transaction_type.as_str().to_string(),
+            amounts.format(disputable.amount),
+            disputable.status.as_str().to_string(),
+            DisputeReason::field(disputable.reason).to_string(),
+        ]);
+        wtr.write_record(&row)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_as_of() {
+        assert_eq!(Ok(AsOf::Counter(12)), "12".parse());
+        assert_eq!(Ok(AsOf::Counter(12)), "counter=12".parse());
+        assert_eq!(
+            Ok(AsOf::Timestamp(1700000000)),
+            "timestamp=1700000000".parse()
+        );
+        assert!("time=12".parse::<AsOf>().is_err());
+        assert!("counter=x".parse::<AsOf>().is_err());
+    }
+}
@@ -0,0 +1,397 @@
+use crate::checkpoint::{self, HISTORY};
+use crate::id_map;
+use crate::pipeline::{self, PipelineConfig};
+use crate::pseudonym::Pseudonyms;
+use crate::schema::{self, ColumnMap};
+use crate::{
+    check_bounds, get_reader, process_record_with, ClientId, ClientState, Disputable, Record,
+    Rejection, SituatedRecord, REFERENCE_COLUMNS,
+};
+use csv::{Reader, StringRecord};
+use log::debug;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::Path;
+
+/// Records read back from a dead letter file (see `--dead-letter`), keyed by the counter they
+/// had in the original input.
+#[derive(Debug)]
+pub struct Rejects {
+    headers: StringRecord,
+    rows: BTreeMap<usize, StringRecord>,
+}
+
+/// What became of a replayed record. `rejection` is the reason code it was turned down with
+/// again, if it was.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Replayed {
+    pub monotonic_counter: usize,
+    pub client: String,
+    pub tx: String,
+    pub rejection: Option<&'static str>,
+    /// the transaction it refers to, see [`REFERENCE_COLUMNS`]
+    pub reference: [String; 3],
+}
+
+impl Rejects {
+    pub fn read<R: io::Read>(mut reader: Reader<R>) -> csv::Result<Self> {
+        let headers = reader.headers()?.clone();
+        let counter = headers
+            .iter()
+            .position(|header| header == "counter")
+            .ok_or_else(|| invalid("no counter column, is this a dead letter file?".to_string()))?;
+        let mut rows = BTreeMap::new();
+        for row in reader.into_records() {
+            let row = row?;
+            let monotonic_counter = row
+                .get(counter)
+                .and_then(|counter| counter.parse().ok())
+                .ok_or_else(|| invalid(format!("invalid counter in row {:?}", row)))?;
+            rows.insert(monotonic_counter, row);
+        }
+        Ok(Rejects { headers, rows })
+    }
+
+    /// whether the record at this counter of the original input was dead lettered, in which case
+    /// it's held back while rebuilding state and applied by `replay` instead.
+    pub fn contains(&self, monotonic_counter: usize) -> bool {
+        self.rows.contains_key(&monotonic_counter)
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Re-submit every record, in original input order, to the engine, as a run with
+    /// `pipeline_config` would apply it.
+    pub fn replay(
+        &self,
+        clients: &mut HashMap<ClientId, ClientState>,
+        pipeline_config: &PipelineConfig,
+    ) -> Vec<Replayed> {
+        self.rows
+            .iter()
+            .map(|(&monotonic_counter, row)| {
+                let parsed = pipeline_config
+                    .amount_policy
+                    .apply(&self.headers, row)
+                    .ok()
+                    .and_then(|(policed, _)| {
+                        policed.deserialize::<Record>(Some(&self.headers)).ok()
+                    });
+                let (rejection, reference) = match parsed {
+                    Some(record) => {
+                        let situated_record = SituatedRecord {
+                            monotonic_counter,
+                            record,
+                        };
+                        let (record, processed) = apply(situated_record, clients, pipeline_config);
+                        let reference = clients
+                            .get(&record.client_id)
+                            .and_then(|client| client.referenced(&record));
+                        (processed.err().map(|rejection| rejection.code()), reference)
+                    }
+                    None => (Some("parse_error"), None),
+                };
+                Replayed {
+                    monotonic_counter,
+                    client: self.field(row, "client").to_string(),
+                    tx: self.field(row, "tx").to_string(),
+                    rejection,
+                    reference: Disputable::fields(reference.as_ref()),
+                }
+            })
+            .collect()
+    }
+
+    fn field<'r>(&self, row: &'r StringRecord, column: &str) -> &'r str {
+        self.headers
+            .iter()
+            .position(|header| header == column)
+            .and_then(|index| row.get(index))
+            .unwrap_or_default()
+    }
+}
+
+fn invalid(message: String) -> csv::Error {
+    csv::Error::from(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+/// Apply a record as a run with `pipeline_config` would: its ids mapped, its bounds checked, then
+/// applied to its client. Returns the record as it was applied along with the outcome.
+fn apply(
+    situated_record: SituatedRecord,
+    clients: &mut HashMap<ClientId, ClientState>,
+    pipeline_config: &PipelineConfig,
+) -> (Record, Result<(), Rejection>) {
+    let situated_record =
+        match id_map::canonical(situated_record, pipeline_config.id_map.as_deref()) {
+            Ok(situated_record) => situated_record,
+            Err(rejection) => return (situated_record.record, Err(rejection)),
+        };
+    let processed = check_bounds(&situated_record, pipeline_config)
+        .and_then(|_| process_record_with(situated_record, clients, pipeline_config).map(|_| ()));
+    (situated_record.record, processed)
+}
+
+/// Rebuild client state from the history, minus the records that were dead lettered from it, then
+/// re-submit those in their original order and report which now apply. Without a history, state
+/// is the one checkpointed in `checkpoint_dir`, if there is one. Both are applied as a run with
+/// `pipeline_config` and `columns` would.
+pub fn replay_file(
+    rejects_input: &Path,
+    history: Option<&Path>,
+    checkpoint_dir: &Path,
+    pipeline_config: &PipelineConfig,
+    columns: &ColumnMap,
+) -> io::Result<Vec<Replayed>> {
+    let rejects = Rejects::read(get_reader(rejects_input)?)?;
+    let mut clients = HashMap::new();
+    match history {
+        Some(history) => {
+            let mut reader = get_reader(history)?;
+            schema::prepare(&mut reader, columns)?;
+            pipeline::run(
+                reader,
+                pipeline_config,
+                |situated_record| {
+                    if !rejects.contains(situated_record.monotonic_counter) {
+                        let _ = apply(situated_record, &mut clients, pipeline_config);
+                    }
+                },
+                |failure| {
+                    if rejects.contains(failure.monotonic_counter) {
+                        Ok(())
+                    } else {
+                        Err(failure.into())
+                    }
+                },
+            )?;
+        }
+        None if checkpoint_dir.join(HISTORY).exists() => {
+            // dead lettered records the history keeps, like declined withdrawals, are held back
+            let next = checkpoint::load_except(
+                checkpoint_dir,
+                pipeline_config,
+                &mut clients,
+                &|monotonic_counter| rejects.contains(monotonic_counter),
+            )?;
+            debug!(
+                "Replaying against the state checkpointed before row ({}).",
+                next
+            );
+        }
+        None => {}
+    }
+    debug!("Replaying {} dead lettered records.", rejects.len());
+    Ok(rejects.replay(&mut clients, pipeline_config))
+}
+
+/// A row per replayed record, its client written as `pseudonyms` says.
+pub fn write_report<W: io::Write>(
+    writer: W,
+    replayed: &[Replayed],
+    pseudonyms: &Pseudonyms,
+) -> csv::Result<()> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    let mut header = vec!["counter", "client", "tx", "outcome", "reason"];
+    header.extend(REFERENCE_COLUMNS);
+    wtr.write_record(&header)?;
+    for replayed in replayed {
+        let counter = replayed.monotonic_counter.to_string();
+        let client = pseudonyms.client(&replayed.client);
+        let mut row = vec![
+            counter.as_str(),
+            client.as_str(),
+            replayed.tx.as_str(),
+            match replayed.rejection {
+                Some(_) => "rejected",
+                None => "applied",
+            },
+            replayed.rejection.unwrap_or_default(),
+        ];
+        row.extend(replayed.reference.iter().map(String::as_str));
+        wtr.write_record(&row)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::checkpoint::Checkpoints;
+    use crate::money::Money;
+    use crate::output::AmountFormat;
+    use crate::{apply_and_publish, process_record, Sinks, TransactionType, TxId};
+    use csv::{ReaderBuilder, Trim};
+    use rust_decimal::Decimal;
+    use std::{env, fs};
+
+    #[test]
+    fn test_replay_after_fixing_history() {
+        let dead_letters = "\
+counter,reason,detail,type,client,tx,amount,timestamp
+1,insufficient_funds,insufficient available funds,withdrawal,1,2,10,
+2,parse_error,invalid digit,deposit,x,3,1,
+3,unknown_transaction,no withdrawal or deposit with this transaction id,dispute,1,9,,
+5,not_disputed,transaction is not under dispute,resolve,1,100,,
+";
+        let rejects = Rejects::read(
+            ReaderBuilder::new()
+                .trim(Trim::All)
+                .from_reader(dead_letters.as_bytes()),
+        )
+        .unwrap();
+        assert!(rejects.contains(1) && !rejects.contains(0));
+        // the client has since been topped up enough to cover the withdrawal
+        let mut clients = HashMap::new();
+        for (monotonic_counter, amount) in [(0, 5), (4, 5)] {
+            let record = Record {
+                transaction_type: TransactionType::Deposit,
+                client_id: ClientId(1),
+                transaction_id: TxId(monotonic_counter as u32 + 100),
+                amount: Some(Money::new(amount, 0)),
+                timestamp: None,
+                reason: None,
+            };
+            process_record(
+                SituatedRecord {
+                    monotonic_counter,
+                    record,
+                },
+                &mut clients,
+            )
+            .unwrap();
+        }
+        let replayed = rejects.replay(&mut clients, &PipelineConfig::default());
+        let outcomes: Vec<(usize, Option<&str>)> = replayed
+            .iter()
+            .map(|replayed| (replayed.monotonic_counter, replayed.rejection))
+            .collect();
+        assert_eq!(
+            vec![
+                (1, None),
+                (2, Some("parse_error")),
+                (3, Some("unknown_transaction")),
+                (5, Some("not_disputed"))
+            ],
+            outcomes
+        );
+        assert_eq!(["", "", ""], replayed[2].reference);
+        assert_eq!(["deposit", "5", "0"], replayed[3].reference);
+        assert_eq!(Money::ZERO, clients[&ClientId(1)].get_available_funds());
+    }
+
+    #[test]
+    fn test_not_a_dead_letter_file() {
+        let transactions = "type,client,tx,amount\ndeposit,1,1,1.0\n";
+        assert!(Rejects::read(Reader::from_reader(transactions.as_bytes())).is_err());
+    }
+
+    #[test]
+    fn test_policy_rejections_stay_rejected() {
+        let dir = env::temp_dir().join(format!("replay-policy-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (dead_letters, history) = (dir.join("dead_letters.csv"), dir.join("history.csv"));
+        fs::write(
+            &dead_letters,
+            "counter,reason,detail,type,client,tx,amount,timestamp\n\
+            1,amount_out_of_bounds,amount is out of bounds,deposit,1,2,500,\n",
+        )
+        .unwrap();
+        fs::write(
+            &history,
+            "type,client,tx,amount\ndeposit,1,1,10\ndeposit,1,2,500\n",
+        )
+        .unwrap();
+        let config = PipelineConfig {
+            max_amount: Some(Decimal::new(100, 0)),
+            ..PipelineConfig::default()
+        };
+        let no_checkpoints = dir.join("checkpoints");
+        let replay = |config: &PipelineConfig| {
+            replay_file(
+                &dead_letters,
+                Some(&history),
+                &no_checkpoints,
+                config,
+                &ColumnMap::default(),
+            )
+            .unwrap()[0]
+                .rejection
+        };
+        assert_eq!(Some("amount_out_of_bounds"), replay(&config));
+        // only once the bound is lifted
+        assert_eq!(None, replay(&PipelineConfig::default()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_replay_against_checkpoints() {
+        let dir = env::temp_dir().join(format!("replay-checkpoints-{}", std::process::id()));
+        let checkpoints = Checkpoints::start(
+            &dir,
+            100,
+            Default::default(),
+            AmountFormat::default(),
+            Default::default(),
+        )
+        .unwrap();
+        let sinks = Sinks {
+            history: Some(checkpoints.sender().clone()),
+            ..Sinks::default()
+        };
+        let mut clients = HashMap::new();
+        for (monotonic_counter, (transaction_type, amount)) in [
+            (TransactionType::Deposit, 5),
+            (TransactionType::Withdrawal, 10),
+            (TransactionType::Deposit, 5),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let record = Record {
+                transaction_type,
+                client_id: ClientId(1),
+                transaction_id: TxId(monotonic_counter as u32 + 1),
+                amount: Some(Money::new(amount, 0)),
+                timestamp: None,
+                reason: None,
+            };
+            apply_and_publish(
+                SituatedRecord {
+                    monotonic_counter,
+                    record,
+                },
+                &mut clients,
+                &PipelineConfig::default(),
+                &sinks,
+            );
+        }
+        drop(sinks);
+        checkpoints.finish().unwrap();
+        let dead_letters = dir.join("dead_letters.csv");
+        fs::write(
+            &dead_letters,
+            "counter,reason,detail,type,client,tx,amount,timestamp\n\
+            1,insufficient_funds,insufficient available funds,withdrawal,1,2,10,\n",
+        )
+        .unwrap();
+        // the deposit after it, in the checkpointed state, covers the withdrawal now
+        let replayed = replay_file(
+            &dead_letters,
+            None,
+            &dir,
+            &PipelineConfig::default(),
+            &ColumnMap::default(),
+        )
+        .unwrap();
+        assert_eq!(None, replayed[0].rejection);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,509 @@
+use crate::output::AmountFormat;
+use crate::pseudonym::Pseudonyms;
+use crate::{ClientId, ClientState, Disputable, DisputeReason, DisputeStatus, TxId};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Reports written at the end of a run for the teams that act on it, besides the balances.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReportKind {
+    /// frozen accounts and the chargebacks that froze them
+    Locked,
+    /// clients with negative funds and the disputes behind them
+    Exposure,
+    /// disputes, resolves and chargebacks still waiting for their transaction, see --suspense
+    Suspense,
+    /// disputes still open, with a blank decision column for `apply-decisions`
+    Pending,
+    /// held funds of every client, by the dispute or admin hold that holds them
+    Held,
+}
+
+impl ReportKind {
+    pub const ALL: [ReportKind; 5] = [
+        ReportKind::Locked,
+        ReportKind::Exposure,
+        ReportKind::Suspense,
+        ReportKind::Pending,
+        ReportKind::Held,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ReportKind::Locked => "locked",
+            ReportKind::Exposure => "exposure",
+            ReportKind::Suspense => "suspense",
+            ReportKind::Pending => "pending",
+            ReportKind::Held => "held",
+        }
+    }
+}
+
+impl FromStr for ReportKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ReportKind::ALL
+            .iter()
+            .find(|kind| kind.name() == s)
+            .copied()
+            .ok_or_else(|| {
+                let names: Vec<&str> = ReportKind::ALL.iter().map(ReportKind::name).collect();
+                format!("Unknown report ({}), expected one of {:?}.", s, names)
+            })
+    }
+}
+
+/// A report to write and where, given as `KIND=PATH`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub kind: ReportKind,
+    pub path: PathBuf,
+}
+
+impl FromStr for Report {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((kind, path)) if !path.is_empty() => Ok(Report {
+                kind: kind.trim().parse()?,
+                path: PathBuf::from(path),
+            }),
+            _ => Err(format!("Invalid report ({}), expected KIND=PATH.", s)),
+        }
+    }
+}
+
+impl Report {
+    pub fn write_file(
+        &self,
+        clients: &HashMap<ClientId, ClientState>,
+        amounts: AmountFormat,
+        pseudonyms: &Pseudonyms,
+    ) -> csv::Result<()> {
+        write(
+            self.kind,
+            File::create(&self.path)?,
+            clients,
+            amounts,
+            pseudonyms,
+        )
+    }
+}
+
+pub fn write<W: io::Write>(
+    kind: ReportKind,
+    writer: W,
+    clients: &HashMap<ClientId, ClientState>,
+    amounts: AmountFormat,
+    pseudonyms: &Pseudonyms,
+) -> csv::Result<()> {
+    match kind {
+        ReportKind::Locked => write_locked(writer, clients, amounts, pseudonyms),
+        ReportKind::Exposure => write_exposure(writer, clients, amounts, pseudonyms),
+        ReportKind::Suspense => write_suspense(writer, clients, pseudonyms),
+        ReportKind::Pending => write_pending(writer, clients, amounts, pseudonyms),
+        ReportKind::Held => write_held(writer, clients, amounts, pseudonyms),
+    }
+}
+
+/// A row per charged back transaction of every locked client, with what the client lost to
+/// chargebacks in all and its balances now, ordered by client and transaction.
+fn write_locked<W: io::Write>(
+    writer: W,
+    clients: &HashMap<ClientId, ClientState>,
+    amounts: AmountFormat,
+    pseudonyms: &Pseudonyms,
+) -> csv::Result<()> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    wtr.write_record([
+        "client",
+        "tx",
+        "tx_type",
+        "amount",
+        "reason_code",
+        "charged_back",
+        "available",
+        "held",
+        "total",
+    ])?;
+    let mut locked: Vec<&ClientState> = clients.values().filter(|c| c.is_locked()).collect();
+    locked.sort_by_key(|client| client.client_id);
+    for client in locked {
+        for (tx_id, charged_back) in client.charged_back_transactions() {
+            wtr.write_record([
+                pseudonyms.client(client.client_id),
+                tx_id.to_string(),
+                charged_back.transaction_type.as_str().to_string(),
+                amounts.format(charged_back.amount),
+                DisputeReason::field(charged_back.reason).to_string(),
+                amounts.format(client.get_charged_back()),
+                amounts.format(client.get_available_funds()),
+                amounts.format(client.get_held_funds()),
+                amounts.format(client.get_total_funds()),
+            ])?;
+        }
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// A row per disputed or charged back transaction, the only way funds go negative, of every
+/// client with negative available or total funds, then a `total` row with the sums of the
+/// negative available and total funds across those clients.
+fn write_exposure<W: io::Write>(
+    writer: W,
+    clients: &HashMap<ClientId, ClientState>,
+    amounts: AmountFormat,
+    pseudonyms: &Pseudonyms,
+) -> csv::Result<()> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    wtr.write_record([
+        "client",
+        "available",
+        "held",
+        "total",
+        "tx",
+        "tx_type",
+        "amount",
+        "status",
+        "reason_code",
+    ])?;
+    let mut exposed: Vec<&ClientState> = clients
+        .values()
+        .filter(|c| {
+            c.get_available_funds().amount() < Decimal::ZERO
+                || c.get_total_funds().amount() < Decimal::ZERO
+        })
+        .collect();
+    exposed.sort_by_key(|client| client.client_id);
+    let (mut available, mut total) = (Decimal::ZERO, Decimal::ZERO);
+    for client in exposed {
+        available =
+            available.saturating_add(client.get_available_funds().amount().min(Decimal::ZERO));
+        total = total.saturating_add(client.get_total_funds().amount().min(Decimal::ZERO));
+        let balances = [
+            pseudonyms.client(client.client_id),
+            amounts.format(client.get_available_funds()),
+            amounts.format(client.get_held_funds()),
+            amounts.format(client.get_total_funds()),
+        ];
+        for tx_id in client.disputed_transactions() {
+            let disputed = match client.disputable(tx_id) {
+                Some(disputed)
+                    if matches!(
+                        disputed.status,
+                        DisputeStatus::Disputed
+                            | DisputeStatus::ChargedBack
+                            | DisputeStatus::Represented
+                    ) =>
+                {
+                    disputed
+                }
+                _ => continue,
+            };
+            let mut row = balances.to_vec();
+            row.extend([
+                tx_id.to_string(),
+                disputed.transaction_type.as_str().to_string(),
+                amounts.format(disputed.amount),
+                disputed.status.as_str().to_string(),
+                DisputeReason::field(disputed.reason).to_string(),
+            ]);
+            wtr.write_record(&row)?;
+        }
+    }
+    wtr.write_record([
+        "total",
+        &amounts.format(available),
+        "",
+        &amounts.format(total),
+        "",
+        "",
+        "",
+        "",
+        "",
+    ])?;
+    wtr.flush()?;
+    Ok(())
+}
+
+/// A row per record left in suspense at the end of the run, ordered by client and counter.
+fn write_suspense<W: io::Write>(
+    writer: W,
+    clients: &HashMap<ClientId, ClientState>,
+    pseudonyms: &Pseudonyms,
+) -> csv::Result<()> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    wtr.write_record(["client", "counter", "type", "tx", "timestamp"])?;
+    let mut suspended: Vec<&ClientState> = clients.values().collect();
+    suspended.sort_by_key(|client| client.client_id);
+    for client in suspended {
+        for parked in client.parked() {
+            wtr.write_record([
+                pseudonyms.client(client.client_id),
+                parked.monotonic_counter.to_string(),
+                parked.record.transaction_type.as_str().to_string(),
+                parked.record.transaction_id.to_string(),
+                parked
+                    .record
+                    .timestamp
+                    .map(|timestamp| timestamp.to_string())
+                    .unwrap_or_default(),
+            ])?;
+        }
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// A row per open dispute, ordered by client and transaction, to be decided by filling in the
+/// decision column with `resolve` or `chargeback`, see [`crate::decisions`].
+fn write_pending<W: io::Write>(
+    writer: W,
+    clients: &HashMap<ClientId, ClientState>,
+    amounts: AmountFormat,
+    pseudonyms: &Pseudonyms,
+) -> csv::Result<()> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    wtr.write_record([
+        "client",
+        "tx",
+        "tx_type",
+        "amount",
+        "reason_code",
+        "decision",
+    ])?;
+    let mut disputing: Vec<&ClientState> = clients
+        .values()
+        .filter(|client| client.open_disputes() > 0)
+        .collect();
+    disputing.sort_by_key(|client| client.client_id);
+    for client in disputing {
+        let mut disputed: Vec<(TxId, &Disputable)> = client
+            .disputables()
+            .filter(|(_, disputable)| disputable.status == DisputeStatus::Disputed)
+            .collect();
+        disputed.sort_unstable_by_key(|(tx_id, _)| *tx_id);
+        for (tx_id, disputable) in disputed {
+            wtr.write_record([
+                pseudonyms.client(client.client_id),
+                tx_id.to_string(),
+                disputable.transaction_type.as_str().to_string(),
+                amounts.format(disputable.amount),
+                DisputeReason::field(disputable.reason).to_string(),
+                String::new(),
+            ])?;
+        }
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// A row per open dispute and one for admin holds of every client with held funds, ordered by
+/// client and transaction, so every held amount is tied to what holds it. Held funds the rows
+/// don't add up to, which would be a bug, get an `unexplained` row rather than go unnoticed.
+fn write_held<W: io::Write>(
+    writer: W,
+    clients: &HashMap<ClientId, ClientState>,
+    amounts: AmountFormat,
+    pseudonyms: &Pseudonyms,
+) -> csv::Result<()> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    wtr.write_record([
+        "client",
+        "held_by",
+        "tx",
+        "tx_type",
+        "amount",
+        "reason_code",
+    ])?;
+    let mut holding: Vec<&ClientState> = clients
+        .values()
+        .filter(|client| !client.get_held_funds().is_zero())
+        .collect();
+    holding.sort_by_key(|client| client.client_id);
+    for client in holding {
+        let client_id = pseudonyms.client(client.client_id);
+        let mut disputed: Vec<(TxId, &Disputable)> = client
+            .disputables()
+            .filter(|(_, disputable)| disputable.status == DisputeStatus::Disputed)
+            .collect();
+        disputed.sort_unstable_by_key(|(tx_id, _)| *tx_id);
+        let mut explained = client.get_admin_held_funds().amount();
+        for (tx_id, disputable) in disputed {
+            explained += disputable.amount.amount();
+            wtr.write_record([
+                client_id.as_str(),
+                "dispute",
+                &tx_id.to_string(),
+                disputable.transaction_type.as_str(),
+                &amounts.format(disputable.amount),
+                DisputeReason::field(disputable.reason),
+            ])?;
+        }
+        if !client.get_admin_held_funds().is_zero() {
+            let amount = amounts.format(client.get_admin_held_funds());
+            wtr.write_record([client_id.as_str(), "admin_hold", "", "", &amount, ""])?;
+        }
+        let unexplained = client.get_held_funds().amount() - explained;
+        if !unexplained.is_zero() {
+            let amount = amounts.format(unexplained);
+            wtr.write_record([client_id.as_str(), "unexplained", "", "", &amount, ""])?;
+        }
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::money::Money;
+    use crate::{process_record, Record, SituatedRecord, TransactionType};
+
+    fn run(script: &[(TransactionType, u16, u32, i64)]) -> HashMap<ClientId, ClientState> {
+        let mut clients = HashMap::new();
+        for (monotonic_counter, (transaction_type, client_id, transaction_id, amount)) in
+            script.iter().copied().enumerate()
+        {
+            let record = Record {
+                transaction_type,
+                client_id: ClientId(client_id),
+                transaction_id: TxId(transaction_id),
+                amount: Some(Money::new(amount, 0)),
+                timestamp: None,
+                reason: None,
+            };
+            let _ = process_record(
+                SituatedRecord {
+                    monotonic_counter,
+                    record,
+                },
+                &mut clients,
+            );
+        }
+        clients
+    }
+
+    fn report(kind: ReportKind, clients: &HashMap<ClientId, ClientState>) -> String {
+        let mut output = vec![];
+        write(
+            kind,
+            &mut output,
+            clients,
+            AmountFormat::default(),
+            &Pseudonyms::default(),
+        )
+        .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_locked_report() {
+        let clients = run(&[
+            (TransactionType::Deposit, 2, 1, 50),
+            (TransactionType::Deposit, 2, 2, 20),
+            (TransactionType::Dispute, 2, 1, 0),
+            (TransactionType::Chargeback, 2, 1, 0),
+            (TransactionType::Deposit, 1, 3, 10),
+            (TransactionType::Dispute, 1, 3, 0),
+            (TransactionType::Resolve, 1, 3, 0),
+        ]);
+        assert_eq!(
+            "client,tx,tx_type,amount,reason_code,charged_back,available,held,total\n2,1,deposit,50,,50,20,0,20\n",
+            report(ReportKind::Locked, &clients)
+        );
+    }
+
+    #[test]
+    fn test_exposure_report() {
+        let clients = run(&[
+            // withdrawn, then the deposit is disputed: available goes negative
+            (TransactionType::Deposit, 1, 1, 50),
+            (TransactionType::Withdrawal, 1, 2, 40),
+            (TransactionType::Dispute, 1, 1, 0),
+            // charged back after the money was withdrawn: total goes negative
+            (TransactionType::Deposit, 2, 3, 30),
+            (TransactionType::Deposit, 2, 4, 5),
+            (TransactionType::Withdrawal, 2, 5, 30),
+            (TransactionType::Dispute, 2, 4, 0),
+            (TransactionType::Resolve, 2, 4, 0),
+            (TransactionType::Dispute, 2, 3, 0),
+            (TransactionType::Chargeback, 2, 3, 0),
+            (TransactionType::Deposit, 3, 6, 10),
+        ]);
+        assert_eq!(
+            "client,available,held,total,tx,tx_type,amount,status,reason_code\n\
+            1,-40,50,10,1,deposit,50,disputed,\n\
+            2,-25,0,-25,3,deposit,30,charged_back,\n\
+            total,-65,,-25,,,,,\n",
+            report(ReportKind::Exposure, &clients)
+        );
+    }
+
+    #[test]
+    fn test_suspense_report() {
+        let mut clients = run(&[(TransactionType::Deposit, 1, 1, 10)]);
+        for (monotonic_counter, transaction_type) in
+            [(4, TransactionType::Resolve), (2, TransactionType::Dispute)]
+        {
+            clients.get_mut(&ClientId(1)).unwrap().park(SituatedRecord {
+                monotonic_counter,
+                record: Record {
+                    transaction_type,
+                    client_id: ClientId(1),
+                    transaction_id: TxId(9),
+                    amount: None,
+                    timestamp: Some(17),
+                    reason: None,
+                },
+            });
+        }
+        assert_eq!(
+            "client,counter,type,tx,timestamp\n1,2,dispute,9,17\n1,4,resolve,9,17\n",
+            report(ReportKind::Suspense, &clients)
+        );
+    }
+
+    #[test]
+    fn test_held_report() {
+        let clients = run(&[
+            (TransactionType::Deposit, 1, 1, 50),
+            (TransactionType::Withdrawal, 1, 2, 10),
+            (TransactionType::Deposit, 1, 3, 20),
+            (TransactionType::Dispute, 1, 3, 0),
+            (TransactionType::Dispute, 1, 2, 0),
+            (TransactionType::AdminHold, 1, 4, 5),
+            (TransactionType::Deposit, 2, 5, 30),
+            (TransactionType::Dispute, 2, 5, 0),
+            (TransactionType::Resolve, 2, 5, 0),
+        ]);
+        assert_eq!(
+            "client,held_by,tx,tx_type,amount,reason_code\n\
+            1,dispute,2,withdrawal,10,\n\
+            1,dispute,3,deposit,20,\n\
+            1,admin_hold,,,5,\n",
+            report(ReportKind::Held, &clients)
+        );
+    }
+
+    #[test]
+    fn test_parse_report() {
+        assert_eq!(
+            Ok(Report {
+                kind: ReportKind::Locked,
+                path: PathBuf::from("out/locked.csv")
+            }),
+            "locked=out/locked.csv".parse()
+        );
+        assert!("locked".parse::<Report>().is_err());
+        assert!("locked=".parse::<Report>().is_err());
+        assert!("unlocked=x.csv".parse::<Report>().is_err());
+    }
+}
@@ -0,0 +1,109 @@
+use crate::digest::{hex, Sha256};
+use crate::{ClientId, ClientState, SituatedRecord};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Digests that identify a run's results independently of the machine, worker count and hash
+/// map order, so two runs can be checked for identical results by comparing a couple of lines.
+#[derive(Debug, PartialEq, Eq)]
+pub struct RunHash {
+    /// of the balances, as `client,available,held,total,locked` rows ordered by client with
+    /// amounts stripped of trailing zeros
+    pub output: [u8; 32],
+    /// of every client's applied records (see [`applied_line`]) taken in client order
+    pub applied: [u8; 32],
+}
+
+impl RunHash {
+    pub fn of(clients: &HashMap<ClientId, ClientState>) -> Self {
+        let mut ids: Vec<&ClientId> = clients.keys().collect();
+        ids.sort();
+        let mut output = Sha256::new();
+        output.update(b"client,available,held,total,locked\n");
+        let mut applied = Sha256::new();
+        for id in ids {
+            let client = &clients[id];
+            output.update(
+                format!(
+                    "{},{},{},{},{}\n",
+                    id,
+                    client.get_available_funds().normalize(),
+                    client.get_held_funds().normalize(),
+                    client.get_total_funds().normalize(),
+                    client.is_locked()
+                )
+                .as_bytes(),
+            );
+            // records for different clients land on different shards, so only the order within
+            // a client is the same from run to run
+            applied.update(format!("{},{}\n", id, hex(&client.applied_digest())).as_bytes());
+        }
+        RunHash {
+            output: output.finish(),
+            applied: applied.finish(),
+        }
+    }
+}
+
+/// How an applied record is fed to its client's digest.
+pub fn applied_line(situated_record: &SituatedRecord) -> String {
+    let record = &situated_record.record;
+    format!(
+        "{},{},{},{},{},{}\n",
+        situated_record.monotonic_counter,
+        record.transaction_type.as_str(),
+        record.client_id,
+        record.transaction_id,
+        // a blank amount hashes as the zero it was parsed as before it could be blank
+        record.amount.unwrap_or_default().normalize(),
+        record.timestamp.map(|t| t.to_string()).unwrap_or_default()
+    )
+}
+
+impl fmt::Display for RunHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "output sha256: {}", hex(&self.output))?;
+        writeln!(f, "applied sha256: {}", hex(&self.applied))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pipeline::PipelineConfig;
+    use crate::schema::ColumnMap;
+    use crate::{play_with_money, Sinks};
+    use std::path::PathBuf;
+
+    fn run(file: &str, workers: usize) -> RunHash {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("examples")
+            .join("data")
+            .join(file);
+        let config = PipelineConfig {
+            workers,
+            ..PipelineConfig::default()
+        };
+        let mut clients = HashMap::new();
+        play_with_money(
+            Some(path.as_os_str()),
+            &config,
+            &ColumnMap::default(),
+            &Sinks::default(),
+            None,
+            &mut clients,
+        )
+        .unwrap();
+        RunHash::of(&clients)
+    }
+
+    #[test]
+    fn test_run_hash_is_deterministic() {
+        let sequential = run("sample.csv", 1);
+        assert_eq!(sequential, run("sample.csv", 1));
+        assert_eq!(sequential, run("sample.csv", 4));
+        let other = run("whitespace-sample.csv", 1);
+        assert_ne!(sequential.output, other.output);
+        assert_ne!(sequential.applied, other.applied);
+    }
+}
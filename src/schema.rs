@@ -0,0 +1,240 @@
+use csv::{Reader, StringRecord};
+use std::fmt;
+use std::io;
+
+/// Columns a transactions CSV must have, in the order they're documented.
+pub const REQUIRED_COLUMNS: [&str; 4] = ["type", "client", "tx", "amount"];
+pub const OPTIONAL_COLUMNS: [&str; 2] = ["timestamp", "reason_code"];
+
+/// Other names feeds commonly use for each column, on top of the fuzzy matching in `likeness`.
+const ALIASES: [(&str, &[&str]); 6] = [
+    (
+        "type",
+        &["kind", "transactiontype", "txtype", "txntype", "action"],
+    ),
+    (
+        "client",
+        &["clientid", "customer", "customerid", "account", "accountid"],
+    ),
+    ("tx", &["txid", "txn", "transaction", "transactionid", "id"]),
+    ("amount", &["value", "sum", "quantity"]),
+    (
+        "timestamp",
+        &["time", "ts", "date", "datetime", "createdat"],
+    ),
+    ("reason_code", &["reason", "disputereason", "reasoncode"]),
+];
+
+fn known(column: &str) -> Option<&'static str> {
+    REQUIRED_COLUMNS
+        .iter()
+        .chain(OPTIONAL_COLUMNS.iter())
+        .find(|known| **known == column)
+        .copied()
+}
+
+/// `--columns` overrides: which input header to read each expected column from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ColumnMap(Vec<(&'static str, String)>);
+
+impl ColumnMap {
+    /// parse `expected=header` pairs, e.g. `tx=transaction_id`.
+    pub fn parse<'a, I: IntoIterator<Item = &'a str>>(pairs: I) -> Result<Self, String> {
+        let mut columns = ColumnMap::default();
+        for pair in pairs {
+            let (expected, header) = pair.split_once('=').ok_or_else(|| {
+                format!("Invalid column mapping ({}), expected COLUMN=HEADER.", pair)
+            })?;
+            let expected = known(expected.trim()).ok_or_else(|| {
+                format!(
+                    "Unknown column ({}) in mapping ({}), expected one of {:?}.",
+                    expected, pair, REQUIRED_COLUMNS
+                )
+            })?;
+            columns.0.push((expected, header.trim().to_string()));
+        }
+        Ok(columns)
+    }
+
+    fn rename(&self, headers: &StringRecord) -> Result<StringRecord, String> {
+        let mut renamed: Vec<String> = headers.iter().map(str::to_string).collect();
+        for (expected, header) in &self.0 {
+            let index = headers
+                .iter()
+                .position(|existing| existing == header)
+                .ok_or_else(|| {
+                    format!(
+                        "Column mapping {}={} refers to a header the input doesn't have.",
+                        expected, header
+                    )
+                })?;
+            renamed[index] = expected.to_string();
+        }
+        Ok(StringRecord::from(renamed))
+    }
+}
+
+/// What's wrong with an input's headers, with a guess at the fix.
+#[derive(Debug, PartialEq, Eq)]
+pub struct HeaderMismatch {
+    pub missing: Vec<&'static str>,
+    pub unexpected: Vec<String>,
+    /// (expected column, input header that probably holds it)
+    pub suggestions: Vec<(&'static str, String)>,
+}
+
+impl fmt::Display for HeaderMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Input headers don't match the expected columns ({}, optionally {}).",
+            REQUIRED_COLUMNS.join(", "),
+            OPTIONAL_COLUMNS.join(", ")
+        )?;
+        writeln!(f, "  missing: {}", self.missing.join(", "))?;
+        if !self.unexpected.is_empty() {
+            writeln!(f, "  unexpected: {}", self.unexpected.join(", "))?;
+        }
+        let mappings: Vec<String> = self
+            .suggestions
+            .iter()
+            .map(|(expected, header)| format!("{}={}", expected, header))
+            .collect();
+        if mappings.is_empty() {
+            write!(
+                f,
+                "Map input headers to columns with --columns COLUMN=HEADER,..."
+            )
+        } else {
+            writeln!(f, "  did you mean: {}", mappings.join(", "))?;
+            write!(f, "If so, run with --columns {}", mappings.join(","))
+        }
+    }
+}
+
+/// Apply `columns` to the reader's headers and make sure every required column is present, so a
+/// mismatch is reported up front instead of as a deserialize error on the first row. Unexpected
+/// headers are fine on their own, e.g. the extra columns of a dead letter file.
+pub fn prepare<R: io::Read>(reader: &mut Reader<R>, columns: &ColumnMap) -> io::Result<()> {
+    let headers = columns
+        .rename(reader.headers()?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    check(&headers).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    reader.set_headers(headers);
+    Ok(())
+}
+
+pub fn check(headers: &StringRecord) -> Result<(), HeaderMismatch> {
+    let missing: Vec<&'static str> = REQUIRED_COLUMNS
+        .iter()
+        .filter(|column| !headers.iter().any(|header| header == **column))
+        .copied()
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let unexpected: Vec<String> = headers
+        .iter()
+        .filter(|header| known(header).is_none())
+        .map(str::to_string)
+        .collect();
+    let mut suggestions = vec![];
+    let mut unclaimed = unexpected.clone();
+    for column in &missing {
+        let best = unclaimed
+            .iter()
+            .enumerate()
+            .filter_map(|(index, header)| likeness(column, header).map(|score| (score, index)))
+            .min();
+        if let Some((_, index)) = best {
+            suggestions.push((*column, unclaimed.remove(index)));
+        }
+    }
+    Err(HeaderMismatch {
+        missing,
+        unexpected,
+        suggestions,
+    })
+}
+
+/// lower is likelier, None if `header` doesn't look like `column` at all.
+fn likeness(column: &str, header: &str) -> Option<usize> {
+    let header: String = header
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect();
+    let aliases = ALIASES
+        .iter()
+        .find(|(known, _)| *known == column)
+        .map_or(&[][..], |(_, aliases)| *aliases);
+    if header == column {
+        Some(0)
+    } else if aliases.contains(&header.as_str()) {
+        Some(1)
+    } else if header.contains(column) {
+        Some(2)
+    } else {
+        let distance = edit_distance(column, &header);
+        (distance <= 2).then(|| 2 + distance)
+    }
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::TxId;
+
+    #[test]
+    fn test_header_suggestions() {
+        let headers = StringRecord::from(vec!["Type", "client_id", "transaction_id", "amont"]);
+        let mismatch = check(&headers).unwrap_err();
+        assert_eq!(vec!["type", "client", "tx", "amount"], mismatch.missing);
+        assert_eq!(
+            vec![
+                ("type", "Type".to_string()),
+                ("client", "client_id".to_string()),
+                ("tx", "transaction_id".to_string()),
+                ("amount", "amont".to_string()),
+            ],
+            mismatch.suggestions
+        );
+        assert!(mismatch
+            .to_string()
+            .ends_with("--columns type=Type,client=client_id,tx=transaction_id,amount=amont"));
+        // extra columns alone are fine
+        let headers = StringRecord::from(vec!["counter", "type", "client", "tx", "amount"]);
+        assert_eq!(Ok(()), check(&headers));
+    }
+
+    #[test]
+    fn test_column_overrides() {
+        let input = "kind,client,id,value\ndeposit,1,1,1.0\n";
+        let mut reader = Reader::from_reader(input.as_bytes());
+        assert!(prepare(&mut reader, &ColumnMap::default()).is_err());
+        let columns = ColumnMap::parse(vec!["type=kind", "tx=id", "amount=value"]).unwrap();
+        let mut reader = Reader::from_reader(input.as_bytes());
+        prepare(&mut reader, &columns).unwrap();
+        let record: crate::Record = reader.deserialize().next().unwrap().unwrap();
+        assert_eq!(TxId(1), record.transaction_id);
+        assert!(ColumnMap::parse(vec!["kind"]).is_err());
+        assert!(ColumnMap::parse(vec!["kind=type"]).is_err());
+        let columns = ColumnMap::parse(vec!["tx=txid"]).unwrap();
+        let mut reader = Reader::from_reader(input.as_bytes());
+        assert!(prepare(&mut reader, &columns).is_err());
+    }
+}
@@ -0,0 +1,194 @@
+use crate::pseudonym::Pseudonyms;
+use crate::{ClientId, Rejection, SituatedRecord, TransactionType};
+use csv::{ReaderBuilder, Trim};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+
+/// What happens to the records of a client on the --screen list.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// withdrawals are turned down and deposits land on admin hold
+    Block,
+    /// records are applied as usual and only reported
+    Flag,
+}
+
+impl Action {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::Block => "block",
+            Action::Flag => "flag",
+        }
+    }
+}
+
+/// The clients to screen, see --screen.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Screening {
+    clients: HashMap<ClientId, Action>,
+}
+
+#[derive(Deserialize)]
+struct Row {
+    client: ClientId,
+    action: Option<Action>,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl Screening {
+    /// a list from a CSV with a `client` column and an optional `action` one, `block` or `flag`,
+    /// where a blank or missing action blocks. A client listed twice is an error.
+    pub fn read<R: io::Read>(reader: R) -> io::Result<Self> {
+        let mut reader = ReaderBuilder::new().trim(Trim::All).from_reader(reader);
+        let mut clients = HashMap::new();
+        for row in reader.deserialize() {
+            let row: Row =
+                row.map_err(|e| invalid(format!("Malformed screening entry: {}.", e)))?;
+            let action = row.action.unwrap_or(Action::Block);
+            if clients.insert(row.client, action).is_some() {
+                return Err(invalid(format!(
+                    "Client ({}) is listed twice for screening.",
+                    row.client
+                )));
+            }
+        }
+        Ok(Screening { clients })
+    }
+
+    pub fn read_file(path: &Path) -> io::Result<Self> {
+        Self::read(File::open(path)?)
+    }
+
+    /// What to do with the records of `client_id`, None unless it's on the list.
+    pub fn of(&self, client_id: ClientId) -> Option<Action> {
+        self.clients.get(&client_id).copied()
+    }
+}
+
+/// What to do with the records of `client_id`, None unless there's a list with it on.
+pub fn action(screening: Option<&Screening>, client_id: ClientId) -> Option<Action> {
+    screening?.of(client_id)
+}
+
+/// What became of a screened client's record.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// turned down as [`Rejection::Screened`]
+    Blocked,
+    /// a deposit applied and put on admin hold
+    Held,
+    Applied,
+    /// turned down for another reason
+    Rejected,
+}
+
+impl Outcome {
+    pub fn of(
+        situated_record: &SituatedRecord,
+        action: Action,
+        rejection: Option<&Rejection>,
+    ) -> Self {
+        match (rejection, action, situated_record.record.transaction_type) {
+            (Some(Rejection::Screened), _, _) => Outcome::Blocked,
+            (Some(_), _, _) => Outcome::Rejected,
+            (None, Action::Block, TransactionType::Deposit) => Outcome::Held,
+            (None, _, _) => Outcome::Applied,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Blocked => "blocked",
+            Outcome::Held => "held",
+            Outcome::Applied => "applied",
+            Outcome::Rejected => "rejected",
+        }
+    }
+}
+
+/// A record of a client on the list and what became of it, see [`crate::Sinks`].
+#[derive(Debug, Copy, Clone)]
+pub struct Entry {
+    pub situated_record: SituatedRecord,
+    pub action: Action,
+    pub outcome: Outcome,
+}
+
+/// Writes a CSV row for every record of a screened client from a background thread, fed by the
+/// engine stage (or its shards) through [`Compliance::sender`], for a compliance team to work
+/// through. With several workers only the order of the rows changes from run to run.
+pub struct Compliance {
+    sender: Sender<Entry>,
+    writer: JoinHandle<io::Result<usize>>,
+}
+
+impl Compliance {
+    pub fn create(path: &Path, pseudonyms: Pseudonyms) -> io::Result<Self> {
+        let mut wtr = csv::Writer::from_writer(BufWriter::new(File::create(path)?));
+        let (sender, receiver) = mpsc::channel::<Entry>();
+        let writer = thread::spawn(move || {
+            wtr.write_record([
+                "counter", "client", "tx", "type", "amount", "action", "outcome",
+            ])?;
+            let mut written = 0;
+            for entry in receiver {
+                let record = &entry.situated_record.record;
+                wtr.write_record([
+                    entry.situated_record.monotonic_counter.to_string(),
+                    pseudonyms.client(record.client_id),
+                    record.transaction_id.to_string(),
+                    record.transaction_type.as_str().to_string(),
+                    record.amount.map(|a| a.to_string()).unwrap_or_default(),
+                    entry.action.as_str().to_string(),
+                    entry.outcome.as_str().to_string(),
+                ])?;
+                written += 1;
+            }
+            wtr.flush()?;
+            Ok(written)
+        });
+        Ok(Compliance { sender, writer })
+    }
+
+    pub fn sender(&self) -> &Sender<Entry> {
+        &self.sender
+    }
+
+    /// Wait for every entry to be written and return how many were. Clones of the sender must be
+    /// dropped first.
+    pub fn finish(self) -> io::Result<usize> {
+        drop(self.sender);
+        match self.writer.join() {
+            Ok(written) => written,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_screening() {
+        let screening = Screening::read("client,action\n1,block\n2,flag\n3,\n".as_bytes()).unwrap();
+        assert_eq!(Some(Action::Block), screening.of(ClientId(1)));
+        assert_eq!(Some(Action::Flag), screening.of(ClientId(2)));
+        assert_eq!(Some(Action::Block), screening.of(ClientId(3)));
+        assert_eq!(None, screening.of(ClientId(4)));
+        let listed = Screening::read("client\n7\n".as_bytes()).unwrap();
+        assert_eq!(Some(Action::Block), listed.of(ClientId(7)));
+
+        assert!(Screening::read("client,action\n1,block\n1,flag\n".as_bytes()).is_err());
+        assert!(Screening::read("client,action\n1,freeze\n".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,236 @@
+use crate::amount::{ExcessPrecision, Scientific};
+use crate::changes::Snapshot;
+use crate::foreign::ForeignPolicy;
+use crate::output::AmountFormat;
+use crate::pipeline::PipelineConfig;
+use crate::pseudonym::Pseudonyms;
+use crate::schema::ColumnMap;
+use crate::velocity::Velocity;
+use crate::{play_with_money, ClientId, ClientState, LockedDeposits, Sinks};
+use rust_decimal::Decimal;
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::OsStr;
+use std::io;
+use std::str::FromStr;
+
+/// A policy the shadow run sets differently, given as `KEY=VALUE` where the key is the flag that
+/// sets it for a usual run, e.g. `foreign-disputes=route` or `fraud-lock-after=none`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Setting {
+    Suspense(bool),
+    ForeignDisputes(ForeignPolicy),
+    UnlockOnRepresentment(bool),
+    FraudLockAfter(Option<usize>),
+    Velocity(Option<Velocity>),
+    LockedDeposits(LockedDeposits),
+    MaxAmount(Option<Decimal>),
+    Scientific(Scientific),
+    ExcessPrecision(ExcessPrecision),
+}
+
+impl Setting {
+    pub fn apply(self, pipeline_config: &mut PipelineConfig) {
+        match self {
+            Setting::Suspense(suspense) => pipeline_config.suspense = suspense,
+            Setting::ForeignDisputes(policy) => pipeline_config.foreign_disputes = policy,
+            Setting::UnlockOnRepresentment(unlock) => {
+                pipeline_config.unlock_on_representment = unlock
+            }
+            Setting::FraudLockAfter(after) => pipeline_config.fraud_lock_after = after,
+            Setting::Velocity(velocity) => pipeline_config.velocity = velocity,
+            Setting::LockedDeposits(policy) => pipeline_config.locked_deposits = policy,
+            Setting::MaxAmount(max_amount) => pipeline_config.max_amount = max_amount,
+            Setting::Scientific(policy) => pipeline_config.amount_policy.scientific = policy,
+            Setting::ExcessPrecision(policy) => {
+                pipeline_config.amount_policy.excess_precision = policy
+            }
+        }
+    }
+}
+
+impl FromStr for Setting {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Shadow setting ({}) isn't KEY=VALUE.", s))?;
+        fn parsed<T: FromStr>(value: &str) -> Result<T, String> {
+            value
+                .parse()
+                .map_err(|_| format!("Malformed shadow setting value ({}).", value))
+        }
+        fn optional<T: FromStr>(value: &str) -> Result<Option<T>, String> {
+            match value {
+                "none" => Ok(None),
+                value => parsed(value).map(Some),
+            }
+        }
+        match key {
+            "suspense" => parsed(value).map(Setting::Suspense),
+            "foreign-disputes" => value.parse().map(Setting::ForeignDisputes),
+            "unlock-on-representment" => parsed(value).map(Setting::UnlockOnRepresentment),
+            "fraud-lock-after" => optional(value).map(Setting::FraudLockAfter),
+            "velocity" => optional(value).map(Setting::Velocity),
+            "locked-deposits" => value.parse().map(Setting::LockedDeposits),
+            "max-amount" => optional(value).map(Setting::MaxAmount),
+            "scientific" => value.parse().map(Setting::Scientific),
+            "excess-precision" => value.parse().map(Setting::ExcessPrecision),
+            _ => Err(format!(
+                "Unknown shadow setting ({}), expected one of suspense, foreign-disputes, \
+                unlock-on-representment, fraud-lock-after, velocity, locked-deposits, \
+                max-amount, scientific or excess-precision.",
+                key
+            )),
+        }
+    }
+}
+
+/// A client that ended up differently in the two runs, None in a run it doesn't exist in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub client_id: ClientId,
+    pub primary: Option<Snapshot>,
+    pub shadow: Option<Snapshot>,
+}
+
+/// The clients whose balances or lock differ between `primary` and `shadow`, by client.
+pub fn compare(
+    primary: &HashMap<ClientId, ClientState>,
+    shadow: &HashMap<ClientId, ClientState>,
+) -> Vec<Divergence> {
+    let client_ids: BTreeSet<ClientId> = primary.keys().chain(shadow.keys()).copied().collect();
+    client_ids
+        .into_iter()
+        .map(|client_id| Divergence {
+            client_id,
+            primary: primary.get(&client_id).map(|c| Snapshot::of(Some(c))),
+            shadow: shadow.get(&client_id).map(|c| Snapshot::of(Some(c))),
+        })
+        .filter(|divergence| divergence.primary != divergence.shadow)
+        .collect()
+}
+
+/// Run `input` with `primary`, then again with `shadow`, and compare the clients they end up
+/// with. Neither run publishes anything or checkpoints, and both see the same client limits.
+/// Returns how many clients there were and the divergences.
+pub fn run(
+    input: &OsStr,
+    primary: &PipelineConfig,
+    shadow: &PipelineConfig,
+    columns: &ColumnMap,
+) -> io::Result<(usize, Vec<Divergence>)> {
+    let play = |pipeline_config| {
+        let mut clients = HashMap::new();
+        play_with_money(
+            Some(input),
+            pipeline_config,
+            columns,
+            &Sinks::default(),
+            None,
+            &mut clients,
+        )
+        .map(|_| clients)
+    };
+    let (primary, shadow) = (play(primary)?, play(shadow)?);
+    let divergences = compare(&primary, &shadow);
+    Ok((primary.len().max(shadow.len()), divergences))
+}
+
+/// A row per divergence, the primary run's balances then the shadow run's under column names
+/// starting with `prefix`, blank for a run the client doesn't exist in.
+pub fn write_report<W: io::Write>(
+    writer: W,
+    divergences: &[Divergence],
+    prefix: &str,
+    amounts: AmountFormat,
+    pseudonyms: &Pseudonyms,
+) -> csv::Result<()> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    let columns = ["available", "held", "total", "locked"];
+    let mut headers = vec!["client".to_string()];
+    headers.extend(columns.iter().map(|column| column.to_string()));
+    headers.extend(columns.iter().map(|column| format!("{}{}", prefix, column)));
+    wtr.write_record(&headers)?;
+    let fields = |snapshot: Option<Snapshot>| match snapshot {
+        Some(snapshot) => [
+            amounts.format(snapshot.available),
+            amounts.format(snapshot.held),
+            amounts.format(snapshot.available.amount() + snapshot.held.amount()),
+            snapshot.locked.to_string(),
+        ],
+        None => Default::default(),
+    };
+    for divergence in divergences {
+        let mut row = vec![pseudonyms.client(divergence.client_id)];
+        row.extend(fields(divergence.primary));
+        row.extend(fields(divergence.shadow));
+        wtr.write_record(&row)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_parse_setting() {
+        assert_eq!(
+            Ok(Setting::ForeignDisputes(ForeignPolicy::Route)),
+            "foreign-disputes=route".parse()
+        );
+        assert_eq!(
+            Ok(Setting::FraudLockAfter(None)),
+            "fraud-lock-after=none".parse()
+        );
+        assert_eq!(
+            Ok(Setting::MaxAmount(Some(Decimal::new(5, 1)))),
+            "max-amount=0.5".parse()
+        );
+        assert!("suspense".parse::<Setting>().is_err());
+        assert!("suspense=maybe".parse::<Setting>().is_err());
+        assert!("workers=2".parse::<Setting>().is_err());
+    }
+
+    #[test]
+    fn test_shadow() {
+        let input = env::temp_dir().join(format!("shadow-{}.csv", std::process::id()));
+        fs::write(
+            &input,
+            "type,client,tx,amount\n\
+            deposit,1,1,10\n\
+            deposit,2,2,500\n\
+            deposit,3,3,20\n\
+            dispute,1,3,\n",
+        )
+        .unwrap();
+        let primary = PipelineConfig::default();
+        let mut shadow = primary.clone();
+        Setting::MaxAmount(Some(Decimal::new(100, 0))).apply(&mut shadow);
+        Setting::ForeignDisputes(ForeignPolicy::Route).apply(&mut shadow);
+        let (clients, divergences) =
+            run(input.as_os_str(), &primary, &shadow, &ColumnMap::default()).unwrap();
+        assert_eq!(3, clients);
+        let mut report = vec![];
+        write_report(
+            &mut report,
+            &divergences,
+            "shadow_",
+            AmountFormat::default(),
+            &Pseudonyms::default(),
+        )
+        .unwrap();
+        // client 2's deposit is too big for the shadow run, client 1's dispute is routed to 3
+        assert_eq!(
+            "client,available,held,total,locked,shadow_available,shadow_held,shadow_total,shadow_locked\n\
+            2,500,0,500,false,,,,\n\
+            3,20,0,20,false,0,20,20,false\n",
+            String::from_utf8(report).unwrap()
+        );
+        fs::remove_file(&input).unwrap();
+    }
+}
@@ -0,0 +1,369 @@
+use crate::checkpoint::Saved;
+use crate::pipeline::PipelineConfig;
+use crate::pipeline::{bounded, BoundedSender, Queue};
+use crate::sync::mpsc::{self, Sender};
+use crate::sync::thread::{self, JoinHandle};
+use crate::{apply_and_publish, park, ClientId, ClientState, Sinks, SituatedRecord};
+use log::info;
+use std::collections::HashMap;
+use std::io;
+use std::time::Duration;
+
+enum Work {
+    Apply(SituatedRecord),
+    Park(SituatedRecord),
+    /// reply with every client the shard owns, once what was sent before it is applied
+    Save(Sender<Vec<Saved>>),
+}
+
+/// Seeded stalls for the shard workers, so a test or `--chaos` run shakes out interleavings of
+/// the workers that a quiet machine rarely produces. The stalls are the same for a seed, the
+/// interleavings they lead to still depend on the OS scheduler.
+struct Chaos(u64);
+
+impl Chaos {
+    fn new(seed: u64, shard: usize) -> Self {
+        // a zero state would stay zero
+        Chaos((seed ^ (shard as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)) | 1)
+    }
+
+    /// xorshift64*
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn stall(&mut self) {
+        match self.next_u64() % 8 {
+            0 => thread::yield_now(),
+            1 => std::thread::sleep(Duration::from_micros(self.next_u64() % 200)),
+            _ => {}
+        }
+    }
+}
+
+/// Engine stage split across worker threads. Every client is owned by exactly one worker, picked
+/// by client id, so records for one client are applied in input order while different clients
+/// are applied concurrently.
+pub struct Shards {
+    senders: Vec<BoundedSender<Work>>,
+    queues: Vec<Queue>,
+    workers: Vec<JoinHandle<HashMap<ClientId, ClientState>>>,
+}
+
+impl Shards {
+    /// `config.workers` workers fed through queues of `config.parse_queue_capacity`. `clients`
+    /// are handed to the workers that own them, e.g. when resuming from a checkpoint, and the
+    /// rest of `config` is applied as in [`apply_and_publish`].
+    pub fn spawn(
+        config: PipelineConfig,
+        sinks: Sinks,
+        clients: HashMap<ClientId, ClientState>,
+    ) -> Self {
+        let mut shards = Shards {
+            senders: vec![],
+            queues: vec![],
+            workers: vec![],
+        };
+        let mut owned: Vec<HashMap<ClientId, ClientState>> =
+            (0..config.workers.max(1)).map(|_| HashMap::new()).collect();
+        for (client_id, client) in clients {
+            let shard = shard_of(client_id, owned.len());
+            owned[shard].insert(client_id, client);
+        }
+        for (shard, mut clients) in owned.into_iter().enumerate() {
+            let (tx, rx, queue) = bounded::<Work>("shard", config.parse_queue_capacity);
+            shards.senders.push(tx);
+            shards.queues.push(queue);
+            let sinks = sinks.clone();
+            let config = config.clone();
+            let mut chaos = config.chaos.map(|seed| Chaos::new(seed, shard));
+            shards.workers.push(thread::spawn(move || {
+                for work in rx {
+                    if let Some(chaos) = &mut chaos {
+                        chaos.stall();
+                    }
+                    match work {
+                        Work::Apply(situated_record) => {
+                            apply_and_publish(situated_record, &mut clients, &config, &sinks)
+                        }
+                        Work::Park(situated_record) => park(situated_record, &mut clients, &config),
+                        Work::Save(reply) => {
+                            let _ = reply.send(clients.values().map(ClientState::save).collect());
+                        }
+                    }
+                }
+                clients
+            }));
+        }
+        shards
+    }
+
+    fn shard_for(&self, client_id: ClientId) -> usize {
+        shard_of(client_id, self.senders.len())
+    }
+
+    pub fn apply(&self, situated_record: SituatedRecord) {
+        let shard = self.shard_for(situated_record.record.client_id);
+        // a send only fails once the worker has panicked, which join surfaces
+        let _ = self.senders[shard].send(Work::Apply(situated_record));
+    }
+
+    pub fn park(&self, situated_record: SituatedRecord) {
+        let shard = self.shard_for(situated_record.record.client_id);
+        let _ = self.senders[shard].send(Work::Park(situated_record));
+    }
+
+    /// The state of every client once the records applied so far have been, for a checkpoint.
+    pub fn save(&self) -> io::Result<Vec<Saved>> {
+        let (reply, replies) = mpsc::channel();
+        for sender in &self.senders {
+            let _ = sender.send(Work::Save(reply.clone()));
+        }
+        drop(reply);
+        let replies: Vec<Vec<Saved>> = replies.iter().collect();
+        if replies.len() < self.senders.len() {
+            return Err(io::Error::other(
+                "a shard stopped before it could be checkpointed",
+            ));
+        }
+        Ok(replies.into_iter().flatten().collect())
+    }
+
+    /// Hang up on the workers, wait for them to drain and gather every client they own.
+    pub fn join(self) -> HashMap<ClientId, ClientState> {
+        drop(self.senders);
+        let mut clients = HashMap::new();
+        for worker in self.workers {
+            match worker.join() {
+                Ok(shard_clients) => clients.extend(shard_clients),
+                Err(panic) => std::panic::resume_unwind(panic),
+            }
+        }
+        for queue in &self.queues {
+            info!("{}", queue.stats());
+        }
+        clients
+    }
+}
+
+fn shard_of(client_id: ClientId, shards: usize) -> usize {
+    client_id.0 as usize % shards
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::conservation::Conservation;
+    use crate::money::Money;
+    use crate::{process_record, Record, TransactionType, TxId};
+
+    /// the same script for each of 50 clients, one client after the other.
+    fn scripted() -> Vec<Vec<SituatedRecord>> {
+        let mut clients = vec![];
+        let mut monotonic_counter = 0;
+        for client_id in 0..50u16 {
+            let tx = client_id as u32 * 10;
+            let settle = if client_id % 2 == 0 {
+                TransactionType::Chargeback
+            } else {
+                TransactionType::Resolve
+            };
+            let script = [
+                (TransactionType::Deposit, tx, 100),
+                (TransactionType::Withdrawal, tx + 1, 30),
+                (TransactionType::Dispute, tx, 0),
+                (settle, tx, 0),
+                (TransactionType::Withdrawal, tx + 2, 60),
+            ];
+            let mut records = vec![];
+            for (transaction_type, transaction_id, amount) in script {
+                records.push(SituatedRecord {
+                    monotonic_counter,
+                    record: Record {
+                        transaction_type,
+                        client_id: ClientId(client_id),
+                        transaction_id: TxId(transaction_id),
+                        amount: Some(Money::new(amount, 0)),
+                        timestamp: None,
+                        reason: None,
+                    },
+                });
+                monotonic_counter += 1;
+            }
+            clients.push(records);
+        }
+        clients
+    }
+
+    fn sequential(records: &[SituatedRecord]) -> HashMap<ClientId, ClientState> {
+        let mut clients = HashMap::new();
+        for situated_record in records {
+            let _ = process_record(*situated_record, &mut clients);
+        }
+        clients
+    }
+
+    fn sharded(
+        records: &[SituatedRecord],
+        config: PipelineConfig,
+    ) -> HashMap<ClientId, ClientState> {
+        let shards = Shards::spawn(config, Sinks::default(), HashMap::new());
+        for situated_record in records {
+            shards.apply(*situated_record);
+        }
+        shards.join()
+    }
+
+    /// same balances, lock and records applied in the same order, client by client.
+    fn assert_same(
+        expected: &HashMap<ClientId, ClientState>,
+        actual: &HashMap<ClientId, ClientState>,
+    ) {
+        assert_eq!(expected.len(), actual.len());
+        for (client_id, expected) in expected {
+            let actual = actual.get(client_id).unwrap();
+            assert_eq!(expected.get_available_funds(), actual.get_available_funds());
+            assert_eq!(expected.get_held_funds(), actual.get_held_funds());
+            assert_eq!(expected.is_locked(), actual.is_locked());
+            assert_eq!(expected.applied_digest(), actual.applied_digest());
+        }
+        assert!(Conservation::check(actual).violations.is_empty());
+    }
+
+    #[test]
+    fn test_sharded_matches_sequential() {
+        let records: Vec<SituatedRecord> = scripted().into_iter().flatten().collect();
+        let config = PipelineConfig {
+            workers: 4,
+            parse_queue_capacity: 2,
+            ..PipelineConfig::default()
+        };
+        assert_same(&sequential(&records), &sharded(&records, config));
+    }
+
+    #[test]
+    fn test_interleavings() {
+        let in_order: Vec<SituatedRecord> = scripted().into_iter().flatten().collect();
+        let expected = sequential(&in_order);
+        for seed in 0..16 {
+            // a seeded interleaving of the clients that keeps each one's records in order, as
+            // the reader could hand them over
+            let mut chaos = Chaos::new(seed, 0);
+            let mut pending = scripted();
+            let mut interleaved = vec![];
+            while !pending.is_empty() {
+                let client = (chaos.next_u64() % pending.len() as u64) as usize;
+                interleaved.push(pending[client].remove(0));
+                if pending[client].is_empty() {
+                    pending.swap_remove(client);
+                }
+            }
+            assert_same(&expected, &sequential(&interleaved));
+            // and the workers stalled at seeded points on top
+            let config = PipelineConfig {
+                workers: 1 + seed as usize % 4,
+                parse_queue_capacity: 1,
+                chaos: Some(seed),
+                ..PipelineConfig::default()
+            };
+            assert_same(&expected, &sharded(&interleaved, config));
+        }
+    }
+
+    /// client (0) deposits 10 and withdraws 3 around client (1)'s deposit of 5, with the two on
+    /// different workers and room for one record in each queue.
+    #[cfg(loom)]
+    fn deposits() -> Vec<SituatedRecord> {
+        [
+            (TransactionType::Deposit, 0, 10),
+            (TransactionType::Deposit, 1, 5),
+            (TransactionType::Withdrawal, 0, 3),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(
+            |(monotonic_counter, (transaction_type, client_id, amount))| SituatedRecord {
+                monotonic_counter,
+                record: Record {
+                    transaction_type,
+                    client_id: ClientId(client_id),
+                    transaction_id: TxId(monotonic_counter as u32),
+                    amount: Some(Money::new(amount, 0)),
+                    timestamp: None,
+                    reason: None,
+                },
+            },
+        )
+        .collect()
+    }
+
+    #[cfg(loom)]
+    fn model(check: impl Fn() + Sync + Send + 'static) {
+        let mut builder = loom::model::Builder::new();
+        builder.preemption_bound.get_or_insert(3);
+        builder.check(check);
+    }
+
+    #[cfg(loom)]
+    fn two_shards() -> Shards {
+        let config = PipelineConfig {
+            workers: 2,
+            parse_queue_capacity: 1,
+            ..PipelineConfig::default()
+        };
+        Shards::spawn(config, Sinks::default(), HashMap::new())
+    }
+
+    /// every record handed to a worker is applied, in order for its client, by the time join
+    /// returns, however the workers and the thread feeding them interleave.
+    #[cfg(loom)]
+    #[test]
+    fn test_loom_hand_off() {
+        model(|| {
+            let shards = two_shards();
+            for situated_record in deposits() {
+                shards.apply(situated_record);
+            }
+            let clients = shards.join();
+            assert_eq!(
+                Money::new(7, 0),
+                clients[&ClientId(0)].get_available_funds()
+            );
+            assert_eq!(
+                Money::new(5, 0),
+                clients[&ClientId(1)].get_available_funds()
+            );
+        });
+    }
+
+    /// a save sees every record sent before it and none sent after, from every worker.
+    #[cfg(loom)]
+    #[test]
+    fn test_loom_save() {
+        model(|| {
+            let shards = two_shards();
+            let records = deposits();
+            shards.apply(records[0]);
+            shards.apply(records[1]);
+            let mut saved: Vec<[String; 13]> = shards
+                .save()
+                .unwrap()
+                .iter()
+                .map(crate::checkpoint::snapshot_row)
+                .collect();
+            saved.sort();
+            assert_eq!(2, saved.len());
+            assert_eq!(["0", "10"], [&saved[0][0], &saved[0][1]]);
+            assert_eq!(["1", "5"], [&saved[1][0], &saved[1][1]]);
+            shards.apply(records[2]);
+            let clients = shards.join();
+            assert_eq!(
+                Money::new(7, 0),
+                clients[&ClientId(0)].get_available_funds()
+            );
+        });
+    }
+}
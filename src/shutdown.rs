@@ -0,0 +1,34 @@
+use std::sync::atomic::{AtomicI32, Ordering};
+
+// set from a signal handler, so nothing but an atomic store may happen there
+static SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+/// Stop reading on SIGINT or SIGTERM, so the rows already read are applied and checkpointed
+/// before the run ends, see [`crate::play_with_money`]. A second signal exits straight away.
+pub fn install() {
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    // SAFETY: on_signal only touches an atomic and calls _exit, both async-signal-safe
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+extern "C" fn on_signal(signal: libc::c_int) {
+    if SIGNAL.swap(signal, Ordering::SeqCst) != 0 {
+        // SAFETY: _exit is async-signal-safe, unlike process::exit
+        unsafe { libc::_exit(128 + signal) }
+    }
+}
+
+/// the signal that asked the run to stop, if one did.
+pub fn requested() -> Option<i32> {
+    match SIGNAL.load(Ordering::SeqCst) {
+        0 => None,
+        signal => Some(signal),
+    }
+}
+
+pub fn is_requested() -> bool {
+    requested().is_some()
+}
@@ -0,0 +1,186 @@
+use crate::validate_input;
+use crate::webhook::HttpUrl;
+use flate2::read::{MultiGzDecoder, ZlibDecoder};
+use log::debug;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{self, Read};
+#[cfg(test)]
+use std::io::{BufRead, BufReader, Write};
+use std::time::Duration;
+
+/// how long connecting, and then waiting for the response to start, may take.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+pub type Source = Box<dyn Read + Send>;
+
+/// The transactions to read: a file, or an `http://` or `https://` URL streamed as it's parsed.
+pub fn open(input: Option<&OsStr>) -> io::Result<Source> {
+    match input.and_then(OsStr::to_str) {
+        Some(url) if url.starts_with("http://") || url.starts_with("https://") => {
+            fetch(&HttpUrl::parse(url)?)
+        }
+        _ => Ok(Box::new(File::open(validate_input(input)?)?)),
+    }
+}
+
+/// GET `url` and return its body once the headers are read, decompressed as it's read when it's
+/// sent gzip or deflate encoded, or is a `.gz` file. Redirected responses are errors rather than
+/// guesses, as is a body that ends before its Content-Length or mid chunk.
+pub fn fetch(url: &HttpUrl) -> io::Result<Source> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .max_redirects(0)
+        .http_status_as_error(false)
+        .timeout_connect(Some(TIMEOUT))
+        .timeout_recv_response(Some(TIMEOUT))
+        .build()
+        .into();
+    let response = agent
+        .get(url.to_string())
+        .header("Accept-Encoding", "gzip, deflate")
+        .call()
+        .map_err(ureq::Error::into_io)?;
+    let status = response.status().as_u16();
+    let header = |name: &str| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    if !(200..300).contains(&status) {
+        return Err(invalid(match header("location") {
+            Some(location) => format!(
+                "{}{} redirected ({}) to {}, use that url instead.",
+                url.host, url.path, status, location
+            ),
+            None => format!("{}{} responded with status {}.", url.host, url.path, status),
+        }));
+    }
+    let encoding = header("content-encoding").map(|encoding| encoding.to_ascii_lowercase());
+    let gz = url
+        .path
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .ends_with(".gz");
+    debug!("Streaming transactions from {:?}.", url);
+    let body = response.into_body().into_reader();
+    match encoding.as_deref() {
+        Some("gzip" | "x-gzip") => Ok(Box::new(MultiGzDecoder::new(body))),
+        Some("deflate") => Ok(Box::new(ZlibDecoder::new(body))),
+        None | Some("identity") if gz => Ok(Box::new(MultiGzDecoder::new(body))),
+        None | Some("identity") => Ok(Box::new(body)),
+        Some(encoding) => Err(invalid(format!(
+            "Response is compressed ({}), which isn't supported.",
+            encoding
+        ))),
+    }
+}
+
+/// serve `response` once and return the url to fetch it from.
+#[cfg(test)]
+pub(crate) fn serve<B: AsRef<[u8]> + Send + 'static>(response: B) -> HttpUrl {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = String::new();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        while reader.read_line(&mut request).unwrap() > 2 {
+            request.clear();
+        }
+        stream.write_all(response.as_ref()).unwrap();
+    });
+    HttpUrl::parse(&format!("http://127.0.0.1:{}/daily.csv", port)).unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+
+    const CSV: &str = "type,client,tx,amount\ndeposit,1,1,1.0\n";
+
+    fn read(url: &HttpUrl) -> io::Result<String> {
+        let mut body = String::new();
+        fetch(url)?.read_to_string(&mut body)?;
+        Ok(body)
+    }
+
+    fn body<B: AsRef<[u8]> + Send + 'static>(response: B) -> io::Result<String> {
+        read(&serve(response))
+    }
+
+    /// `headers` followed by `body` compressed by `encoder`.
+    fn compressed<W: Write>(headers: &str, mut encoder: W, finish: fn(W) -> Vec<u8>) -> Vec<u8> {
+        encoder.write_all(CSV.as_bytes()).unwrap();
+        [headers.as_bytes(), &finish(encoder)].concat()
+    }
+
+    fn gzipped(headers: &str) -> Vec<u8> {
+        compressed(
+            headers,
+            GzEncoder::new(vec![], Compression::default()),
+            |encoder| encoder.finish().unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_fetch() {
+        assert_eq!(
+            CSV,
+            body("HTTP/1.1 200 OK\r\nContent-Length: 38\r\n\r\ntype,client,tx,amount\ndeposit,1,1,1.0\n").unwrap()
+        );
+        assert_eq!(
+            CSV,
+            body("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n16\r\ntype,client,tx,amount\n\r\n10;ext=1\r\ndeposit,1,1,1.0\n\r\n0\r\n\r\n").unwrap()
+        );
+        assert_eq!(
+            CSV,
+            body("HTTP/1.1 200 OK\r\n\r\ntype,client,tx,amount\ndeposit,1,1,1.0\n").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_fetch_compressed() {
+        assert_eq!(
+            CSV,
+            body(gzipped("HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\n\r\n")).unwrap()
+        );
+        let deflated = compressed(
+            "HTTP/1.1 200 OK\r\nContent-Encoding: deflate\r\n\r\n",
+            ZlibEncoder::new(vec![], Compression::default()),
+            |encoder| encoder.finish().unwrap(),
+        );
+        assert_eq!(CSV, body(deflated).unwrap());
+        // a .gz file sent as it is
+        let mut url = serve(gzipped("HTTP/1.1 200 OK\r\n\r\n"));
+        url.path = "/daily.csv.gz".to_string();
+        assert_eq!(CSV, read(&url).unwrap());
+    }
+
+    #[test]
+    fn test_fetch_errors() {
+        let truncated = body("HTTP/1.1 200 OK\r\nContent-Length: 99\r\n\r\ntype,client,tx\n");
+        assert_eq!(io::ErrorKind::UnexpectedEof, truncated.unwrap_err().kind());
+        let truncated = body("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n16\r\ntype");
+        assert_eq!(io::ErrorKind::UnexpectedEof, truncated.unwrap_err().kind());
+        let mut gzip = gzipped("HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\n\r\n");
+        gzip.truncate(gzip.len() - 4);
+        assert!(body(gzip).is_err());
+        assert!(body("HTTP/1.1 404 Not Found\r\n\r\n").is_err());
+        assert!(
+            body("HTTP/1.1 302 Found\r\nLocation: http://elsewhere/\r\n\r\n")
+                .unwrap_err()
+                .to_string()
+                .contains("http://elsewhere/")
+        );
+        assert!(body("HTTP/1.1 200 OK\r\nContent-Encoding: br\r\n\r\n").is_err());
+        // https is fetched, here from a port nothing listens on
+        let refused = open(Some(OsStr::new("https://127.0.0.1:1/daily.csv"))).err();
+        assert_ne!(Some(io::ErrorKind::InvalidInput), refused.map(|e| e.kind()));
+    }
+}
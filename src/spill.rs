@@ -0,0 +1,168 @@
+use crate::checkpoint::{read_history, write_history, HISTORY_COLUMNS};
+use crate::SituatedRecord;
+use csv::{ReaderBuilder, StringRecord};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// What a kept record is counted as against the budget: its own size plus its share of the
+/// map it's kept in, roughly.
+pub const RECORD_BYTES: usize = std::mem::size_of::<SituatedRecord>() + 16;
+
+/// Where a client's records were written to in a [`Spill`], see [`Spill::write`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    offset: u64,
+    len: usize,
+}
+
+/// A budget for the records clients keep of their transactions and the file they're written out
+/// to once it's used up, see --max-memory. The file is append only, so a chunk written stays
+/// readable for as long as the spill is around, and deleted as soon as it's created where the OS
+/// lets an open file be. What disputes need of each transaction, see [`crate::Disputable`], is
+/// never written out.
+#[derive(Debug)]
+pub struct Spill {
+    budget: usize,
+    resident: AtomicUsize,
+    file: Mutex<(File, u64)>,
+}
+
+impl Spill {
+    pub fn create(budget: usize, dir: &Path) -> io::Result<Self> {
+        let path = dir.join(format!("playing-with-money-{}.spill", std::process::id()));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        // only where an open file can't be removed does it stay behind
+        let _ = fs::remove_file(&path);
+        Ok(Spill {
+            budget,
+            resident: AtomicUsize::new(0),
+            file: Mutex::new((file, 0)),
+        })
+    }
+
+    /// count `records` more kept in memory, and return whether that's over the budget.
+    pub fn kept(&self, records: usize) -> bool {
+        let added = records * RECORD_BYTES;
+        self.resident.fetch_add(added, Ordering::Relaxed) + added > self.budget
+    }
+
+    /// count `records` fewer kept in memory.
+    pub fn released(&self, records: usize) {
+        let released = records * RECORD_BYTES;
+        let _ = self
+            .resident
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |resident| {
+                Some(resident.saturating_sub(released))
+            });
+    }
+
+    /// bytes of records counted as kept in memory.
+    pub fn resident(&self) -> usize {
+        self.resident.load(Ordering::Relaxed)
+    }
+
+    /// Append `records` to the file and return where they went.
+    pub(crate) fn write(&self, records: &[SituatedRecord]) -> io::Result<Chunk> {
+        let mut rows = vec![];
+        for situated_record in records {
+            write_history(&mut rows, situated_record)?;
+        }
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        let (ref mut file, ref mut end) = *file;
+        file.seek(SeekFrom::Start(*end))?;
+        file.write_all(&rows)?;
+        let chunk = Chunk {
+            offset: *end,
+            len: rows.len(),
+        };
+        *end += rows.len() as u64;
+        Ok(chunk)
+    }
+
+    /// the records written to `chunk`, in the order they were.
+    pub(crate) fn read(&self, chunk: Chunk) -> io::Result<Vec<SituatedRecord>> {
+        let mut rows = vec![0; chunk.len];
+        {
+            let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+            file.0.seek(SeekFrom::Start(chunk.offset))?;
+            file.0.read_exact(&mut rows)?;
+        }
+        let headers = StringRecord::from(HISTORY_COLUMNS.to_vec());
+        let mut reader = ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(rows.as_slice());
+        reader
+            .records()
+            .map(|row| read_history(&headers, &row?))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::money::Money;
+    use crate::{ClientId, ClientState, Record, TransactionType, TxId};
+    use std::env;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_spill() {
+        let spill = Arc::new(Spill::create(RECORD_BYTES * 4, &env::temp_dir()).unwrap());
+        let mut client = ClientState::new(ClientId(1));
+        client.spill = Some(spill.clone());
+        let mut retained = ClientState::new(ClientId(1));
+        for (monotonic_counter, (transaction_type, transaction_id, amount)) in [
+            (TransactionType::Deposit, 1, 10),
+            (TransactionType::Deposit, 2, 20),
+            (TransactionType::Withdrawal, 3, 5),
+            (TransactionType::Deposit, 4, 40),
+            (TransactionType::Dispute, 1, 0),
+            (TransactionType::Deposit, 5, 50),
+            (TransactionType::Chargeback, 1, 0),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let situated_record = SituatedRecord {
+                monotonic_counter,
+                record: Record {
+                    transaction_type,
+                    client_id: ClientId(1),
+                    transaction_id: TxId(transaction_id),
+                    amount: Some(Money::new(amount, 0)),
+                    timestamp: Some(monotonic_counter as u64),
+                    reason: None,
+                },
+            };
+            let _ = client.add_transaction(situated_record);
+            let _ = retained.add_transaction(situated_record);
+        }
+        // written out once over 4 records, the 5 then kept and 2 since
+        assert_eq!(1, client.spilled.len());
+        assert_eq!(2 * RECORD_BYTES, spill.resident());
+        assert_eq!(
+            format!("{:?}", retained.transaction_history(TxId(1))),
+            format!("{:?}", client.transaction_history(TxId(1)))
+        );
+        for counter in 0..8 {
+            let (expected, actual) = (retained.as_of(counter), client.as_of(counter));
+            assert_eq!(
+                expected.map(|c| c.get_total_funds()),
+                actual.map(|c| c.get_total_funds())
+            );
+        }
+        assert_eq!(retained.first_after(2), client.first_after(2));
+        // disputes are settled from the index, which is never written out
+        assert_eq!(Money::new(105, 0), client.get_total_funds());
+        assert!(client.is_locked());
+    }
+}
@@ -0,0 +1,420 @@
+use crate::checkpoint::{
+    self, read_history, read_saved, snapshot_row, Checkpoints, Commit, Entry, HISTORY_COLUMNS,
+    SNAPSHOT_COLUMNS,
+};
+use crate::output::AmountFormat;
+use crate::pipeline::PipelineConfig;
+use crate::pseudonym::Pseudonyms;
+use crate::{ClientState, Disputable, DisputeReason, DisputeStatus};
+use csv::StringRecord;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+/// What the first line of an export says it is.
+pub const FORMAT: &str = "playing-with-money-state";
+/// The version of the export format written, and the newest one read.
+pub const VERSION: u32 = 1;
+
+// fields written as JSON numbers or booleans, every other one is a string so amounts keep their
+// precision, and a blank field is null
+const NUMBERS: [&str; 7] = [
+    "client",
+    "transactions",
+    "last_counter",
+    "last_timestamp",
+    "counter",
+    "tx",
+    "timestamp",
+];
+const BOOLEANS: [&str; 1] = ["locked"];
+
+/// A JSON object of `kind` with a field per column, in the order of `columns`.
+fn object<'a>(kind: &str, columns: &[&str], values: impl IntoIterator<Item = &'a str>) -> String {
+    let mut json = format!(r#"{{"kind":"{}""#, kind);
+    for (column, value) in columns.iter().zip(values) {
+        let value = match value {
+            "" => "null".to_string(),
+            _ if NUMBERS.contains(column) || BOOLEANS.contains(column) => value.to_string(),
+            _ => format!(r#""{}""#, value.replace('\\', r"\\").replace('"', r#"\""#)),
+        };
+        json += &format!(r#","{}":{}"#, column, value);
+    }
+    json + "}"
+}
+
+/// A flat JSON object, see [`fields`].
+type Fields = HashMap<String, Option<String>>;
+
+/// The fields of a flat JSON object, with the text of every value and None for null. Nested
+/// objects and arrays aren't part of the format, so they're malformed here.
+fn fields(line: &str) -> Result<Fields, String> {
+    let mut chars = line.trim().chars().peekable();
+    let mut fields = HashMap::new();
+    let skip_whitespace = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    };
+    let string = |chars: &mut std::iter::Peekable<std::str::Chars>| -> Result<String, String> {
+        let mut string = String::new();
+        loop {
+            match chars.next() {
+                Some('"') => return Ok(string),
+                Some('\\') => match chars.next() {
+                    Some(c @ ('"' | '\\' | '/')) => string.push(c),
+                    Some('n') => string.push('\n'),
+                    Some('t') => string.push('\t'),
+                    other => return Err(format!("unsupported escape ({:?})", other)),
+                },
+                Some(c) => string.push(c),
+                None => return Err("unterminated string".to_string()),
+            }
+        }
+    };
+    if chars.next() != Some('{') {
+        return Err("not an object".to_string());
+    }
+    skip_whitespace(&mut chars);
+    if chars.next_if_eq(&'}').is_some() {
+        return Ok(fields);
+    }
+    loop {
+        skip_whitespace(&mut chars);
+        if chars.next() != Some('"') {
+            return Err("expected a key".to_string());
+        }
+        let key = string(&mut chars)?;
+        skip_whitespace(&mut chars);
+        if chars.next() != Some(':') {
+            return Err(format!("expected a value for ({})", key));
+        }
+        skip_whitespace(&mut chars);
+        let value = match chars.peek() {
+            Some('"') => {
+                chars.next();
+                Some(string(&mut chars)?)
+            }
+            _ => {
+                let mut literal = String::new();
+                while let Some(c) = chars.next_if(|c| !matches!(c, ',' | '}') && !c.is_whitespace())
+                {
+                    literal.push(c);
+                }
+                match literal.as_str() {
+                    "null" => None,
+                    "" => return Err(format!("expected a value for ({})", key)),
+                    _ if literal.starts_with(['{', '[']) => {
+                        return Err(format!("nested value for ({})", key))
+                    }
+                    _ => Some(literal),
+                }
+            }
+        };
+        fields.insert(key, value);
+        skip_whitespace(&mut chars);
+        match chars.next() {
+            Some(',') => continue,
+            Some('}') if chars.all(char::is_whitespace) => return Ok(fields),
+            _ => return Err("expected , or }".to_string()),
+        }
+    }
+}
+
+/// `fields` as a row of `columns`, blank where a field is null or missing.
+fn row(fields: &Fields, columns: &[&str]) -> StringRecord {
+    columns
+        .iter()
+        .map(|&column| fields.get(column).cloned().flatten().unwrap_or_default())
+        .collect()
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Write the state checkpointed in `dir`, as [`checkpoint::load`] brings it up to date with
+/// `pipeline_config`, to `writer` as JSON lines:
+/// - a `state` line first, with the format, its version, the counter to go on from and how many
+///   client and record lines follow
+/// - a `client` line per client, with the fields of a checkpoint snapshot row
+/// - a `record` line per record in the checkpoint history, which disputes are settled from
+/// - a `dispute` line per open dispute, for reading the export; imports work them out from the
+///   records instead
+///
+/// Returns how many clients were written.
+pub fn export<W: Write>(
+    dir: &Path,
+    pipeline_config: &PipelineConfig,
+    mut writer: W,
+) -> io::Result<usize> {
+    let mut clients = HashMap::new();
+    let next = checkpoint::load(dir, pipeline_config, &mut clients)?;
+    let records = checkpoint::history(dir)?;
+    let mut clients: Vec<&ClientState> = clients.values().collect();
+    clients.sort_unstable_by_key(|client| client.client_id);
+    writeln!(
+        writer,
+        r#"{{"kind":"state","format":"{}","version":{},"resume_from":{},"clients":{},"records":{}}}"#,
+        FORMAT,
+        VERSION,
+        next,
+        clients.len(),
+        records.len()
+    )?;
+    for client in &clients {
+        let row = snapshot_row(&client.save());
+        writeln!(
+            writer,
+            "{}",
+            object("client", &SNAPSHOT_COLUMNS, row.iter().map(String::as_str))
+        )?;
+    }
+    for situated_record in &records {
+        let record = &situated_record.record;
+        let values = [
+            situated_record.monotonic_counter.to_string(),
+            record.transaction_type.as_str().to_string(),
+            record.client_id.to_string(),
+            record.transaction_id.to_string(),
+            record.amount.map(|a| a.to_string()).unwrap_or_default(),
+            record.timestamp.map(|t| t.to_string()).unwrap_or_default(),
+            DisputeReason::field(record.reason).to_string(),
+        ];
+        writeln!(
+            writer,
+            "{}",
+            object(
+                "record",
+                &HISTORY_COLUMNS,
+                values.iter().map(String::as_str)
+            )
+        )?;
+    }
+    for client in &clients {
+        let mut disputed: Vec<_> = client
+            .disputables()
+            .filter(|(_, disputable)| disputable.status == DisputeStatus::Disputed)
+            .collect();
+        disputed.sort_unstable_by_key(|(tx_id, _)| *tx_id);
+        for (tx_id, disputable) in disputed {
+            let mut values = vec![client.client_id.to_string(), tx_id.to_string()];
+            values.extend(Disputable::fields(Some(disputable)));
+            writeln!(
+                writer,
+                "{}",
+                object(
+                    "dispute",
+                    &["client", "tx", "type", "amount", "counter"],
+                    values.iter().map(String::as_str)
+                )
+            )?;
+        }
+    }
+    writer.flush()?;
+    Ok(clients.len())
+}
+
+/// Checkpoint the state exported to `reader` into `dir`, which mustn't hold any yet, so a run
+/// can be resumed or reopened from it. The result is loaded back before returning, so an export
+/// that doesn't add up (say, a record of a client it has no line for) fails here rather than at
+/// the next run. Returns how many clients were imported and the counter to go on from.
+pub fn import<R: BufRead>(
+    reader: R,
+    dir: &Path,
+    pipeline_config: &PipelineConfig,
+) -> io::Result<(usize, usize)> {
+    if dir.join(checkpoint::HISTORY).exists() {
+        return Err(invalid(format!(
+            "Checkpoint dir ({:?}) already holds state, import into an empty one.",
+            dir
+        )));
+    }
+    let mut lines = reader.lines().enumerate();
+    let mut next = || -> io::Result<Option<(usize, Fields)>> {
+        for (index, line) in lines.by_ref() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            return fields(&line)
+                .map(|fields| Some((index + 1, fields)))
+                .map_err(|e| invalid(format!("Malformed state line ({}): {}.", index + 1, e)));
+        }
+        Ok(None)
+    };
+    let header = next()?.map(|(_, header)| header).unwrap_or_default();
+    let field = |name: &str| header.get(name).cloned().flatten();
+    if field("kind").as_deref() != Some("state") || field("format").as_deref() != Some(FORMAT) {
+        return Err(invalid(format!(
+            "Not a state export, expected a first line of kind state and format {}.",
+            FORMAT
+        )));
+    }
+    let count = |name: &str| -> io::Result<usize> {
+        field(name)
+            .and_then(|value| value.parse().ok())
+            .ok_or_else(|| invalid(format!("State export has no {} count.", name)))
+    };
+    match field("version").and_then(|version| version.parse::<u32>().ok()) {
+        Some(version) if version <= VERSION => {}
+        version => {
+            return Err(invalid(format!(
+                "Unsupported state export version ({}), this build reads up to {}.",
+                version.map(|v| v.to_string()).unwrap_or_default(),
+                VERSION
+            )))
+        }
+    }
+    let resume_from = count("resume_from")?;
+    let (expected_clients, expected_records) = (count("clients")?, count("records")?);
+    let mut saved = vec![];
+    let mut records = vec![];
+    let headers = StringRecord::from(HISTORY_COLUMNS.to_vec());
+    while let Some((line, fields)) = next()? {
+        let kind = fields.get("kind").cloned().flatten().unwrap_or_default();
+        let located = |e: io::Error| invalid(format!("State line ({}): {}", line, e));
+        match kind.as_str() {
+            "client" => saved.push(read_saved(&row(&fields, &SNAPSHOT_COLUMNS)).map_err(located)?),
+            "record" => records
+                .push(read_history(&headers, &row(&fields, &HISTORY_COLUMNS)).map_err(located)?),
+            // worked out again from the records
+            "dispute" => {}
+            _ => {
+                return Err(invalid(format!(
+                    "Unknown kind of state line ({}) on line ({}).",
+                    kind, line
+                )))
+            }
+        }
+    }
+    if (saved.len(), records.len()) != (expected_clients, expected_records) {
+        return Err(invalid(format!(
+            "State export is incomplete, it has {} clients and {} records of {} and {}.",
+            saved.len(),
+            records.len(),
+            expected_clients,
+            expected_records
+        )));
+    }
+    let mut checkpoints = Checkpoints::start(
+        dir,
+        usize::MAX,
+        Commit::default(),
+        AmountFormat::default(),
+        Pseudonyms::default(),
+    )?;
+    for situated_record in records {
+        let _ = checkpoints.sender().send(Entry::Recorded(situated_record));
+    }
+    checkpoints.save(resume_from, &saved)?;
+    checkpoints.finish()?;
+    let mut clients = HashMap::new();
+    checkpoint::load(dir, pipeline_config, &mut clients)?;
+    Ok((clients.len(), resume_from))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::schema::ColumnMap;
+    use crate::{play_with_money, ClientId, Sinks};
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_fields() {
+        let fields = fields(r#"{"a":"x \"y\"", "b": 12 ,"c":null,"d":true}"#).unwrap();
+        assert_eq!(Some(&Some(r#"x "y""#.to_string())), fields.get("a"));
+        assert_eq!(Some(&Some("12".to_string())), fields.get("b"));
+        assert_eq!(Some(&None), fields.get("c"));
+        assert_eq!(Some(&Some("true".to_string())), fields.get("d"));
+        assert!(super::fields(r#"{"a":{"b":1}}"#).is_err());
+        assert!(super::fields(r#"{"a":1"#).is_err());
+    }
+
+    #[test]
+    fn test_export_import() {
+        let dir = env::temp_dir().join(format!("state-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("input.csv");
+        fs::write(
+            &input,
+            "type,client,tx,amount,timestamp\n\
+            deposit,1,1,10.50,100\n\
+            deposit,2,2,20,\n\
+            withdrawal,2,3,5,\n\
+            dispute,1,1,,200\n\
+            deposit,3,4,1,\n\
+            dispute,3,4,,\n\
+            chargeback,3,4,,\n",
+        )
+        .unwrap();
+        let exported = dir.join("exported");
+        let mut checkpoints = Checkpoints::start(
+            &exported,
+            4,
+            Commit::default(),
+            AmountFormat::default(),
+            Pseudonyms::default(),
+        )
+        .unwrap();
+        let sinks = Sinks {
+            history: Some(checkpoints.sender().clone()),
+            ..Sinks::default()
+        };
+        let config = PipelineConfig::default();
+        let mut clients = HashMap::new();
+        play_with_money(
+            Some(input.as_os_str()),
+            &config,
+            &ColumnMap::default(),
+            &sinks,
+            Some(&mut checkpoints),
+            &mut clients,
+        )
+        .unwrap();
+        drop(sinks);
+        checkpoints.finish().unwrap();
+
+        let mut export = vec![];
+        assert_eq!(3, super::export(&exported, &config, &mut export).unwrap());
+        let export = String::from_utf8(export).unwrap();
+        let lines: Vec<&str> = export.lines().collect();
+        assert_eq!(
+            r#"{"kind":"state","format":"playing-with-money-state","version":1,"resume_from":7,"clients":3,"records":7}"#,
+            lines[0]
+        );
+        assert!(lines[1].starts_with(
+            r#"{"kind":"client","client":1,"available":"0","held":"10.5","locked":false,"#
+        ));
+        assert_eq!(
+            r#"{"kind":"record","counter":0,"type":"deposit","client":1,"tx":1,"amount":"10.5","timestamp":100,"reason_code":null}"#,
+            lines[4]
+        );
+        assert_eq!(
+            r#"{"kind":"dispute","client":1,"tx":1,"type":"deposit","amount":"10.5","counter":0}"#,
+            lines[11]
+        );
+
+        let imported = dir.join("imported");
+        assert_eq!(
+            (3, 7),
+            import(export.as_bytes(), &imported, &config).unwrap()
+        );
+        let mut reexport = vec![];
+        super::export(&imported, &config, &mut reexport).unwrap();
+        assert_eq!(export, String::from_utf8(reexport).unwrap());
+        // the open dispute can still be settled
+        let mut clients = HashMap::new();
+        checkpoint::load(&imported, &config, &mut clients).unwrap();
+        assert_eq!(1, clients[&ClientId(1)].open_disputes());
+        assert!(clients[&ClientId(3)].is_locked());
+
+        // not over existing state, nor from a newer version or a cut off export
+        assert!(import(export.as_bytes(), &imported, &config).is_err());
+        let newer = export.replacen(r#""version":1"#, r#""version":2"#, 1);
+        assert!(import(newer.as_bytes(), &dir.join("newer"), &config).is_err());
+        let cut = lines[..lines.len() - 2].join("\n");
+        assert!(import(cut.as_bytes(), &dir.join("cut"), &config).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
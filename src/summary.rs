@@ -0,0 +1,241 @@
+use crate::messages::Messages;
+use crate::money::Money;
+use crate::pseudonym::Pseudonyms;
+use crate::{ClientId, ClientState, DisputeStatus};
+use fluent::{FluentArgs, FluentValue};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::fmt;
+
+/// How every dispute raised in the batch stands, by transaction.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct DisputeCounts {
+    pub open: usize,
+    pub resolved: usize,
+    /// withdrawn by the disputing party rather than resolved
+    pub cancelled: usize,
+    /// including those being represented
+    pub charged_back: usize,
+    /// charged back, then overturned by a won representment
+    pub reversed: usize,
+}
+
+/// Batch wide aggregates, a quick sanity check on a run without eyeballing every client row.
+#[derive(Debug, PartialEq)]
+pub struct Summary {
+    pub clients: usize,
+    pub locked: usize,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    /// (client_id, total funds), largest first
+    pub top: Vec<(ClientId, Decimal)>,
+    pub disputes: DisputeCounts,
+    /// whether a sum went beyond `Decimal`'s range, in which case it's pinned to the limit
+    pub overflowed: bool,
+    /// how the client ids of `top` are written
+    pub pseudonyms: Pseudonyms,
+}
+
+impl Summary {
+    pub fn of(
+        clients: &HashMap<ClientId, ClientState>,
+        top_n: usize,
+        pseudonyms: Pseudonyms,
+    ) -> Self {
+        let mut summary = Summary {
+            clients: clients.len(),
+            locked: 0,
+            available: Decimal::ZERO,
+            held: Decimal::ZERO,
+            total: Decimal::ZERO,
+            top: vec![],
+            disputes: DisputeCounts::default(),
+            overflowed: false,
+            pseudonyms,
+        };
+        let mut add = |sum: &mut Decimal, amount: Money| match sum.checked_add(amount.amount()) {
+            Some(added) => *sum = added,
+            None => {
+                *sum = sum.saturating_add(amount.amount());
+                summary.overflowed = true;
+            }
+        };
+        for client in clients.values() {
+            if client.is_locked() {
+                summary.locked += 1;
+            }
+            add(&mut summary.available, client.get_available_funds());
+            add(&mut summary.held, client.get_held_funds());
+            add(&mut summary.total, client.get_total_funds());
+            summary
+                .top
+                .push((client.client_id, client.get_total_funds().amount()));
+            for (_, disputable) in client.disputables() {
+                let counter = match disputable.status {
+                    DisputeStatus::Undisputed => continue,
+                    DisputeStatus::Disputed => &mut summary.disputes.open,
+                    DisputeStatus::Resolved => &mut summary.disputes.resolved,
+                    DisputeStatus::Cancelled => &mut summary.disputes.cancelled,
+                    DisputeStatus::ChargedBack | DisputeStatus::Represented => {
+                        &mut summary.disputes.charged_back
+                    }
+                    DisputeStatus::Reversed => &mut summary.disputes.reversed,
+                };
+                *counter += 1;
+            }
+        }
+        summary
+            .top
+            .sort_by(|(a_id, a_total), (b_id, b_total)| b_total.cmp(a_total).then(a_id.cmp(b_id)));
+        summary.top.truncate(top_n);
+        summary
+    }
+}
+
+impl Summary {
+    /// The summary in the words of `messages`, one line each, as [`fmt::Display`] writes it in
+    /// English.
+    pub fn localized(&self, messages: &Messages) -> String {
+        let line = |id: &str, args: &[(&'static str, FluentValue)]| {
+            let mut fluent_args = FluentArgs::new();
+            for (name, value) in args {
+                fluent_args.set(*name, value.clone());
+            }
+            messages.get(id, Some(&fluent_args)) + "\n"
+        };
+        let count = |count: usize| FluentValue::from(count);
+        let amount = |amount: Decimal| FluentValue::from(amount.to_string());
+        let mut text = line("summary-clients", &[("count", count(self.clients))]);
+        text += &line("summary-locked", &[("count", count(self.locked))]);
+        text += &line("summary-available", &[("amount", amount(self.available))]);
+        text += &line("summary-held", &[("amount", amount(self.held))]);
+        text += &line("summary-total", &[("amount", amount(self.total))]);
+        text += &line(
+            "summary-disputes",
+            &[
+                ("open", count(self.disputes.open)),
+                ("resolved", count(self.disputes.resolved)),
+                ("cancelled", count(self.disputes.cancelled)),
+                ("charged_back", count(self.disputes.charged_back)),
+                ("reversed", count(self.disputes.reversed)),
+            ],
+        );
+        if self.overflowed {
+            text += &line("summary-overflowed", &[]);
+        }
+        text += &line("summary-top", &[("count", count(self.top.len()))]);
+        for (client_id, total) in &self.top {
+            text += &format!("  {}: {}\n", self.pseudonyms.client(client_id), total);
+        }
+        text
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.localized(&Messages::default()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::money::Money;
+    use crate::{process_record, Record, SituatedRecord, TransactionType, TxId};
+
+    #[test]
+    fn test_summary() {
+        let mut clients = HashMap::new();
+        let script = [
+            (TransactionType::Deposit, 1, 1, 500),
+            (TransactionType::Deposit, 2, 2, 300),
+            (TransactionType::Deposit, 3, 3, 300),
+            (TransactionType::Deposit, 4, 4, 900),
+            (TransactionType::Dispute, 2, 2, 0),
+            (TransactionType::Dispute, 4, 4, 0),
+            (TransactionType::Chargeback, 4, 4, 0),
+            (TransactionType::Deposit, 3, 5, 100),
+            (TransactionType::Dispute, 3, 5, 0),
+            (TransactionType::DisputeCancel, 3, 5, 0),
+        ];
+        for (monotonic_counter, (transaction_type, client_id, transaction_id, amount)) in
+            script.into_iter().enumerate()
+        {
+            let record = Record {
+                transaction_type,
+                client_id: ClientId(client_id),
+                transaction_id: TxId(transaction_id),
+                amount: Some(Money::new(amount, 2)),
+                timestamp: None,
+                reason: None,
+            };
+            let _ = process_record(
+                SituatedRecord {
+                    monotonic_counter,
+                    record,
+                },
+                &mut clients,
+            );
+        }
+
+        let summary = Summary::of(&clients, 2, Pseudonyms::default());
+        assert_eq!(4, summary.clients);
+        assert_eq!(1, summary.locked);
+        assert_eq!(Decimal::new(900, 2), summary.available);
+        assert_eq!(Decimal::new(300, 2), summary.held);
+        assert_eq!(Decimal::new(1200, 2), summary.total);
+        // ties on total funds fall back to client id
+        assert_eq!(
+            vec![
+                (ClientId(1), Decimal::new(500, 2)),
+                (ClientId(3), Decimal::new(400, 2))
+            ],
+            summary.top
+        );
+        assert_eq!(
+            DisputeCounts {
+                open: 1,
+                resolved: 0,
+                cancelled: 1,
+                charged_back: 1,
+                reversed: 0,
+            },
+            summary.disputes
+        );
+        assert!(summary
+            .to_string()
+            .contains("disputes: 1 open, 0 resolved, 1 cancelled, 1 charged back, 0 reversed\n"));
+        assert!(summary
+            .to_string()
+            .contains("top 2 clients by total funds:\n  1: 5\n"));
+        assert!(!summary.overflowed);
+    }
+
+    #[test]
+    fn test_summary_overflow() {
+        let mut clients = HashMap::new();
+        for client_id in 1..=2 {
+            let record = Record {
+                transaction_type: TransactionType::Deposit,
+                client_id: ClientId(client_id),
+                transaction_id: TxId(client_id as u32),
+                amount: Some(Decimal::MAX.into()),
+                timestamp: None,
+                reason: None,
+            };
+            process_record(
+                SituatedRecord {
+                    monotonic_counter: client_id as usize,
+                    record,
+                },
+                &mut clients,
+            )
+            .unwrap();
+        }
+        let summary = Summary::of(&clients, 1, Pseudonyms::default());
+        assert!(summary.overflowed);
+        assert_eq!(Decimal::MAX, summary.total);
+        assert!(summary.to_string().contains("overflowed"));
+    }
+}
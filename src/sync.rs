@@ -0,0 +1,176 @@
+// The threads, channels and atomics the shard workers are fed and drained through: std's, or
+// loom's under `--cfg loom` so the model tests in [`crate::shards`] check every interleaving of
+// them rather than the ones the OS scheduler happens to pick.
+
+#[cfg(not(loom))]
+pub(crate) use std::sync::{atomic, mpsc};
+#[cfg(not(loom))]
+pub(crate) use std::thread;
+
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic;
+#[cfg(loom)]
+pub(crate) use loom::thread;
+
+/// `std::sync::mpsc` as far as the shards use it, over loom's mutex and condvar since loom's own
+/// channel has no bounded flavour.
+#[cfg(loom)]
+pub(crate) mod mpsc {
+    use loom::sync::{Arc, Condvar, Mutex};
+    use std::collections::VecDeque;
+    pub(crate) use std::sync::mpsc::{RecvError, SendError, TrySendError};
+
+    struct State<T> {
+        queue: VecDeque<T>,
+        capacity: Option<usize>,
+        senders: usize,
+        receiving: bool,
+    }
+
+    struct Channel<T> {
+        state: Mutex<State<T>>,
+        changed: Condvar,
+    }
+
+    fn open<T>(capacity: Option<usize>) -> (Arc<Channel<T>>, Receiver<T>) {
+        let channel = Arc::new(Channel {
+            state: Mutex::new(State {
+                queue: VecDeque::new(),
+                capacity,
+                senders: 1,
+                receiving: true,
+            }),
+            changed: Condvar::new(),
+        });
+        (channel.clone(), Receiver(channel))
+    }
+
+    pub(crate) fn channel<T>() -> (Sender<T>, Receiver<T>) {
+        let (channel, receiver) = open(None);
+        (Sender(channel), receiver)
+    }
+
+    /// unlike std's a capacity of 0 holds one value rather than handing it over in a rendezvous.
+    pub(crate) fn sync_channel<T>(capacity: usize) -> (SyncSender<T>, Receiver<T>) {
+        let (channel, receiver) = open(Some(capacity.max(1)));
+        (SyncSender(channel), receiver)
+    }
+
+    impl<T> Channel<T> {
+        fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+            let mut state = self.state.lock().unwrap();
+            if !state.receiving {
+                return Err(TrySendError::Disconnected(value));
+            }
+            if state
+                .capacity
+                .is_some_and(|capacity| state.queue.len() >= capacity)
+            {
+                return Err(TrySendError::Full(value));
+            }
+            state.queue.push_back(value);
+            self.changed.notify_all();
+            Ok(())
+        }
+
+        fn send(&self, mut value: T) -> Result<(), SendError<T>> {
+            loop {
+                match self.try_send(value) {
+                    Ok(()) => return Ok(()),
+                    Err(TrySendError::Disconnected(value)) => return Err(SendError(value)),
+                    Err(TrySendError::Full(full)) => {
+                        value = full;
+                        let state = self.state.lock().unwrap();
+                        if state.receiving && state.capacity.is_some_and(|c| state.queue.len() >= c)
+                        {
+                            drop(self.changed.wait(state).unwrap());
+                        }
+                    }
+                }
+            }
+        }
+
+        fn hang_up(&self) {
+            self.state.lock().unwrap().senders -= 1;
+            self.changed.notify_all();
+        }
+    }
+
+    fn clone_sender<T>(channel: &Arc<Channel<T>>) -> Arc<Channel<T>> {
+        channel.state.lock().unwrap().senders += 1;
+        channel.clone()
+    }
+
+    pub(crate) struct Sender<T>(Arc<Channel<T>>);
+
+    impl<T> Sender<T> {
+        pub(crate) fn send(&self, value: T) -> Result<(), SendError<T>> {
+            self.0.send(value)
+        }
+    }
+
+    impl<T> Clone for Sender<T> {
+        fn clone(&self) -> Self {
+            Sender(clone_sender(&self.0))
+        }
+    }
+
+    impl<T> Drop for Sender<T> {
+        fn drop(&mut self) {
+            self.0.hang_up();
+        }
+    }
+
+    pub(crate) struct SyncSender<T>(Arc<Channel<T>>);
+
+    impl<T> SyncSender<T> {
+        pub(crate) fn send(&self, value: T) -> Result<(), SendError<T>> {
+            self.0.send(value)
+        }
+
+        pub(crate) fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+            self.0.try_send(value)
+        }
+    }
+
+    impl<T> Clone for SyncSender<T> {
+        fn clone(&self) -> Self {
+            SyncSender(clone_sender(&self.0))
+        }
+    }
+
+    impl<T> Drop for SyncSender<T> {
+        fn drop(&mut self) {
+            self.0.hang_up();
+        }
+    }
+
+    pub(crate) struct Receiver<T>(Arc<Channel<T>>);
+
+    impl<T> Receiver<T> {
+        pub(crate) fn recv(&self) -> Result<T, RecvError> {
+            let mut state = self.0.state.lock().unwrap();
+            loop {
+                if let Some(value) = state.queue.pop_front() {
+                    self.0.changed.notify_all();
+                    return Ok(value);
+                }
+                if state.senders == 0 {
+                    return Err(RecvError);
+                }
+                state = self.0.changed.wait(state).unwrap();
+            }
+        }
+
+        pub(crate) fn iter(&self) -> impl Iterator<Item = T> + '_ {
+            std::iter::from_fn(move || self.recv().ok())
+        }
+    }
+
+    impl<T> Drop for Receiver<T> {
+        fn drop(&mut self) {
+            self.0.state.lock().unwrap().receiving = false;
+            self.0.changed.notify_all();
+        }
+    }
+}
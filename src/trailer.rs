@@ -0,0 +1,128 @@
+use crate::lenient;
+use csv::Reader;
+use rust_decimal::Decimal;
+use std::io::{self, Read};
+
+/// The type of the row closing a batch, whose tx column holds how many records the batch has and
+/// whose amount column what their amounts add up to, e.g. `trailer,,3,150.5`.
+pub const TRAILER_TYPE: &str = "trailer";
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// What the records before a trailer row come to, and what the trailer says they should.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Totals {
+    records: u64,
+    amount: Decimal,
+}
+
+/// Check the batch `reader` reads, whose headers were already prepared (see
+/// [`crate::schema::prepare`]), against the trailer row ending it: the number of records before
+/// it and the sum of their amounts, blank amounts counting as 0, have to match. Returns the row
+/// of the trailer, which the run skips. Reads to the end, so it's done before anything is applied
+/// and a batch that doesn't add up fails as a whole.
+pub fn verify<R: Read>(reader: &mut Reader<R>, lenient: bool) -> io::Result<usize> {
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|header| header == name)
+            .ok_or_else(|| invalid(format!("Input has no {} column.", name)))
+    };
+    let (type_column, tx_column, amount_column) =
+        (column("type")?, column("tx")?, column("amount")?);
+    let mut counted = Totals::default();
+    let mut trailer: Option<(usize, Totals)> = None;
+    for (row_number, row) in reader.records().enumerate() {
+        let row = row?;
+        if lenient && row.iter().all(|field| field.trim().is_empty()) {
+            continue;
+        }
+        if let Some((trailer_row, _)) = trailer {
+            return Err(invalid(format!(
+                "Trailer row ({}) is followed by row ({}), it has to be the last one.",
+                trailer_row, row_number
+            )));
+        }
+        let field = |index: usize| row.get(index).unwrap_or_default().trim();
+        let amount = |raw: &str| -> io::Result<Decimal> {
+            let raw = if lenient {
+                lenient::normalize_amount(raw)
+            } else {
+                raw.to_string()
+            };
+            match raw.as_str() {
+                "" => Ok(Decimal::ZERO),
+                raw => raw.parse().map_err(|_| {
+                    invalid(format!(
+                        "Row ({}) has an amount ({}) the batch total can't include.",
+                        row_number, raw
+                    ))
+                }),
+            }
+        };
+        if field(type_column).eq_ignore_ascii_case(TRAILER_TYPE) {
+            let records = field(tx_column).parse().map_err(|_| {
+                invalid(format!(
+                    "Trailer row ({}) has a malformed record count ({}).",
+                    row_number,
+                    field(tx_column)
+                ))
+            })?;
+            let amount = amount(field(amount_column))?;
+            trailer = Some((row_number, Totals { records, amount }));
+            continue;
+        }
+        counted.records += 1;
+        counted.amount = counted
+            .amount
+            .checked_add(amount(field(amount_column))?)
+            .ok_or_else(|| invalid("Batch total overflows.".to_string()))?;
+    }
+    match trailer {
+        Some((trailer_row, declared)) if declared == counted => Ok(trailer_row),
+        Some((_, declared)) => Err(invalid(format!(
+            "Trailer declares {} records totalling {}, the batch has {} totalling {}.",
+            declared.records, declared.amount, counted.records, counted.amount
+        ))),
+        None => Err(invalid(
+            "Batch has no trailer row to check it against.".to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use csv::{ReaderBuilder, Trim};
+
+    fn verify_str(input: &str) -> io::Result<usize> {
+        let mut reader = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(input.as_bytes());
+        verify(&mut reader, false)
+    }
+
+    #[test]
+    fn test_verify() {
+        let batch = "type,client,tx,amount\n\
+            deposit,1,1,100.5\n\
+            withdrawal,1,2,50\n\
+            dispute,1,1,\n";
+        assert_eq!(
+            3,
+            verify_str(&format!("{}trailer,,3,150.5\n", batch)).unwrap()
+        );
+        // amounts compare as numbers
+        assert_eq!(
+            3,
+            verify_str(&format!("{}trailer,,3,150.50\n", batch)).unwrap()
+        );
+        assert!(verify_str(&format!("{}trailer,,2,150.5\n", batch)).is_err());
+        assert!(verify_str(&format!("{}trailer,,3,150\n", batch)).is_err());
+        assert!(verify_str(batch).is_err());
+        assert!(verify_str(&format!("{}trailer,,3,150.5\ndeposit,1,3,1\n", batch)).is_err());
+    }
+}
@@ -0,0 +1,152 @@
+use serde::{de, Deserialize, Deserializer};
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+
+/// How far back a [`Velocity`] counts.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Window {
+    /// the last N records of the input, whoever's they are
+    Records(usize),
+    /// the last N seconds, by the records' timestamps
+    Seconds(u64),
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Window::Records(records) => write!(f, "{} records", records),
+            Window::Seconds(seconds) => write!(f, "{} seconds", seconds),
+        }
+    }
+}
+
+/// What happens to a client that reaches its [`Velocity`] limit.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Action {
+    Lock,
+    /// a warning, the client carries on as it is
+    Flag,
+}
+
+/// A limit on how many disputes and chargebacks a client can have within a window, see
+/// --velocity, written `N/WINDOW` for a window of records or `N/WINDOWs` for one of seconds,
+/// followed by `:flag` to only warn about the client rather than lock it, e.g. `3/86400s:flag`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Velocity {
+    pub limit: usize,
+    pub window: Window,
+    pub action: Action,
+}
+
+impl FromStr for Velocity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let malformed = || {
+            format!(
+                "Unknown velocity ({}), expected N/WINDOW with an s for seconds and an optional :lock or :flag, e.g. 3/86400s",
+                s
+            )
+        };
+        let (velocity, action) = match s.split_once(':') {
+            Some((velocity, "lock")) => (velocity, Action::Lock),
+            Some((velocity, "flag")) => (velocity, Action::Flag),
+            Some(_) => return Err(malformed()),
+            None => (s, Action::Lock),
+        };
+        let (limit, window) = velocity.split_once('/').ok_or_else(malformed)?;
+        let limit: usize = limit.parse().map_err(|_| malformed())?;
+        let window = match window.strip_suffix('s') {
+            Some(seconds) => Window::Seconds(seconds.parse().map_err(|_| malformed())?),
+            None => Window::Records(window.parse().map_err(|_| malformed())?),
+        };
+        if limit == 0 || matches!(window, Window::Records(0) | Window::Seconds(0)) {
+            return Err(malformed());
+        }
+        Ok(Velocity {
+            limit,
+            window,
+            action,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for Velocity {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// The disputes and chargebacks of a client still within its window, by monotonic counter and
+/// timestamp. A record without a timestamp counts as happening at the latest one before it.
+#[derive(Debug, Clone, Default)]
+pub struct Recent {
+    seen: VecDeque<(usize, u64)>,
+    latest: u64,
+}
+
+impl Recent {
+    /// take in a dispute or chargeback and return how many there are within `window` of it,
+    /// itself included.
+    pub fn observe(&mut self, window: Window, counter: usize, timestamp: Option<u64>) -> usize {
+        let now = timestamp.unwrap_or(self.latest).max(self.latest);
+        self.latest = now;
+        self.seen.push_back((counter, now));
+        while let Some(&(then_counter, then)) = self.seen.front() {
+            let expired = match window {
+                Window::Records(records) => counter.saturating_sub(then_counter) >= records,
+                Window::Seconds(seconds) => now - then >= seconds,
+            };
+            if !expired {
+                break;
+            }
+            self.seen.pop_front();
+        }
+        self.seen.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_velocity() {
+        assert_eq!(
+            Ok(Velocity {
+                limit: 3,
+                window: Window::Records(100),
+                action: Action::Lock,
+            }),
+            "3/100".parse()
+        );
+        assert_eq!(
+            Ok(Velocity {
+                limit: 2,
+                window: Window::Seconds(86400),
+                action: Action::Flag,
+            }),
+            "2/86400s:flag".parse()
+        );
+        for malformed in ["3", "0/10", "3/0s", "3/10m", "3/10:warn", "x/10"] {
+            assert!(malformed.parse::<Velocity>().is_err(), "{}", malformed);
+        }
+
+        let mut recent = Recent::default();
+        let by_records: Vec<usize> = [0, 5, 9, 10, 30]
+            .into_iter()
+            .map(|counter| recent.observe(Window::Records(10), counter, None))
+            .collect();
+        assert_eq!(vec![1, 2, 3, 3, 1], by_records);
+
+        let mut recent = Recent::default();
+        let by_seconds: Vec<usize> = [(0, Some(100)), (1, None), (2, Some(150)), (3, Some(200))]
+            .into_iter()
+            .map(|(counter, timestamp)| recent.observe(Window::Seconds(100), counter, timestamp))
+            .collect();
+        assert_eq!(vec![1, 2, 3, 2], by_seconds);
+    }
+}
@@ -0,0 +1,196 @@
+use crate::events::{Event, EventKind};
+use crate::pseudonym::Pseudonyms;
+use log::{debug, warn};
+use std::fmt;
+use std::io;
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An `http://` or `https://` `host[:port]/path` endpoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpUrl {
+    pub https: bool,
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl HttpUrl {
+    pub fn parse(url: &str) -> io::Result<Self> {
+        let invalid = |reason: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid url ({}): {}.", url, reason),
+            )
+        };
+        let (https, rest) = match (url.strip_prefix("https://"), url.strip_prefix("http://")) {
+            (Some(rest), _) => (true, rest),
+            (None, Some(rest)) => (false, rest),
+            (None, None) => return Err(invalid("must start with http:// or https://")),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => (&rest[..slash], &rest[slash..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse::<u16>().map_err(|_| invalid("bad port"))?),
+            None if https => (authority, 443),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(invalid("missing host"));
+        }
+        Ok(HttpUrl {
+            https,
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for HttpUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = match self.https {
+            true => "https",
+            false => "http",
+        };
+        write!(f, "{}://{}:{}{}", scheme, self.host, self.port, self.path)
+    }
+}
+
+/// POST `body` as JSON and return the response status code.
+pub fn post_json(url: &HttpUrl, body: &str) -> io::Result<u16> {
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .max_redirects(0)
+        .http_status_as_error(false)
+        .timeout_global(Some(TIMEOUT))
+        .build()
+        .into();
+    let response = agent
+        .post(url.to_string())
+        .header("Content-Type", "application/json")
+        .send(body)
+        .map_err(ureq::Error::into_io)?;
+    Ok(response.status().as_u16())
+}
+
+/// Delivers events to webhooks from a background thread so slow receivers don't stall the
+/// engine. Delivery is best effort: failures are logged and the event is dropped.
+pub struct Notifier {
+    sender: Sender<Event>,
+    delivery: JoinHandle<()>,
+}
+
+impl Notifier {
+    pub fn spawn(urls: Vec<HttpUrl>, kinds: Vec<EventKind>, pseudonyms: Pseudonyms) -> Self {
+        let (sender, receiver) = mpsc::channel::<Event>();
+        let delivery = thread::spawn(move || {
+            for event in receiver.iter().filter(|event| kinds.contains(&event.kind)) {
+                let body = event.to_json(&pseudonyms);
+                for url in &urls {
+                    match post_json(url, &body) {
+                        Ok(status) if (200..300).contains(&status) => {
+                            debug!("Delivered {} to webhook {:?}.", body, url);
+                        }
+                        Ok(status) => {
+                            warn!(
+                                "Webhook {:?} rejected {} with status {}.",
+                                url, body, status
+                            )
+                        }
+                        Err(e) => warn!("Webhook {:?} failed to receive {}: {}.", url, body, e),
+                    }
+                }
+            }
+        });
+        Notifier { sender, delivery }
+    }
+
+    pub fn sender(&self) -> &Sender<Event> {
+        &self.sender
+    }
+
+    /// Wait for queued events to be delivered. Clones of the sender must be dropped first.
+    pub fn finish(self) {
+        drop(self.sender);
+        if let Err(panic) = self.delivery.join() {
+            std::panic::resume_unwind(panic);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ClientId, TxId};
+    use rust_decimal::Decimal;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_parse_url() {
+        let url = HttpUrl::parse("http://localhost:8080/hooks/risk").unwrap();
+        assert_eq!("localhost", url.host);
+        assert_eq!(8080, url.port);
+        assert_eq!("/hooks/risk", url.path);
+        let url = HttpUrl::parse("http://example.com").unwrap();
+        assert_eq!(80, url.port);
+        assert_eq!("/", url.path);
+        let url = HttpUrl::parse("https://example.com/hooks").unwrap();
+        assert!(url.https);
+        assert_eq!(443, url.port);
+        assert_eq!("https://example.com:443/hooks", url.to_string());
+        assert!(HttpUrl::parse("ftp://example.com/").is_err());
+        assert!(HttpUrl::parse("http://:80/").is_err());
+    }
+
+    #[test]
+    fn test_notifier_posts_matching_events() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let receiver = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = vec![];
+            let mut buf = [0u8; 1024];
+            while !String::from_utf8_lossy(&request).ends_with('}') {
+                let read = stream.read(&mut buf).unwrap();
+                if read == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..read]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let url = HttpUrl::parse(&format!("http://127.0.0.1:{}/locks", port)).unwrap();
+        let notifier = Notifier::spawn(
+            vec![url],
+            vec![EventKind::AccountLocked],
+            Pseudonyms::default(),
+        );
+        for kind in [EventKind::Chargeback, EventKind::AccountLocked] {
+            let event = Event {
+                kind,
+                client_id: ClientId(3),
+                transaction_id: TxId(1),
+                amount: Decimal::new(100, 0),
+                monotonic_counter: 13,
+            };
+            notifier.sender().send(event).unwrap();
+        }
+        notifier.finish();
+
+        let request = receiver.join().unwrap();
+        assert!(request.starts_with("POST /locks HTTP/1.1\r\n"));
+        assert!(request.ends_with(
+            r#"{"event":"account_locked","client":3,"tx":1,"amount":"100","counter":13}"#
+        ));
+    }
+}